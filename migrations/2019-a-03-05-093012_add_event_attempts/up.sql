@@ -0,0 +1,12 @@
+CREATE TABLE event_attempts
+(
+    id bigserial PRIMARY KEY,
+    event_id bigint NOT NULL REFERENCES event_store (id) ON DELETE CASCADE,
+    worker_instance text NOT NULL,
+    started_at timestamp without time zone NOT NULL,
+    finished_at timestamp without time zone NOT NULL,
+    error_kind text,
+    error_message text
+);
+
+CREATE INDEX event_attempts_event_id_idx ON event_attempts (event_id);
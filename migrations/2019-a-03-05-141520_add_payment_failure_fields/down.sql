@@ -0,0 +1,2 @@
+ALTER TABLE invoices_v2 DROP COLUMN payment_failed_at;
+ALTER TABLE payment_intent DROP COLUMN last_payment_error_code;
@@ -0,0 +1,2 @@
+ALTER TABLE payment_intent ADD COLUMN last_payment_error_code VARCHAR;
+ALTER TABLE invoices_v2 ADD COLUMN payment_failed_at TIMESTAMP;
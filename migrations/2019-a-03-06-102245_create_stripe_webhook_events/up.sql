@@ -0,0 +1,6 @@
+CREATE TABLE stripe_webhook_events
+(
+    stripe_event_id VARCHAR PRIMARY KEY,
+    event_type VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
@@ -0,0 +1,2 @@
+DROP INDEX event_store_status_status_updated_at_idx;
+DROP TABLE event_store_archive;
@@ -0,0 +1,13 @@
+CREATE TABLE event_store_archive
+(
+    id bigint PRIMARY KEY,
+    event jsonb NOT NULL,
+    status text NOT NULL,
+    attempt_count integer NOT NULL,
+    created_at timestamp without time zone NOT NULL,
+    status_updated_at timestamp without time zone NOT NULL,
+    scheduled_on timestamp without time zone,
+    archived_at timestamp without time zone NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX event_store_status_status_updated_at_idx ON event_store (status, status_updated_at);
@@ -0,0 +1 @@
+ALTER TABLE orders ADD COLUMN captured_amount NUMERIC;
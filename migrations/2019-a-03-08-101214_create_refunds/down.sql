@@ -0,0 +1,3 @@
+ALTER TABLE orders DROP COLUMN refunded_amount;
+
+DROP TABLE refunds;
@@ -0,0 +1,18 @@
+CREATE TABLE refunds
+(
+    id UUID PRIMARY KEY,
+    order_id UUID NOT NULL REFERENCES orders (id),
+    amount NUMERIC NOT NULL,
+    reason TEXT,
+    stripe_refund_id VARCHAR,
+    status VARCHAR NOT NULL,
+    fee_adjustment NUMERIC NOT NULL DEFAULT 0,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    updated_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+CREATE INDEX refunds_order_id_idx ON refunds (order_id);
+
+SELECT diesel_manage_updated_at('refunds');
+
+ALTER TABLE orders ADD COLUMN refunded_amount NUMERIC NOT NULL DEFAULT 0;
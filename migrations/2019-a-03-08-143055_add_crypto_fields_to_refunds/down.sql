@@ -0,0 +1,5 @@
+ALTER TABLE refunds DROP COLUMN blockchain_fee;
+ALTER TABLE refunds DROP COLUMN transaction_id;
+ALTER TABLE refunds DROP COLUMN wallet_address;
+ALTER TABLE refunds DROP COLUMN crypto_amount;
+ALTER TABLE refunds DROP COLUMN crypto_currency;
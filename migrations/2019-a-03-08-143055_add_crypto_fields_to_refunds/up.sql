@@ -0,0 +1,5 @@
+ALTER TABLE refunds ADD COLUMN crypto_currency VARCHAR;
+ALTER TABLE refunds ADD COLUMN crypto_amount NUMERIC;
+ALTER TABLE refunds ADD COLUMN wallet_address VARCHAR;
+ALTER TABLE refunds ADD COLUMN transaction_id UUID;
+ALTER TABLE refunds ADD COLUMN blockchain_fee NUMERIC;
@@ -0,0 +1,13 @@
+CREATE TABLE order_state_transitions
+(
+    id BIGSERIAL PRIMARY KEY,
+    order_id UUID NOT NULL REFERENCES orders (id),
+    previous_state VARCHAR NOT NULL,
+    new_state VARCHAR NOT NULL,
+    user_id INTEGER,
+    event_id UUID,
+    reason TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+CREATE INDEX order_state_transitions_order_id_idx ON order_state_transitions (order_id);
@@ -0,0 +1,9 @@
+CREATE TABLE store_capture_policies
+(
+    store_id INTEGER PRIMARY KEY,
+    action VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    updated_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+SELECT diesel_manage_updated_at('store_capture_policies');
@@ -0,0 +1,22 @@
+CREATE TABLE disputes
+(
+    id UUID PRIMARY KEY,
+    stripe_dispute_id VARCHAR NOT NULL,
+    order_id UUID NOT NULL REFERENCES orders (id),
+    charge_id VARCHAR NOT NULL,
+    amount NUMERIC NOT NULL,
+    currency VARCHAR NOT NULL,
+    reason VARCHAR,
+    status VARCHAR NOT NULL,
+    evidence_due_by TIMESTAMP,
+    previous_state VARCHAR NOT NULL,
+    balance_adjustment NUMERIC NOT NULL DEFAULT 0,
+    closed_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    updated_at TIMESTAMP NOT NULL DEFAULT now(),
+    UNIQUE (stripe_dispute_id, order_id)
+);
+
+CREATE INDEX disputes_order_id_idx ON disputes (order_id);
+
+SELECT diesel_manage_updated_at('disputes');
@@ -0,0 +1,3 @@
+DROP TABLE invoice_surpluses;
+
+ALTER TABLE invoices_v2 DROP COLUMN surplus_amount;
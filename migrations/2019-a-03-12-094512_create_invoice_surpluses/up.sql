@@ -0,0 +1,20 @@
+ALTER TABLE invoices_v2 ADD COLUMN surplus_amount NUMERIC NOT NULL DEFAULT 0;
+
+CREATE TABLE invoice_surpluses
+(
+    id UUID PRIMARY KEY,
+    invoice_id UUID NOT NULL UNIQUE REFERENCES invoices_v2 (id),
+    buyer_user_id INTEGER NOT NULL,
+    currency VARCHAR NOT NULL,
+    amount NUMERIC NOT NULL,
+    handling VARCHAR NOT NULL,
+    status VARCHAR NOT NULL,
+    wallet_address VARCHAR,
+    transaction_id UUID,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    updated_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+CREATE INDEX invoice_surpluses_buyer_user_id_idx ON invoice_surpluses (buyer_user_id);
+
+SELECT diesel_manage_updated_at('invoice_surpluses');
@@ -0,0 +1,29 @@
+CREATE TABLE document_sequences
+(
+    store_id INTEGER NOT NULL,
+    kind VARCHAR NOT NULL,
+    last_number BIGINT NOT NULL,
+    PRIMARY KEY (store_id, kind)
+);
+
+CREATE TABLE documents
+(
+    id UUID PRIMARY KEY,
+    kind VARCHAR NOT NULL,
+    store_id INTEGER NOT NULL,
+    invoice_id UUID REFERENCES invoices_v2 (id),
+    fee_id INTEGER UNIQUE REFERENCES fees (id),
+    buyer_user_id INTEGER,
+    number BIGINT NOT NULL,
+    document_number VARCHAR NOT NULL,
+    format VARCHAR NOT NULL,
+    content BYTEA NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    updated_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+CREATE UNIQUE INDEX documents_store_id_kind_number_idx ON documents (store_id, kind, number);
+CREATE UNIQUE INDEX documents_invoice_id_store_id_idx ON documents (invoice_id, store_id) WHERE invoice_id IS NOT NULL;
+CREATE INDEX documents_buyer_user_id_idx ON documents (buyer_user_id);
+
+SELECT diesel_manage_updated_at('documents');
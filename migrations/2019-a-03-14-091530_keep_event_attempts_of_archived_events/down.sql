@@ -0,0 +1,2 @@
+DELETE FROM event_attempts WHERE event_id NOT IN (SELECT id FROM event_store);
+ALTER TABLE event_attempts ADD CONSTRAINT event_attempts_event_id_fkey FOREIGN KEY (event_id) REFERENCES event_store (id) ON DELETE CASCADE;
@@ -0,0 +1 @@
+ALTER TABLE event_attempts DROP CONSTRAINT event_attempts_event_id_fkey;
@@ -0,0 +1 @@
+ALTER TABLE disputes ADD COLUMN settled_payout_id UUID REFERENCES payouts (id);
@@ -0,0 +1 @@
+ALTER TABLE orders ADD COLUMN delivered_at TIMESTAMP;
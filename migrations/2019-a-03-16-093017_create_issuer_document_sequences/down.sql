@@ -0,0 +1,4 @@
+INSERT INTO document_sequences (store_id, kind, last_number)
+    SELECT store_id, kind, MAX(number) FROM documents WHERE kind = 'fee_invoice' GROUP BY store_id, kind;
+
+DROP TABLE issuer_document_sequences;
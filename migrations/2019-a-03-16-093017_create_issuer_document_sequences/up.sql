@@ -0,0 +1,10 @@
+CREATE TABLE issuer_document_sequences
+(
+    kind VARCHAR PRIMARY KEY,
+    last_number BIGINT NOT NULL
+);
+
+INSERT INTO issuer_document_sequences (kind, last_number)
+    SELECT kind, MAX(number) FROM documents WHERE kind = 'fee_invoice' GROUP BY kind;
+
+DELETE FROM document_sequences WHERE kind = 'fee_invoice';
@@ -0,0 +1 @@
+ALTER TABLE refunds ADD COLUMN kind VARCHAR NOT NULL DEFAULT 'refund';
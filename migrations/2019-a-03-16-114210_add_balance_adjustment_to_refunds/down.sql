@@ -0,0 +1,2 @@
+ALTER TABLE refunds DROP COLUMN settled_payout_id;
+ALTER TABLE refunds DROP COLUMN balance_adjustment;
@@ -0,0 +1,2 @@
+ALTER TABLE refunds ADD COLUMN balance_adjustment NUMERIC NOT NULL DEFAULT 0;
+ALTER TABLE refunds ADD COLUMN settled_payout_id UUID REFERENCES payouts (id);
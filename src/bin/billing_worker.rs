@@ -0,0 +1,17 @@
+//! Runs the billing event processor without the HTTP server, so that it can be scaled separately.
+//! See `billing_lib` for details.
+
+extern crate billing_lib;
+extern crate stq_logging;
+
+fn main() {
+    let config = billing_lib::config::Config::new().expect("Can't load app config!");
+
+    // Prepare sentry integration
+    let _sentry = billing_lib::sentry_integration::init(config.sentry.as_ref());
+
+    // Prepare logger
+    stq_logging::init(config.graylog.as_ref());
+
+    billing_lib::start_event_worker(config);
+}
@@ -2,6 +2,7 @@ mod error;
 mod types;
 pub use self::types::{NewPaymentIntent, *};
 
+use futures::future;
 use futures::Future;
 use futures::IntoFuture;
 use stripe::{
@@ -45,6 +46,16 @@ pub trait StripeClient: Send + Sync + 'static {
 
     fn refund(&self, charge_id: ChargeId, amount: Amount, order_id: OrderId) -> Box<Future<Item = Refund, Error = Error> + Send>;
 
+    /// Refunds the charge unless it already has a refund created for `refund_id`, that refund is returned then.
+    /// A refund recorded before calling Stripe can be retried this way without moving the money twice
+    fn refund_once(
+        &self,
+        charge_id: ChargeId,
+        amount: Amount,
+        order_id: OrderId,
+        refund_id: RefundId,
+    ) -> Box<Future<Item = Refund, Error = Error> + Send>;
+
     fn create_payout(
         &self,
         amount: Amount,
@@ -73,6 +84,23 @@ impl StripeClientImpl {
             client,
         }
     }
+
+    fn create_refund(&self, charge_id: ChargeId, amount: Amount, metadata: Metadata) -> Box<Future<Item = Refund, Error = Error> + Send> {
+        Box::new(
+            Refund::create(
+                &self.client,
+                RefundParams {
+                    charge: &charge_id.inner(),
+                    amount: Some(amount.inner() as u64),
+                    metadata,
+                    reason: None,
+                    refund_application_fee: None,
+                    reverse_transfer: None,
+                },
+            )
+            .map_err(From::from),
+        )
+    }
 }
 
 impl StripeClient for StripeClientImpl {
@@ -186,20 +214,35 @@ impl StripeClient for StripeClientImpl {
     fn refund(&self, charge_id: ChargeId, amount: Amount, order_id: OrderId) -> Box<Future<Item = Refund, Error = Error> + Send> {
         let mut metadata = Metadata::new();
         metadata.insert("order_id".to_string(), format!("{}", order_id));
-        Box::new(
-            Refund::create(
-                &self.client,
-                RefundParams {
-                    charge: &charge_id.inner(),
-                    amount: Some(amount.inner() as u64),
-                    metadata,
-                    reason: None,
-                    refund_application_fee: None,
-                    reverse_transfer: None,
-                },
-            )
-            .map_err(From::from),
-        )
+        self.create_refund(charge_id, amount, metadata)
+    }
+
+    fn refund_once(
+        &self,
+        charge_id: ChargeId,
+        amount: Amount,
+        order_id: OrderId,
+        refund_id: RefundId,
+    ) -> Box<Future<Item = Refund, Error = Error> + Send> {
+        let self_ = self.clone();
+        Box::new(self.get_charge(charge_id.clone()).and_then(move |charge| {
+            let refund_id_value = format!("{}", refund_id);
+            let existing_refund = charge
+                .refunds
+                .data
+                .into_iter()
+                .find(|refund| refund.metadata.get("refund_id") == Some(&refund_id_value));
+
+            match existing_refund {
+                Some(refund) => Box::new(future::ok(refund)) as Box<Future<Item = Refund, Error = Error> + Send>,
+                None => {
+                    let mut metadata = Metadata::new();
+                    metadata.insert("order_id".to_string(), format!("{}", order_id));
+                    metadata.insert("refund_id".to_string(), refund_id_value);
+                    self_.create_refund(charge_id, amount, metadata)
+                }
+            }
+        }))
     }
 
     fn create_payout(
@@ -1,6 +1,8 @@
 //! Config module contains the top-level config for the app.
+use std::collections::HashMap;
 use std::env;
 
+use bigdecimal::BigDecimal;
 use config_crate::{Config as RawConfig, ConfigError, Environment, File};
 use sentry_integration::SentryConfig;
 use uuid::Uuid;
@@ -8,6 +10,8 @@ use uuid::Uuid;
 use stq_http;
 use stq_logging::GrayLogConfig;
 
+use models::{CaptureSlaAction, Currency, SurplusHandling};
+
 /// Basic settings - HTTP binding, saga and external billing addresses
 #[derive(Debug, Deserialize, Clone)]
 pub struct Config {
@@ -26,6 +30,11 @@ pub struct Config {
     pub fee: FeeValues,
     pub payment_expiry: PaymentExpiry,
     pub subscription: Subscription,
+    pub crypto_refund: CryptoRefund,
+    pub capture_sla: CaptureSla,
+    pub escrow: Escrow,
+    pub invoice_payment: InvoicePayment,
+    pub documents: Documents,
 }
 
 /// Common server settings
@@ -38,6 +47,8 @@ pub struct Server {
     pub redis: Option<String>,
     pub cache_ttl_sec: u64,
     pub processing_timeout_ms: u32,
+    /// Run the event processor inside the HTTP server process, disable when events are handled by `billing-worker`
+    pub process_events: bool,
 }
 
 /// Http client settings
@@ -116,6 +127,33 @@ pub struct EventStore {
     pub max_processing_attempts: u32,
     pub stuck_threshold_sec: u32,
     pub polling_rate_sec: u32,
+    pub batch_size: u32,
+    pub retry: EventRetry,
+    /// Per-payload overrides of the retry policy, keyed by `EventPayload` variant name (e.g. "InvoicePaid")
+    #[serde(default)]
+    pub retry_by_payload: HashMap<String, EventRetry>,
+    pub retention: EventRetention,
+}
+
+/// Retention policy for completed events
+#[derive(Debug, Deserialize, Clone)]
+pub struct EventRetention {
+    pub enabled: bool,
+    /// Completed events are kept in `event_store` for this many days after completion
+    pub retention_days: u32,
+    /// Move expired events to `event_store_archive` if set, delete them otherwise
+    pub archive: bool,
+    pub interval_sec: u32,
+    pub batch_size: u32,
+}
+
+/// Exponential backoff settings for failed events
+#[derive(Debug, Deserialize, Clone)]
+pub struct EventRetry {
+    pub initial_delay_sec: u32,
+    pub max_delay_sec: u32,
+    pub jitter_percent: u32,
+    pub max_processing_attempts: Option<u32>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -136,6 +174,81 @@ pub struct Subscription {
     pub trial_time_duration_days: i64,
 }
 
+/// Crypto refund settings
+#[derive(Debug, Deserialize, Clone)]
+pub struct CryptoRefund {
+    /// Delay between checks of an outbound refund transaction until it gets confirmed
+    pub confirmation_check_interval_sec: u32,
+}
+
+/// Handling of paid orders the seller neither captures nor declines
+#[derive(Debug, Deserialize, Clone)]
+pub struct CaptureSla {
+    /// Time after the invoice is paid before the order is handled automatically, card authorisations expire in about 7 days
+    pub window_hours: u32,
+    /// Used for stores without their own capture policy
+    pub default_action: CaptureSlaAction,
+}
+
+/// Holding of captured orders until the buyer had time to check the delivery
+#[derive(Debug, Deserialize, Clone)]
+pub struct Escrow {
+    /// Time after the delivery before the order is released for payout
+    pub release_after_days: u32,
+    /// Delay before checking again an order that could not be released because of an open dispute or refund
+    pub recheck_interval_hours: u32,
+}
+
+/// Settlement of crypto invoices paid with a slightly different amount than the price
+#[derive(Debug, Deserialize, Clone)]
+pub struct InvoicePayment {
+    /// Shortfall an invoice still counts as paid with, keyed by currency code (e.g. "btc")
+    #[serde(default)]
+    pub underpayment_tolerance: HashMap<String, PaymentTolerance>,
+    /// What happens to the amount paid above the invoice price
+    pub surplus_handling: SurplusHandling,
+}
+
+/// Shortfall in super units of the currency and in percent of the invoice price, the larger one applies
+#[derive(Debug, Deserialize, Clone)]
+pub struct PaymentTolerance {
+    pub absolute: Option<BigDecimal>,
+    pub percent: Option<BigDecimal>,
+}
+
+impl InvoicePayment {
+    /// Shortfall accepted for an invoice with the given price, in super units of the currency
+    pub fn underpayment_tolerance(&self, currency: Currency, total_price: &BigDecimal) -> BigDecimal {
+        let tolerance = match self.underpayment_tolerance.get(&currency.to_string()) {
+            Some(tolerance) => tolerance,
+            None => return BigDecimal::from(0),
+        };
+
+        let absolute = tolerance.absolute.clone().unwrap_or_else(|| BigDecimal::from(0));
+        let relative = tolerance
+            .percent
+            .clone()
+            .map(|percent| total_price.clone() * percent / BigDecimal::from(100))
+            .unwrap_or_else(|| BigDecimal::from(0));
+
+        if absolute > relative {
+            absolute
+        } else {
+            relative
+        }
+    }
+}
+
+/// Printable receipts of paid invoices and invoices of paid fees
+#[derive(Debug, Deserialize, Clone)]
+pub struct Documents {
+    /// Command reading HTML from stdin and writing PDF to stdout, documents are stored as HTML if it is not set or fails
+    #[serde(default)]
+    pub pdf_converter: Option<Vec<String>>,
+    /// Legal name of the marketplace printed as the issuer of fee invoices
+    pub issuer_name: String,
+}
+
 /// Creates new app config struct
 /// #Examples
 /// ```
@@ -148,11 +261,28 @@ impl Config {
         let mut s = RawConfig::new();
 
         s.set_default("server.processing_timeout_ms", 1000i64).unwrap();
+        s.set_default("server.process_events", true).unwrap();
         s.set_default("event_store.max_processing_attempts", 3i64).unwrap();
         s.set_default("event_store.stuck_threshold_sec", 300i64).unwrap();
         s.set_default("event_store.polling_rate_sec", 10i64).unwrap();
+        s.set_default("event_store.batch_size", 10i64).unwrap();
+        s.set_default("event_store.retry.initial_delay_sec", 30i64).unwrap();
+        s.set_default("event_store.retry.max_delay_sec", 3600i64).unwrap();
+        s.set_default("event_store.retry.jitter_percent", 20i64).unwrap();
+        s.set_default("event_store.retention.enabled", true).unwrap();
+        s.set_default("event_store.retention.retention_days", 30i64).unwrap();
+        s.set_default("event_store.retention.archive", true).unwrap();
+        s.set_default("event_store.retention.interval_sec", 3600i64).unwrap();
+        s.set_default("event_store.retention.batch_size", 1000i64).unwrap();
         s.set_default("payment_expiry.crypto_timeout_min", 4320i64).unwrap();
         s.set_default("payment_expiry.fiat_timeout_min", 60i64).unwrap();
+        s.set_default("crypto_refund.confirmation_check_interval_sec", 60i64).unwrap();
+        s.set_default("capture_sla.window_hours", 144i64).unwrap();
+        s.set_default("capture_sla.default_action", "decline").unwrap();
+        s.set_default("escrow.release_after_days", 14i64).unwrap();
+        s.set_default("escrow.recheck_interval_hours", 24i64).unwrap();
+        s.set_default("invoice_payment.surplus_handling", "store_credit").unwrap();
+        s.set_default("documents.issuer_name", "Storiqa").unwrap();
         s.set_default("payments_mock.use_mock", false).unwrap();
         s.set_default("payments_mock.min_pooled_accounts", 10).unwrap();
         s.set_default("payments_mock.accounts.main_stq", "cc3f3875-e719-427f-9b83-d4dae8d4263a")
@@ -184,3 +314,53 @@ impl Config {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn invoice_payment(absolute: Option<&str>, percent: Option<&str>) -> InvoicePayment {
+        let tolerance = PaymentTolerance {
+            absolute: absolute.map(|absolute| BigDecimal::from_str(absolute).unwrap()),
+            percent: percent.map(|percent| BigDecimal::from_str(percent).unwrap()),
+        };
+        InvoicePayment {
+            underpayment_tolerance: vec![("btc".to_string(), tolerance)].into_iter().collect(),
+            surplus_handling: SurplusHandling::Refund,
+        }
+    }
+
+    #[test]
+    fn test_underpayment_tolerance() {
+        let price = BigDecimal::from(2);
+
+        let payment = invoice_payment(Some("0.001"), None);
+        assert_eq!(
+            payment.underpayment_tolerance(Currency::Btc, &price),
+            BigDecimal::from_str("0.001").unwrap()
+        );
+
+        let payment = invoice_payment(None, Some("1"));
+        assert_eq!(
+            payment.underpayment_tolerance(Currency::Btc, &price),
+            BigDecimal::from_str("0.02").unwrap()
+        );
+
+        // the larger of the two applies
+        let payment = invoice_payment(Some("0.001"), Some("1"));
+        assert_eq!(
+            payment.underpayment_tolerance(Currency::Btc, &price),
+            BigDecimal::from_str("0.02").unwrap()
+        );
+        let payment = invoice_payment(Some("0.05"), Some("1"));
+        assert_eq!(
+            payment.underpayment_tolerance(Currency::Btc, &price),
+            BigDecimal::from_str("0.05").unwrap()
+        );
+
+        // no tolerance for a currency missing from the config
+        assert_eq!(payment.underpayment_tolerance(Currency::Eth, &price), BigDecimal::from(0));
+    }
+}
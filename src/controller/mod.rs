@@ -34,7 +34,7 @@ use client::payments::mock::MockPaymentsClient;
 use client::payments::{PaymentsClient, PaymentsClientImpl};
 use controller::requests::*;
 use errors::Error;
-use models::order_v2::OrdersSearch;
+use models::order_v2::{OrdersSearch, StoreId as StoreV2Id};
 use models::*;
 use repos::repo_factory::*;
 use repos::SearchFee;
@@ -44,6 +44,8 @@ use services::billing_info::{BillingInfoService, BillingInfoServiceImpl};
 use services::billing_type::{BillingTypeService, BillingTypeServiceImpl};
 use services::customer::CustomersService;
 use services::customer::CustomersServiceImpl;
+use services::document::{DocumentService, DocumentServiceImpl};
+use services::event_store::{EventStoreService, EventStoreServiceImpl};
 use services::fee::{FeesService, FeesServiceImpl};
 use services::invoice::InvoiceService;
 use services::merchant::MerchantService;
@@ -51,11 +53,13 @@ use services::order::OrderService;
 use services::order_billing::{OrderBillingService, OrderBillingServiceImpl};
 use services::payment_intent::{PaymentIntentService, PaymentIntentServiceImpl};
 use services::payout::{CalculatePayoutPayload, GetPayoutsPayload, PayOutToSellerPayload, PayoutService, PayoutServiceImpl};
+use services::store_capture_policy::{StoreCapturePolicyService, StoreCapturePolicyServiceImpl};
 use services::store_subscription::{StoreSubscriptionService, StoreSubscriptionServiceImpl};
 use services::stripe::{StripeService, StripeServiceImpl};
 use services::subscription::{SubscriptionService, SubscriptionServiceImpl};
 use services::subscription_payment::{SubscriptionPaymentService, SubscriptionPaymentServiceImpl};
 use services::user_roles::UserRolesService;
+use services::webhook_subscription::{WebhookSubscriptionService, WebhookSubscriptionServiceImpl};
 use services::Service;
 
 /// Controller handles route parsing and calling `Service` layer
@@ -250,6 +254,34 @@ impl<
             config: self.static_context.config.subscription.clone(),
         });
 
+        let store_capture_policy_service = Arc::new(StoreCapturePolicyServiceImpl {
+            db_pool: self.static_context.db_pool.clone(),
+            cpu_pool: self.static_context.cpu_pool.clone(),
+            repo_factory: self.static_context.repo_factory.clone(),
+            dynamic_context: dynamic_context.clone(),
+        });
+
+        let document_service = Arc::new(DocumentServiceImpl {
+            db_pool: self.static_context.db_pool.clone(),
+            cpu_pool: self.static_context.cpu_pool.clone(),
+            repo_factory: self.static_context.repo_factory.clone(),
+            dynamic_context: dynamic_context.clone(),
+        });
+
+        let event_store_service = Arc::new(EventStoreServiceImpl {
+            db_pool: self.static_context.db_pool.clone(),
+            cpu_pool: self.static_context.cpu_pool.clone(),
+            repo_factory: self.static_context.repo_factory.clone(),
+            dynamic_context: dynamic_context.clone(),
+        });
+
+        let webhook_subscription_service = Arc::new(WebhookSubscriptionServiceImpl {
+            db_pool: self.static_context.db_pool.clone(),
+            cpu_pool: self.static_context.cpu_pool.clone(),
+            repo_factory: self.static_context.repo_factory.clone(),
+            dynamic_context: dynamic_context.clone(),
+        });
+
         let path = req.path().to_string();
 
         let fut = match (&req.method().clone(), self.static_context.route_parser.test(req.path())) {
@@ -318,6 +350,17 @@ impl<
             (Get, Some(Route::InvoiceByIdV2 { id })) => {
                 serialize_future(service.recalc_invoice_v2(id).map_err(Error::from).map_err(failure::Error::from))
             }
+            (Post, Some(Route::InvoiceByIdV2Cancel { id })) => {
+                serialize_future(service.cancel_invoice_v2(id).map_err(Error::from).map_err(failure::Error::from))
+            }
+            (Put, Some(Route::InvoiceByIdV2BuyerCurrency { id })) => {
+                serialize_future(parse_body::<SetInvoiceBuyerCurrencyRequest>(req.body()).and_then(move |data| {
+                    service
+                        .set_invoice_buyer_currency_v2(id, data)
+                        .map_err(Error::from)
+                        .map_err(failure::Error::from)
+                }))
+            }
             (Post, Some(Route::InvoiceByIdRecalc { id })) => serialize_future({ service.recalc_invoice(id) }),
             (Get, Some(Route::InvoiceOrdersIds { id })) => serialize_future({ service.get_invoice_orders_ids(id) }),
             (Get, Some(Route::RolesByUserId { user_id })) => serialize_future({ service.get_roles(user_id) }),
@@ -334,8 +377,61 @@ impl<
                 serialize_future({ payment_intent_service.get_by_invoice(invoice_id) })
             }
             (Post, Some(Route::PaymentIntentByFee { fee_id })) => serialize_future({ payment_intent_service.create_by_fee(fee_id) }),
-            (Post, Some(Route::OrdersByIdCapture { id })) => serialize_future({ service.order_capture(id) }),
+            (Post, Some(Route::OrdersByIdCapture { id })) => serialize_future({
+                read_body(req.body()).map_err(failure::Error::from).and_then(move |body| {
+                    // the body is optional, an empty one captures the whole order
+                    let payload: Result<CaptureOrderRequest, failure::Error> = if body.trim().is_empty() {
+                        Ok(CaptureOrderRequest::default())
+                    } else {
+                        serde_json::from_str(&body).map_err(|e| failure::Error::from(e).context(Error::Parse).into())
+                    };
+
+                    payload.into_future().and_then(move |payload| {
+                        service
+                            .order_capture(id, payload)
+                            .map_err(Error::from)
+                            .map_err(failure::Error::from)
+                    })
+                })
+            }),
             (Post, Some(Route::OrdersByIdDecline { id })) => serialize_future({ service.order_decline(id) }),
+            (Post, Some(Route::OrdersBulkCapture)) => serialize_future({
+                parse_body::<BulkOrdersRequest>(req.body())
+                    .map_err(failure::Error::from)
+                    .and_then(move |payload| service.orders_bulk_capture(payload).map_err(failure::Error::from))
+            }),
+            (Post, Some(Route::OrdersBulkDecline)) => serialize_future({
+                parse_body::<BulkOrdersRequest>(req.body())
+                    .map_err(failure::Error::from)
+                    .and_then(move |payload| service.orders_bulk_decline(payload).map_err(failure::Error::from))
+            }),
+            (Get, Some(Route::OrdersByIdRefunds { id })) => serialize_future({ service.get_order_refunds(id) }),
+            (Post, Some(Route::OrdersByIdRefunds { id })) => serialize_future({
+                parse_body::<CreateRefundRequest>(req.body())
+                    .map_err(failure::Error::from)
+                    .and_then(move |payload| service.order_refund(id, payload).map_err(failure::Error::from))
+            }),
+            (Get, Some(Route::OrdersByIdHistory { id })) => serialize_future({ service.get_order_history(id) }),
+            (Get, Some(Route::OrdersByIdDisputes { id })) => serialize_future({ service.get_order_disputes(id) }),
+            (Get, Some(Route::OrdersByIdTransitions { id })) => serialize_future({ service.get_order_transitions(id) }),
+            (Post, Some(Route::OrdersByIdDelivered { id })) => serialize_future({ service.order_delivered(id) }),
+            (Post, Some(Route::OrdersByIdCryptoRefund { id })) => serialize_future({
+                read_body(req.body()).map_err(failure::Error::from).and_then(move |body| {
+                    // the body is optional, the buyer's active wallet is used if no address is given
+                    let payload: Result<CreateCryptoRefundRequest, failure::Error> = if body.trim().is_empty() {
+                        Ok(CreateCryptoRefundRequest::default())
+                    } else {
+                        serde_json::from_str(&body).map_err(|e| failure::Error::from(e).context(Error::Parse).into())
+                    };
+
+                    payload.into_future().and_then(move |payload| {
+                        service
+                            .order_refund_crypto(id, payload)
+                            .map_err(Error::from)
+                            .map_err(failure::Error::from)
+                    })
+                })
+            }),
 
             (Post, Some(Route::OrdersSetPaymentState { order_id })) => serialize_future({
                 parse_body::<OrderPaymentStateRequest>(req.body())
@@ -535,6 +631,130 @@ impl<
                 }))
             }
 
+            (Get, Some(Route::StoreCapturePolicyByStoreId { store_id })) => {
+                serialize_future({ store_capture_policy_service.get(store_id).map_err(failure::Error::from) })
+            }
+            (Put, Some(Route::StoreCapturePolicyByStoreId { store_id })) => {
+                serialize_future(parse_body::<SetStoreCapturePolicyRequest>(req.body()).and_then(move |payload| {
+                    store_capture_policy_service
+                        .set(store_id, payload)
+                        .map_err(Error::from)
+                        .map_err(failure::Error::from)
+                }))
+            }
+            (Get, Some(Route::InvoiceByIdV2Documents { id })) => {
+                serialize_future({ document_service.list_invoice_documents(id).map_err(failure::Error::from) })
+            }
+            (Get, Some(Route::FeeDocument { id })) => {
+                serialize_future({ document_service.get_fee_document(id).map_err(failure::Error::from) })
+            }
+            (Get, Some(Route::DocumentById { id })) => {
+                serialize_future({ document_service.get_document(id).map_err(failure::Error::from) })
+            }
+
+            (Post, Some(Route::EventsSearch)) => {
+                let (skip_opt, count_opt) = parse_query!(
+                    req.query().unwrap_or_default(),
+                    "skip" => i64, "count" => i64
+                );
+
+                let skip = skip_opt.unwrap_or(0);
+                let count = count_opt.unwrap_or(0);
+
+                serialize_future(parse_body::<EventEntrySearch>(req.body()).and_then(move |payload| {
+                    event_store_service
+                        .search_events(skip, count, payload)
+                        .map_err(Error::from)
+                        .map_err(failure::Error::from)
+                }))
+            }
+            (Get, Some(Route::EventById { id })) => {
+                serialize_future(event_store_service.get_event(id).map_err(Error::from).map_err(failure::Error::from))
+            }
+            (Post, Some(Route::EventByIdRequeue { id })) => serialize_future(
+                event_store_service
+                    .requeue_event(id)
+                    .map_err(Error::from)
+                    .map_err(failure::Error::from),
+            ),
+            (Post, Some(Route::EventsRequeue)) => serialize_future({
+                parse_body::<RequeueEventsRequest>(req.body()).and_then(move |payload| {
+                    event_store_service
+                        .requeue_events(payload.event_ids)
+                        .map_err(Error::from)
+                        .map_err(failure::Error::from)
+                })
+            }),
+            (Post, Some(Route::EventsReplay)) => serialize_future({
+                parse_body::<ReplayEventsRequest>(req.body()).and_then(move |payload| {
+                    event_store_service
+                        .replay_events(payload.search, payload.dry_run)
+                        .map_err(Error::from)
+                        .map_err(failure::Error::from)
+                })
+            }),
+            (Post, Some(Route::EventByIdResolve { id })) => serialize_future(
+                event_store_service
+                    .resolve_event(id)
+                    .map_err(Error::from)
+                    .map_err(failure::Error::from),
+            ),
+
+            (Post, Some(Route::WebhookSubscriptions)) => serialize_future({
+                parse_body::<CreateWebhookSubscriptionRequest>(req.body()).and_then(move |payload| {
+                    webhook_subscription_service
+                        .create(payload)
+                        .map_err(Error::from)
+                        .map_err(failure::Error::from)
+                })
+            }),
+            (Get, Some(Route::WebhookSubscriptions)) => {
+                let store_id = parse_query!(req.query().unwrap_or_default(), "store_id" => StoreV2Id);
+
+                serialize_future(
+                    webhook_subscription_service
+                        .list(store_id)
+                        .map_err(Error::from)
+                        .map_err(failure::Error::from),
+                )
+            }
+            (Get, Some(Route::WebhookSubscriptionById { id })) => serialize_future(
+                webhook_subscription_service
+                    .get(id)
+                    .map_err(Error::from)
+                    .map_err(failure::Error::from),
+            ),
+            (Put, Some(Route::WebhookSubscriptionById { id })) => serialize_future({
+                parse_body::<UpdateWebhookSubscriptionRequest>(req.body()).and_then(move |payload| {
+                    webhook_subscription_service
+                        .update(id, payload)
+                        .map_err(Error::from)
+                        .map_err(failure::Error::from)
+                })
+            }),
+            (Delete, Some(Route::WebhookSubscriptionById { id })) => serialize_future(
+                webhook_subscription_service
+                    .delete(id)
+                    .map_err(Error::from)
+                    .map_err(failure::Error::from),
+            ),
+            (Get, Some(Route::WebhookSubscriptionDeliveries { id })) => {
+                let (skip_opt, count_opt) = parse_query!(
+                    req.query().unwrap_or_default(),
+                    "skip" => i64, "count" => i64
+                );
+
+                let skip = skip_opt.unwrap_or(0);
+                let count = count_opt.unwrap_or(0);
+
+                serialize_future(
+                    webhook_subscription_service
+                        .get_deliveries(id, skip, count)
+                        .map_err(Error::from)
+                        .map_err(failure::Error::from),
+                )
+            }
+
             // Fallback
             (m, _) => not_found(m, path),
         }
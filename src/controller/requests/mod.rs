@@ -1,7 +1,11 @@
 use stq_static_resources::Currency as StqCurrency;
 
-use models::order_v2::OrderId as Orderv2Id;
-use models::{CreateStoreSubscription, CustomerId, NewSubscription, PaymentState, StoreSubscriptionStatus, UpdateStoreSubscription};
+use models::order_v2::{OrderId as Orderv2Id, StoreId};
+use models::{
+    CaptureSlaAction, CreateStoreSubscription, Currency, CustomerId, EventEntryId, EventReplaySearch, NewSubscription,
+    NewWebhookSubscription, PaymentState, StoreSubscriptionStatus, UpdateStoreSubscription, UpdateWebhookSubscription, WalletAddress,
+    WebhookEventType, WebhookSubscriptionId,
+};
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct NewCustomerWithSourceRequest {
@@ -56,6 +60,12 @@ impl From<UpdateStoreSubscriptionRequest> for UpdateStoreSubscription {
     }
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct SetStoreCapturePolicyRequest {
+    /// What happens to orders of the store not captured within the capture SLA
+    pub action: CaptureSlaAction,
+}
+
 impl From<CreateStoreSubscriptionRequest> for CreateStoreSubscription {
     fn from(data: CreateStoreSubscriptionRequest) -> Self {
         CreateStoreSubscription {
@@ -63,3 +73,87 @@ impl From<CreateStoreSubscriptionRequest> for CreateStoreSubscription {
         }
     }
 }
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct CaptureOrderRequest {
+    /// Amount in seller currency, the whole order is captured if not set
+    pub amount: Option<f64>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SetInvoiceBuyerCurrencyRequest {
+    /// Currency the buyer pays the invoice in
+    pub currency: Currency,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct BulkOrdersRequest {
+    pub order_ids: Vec<Orderv2Id>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CreateRefundRequest {
+    /// Amount in seller currency
+    pub amount: f64,
+    pub reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct CreateCryptoRefundRequest {
+    /// Buyer wallet the refund is sent to, the buyer's active wallet is used if not set
+    pub wallet_address: Option<WalletAddress>,
+    pub reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RequeueEventsRequest {
+    pub event_ids: Vec<EventEntryId>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ReplayEventsRequest {
+    #[serde(flatten)]
+    pub search: EventReplaySearch,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CreateWebhookSubscriptionRequest {
+    pub store_id: Option<StoreId>,
+    pub url: String,
+    pub secret: String,
+    #[serde(default)]
+    pub event_types: Vec<WebhookEventType>,
+}
+
+impl From<CreateWebhookSubscriptionRequest> for NewWebhookSubscription {
+    fn from(data: CreateWebhookSubscriptionRequest) -> Self {
+        NewWebhookSubscription {
+            id: WebhookSubscriptionId::generate(),
+            store_id: data.store_id,
+            url: data.url,
+            secret: data.secret,
+            event_types: data.event_types,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct UpdateWebhookSubscriptionRequest {
+    pub url: Option<String>,
+    pub secret: Option<String>,
+    pub event_types: Option<Vec<WebhookEventType>>,
+    pub is_active: Option<bool>,
+}
+
+impl From<UpdateWebhookSubscriptionRequest> for UpdateWebhookSubscription {
+    fn from(data: UpdateWebhookSubscriptionRequest) -> Self {
+        UpdateWebhookSubscription {
+            url: data.url,
+            secret: data.secret,
+            event_types: data.event_types,
+            is_active: data.is_active,
+        }
+    }
+}
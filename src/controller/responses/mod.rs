@@ -11,7 +11,8 @@ use models::{
     fee::FeeId,
     invoice_v2::InvoiceId,
     order_v2::{OrderId, RawOrder, StoreId},
-    ChargeId, CustomerId, Fee, FeeStatus, PaymentIntent, PaymentIntentStatus, PaymentState, StoreSubscriptionStatus, SubscriptionPayment,
+    ChargeId, Currency, CustomerId, Document, DocumentFormat, DocumentId, DocumentKind, Fee, FeeStatus, PaymentIntent, PaymentIntentStatus,
+    PaymentState, Refund, RefundId, RefundKind, RefundStatus, StoreSubscriptionStatus, SubscriptionPayment,
     SubscriptionPaymentSearchResults, SubscriptionPaymentStatus, TransactionId, WalletAddress,
 };
 use stq_static_resources::Currency as StqCurrency;
@@ -26,6 +27,7 @@ pub struct PaymentIntentResponse {
     pub client_secret: Option<String>,
     pub currency: StqCurrency,
     pub last_payment_error_message: Option<String>,
+    pub last_payment_error_code: Option<String>,
     pub receipt_email: Option<String>,
     pub charge_id: Option<ChargeId>,
     pub status: PaymentIntentStatus,
@@ -44,6 +46,7 @@ impl PaymentIntentResponse {
                 client_secret: other.client_secret,
                 currency: other.currency.into(),
                 last_payment_error_message: other.last_payment_error_message,
+                last_payment_error_code: other.last_payment_error_code,
                 receipt_email: other.receipt_email,
                 charge_id: other.charge_id,
                 status: other.status,
@@ -65,6 +68,8 @@ pub struct OrderResponse {
     pub store_id: StoreId,
     pub state: PaymentState,
     pub stripe_fee: Option<f64>,
+    pub captured_amount: Option<f64>,
+    pub refunded_amount: f64,
 }
 
 impl OrderResponse {
@@ -88,6 +93,20 @@ impl OrderResponse {
         } else {
             None
         };
+        let refunded_amount = raw_order
+            .refunded_amount
+            .to_super_unit(raw_order.seller_currency)
+            .to_f64()
+            .ok_or(ectx!(try err ErrorContext::AmountConversion, ErrorKind::Internal))?;
+        let captured_amount = if let Some(c) = raw_order.captured_amount {
+            let c = c
+                .to_super_unit(raw_order.seller_currency)
+                .to_f64()
+                .ok_or(ectx!(try err ErrorContext::AmountConversion, ErrorKind::Internal))?;
+            Some(c)
+        } else {
+            None
+        };
 
         Ok(OrderResponse {
             id: raw_order.id,
@@ -100,14 +119,48 @@ impl OrderResponse {
             store_id: raw_order.store_id,
             state: raw_order.state,
             stripe_fee,
+            captured_amount,
+            refunded_amount,
         })
     }
 }
 
+#[derive(Debug, Clone, Serialize)]
+pub struct BulkOrderResultResponse {
+    pub order_id: OrderId,
+    pub success: bool,
+    /// Validation errors of the order or the kind of the failure
+    pub error: Option<serde_json::Value>,
+}
+
+impl BulkOrderResultResponse {
+    pub fn from_result(order_id: OrderId, result: Result<(), Error>) -> Self {
+        let error = result.err().map(|e| match e.kind() {
+            ErrorKind::Validation(errors) => errors,
+            kind => serde_json::Value::String(kind.to_string()),
+        });
+
+        BulkOrderResultResponse {
+            order_id,
+            success: error.is_none(),
+            error,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OrderTransitionsResponse {
+    pub state: PaymentState,
+    /// States the order can be moved to by the current user
+    pub transitions: Vec<PaymentState>,
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct OrderSearchResultsResponse {
     pub total_count: i64,
     pub orders: Vec<OrderResponse>,
+    pub next_cursor: Option<OrderId>,
+    pub totals: HashMap<StqCurrency, BigDecimal>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -203,6 +256,74 @@ impl FeeResponse {
     }
 }
 
+#[derive(Clone, Debug, Serialize)]
+pub struct RefundResponse {
+    pub id: RefundId,
+    pub order_id: OrderId,
+    pub amount: f64,
+    pub currency: StqCurrency,
+    pub reason: Option<String>,
+    pub stripe_refund_id: Option<String>,
+    pub status: RefundStatus,
+    pub fee_adjustment: f64,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub crypto_currency: Option<StqCurrency>,
+    pub crypto_amount: Option<f64>,
+    pub wallet_address: Option<WalletAddress>,
+    pub transaction_id: Option<TransactionId>,
+    pub kind: RefundKind,
+    pub balance_adjustment: f64,
+}
+
+impl RefundResponse {
+    pub fn try_from_refund(refund: Refund, currency: Currency) -> Result<Self, Error> {
+        let amount = refund
+            .amount
+            .to_super_unit(currency)
+            .to_f64()
+            .ok_or(ectx!(try err ErrorContext::AmountConversion, ErrorKind::Internal))?;
+        let fee_adjustment = refund
+            .fee_adjustment
+            .to_super_unit(currency)
+            .to_f64()
+            .ok_or(ectx!(try err ErrorContext::AmountConversion, ErrorKind::Internal))?;
+        let crypto_amount = match (refund.crypto_amount, refund.crypto_currency) {
+            (Some(crypto_amount), Some(crypto_currency)) => Some(
+                crypto_amount
+                    .to_super_unit(crypto_currency)
+                    .to_f64()
+                    .ok_or(ectx!(try err ErrorContext::AmountConversion, ErrorKind::Internal))?,
+            ),
+            _ => None,
+        };
+        let balance_adjustment = refund
+            .balance_adjustment
+            .to_super_unit(currency)
+            .to_f64()
+            .ok_or(ectx!(try err ErrorContext::AmountConversion, ErrorKind::Internal))?;
+
+        Ok(Self {
+            id: refund.id,
+            order_id: refund.order_id,
+            amount,
+            currency: currency.into(),
+            reason: refund.reason,
+            stripe_refund_id: refund.stripe_refund_id,
+            status: refund.status,
+            fee_adjustment,
+            created_at: refund.created_at,
+            updated_at: refund.updated_at,
+            crypto_currency: refund.crypto_currency.map(Into::into),
+            crypto_amount,
+            wallet_address: refund.wallet_address,
+            transaction_id: refund.transaction_id,
+            kind: refund.kind,
+            balance_adjustment,
+        })
+    }
+}
+
 #[derive(Clone, Debug, Serialize)]
 pub struct SubscriptionPaymentResponse {
     pub id: SubscriptionPaymentId,
@@ -272,3 +393,48 @@ impl BalancesResponse {
         Self { currencies }
     }
 }
+
+#[derive(Clone, Debug, Serialize)]
+pub struct DocumentResponse {
+    pub id: DocumentId,
+    pub kind: DocumentKind,
+    pub store_id: StqStoreId,
+    pub invoice_id: Option<InvoiceId>,
+    pub fee_id: Option<FeeId>,
+    pub document_number: String,
+    pub format: DocumentFormat,
+    pub content_type: String,
+    pub created_at: NaiveDateTime,
+}
+
+impl<'a> From<&'a Document> for DocumentResponse {
+    fn from(document: &'a Document) -> Self {
+        Self {
+            id: document.id,
+            kind: document.kind,
+            store_id: document.store_id,
+            invoice_id: document.invoice_id,
+            fee_id: document.fee_id,
+            document_number: document.document_number.clone(),
+            format: document.format,
+            content_type: document.format.content_type().to_string(),
+            created_at: document.created_at,
+        }
+    }
+}
+
+/// Document together with its base64 encoded content
+#[derive(Clone, Debug, Serialize)]
+pub struct DocumentContentResponse {
+    pub document: DocumentResponse,
+    pub content: String,
+}
+
+impl From<Document> for DocumentContentResponse {
+    fn from(document: Document) -> Self {
+        Self {
+            document: DocumentResponse::from(&document),
+            content: base64::encode(&document.content),
+        }
+    }
+}
@@ -3,7 +3,7 @@ use stq_types::{InternationalBillingId, InvoiceId, OrderId, RoleId, RussiaBillin
 
 use models::invoice_v2;
 use models::order_v2::{OrderId as Orderv2Id, StoreId as BillingStoreId};
-use models::{FeeId, PayoutId};
+use models::{DocumentId, EventEntryId, FeeId, PayoutId, WebhookSubscriptionId};
 
 pub const PAYMENTS_CALLBACK_ENDPOINT: &'static str = "/v2/callback/payments/inbound_tx";
 
@@ -18,11 +18,22 @@ pub enum Route {
     InvoiceBySagaId { id: SagaId },
     InvoiceById { id: InvoiceId },
     InvoiceByIdV2 { id: invoice_v2::InvoiceId },
+    InvoiceByIdV2Cancel { id: invoice_v2::InvoiceId },
+    InvoiceByIdV2BuyerCurrency { id: invoice_v2::InvoiceId },
+    InvoiceByIdV2Documents { id: invoice_v2::InvoiceId },
     InvoiceByOrderId { id: OrderId },
     InvoiceOrdersIds { id: InvoiceId },
     InvoiceByIdRecalc { id: InvoiceId },
     OrdersByIdCapture { id: Orderv2Id },
     OrdersByIdDecline { id: Orderv2Id },
+    OrdersBulkCapture,
+    OrdersBulkDecline,
+    OrdersByIdRefunds { id: Orderv2Id },
+    OrdersByIdCryptoRefund { id: Orderv2Id },
+    OrdersByIdHistory { id: Orderv2Id },
+    OrdersByIdDisputes { id: Orderv2Id },
+    OrdersByIdTransitions { id: Orderv2Id },
+    OrdersByIdDelivered { id: Orderv2Id },
     UserMerchants,
     StoreMerchants,
     UserMerchant { user_id: UserId },
@@ -50,6 +61,8 @@ pub enum Route {
     FeesPay { id: FeeId },
     FeesPayByOrder { id: Orderv2Id },
     FeesPayByOrders,
+    FeeDocument { id: FeeId },
+    DocumentById { id: DocumentId },
     Payouts,
     PayoutById { id: PayoutId },
     PayoutsByOrderIds,
@@ -62,6 +75,16 @@ pub enum Route {
     SubscriptionPaymentSearch,
     StoreSubscription,
     StoreSubscriptionByStoreId { store_id: StoreId },
+    StoreCapturePolicyByStoreId { store_id: StoreId },
+    EventsSearch,
+    EventById { id: EventEntryId },
+    EventByIdRequeue { id: EventEntryId },
+    EventByIdResolve { id: EventEntryId },
+    EventsRequeue,
+    EventsReplay,
+    WebhookSubscriptions,
+    WebhookSubscriptionById { id: WebhookSubscriptionId },
+    WebhookSubscriptionDeliveries { id: WebhookSubscriptionId },
 }
 
 pub fn create_route_parser() -> RouteParser<Route> {
@@ -101,6 +124,24 @@ pub fn create_route_parser() -> RouteParser<Route> {
             .and_then(|string_id| string_id.parse().ok())
             .map(|id| Route::InvoiceByIdV2 { id })
     });
+    route_parser.add_route_with_params(r"^/v2/invoices/by-id/([a-zA-Z0-9-]+)/cancel$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|id| Route::InvoiceByIdV2Cancel { id })
+    });
+    route_parser.add_route_with_params(r"^/v2/invoices/by-id/([a-zA-Z0-9-]+)/buyer_currency$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|id| Route::InvoiceByIdV2BuyerCurrency { id })
+    });
+    route_parser.add_route_with_params(r"^/v2/invoices/by-id/([a-zA-Z0-9-]+)/documents$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|id| Route::InvoiceByIdV2Documents { id })
+    });
     route_parser.add_route_with_params(r"^/invoices/by-order-id/([a-zA-Z0-9-]+)$", |params| {
         params
             .get(0)
@@ -176,6 +217,52 @@ pub fn create_route_parser() -> RouteParser<Route> {
             .map(|id| Route::OrdersByIdDecline { id })
     });
 
+    route_parser.add_route(r"^/orders/bulk_capture$", || Route::OrdersBulkCapture);
+
+    route_parser.add_route(r"^/orders/bulk_decline$", || Route::OrdersBulkDecline);
+
+    route_parser.add_route_with_params(r"^/orders/([a-zA-Z0-9-]+)/refunds$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|id| Route::OrdersByIdRefunds { id })
+    });
+
+    route_parser.add_route_with_params(r"^/orders/([a-zA-Z0-9-]+)/crypto_refund$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|id| Route::OrdersByIdCryptoRefund { id })
+    });
+
+    route_parser.add_route_with_params(r"^/orders/([a-zA-Z0-9-]+)/history$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|id| Route::OrdersByIdHistory { id })
+    });
+
+    route_parser.add_route_with_params(r"^/orders/([a-zA-Z0-9-]+)/disputes$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|id| Route::OrdersByIdDisputes { id })
+    });
+
+    route_parser.add_route_with_params(r"^/orders/([a-zA-Z0-9-]+)/transitions$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|id| Route::OrdersByIdTransitions { id })
+    });
+
+    route_parser.add_route_with_params(r"^/orders/([a-zA-Z0-9-]+)/delivered$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|id| Route::OrdersByIdDelivered { id })
+    });
+
     route_parser.add_route_with_params(r"^/orders/([a-zA-Z0-9-]+)/set_payment_state$", |params| {
         params
             .get(0)
@@ -204,6 +291,17 @@ pub fn create_route_parser() -> RouteParser<Route> {
 
     route_parser.add_route(r"^fees/by-order-ids/pay$", || Route::FeesPayByOrders);
 
+    route_parser.add_route_with_params(r"^/fees/(\d+)/document$", |params| {
+        params.get(0).and_then(|id| id.parse().ok()).map(|id| Route::FeeDocument { id })
+    });
+
+    route_parser.add_route_with_params(r"^/documents/by-id/([a-zA-Z0-9-]+)$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|id| Route::DocumentById { id })
+    });
+
     route_parser.add_route(r"^/customers/with_source$", || Route::CustomersWithSource);
     route_parser.add_route(r"^/order_billing_info$", || Route::OrderBillingInfo);
     route_parser.add_route(r"^/billing_info/international$", || Route::InternationalBillingInfos);
@@ -275,6 +373,46 @@ pub fn create_route_parser() -> RouteParser<Route> {
             .and_then(|string_id| string_id.parse().ok())
             .map(|store_id| Route::StoreSubscriptionByStoreId { store_id })
     });
+    route_parser.add_route_with_params(r"^/store_capture_policy/by-store-id/(\d+)$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|store_id| Route::StoreCapturePolicyByStoreId { store_id })
+    });
+    route_parser.add_route(r"^/events/search$", || Route::EventsSearch);
+    route_parser.add_route(r"^/events/requeue$", || Route::EventsRequeue);
+    route_parser.add_route(r"^/events/replay$", || Route::EventsReplay);
+    route_parser.add_route_with_params(r"^/events/(\d+)$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|id| Route::EventById { id })
+    });
+    route_parser.add_route_with_params(r"^/events/(\d+)/requeue$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|id| Route::EventByIdRequeue { id })
+    });
+    route_parser.add_route_with_params(r"^/events/(\d+)/resolve$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|id| Route::EventByIdResolve { id })
+    });
+    route_parser.add_route(r"^/webhook_subscriptions$", || Route::WebhookSubscriptions);
+    route_parser.add_route_with_params(r"^/webhook_subscriptions/([a-zA-Z0-9-]+)$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|id| Route::WebhookSubscriptionById { id })
+    });
+    route_parser.add_route_with_params(r"^/webhook_subscriptions/([a-zA-Z0-9-]+)/deliveries$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|id| Route::WebhookSubscriptionDeliveries { id })
+    });
 
     route_parser
 }
@@ -0,0 +1,135 @@
+use std::sync::Arc;
+
+use chrono::{Duration as ChronoDuration, Utc};
+use diesel::{connection::AnsiTransactionManager, pg::Pg, Connection};
+use failure::Fail;
+use futures::{stream, Future, Stream};
+use r2d2::ManageConnection;
+use stq_http::client::HttpClient;
+use stq_types::StoreId;
+
+use client::{payments::PaymentsClient, saga::SagaClient, stores::StoresClient, stripe::StripeClient};
+use config;
+use models::{invoice_v2::InvoiceId, order_v2::RawOrder, CaptureSlaAction, Event, EventId, EventPayload, OrderStateChange, PaymentState};
+use repos::{EventStoreRepo, ReposFactory};
+use services::accounts::AccountService;
+use services::order::{order_capture_crypto, order_capture_fiat, order_decline_crypto, order_decline_fiat};
+
+use super::error::*;
+use super::{spawn_on_pool, EventHandler, EventHandlerFuture, EventHandlerResult};
+
+impl<T, M, F, HC, PC, SC, STC, STRC, AS> EventHandler<T, M, F, HC, PC, SC, STC, STRC, AS>
+where
+    T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static,
+    M: ManageConnection<Connection = T>,
+    F: ReposFactory<T>,
+    HC: HttpClient + Clone,
+    PC: PaymentsClient + Clone,
+    SC: SagaClient + Clone,
+    STC: StoresClient + Clone,
+    STRC: StripeClient + Clone,
+    AS: AccountService + Clone + 'static,
+{
+    pub fn schedule_capture_sla(self, invoice_id: InvoiceId) -> EventHandlerFuture<()> {
+        let EventHandler {
+            db_pool,
+            cpu_pool,
+            repo_factory,
+            capture_sla,
+            ..
+        } = self;
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
+            add_capture_sla_event(&*event_store_repo, &capture_sla, invoice_id)
+        })
+    }
+
+    /// Declines or captures the orders of the invoice the seller has not handled in time
+    pub fn handle_capture_sla_expired(self, event_id: EventId, invoice_id: InvoiceId) -> EventHandlerFuture<()> {
+        let EventHandler {
+            db_pool,
+            cpu_pool,
+            repo_factory,
+            capture_sla,
+            ..
+        } = self.clone();
+
+        let fut = spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let orders_repo = repo_factory.create_orders_repo_with_sys_acl(&conn);
+            let store_capture_policies_repo = repo_factory.create_store_capture_policies_repo_with_sys_acl(&conn);
+
+            let orders = orders_repo
+                .get_many_by_invoice_id(invoice_id)
+                .map_err(ectx!(try convert => invoice_id))?;
+
+            orders
+                .into_iter()
+                .filter(|order| order.state == PaymentState::Initial)
+                .map(|order| {
+                    let store_id = StoreId(order.store_id.inner());
+                    let action = store_capture_policies_repo
+                        .get(store_id)
+                        .map_err(ectx!(try convert => store_id))?
+                        .map(|policy| policy.action)
+                        .unwrap_or(capture_sla.default_action);
+                    Ok((order, action))
+                })
+                .collect::<EventHandlerResult<Vec<_>>>()
+        })
+        .and_then(move |orders| {
+            stream::iter_ok::<_, Error>(orders)
+                .for_each(move |(order, action)| self.clone().apply_capture_sla_action(event_id, order, action))
+        });
+
+        Box::new(fut)
+    }
+
+    fn apply_capture_sla_action(self, event_id: EventId, order: RawOrder, action: CaptureSlaAction) -> EventHandlerFuture<()> {
+        let EventHandler {
+            db_pool,
+            cpu_pool,
+            repo_factory,
+            stripe_client,
+            ..
+        } = self;
+
+        info!("Order {} was not captured in time, applying action \'{}\'", order.id, action);
+
+        let order_id = order.id;
+        let fut = match action {
+            CaptureSlaAction::Capture => {
+                if order.seller_currency.is_fiat() {
+                    order_capture_fiat(cpu_pool, db_pool, repo_factory, order, None)
+                } else {
+                    let change = OrderStateChange::by_event(event_id, "Captured automatically, not handled by the seller in time");
+                    order_capture_crypto(cpu_pool, db_pool, repo_factory, order, change)
+                }
+            }
+            CaptureSlaAction::Decline => {
+                let change = OrderStateChange::by_event(event_id, "Declined automatically, not handled by the seller in time");
+                if order.seller_currency.is_fiat() {
+                    order_decline_fiat(cpu_pool, db_pool, repo_factory, Arc::new(stripe_client), order, change)
+                } else {
+                    order_decline_crypto(cpu_pool, db_pool, repo_factory, order, change)
+                }
+            }
+        };
+
+        Box::new(fut.map_err(ectx!(ErrorKind::Internal => order_id)))
+    }
+}
+
+/// Schedules the capture SLA check of the orders of a paid invoice
+pub fn add_capture_sla_event(
+    event_store_repo: &EventStoreRepo,
+    capture_sla: &config::CaptureSla,
+    invoice_id: InvoiceId,
+) -> EventHandlerResult<()> {
+    let scheduled_on = Utc::now().naive_utc() + ChronoDuration::hours(capture_sla.window_hours.into());
+    let event = Event::new(EventPayload::CaptureSlaExpired { invoice_id });
+    event_store_repo
+        .add_scheduled_event(event.clone(), scheduled_on)
+        .map_err(ectx!(convert => event, scheduled_on))
+        .map(|_| ())
+}
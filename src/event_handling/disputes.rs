@@ -0,0 +1,267 @@
+use bigdecimal::BigDecimal;
+use chrono::{NaiveDateTime, Utc};
+use diesel::{connection::AnsiTransactionManager, pg::Pg, Connection};
+use failure::Fail;
+use r2d2::ManageConnection;
+use stq_http::client::HttpClient;
+use stripe::{Dispute as StripeDispute, DisputeStatus as StripeDisputeStatus};
+
+use client::{payments::PaymentsClient, saga::SagaClient, stores::StoresClient, stripe::StripeClient};
+use models::{
+    split_disputed_amount, Amount, ChargeId, Dispute, DisputeId, DisputeStatus, DisputedOrder, EventId, NewDispute, OrderStateChange,
+    PaymentState, UpdateDispute,
+};
+use repos::{ReposFactory, SearchPaymentIntent, SearchPaymentIntentInvoice};
+use services::accounts::AccountService;
+use services::order::adjust_fee_for_refund;
+
+use super::error::*;
+use super::{spawn_on_pool, EventHandler, EventHandlerFuture};
+
+impl<T, M, F, HC, PC, SC, STC, STRC, AS> EventHandler<T, M, F, HC, PC, SC, STC, STRC, AS>
+where
+    T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static,
+    M: ManageConnection<Connection = T>,
+    F: ReposFactory<T>,
+    HC: HttpClient + Clone,
+    PC: PaymentsClient + Clone,
+    SC: SagaClient + Clone,
+    STC: StoresClient + Clone,
+    STRC: StripeClient + Clone,
+    AS: AccountService + Clone + 'static,
+{
+    /// Freezes the orders paid by the disputed charge, later updates only refresh the evidence deadline
+    pub fn handle_charge_dispute_created_or_updated(self, event_id: EventId, dispute: StripeDispute) -> EventHandlerFuture<()> {
+        let EventHandler {
+            db_pool,
+            cpu_pool,
+            repo_factory,
+            ..
+        } = self;
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let disputes_repo = repo_factory.create_disputes_repo_with_sys_acl(&conn);
+            let orders_repo = repo_factory.create_orders_repo_with_sys_acl(&conn);
+            let invoices_repo = repo_factory.create_invoices_v2_repo_with_sys_acl(&conn);
+            let rates_repo = repo_factory.create_order_exchange_rates_repo_with_sys_acl(&conn);
+            let payment_intent_repo = repo_factory.create_payment_intent_repo_with_sys_acl(&conn);
+            let payment_intent_invoices_repo = repo_factory.create_payment_intent_invoices_repo_with_sys_acl(&conn);
+
+            let stripe_dispute_id = dispute.id.clone();
+            let evidence_due_by = Some(NaiveDateTime::from_timestamp(dispute.evidence_details.due_by, 0));
+            let reason = stripe_dispute_reason(&dispute);
+
+            let existing_disputes = disputes_repo
+                .list_by_stripe_dispute_id(stripe_dispute_id.clone())
+                .map_err(ectx!(try convert => stripe_dispute_id))?;
+
+            if !existing_disputes.is_empty() {
+                return conn.transaction::<_, Error, _>(|| {
+                    for Dispute { id: dispute_id, .. } in existing_disputes {
+                        let update_dispute = UpdateDispute {
+                            reason: reason.clone(),
+                            evidence_due_by,
+                            ..Default::default()
+                        };
+                        disputes_repo
+                            .update(dispute_id, update_dispute.clone())
+                            .map_err(ectx!(try convert => dispute_id, update_dispute))?;
+                    }
+                    Ok(())
+                });
+            }
+
+            let charge_id = ChargeId::new(dispute.charge.clone());
+            let search = SearchPaymentIntent::ChargeId(charge_id.clone());
+            let payment_intent = match payment_intent_repo.get(search.clone()).map_err(ectx!(try convert => search))? {
+                Some(payment_intent) => payment_intent,
+                None => {
+                    warn!(
+                        "Charge {} of dispute {} is unknown, the dispute is skipped",
+                        charge_id, stripe_dispute_id
+                    );
+                    return Ok(());
+                }
+            };
+
+            let search = SearchPaymentIntentInvoice::PaymentIntentId(payment_intent.id.clone());
+            let payment_intent_invoice = match payment_intent_invoices_repo
+                .get(search.clone())
+                .map_err(ectx!(try convert => search))?
+            {
+                Some(payment_intent_invoice) => payment_intent_invoice,
+                None => {
+                    info!(
+                        "Charge {} of dispute {} does not pay an invoice, no orders to freeze",
+                        charge_id, stripe_dispute_id
+                    );
+                    return Ok(());
+                }
+            };
+
+            let invoice_id = payment_intent_invoice.invoice_id;
+            let invoice = invoices_repo.get(invoice_id).map_err(ectx!(try convert => invoice_id))?.ok_or({
+                let e = format_err!("Invoice {} not found", invoice_id);
+                ectx!(try err e, ErrorKind::Internal)
+            })?;
+            let orders = orders_repo
+                .get_many_by_invoice_id(invoice_id)
+                .map_err(ectx!(try convert => invoice_id))?;
+
+            // the charge is made in the buyer currency, the orders are priced in the seller currency
+            let disputed_orders = orders
+                .iter()
+                .map(|order| {
+                    let exchange_rate = if order.seller_currency == invoice.buyer_currency {
+                        BigDecimal::from(1)
+                    } else {
+                        let order_id = order.id;
+                        rates_repo
+                            .get_active_rate_for_order(order_id)
+                            .map_err(ectx!(try convert => order_id))?
+                            .ok_or({
+                                let e = format_err!("Order {} has no active exchange rate", order_id);
+                                ectx!(try err e, ErrorKind::Internal)
+                            })?
+                            .exchange_rate
+                    };
+                    Ok(DisputedOrder {
+                        order_id: order.id,
+                        seller_currency: order.seller_currency,
+                        charged_amount: order.charged_amount(),
+                        exchange_rate,
+                    })
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+            let shares = split_disputed_amount(invoice.buyer_currency, Amount::new(dispute.amount as u128), &disputed_orders);
+
+            conn.transaction::<_, Error, _>(|| {
+                for (order_id, amount) in shares {
+                    let order = match orders.iter().find(|order| order.id == order_id) {
+                        Some(order) => order,
+                        None => continue,
+                    };
+
+                    let new_dispute = NewDispute {
+                        id: DisputeId::generate(),
+                        stripe_dispute_id: stripe_dispute_id.clone(),
+                        order_id,
+                        charge_id: charge_id.clone(),
+                        amount,
+                        currency: order.seller_currency,
+                        reason: reason.clone(),
+                        status: DisputeStatus::Open,
+                        evidence_due_by,
+                        previous_state: order.state,
+                    };
+                    disputes_repo
+                        .create(new_dispute.clone())
+                        .map_err(ectx!(try convert => new_dispute))?;
+
+                    info!("Charge of order {} is disputed, setting order state \'Disputed\'", order_id);
+                    orders_repo
+                        .update_state(
+                            order_id,
+                            PaymentState::Disputed,
+                            OrderStateChange::by_event(event_id, "Charge disputed by the buyer"),
+                        )
+                        .map_err(ectx!(try convert => order_id))?;
+                }
+                Ok(())
+            })
+        })
+    }
+
+    /// Releases the orders of the closed dispute, a lost dispute is refunded from the order
+    /// or, if the order has already been paid out, taken back from the seller balance
+    pub fn handle_charge_dispute_closed(self, event_id: EventId, dispute: StripeDispute) -> EventHandlerFuture<()> {
+        let EventHandler {
+            db_pool,
+            cpu_pool,
+            repo_factory,
+            fee,
+            ..
+        } = self;
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let disputes_repo = repo_factory.create_disputes_repo_with_sys_acl(&conn);
+            let orders_repo = repo_factory.create_orders_repo_with_sys_acl(&conn);
+            let fees_repo = repo_factory.create_fees_repo_with_sys_acl(&conn);
+
+            // disputes closed as warning_closed or charge_refunded do not take any money from the seller
+            let status = match dispute.status {
+                StripeDisputeStatus::Lost => DisputeStatus::Lost,
+                _ => DisputeStatus::Won,
+            };
+
+            let stripe_dispute_id = dispute.id.clone();
+            let disputes = disputes_repo
+                .list_by_stripe_dispute_id(stripe_dispute_id.clone())
+                .map_err(ectx!(try convert => stripe_dispute_id))?;
+
+            conn.transaction::<_, Error, _>(|| {
+                for dispute in disputes.into_iter().filter(|dispute| dispute.status == DisputeStatus::Open) {
+                    let order_id = dispute.order_id;
+                    let order = orders_repo.get(order_id).map_err(ectx!(try convert => order_id))?.ok_or({
+                        let e = format_err!("Order {} not found", order_id);
+                        ectx!(try err e, ErrorKind::Internal)
+                    })?;
+
+                    let mut balance_adjustment = Amount::zero();
+                    let mut new_state = dispute.previous_state;
+                    if status == DisputeStatus::Lost {
+                        if dispute.previous_state == PaymentState::PaidToSeller {
+                            balance_adjustment = dispute.amount;
+                        } else {
+                            let refunded_amount = order.refunded_amount.checked_add(dispute.amount).ok_or({
+                                let e = format_err!("Overflow while adding dispute {} to order {}", dispute.id, order_id);
+                                ectx!(try err e, ErrorKind::Internal)
+                            })?;
+                            let refunded_order = orders_repo
+                                .update_refunded_amount(order_id, refunded_amount)
+                                .map_err(ectx!(try convert => order_id, refunded_amount))?;
+
+                            adjust_fee_for_refund(&*fees_repo, fee.order_percent, &order, &refunded_order)
+                                .map_err(ectx!(try ErrorKind::Internal => order_id))?;
+
+                            if refunded_order.net_amount() == Amount::zero() {
+                                new_state = PaymentState::Refunded;
+                            }
+                        }
+                    }
+
+                    let dispute_id = dispute.id;
+                    let update_dispute = UpdateDispute {
+                        status: Some(status),
+                        balance_adjustment: Some(balance_adjustment),
+                        closed_at: Some(Utc::now().naive_utc()),
+                        ..Default::default()
+                    };
+                    disputes_repo
+                        .update(dispute_id, update_dispute.clone())
+                        .map_err(ectx!(try convert => dispute_id, update_dispute))?;
+
+                    info!(
+                        "Dispute {} is {}, setting order {} state \'{}\'",
+                        dispute_id, status, order_id, new_state
+                    );
+                    let reason = match status {
+                        DisputeStatus::Lost => "Dispute lost",
+                        _ => "Dispute won",
+                    };
+                    orders_repo
+                        .update_state(order_id, new_state, OrderStateChange::by_event(event_id, reason))
+                        .map_err(ectx!(try convert => order_id))?;
+                }
+                Ok(())
+            })
+        })
+    }
+}
+
+/// Stripe dispute reason as sent by Stripe, e.g. `fraudulent`
+fn stripe_dispute_reason(dispute: &StripeDispute) -> Option<String> {
+    serde_json::to_value(&dispute.reason)
+        .ok()
+        .and_then(|reason| reason.as_str().map(|reason| reason.to_string()))
+}
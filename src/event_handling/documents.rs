@@ -0,0 +1,267 @@
+use std::collections::{BTreeMap, HashSet};
+
+use diesel::{connection::AnsiTransactionManager, pg::Pg, Connection};
+use failure::Fail;
+use r2d2::ManageConnection;
+use stq_http::client::HttpClient;
+use stq_types::{BillingType, StoreId};
+
+use client::{payments::PaymentsClient, saga::SagaClient, stores::StoresClient, stripe::StripeClient};
+use models::invoice_v2::InvoiceId;
+use models::order_v2::RawOrder;
+use models::{
+    format_document_number, DocumentId, DocumentKind, Event, EventPayload, FeeId, FeeStatus, InternationalBillingInfoSearch, NewDocument,
+    RussiaBillingInfoSearch, StoreBillingTypeSearch,
+};
+use repos::{EventStoreRepo, InternationalBillingInfoRepo, ReposFactory, RussiaBillingInfoRepo, SearchFee, StoreBillingTypeRepo};
+use services::accounts::AccountService;
+use services::document::{render_fee_invoice, render_receipt, to_printable, SellerDetails};
+
+use super::error::*;
+use super::{spawn_on_pool, EventHandler, EventHandlerFuture, EventHandlerResult};
+
+impl<T, M, F, HC, PC, SC, STC, STRC, AS> EventHandler<T, M, F, HC, PC, SC, STC, STRC, AS>
+where
+    T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static,
+    M: ManageConnection<Connection = T>,
+    F: ReposFactory<T>,
+    HC: HttpClient + Clone,
+    PC: PaymentsClient + Clone,
+    SC: SagaClient + Clone,
+    STC: StoresClient + Clone,
+    STRC: StripeClient + Clone,
+    AS: AccountService + Clone + 'static,
+{
+    pub fn schedule_invoice_receipts(self, invoice_id: InvoiceId) -> EventHandlerFuture<()> {
+        let EventHandler {
+            db_pool,
+            cpu_pool,
+            repo_factory,
+            ..
+        } = self;
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
+            add_invoice_receipts_event(&*event_store_repo, invoice_id)
+        })
+    }
+
+    /// Issues a numbered receipt from each store of the paid invoice, stores that already issued one are skipped
+    pub fn handle_invoice_receipts_due(self, invoice_id: InvoiceId) -> EventHandlerFuture<()> {
+        let EventHandler {
+            db_pool,
+            cpu_pool,
+            repo_factory,
+            documents,
+            ..
+        } = self;
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let invoices_repo = repo_factory.create_invoices_v2_repo_with_sys_acl(&conn);
+            let orders_repo = repo_factory.create_orders_repo_with_sys_acl(&conn);
+            let documents_repo = repo_factory.create_documents_repo_with_sys_acl(&conn);
+            let store_billing_type_repo = repo_factory.create_store_billing_type_repo_with_sys_acl(&conn);
+            let russia_billing_info_repo = repo_factory.create_russia_billing_info_repo_with_sys_acl(&conn);
+            let international_billing_info_repo = repo_factory.create_international_billing_repo_info_with_sys_acl(&conn);
+
+            let invoice = invoices_repo.get(invoice_id).map_err(ectx!(try convert => invoice_id))?.ok_or({
+                let e = format_err!("Invoice {} not found", invoice_id);
+                ectx!(try err e, ErrorKind::Internal)
+            })?;
+
+            if invoice.paid_at.is_none() {
+                info!("Invoice {} is not paid, no receipts are issued", invoice_id);
+                return Ok(());
+            }
+
+            let issued_store_ids = documents_repo
+                .list_by_invoice_id(invoice_id)
+                .map_err(ectx!(try convert => invoice_id))?
+                .into_iter()
+                .filter(|document| document.kind == DocumentKind::Receipt)
+                .map(|document| document.store_id.0)
+                .collect::<HashSet<_>>();
+
+            let mut orders_by_store: BTreeMap<i32, Vec<RawOrder>> = BTreeMap::new();
+            for order in orders_repo
+                .get_many_by_invoice_id(invoice_id)
+                .map_err(ectx!(try convert => invoice_id))?
+            {
+                orders_by_store.entry(order.store_id.inner()).or_insert_with(Vec::new).push(order);
+            }
+
+            for (store_id, orders) in orders_by_store {
+                if issued_store_ids.contains(&store_id) {
+                    continue;
+                }
+
+                let store_id = StoreId(store_id);
+                let seller = get_seller_details(
+                    &*store_billing_type_repo,
+                    &*russia_billing_info_repo,
+                    &*international_billing_info_repo,
+                    store_id,
+                )?;
+
+                // The number is printed in the document, so the document is rendered while the sequence row
+                // of the store is locked. Reserving the number in a separate transaction would leave gaps
+                // in the numbering whenever rendering or saving the document fails
+                conn.transaction::<_, Error, _>(|| {
+                    let number = documents_repo
+                        .next_number(store_id, DocumentKind::Receipt)
+                        .map_err(ectx!(try convert => store_id))?;
+                    let document_number = format_document_number(DocumentKind::Receipt, store_id, number);
+                    let (format, content) = to_printable(&documents, render_receipt(&document_number, &invoice, &orders, &seller));
+
+                    let new_document = NewDocument {
+                        id: DocumentId::generate(),
+                        kind: DocumentKind::Receipt,
+                        store_id,
+                        invoice_id: Some(invoice_id),
+                        fee_id: None,
+                        buyer_user_id: Some(invoice.buyer_user_id),
+                        number,
+                        document_number: document_number.clone(),
+                        format,
+                        content,
+                    };
+                    documents_repo
+                        .create(new_document)
+                        .map_err(ectx!(try convert => invoice_id, store_id))?;
+
+                    info!(
+                        "Receipt {} of invoice {} is issued by store {}",
+                        document_number, invoice_id, store_id
+                    );
+                    Ok(())
+                })?;
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Issues an invoice for the paid fee to the store of the fee order, numbered in the sequence of the marketplace
+    pub fn handle_fee_invoice_due(self, fee_id: FeeId) -> EventHandlerFuture<()> {
+        let EventHandler {
+            db_pool,
+            cpu_pool,
+            repo_factory,
+            documents,
+            ..
+        } = self;
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let fees_repo = repo_factory.create_fees_repo_with_sys_acl(&conn);
+            let orders_repo = repo_factory.create_orders_repo_with_sys_acl(&conn);
+            let documents_repo = repo_factory.create_documents_repo_with_sys_acl(&conn);
+            let store_billing_type_repo = repo_factory.create_store_billing_type_repo_with_sys_acl(&conn);
+            let russia_billing_info_repo = repo_factory.create_russia_billing_info_repo_with_sys_acl(&conn);
+            let international_billing_info_repo = repo_factory.create_international_billing_repo_info_with_sys_acl(&conn);
+
+            let fee = fees_repo.get(SearchFee::Id(fee_id)).map_err(ectx!(try convert => fee_id))?.ok_or({
+                let e = format_err!("Fee {} not found", fee_id);
+                ectx!(try err e, ErrorKind::Internal)
+            })?;
+
+            if fee.status != FeeStatus::Paid {
+                info!("Fee {} is in status {}, no invoice is issued", fee_id, fee.status);
+                return Ok(());
+            }
+
+            if let Some(document) = documents_repo.get_by_fee_id(fee_id).map_err(ectx!(try convert => fee_id))? {
+                info!("Invoice {} has already been issued for fee {}", document.document_number, fee_id);
+                return Ok(());
+            }
+
+            let order_id = fee.order_id;
+            let order = orders_repo.get(order_id).map_err(ectx!(try convert => order_id))?.ok_or({
+                let e = format_err!("Order {} not found", order_id);
+                ectx!(try err e, ErrorKind::Internal)
+            })?;
+
+            let store_id = StoreId(order.store_id.inner());
+            let recipient = get_seller_details(
+                &*store_billing_type_repo,
+                &*russia_billing_info_repo,
+                &*international_billing_info_repo,
+                store_id,
+            )?;
+
+            // Fee invoices of all stores share one sequence, so issuing them is serialized on its row
+            // until the invoice is converted and saved. This keeps the numbering free of gaps and
+            // limits the throughput of fee invoices to one conversion at a time
+            conn.transaction::<_, Error, _>(|| {
+                let number = documents_repo
+                    .next_issuer_number(DocumentKind::FeeInvoice)
+                    .map_err(ectx!(try convert => fee_id))?;
+                let document_number = format_document_number(DocumentKind::FeeInvoice, store_id, number);
+                let html = render_fee_invoice(&document_number, &documents.issuer_name, &fee, store_id, &recipient);
+                let (format, content) = to_printable(&documents, html);
+
+                let new_document = NewDocument {
+                    id: DocumentId::generate(),
+                    kind: DocumentKind::FeeInvoice,
+                    store_id,
+                    invoice_id: None,
+                    fee_id: Some(fee_id),
+                    buyer_user_id: None,
+                    number,
+                    document_number: document_number.clone(),
+                    format,
+                    content,
+                };
+                documents_repo
+                    .create(new_document)
+                    .map_err(ectx!(try convert => fee_id, store_id))?;
+
+                info!("Invoice {} for fee {} is issued to store {}", document_number, fee_id, store_id);
+                Ok(())
+            })
+        })
+    }
+}
+
+/// Requests the receipts of a paid invoice from its stores
+pub fn add_invoice_receipts_event(event_store_repo: &EventStoreRepo, invoice_id: InvoiceId) -> EventHandlerResult<()> {
+    let event = Event::new(EventPayload::InvoiceReceiptsDue { invoice_id });
+    event_store_repo
+        .add_event(event.clone())
+        .map_err(ectx!(convert => event))
+        .map(|_| ())
+}
+
+/// Billing info of the store matching its billing type, documents are not issued until the store sets it
+fn get_seller_details(
+    store_billing_type_repo: &StoreBillingTypeRepo,
+    russia_billing_info_repo: &RussiaBillingInfoRepo,
+    international_billing_info_repo: &InternationalBillingInfoRepo,
+    store_id: StoreId,
+) -> EventHandlerResult<SellerDetails> {
+    let billing_type = store_billing_type_repo
+        .get(StoreBillingTypeSearch::by_store_id(store_id))
+        .map_err(ectx!(try convert => store_id))?
+        .map(|store_billing_type| store_billing_type.billing_type)
+        .unwrap_or(BillingType::International);
+
+    let seller = if billing_type == BillingType::Russia {
+        russia_billing_info_repo
+            .search(RussiaBillingInfoSearch::by_store_id(store_id))
+            .map_err(ectx!(try convert => store_id))?
+            .into_iter()
+            .next()
+            .map(SellerDetails::Russia)
+    } else {
+        international_billing_info_repo
+            .search(InternationalBillingInfoSearch::by_store_id(store_id))
+            .map_err(ectx!(try convert => store_id))?
+            .into_iter()
+            .next()
+            .map(SellerDetails::International)
+    };
+
+    seller.ok_or_else(|| {
+        let e = format_err!("Store {} has no billing info to issue documents with", store_id);
+        ectx!(err e, ErrorKind::Internal)
+    })
+}
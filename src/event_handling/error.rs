@@ -29,10 +29,20 @@ pub enum ErrorSource {
     TokioTimer,
     #[fail(display = "event handler source - r2d2")]
     R2d2,
+    #[fail(display = "event handler source - stq_http")]
+    StqHttp,
 }
 
 derive_error_impls!();
 
+impl From<DieselError> for Error {
+    fn from(e: DieselError) -> Self {
+        Error {
+            inner: ErrorKind::from(&e).into(),
+        }
+    }
+}
+
 impl<'a> From<&'a DieselError> for ErrorKind {
     fn from(_e: &DieselError) -> Self {
         ErrorKind::Internal
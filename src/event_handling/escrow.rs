@@ -0,0 +1,93 @@
+use chrono::{Duration as ChronoDuration, Utc};
+use diesel::{connection::AnsiTransactionManager, pg::Pg, Connection};
+use failure::Fail;
+use r2d2::ManageConnection;
+use stq_http::client::HttpClient;
+
+use client::{payments::PaymentsClient, saga::SagaClient, stores::StoresClient, stripe::StripeClient};
+use models::order_v2::OrderId;
+use models::{DisputeStatus, Event, EventId, EventPayload, OrderStateChange, PaymentState, RefundStatus};
+use repos::ReposFactory;
+use services::accounts::AccountService;
+
+use super::error::*;
+use super::{spawn_on_pool, EventHandler, EventHandlerFuture};
+
+impl<T, M, F, HC, PC, SC, STC, STRC, AS> EventHandler<T, M, F, HC, PC, SC, STC, STRC, AS>
+where
+    T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static,
+    M: ManageConnection<Connection = T>,
+    F: ReposFactory<T>,
+    HC: HttpClient + Clone,
+    PC: PaymentsClient + Clone,
+    SC: SagaClient + Clone,
+    STC: StoresClient + Clone,
+    STRC: StripeClient + Clone,
+    AS: AccountService + Clone + 'static,
+{
+    /// Releases the delivered order for payout, orders with an open dispute or refund are checked again later
+    pub fn handle_escrow_release_due(self, event_id: EventId, order_id: OrderId) -> EventHandlerFuture<()> {
+        let EventHandler {
+            db_pool,
+            cpu_pool,
+            repo_factory,
+            escrow,
+            ..
+        } = self;
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let orders_repo = repo_factory.create_orders_repo_with_sys_acl(&conn);
+            let disputes_repo = repo_factory.create_disputes_repo_with_sys_acl(&conn);
+            let refunds_repo = repo_factory.create_refunds_repo_with_sys_acl(&conn);
+            let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
+
+            let order = orders_repo.get(order_id).map_err(ectx!(try convert => order_id))?.ok_or({
+                let e = format_err!("Order {} not found", order_id);
+                ectx!(try err e, ErrorKind::Internal)
+            })?;
+
+            let has_open_dispute = disputes_repo
+                .list_by_order_id(order_id)
+                .map_err(ectx!(try convert => order_id))?
+                .iter()
+                .any(|dispute| dispute.status == DisputeStatus::Open);
+
+            let has_pending_refund = refunds_repo
+                .list_by_order_id(order_id)
+                .map_err(ectx!(try convert => order_id))?
+                .iter()
+                .any(|refund| refund.status == RefundStatus::Pending);
+
+            if has_open_dispute || has_pending_refund {
+                info!(
+                    "Order {} has an open dispute or refund, postponing its release from escrow",
+                    order_id
+                );
+                let scheduled_on = Utc::now().naive_utc() + ChronoDuration::hours(escrow.recheck_interval_hours.into());
+                let event = Event::new(EventPayload::EscrowReleaseDue { order_id });
+                return event_store_repo
+                    .add_scheduled_event(event.clone(), scheduled_on)
+                    .map_err(ectx!(convert => event, scheduled_on))
+                    .map(|_| ());
+            }
+
+            if order.state != PaymentState::Captured {
+                info!("Order {} is in state \'{}\', it is not released from escrow", order_id, order.state);
+                return Ok(());
+            }
+
+            info!(
+                "Order {} is released from escrow, setting order state \'PaymentToSellerNeeded\'",
+                order_id
+            );
+            orders_repo
+                .update_state(
+                    order_id,
+                    PaymentState::PaymentToSellerNeeded,
+                    OrderStateChange::by_event(event_id, "Released from escrow after delivery"),
+                )
+                .map_err(ectx!(convert => order_id))
+                .map(|_| ())
+        })
+    }
+}
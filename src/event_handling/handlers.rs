@@ -21,18 +21,21 @@ use client::{
 };
 use models::{
     invoice_v2::{InvoiceId, InvoiceSetAmountPaid, PaymentFlow, RawInvoice},
-    order_v2::OrderId,
-    Account, AccountId, AccountWithBalance, Amount, CryptoWalletPayoutTarget, Currency, Event, EventPayload, PaymentState, Payout,
-    PayoutId, PayoutStatus, PayoutTarget,
+    order_v2::{OrderId, RawOrder},
+    Account, AccountId, AccountWithBalance, Amount, CryptoWalletPayoutTarget, Currency, Event, EventId, EventPayload, FeeStatus, NewRefund,
+    OrderStateChange, PaymentState, Payout, PayoutId, PayoutStatus, PayoutTarget, RefundId, RefundKind, RefundStatus, UpdateFee,
+    UpdateRefund, WebhookEventType, WebhookNotification,
 };
-use repos::{ReposFactory, SearchPaymentIntent, SearchPaymentIntentInvoice};
+use repos::{FeeRepo, ReposFactory, SearchFee, SearchPaymentIntent, SearchPaymentIntentInvoice};
 
 use services::accounts::AccountService;
 use services::payment_intent::cancel_payment_intent;
 use services::stripe::PaymentType;
 
+use super::capture_sla::add_capture_sla_event;
+use super::documents::add_invoice_receipts_event;
 use super::error::*;
-use super::{spawn_on_pool, EventHandler, EventHandlerFuture};
+use super::{spawn_on_pool, EventHandler, EventHandlerFuture, EventHandlerResult};
 
 impl<T, M, F, HC, PC, SC, STC, STRC, AS> EventHandler<T, M, F, HC, PC, SC, STC, STRC, AS>
 where
@@ -47,31 +50,109 @@ where
     AS: AccountService + Clone + 'static,
 {
     pub fn handle_event(self, event: Event) -> EventHandlerFuture<()> {
-        let Event { id: _, payload } = event;
+        let Event { id, payload, replay_of } = event;
+
+        if let Some(original_event_entry_id) = replay_of {
+            info!("Replaying {} event of entry {} as event {}", payload, original_event_entry_id, id);
+        }
 
         match payload {
             EventPayload::NoOp => Box::new(future::ok(())),
             EventPayload::InvoicePaid { invoice_id } => self.handle_invoice_paid(invoice_id),
-            EventPayload::PaymentIntentPaymentFailed { payment_intent } => self.handle_payment_intent_payment_failed(payment_intent),
-            EventPayload::PaymentIntentAmountCapturableUpdated { payment_intent } => {
-                self.handle_payment_intent_succeeded_or_amount_capturable_updated(payment_intent)
+            EventPayload::PaymentIntentPaymentFailed { payment_intent, .. } => {
+                self.handle_payment_intent_payment_failed(id, payment_intent)
             }
-            EventPayload::PaymentIntentSucceeded { payment_intent } => {
-                self.handle_payment_intent_succeeded_or_amount_capturable_updated(payment_intent)
+            EventPayload::PaymentIntentAmountCapturableUpdated { payment_intent, .. } => {
+                self.handle_payment_intent_succeeded_or_amount_capturable_updated(id, payment_intent)
             }
-            EventPayload::PaymentIntentCapture { order_id } => self.handle_payment_intent_capture(order_id),
+            EventPayload::PaymentIntentSucceeded { payment_intent, .. } => {
+                self.handle_payment_intent_succeeded_or_amount_capturable_updated(id, payment_intent)
+            }
+            EventPayload::PaymentIntentCapture { order_id, amount } => self.handle_payment_intent_capture(id, order_id, amount),
             EventPayload::PaymentExpired { invoice_id } => self.handle_payment_expired(invoice_id),
+            EventPayload::InvoiceCancelled { invoice_id } => self.handle_invoice_cancelled(invoice_id),
+            EventPayload::InvoiceAccountReplaced { invoice_id, account_id } => self.handle_invoice_account_replaced(invoice_id, account_id),
             EventPayload::PayoutInitiated { payout_id } => self.handle_payout_initiated(payout_id),
+            EventPayload::CryptoRefundInitiated { refund_id } => self.handle_crypto_refund_initiated(id, refund_id),
+            EventPayload::StripeRefundInitiated { refund_id } => self.handle_stripe_refund_initiated(id, refund_id),
+            EventPayload::InvoiceSurplusReceived { invoice_id } => self.handle_invoice_surplus_received(invoice_id),
+            EventPayload::InvoiceSurplusRefundInitiated { surplus_id } => self.handle_invoice_surplus_refund_initiated(surplus_id),
+            EventPayload::CaptureSlaExpired { invoice_id } => self.handle_capture_sla_expired(id, invoice_id),
+            EventPayload::EscrowReleaseDue { order_id } => self.handle_escrow_release_due(id, order_id),
+            EventPayload::InvoiceReceiptsDue { invoice_id } => self.handle_invoice_receipts_due(invoice_id),
+            EventPayload::FeeInvoiceDue { fee_id } => self.handle_fee_invoice_due(fee_id),
+            EventPayload::ChargeDisputeCreated { dispute, .. } => self.handle_charge_dispute_created_or_updated(id, dispute),
+            EventPayload::ChargeDisputeUpdated { dispute, .. } => self.handle_charge_dispute_created_or_updated(id, dispute),
+            EventPayload::ChargeDisputeClosed { dispute, .. } => self.handle_charge_dispute_closed(id, dispute),
+            EventPayload::WebhookNotificationCreated { notification } => self.handle_webhook_notification_created(notification),
+            EventPayload::WebhookDelivery {
+                subscription_id,
+                notification,
+            } => self.handle_webhook_delivery(subscription_id, notification),
         }
     }
 
-    // TODO: handle this event properly
-    pub fn handle_payment_intent_payment_failed(self, _payment_intent: StripePaymentIntent) -> EventHandlerFuture<()> {
-        Box::new(future::ok(()))
+    pub fn handle_payment_intent_payment_failed(self, event_id: EventId, payment_intent: StripePaymentIntent) -> EventHandlerFuture<()> {
+        info!(
+            "payment intent with id {} failed, last payment error: {:?}",
+            payment_intent.id, payment_intent.last_payment_error
+        );
+
+        let saga_client = self.saga_client.clone();
+        let payment_intent_id = PaymentIntentId(payment_intent.id.clone());
+        let payment_intent_id_cloned = payment_intent_id.clone();
+
+        let EventHandler {
+            db_pool,
+            cpu_pool,
+            repo_factory,
+            ..
+        } = self;
+
+        let fut = spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let orders_repo = repo_factory.create_orders_repo_with_sys_acl(&conn);
+            let invoices_repo = repo_factory.create_invoices_v2_repo_with_sys_acl(&conn);
+            let payment_intent_repo = repo_factory.create_payment_intent_repo_with_sys_acl(&conn);
+            let payment_intent_invoices_repo = repo_factory.create_payment_intent_invoices_repo_with_sys_acl(&conn);
+
+            services::stripe::payment_intent_payment_failed(
+                &*conn,
+                &*orders_repo,
+                &*invoices_repo,
+                &*payment_intent_repo,
+                &*payment_intent_invoices_repo,
+                event_id,
+                payment_intent,
+            )
+            .map_err(ectx!(ErrorKind::Internal => payment_intent_id))
+        })
+        .and_then(move |failed_invoice| match failed_invoice {
+            None => future::Either::A(future::ok(())),
+            Some((invoice, orders)) => {
+                let order_state_updates = orders
+                    .into_iter()
+                    .map(|order| OrderStateUpdate {
+                        order_id: order.id,
+                        store_id: order.store_id,
+                        customer_id: invoice.buyer_user_id,
+                        status: invoice.status,
+                    })
+                    .collect();
+
+                future::Either::B(
+                    saga_client
+                        .update_order_states(order_state_updates)
+                        .map_err(ectx!(ErrorKind::Internal => payment_intent_id_cloned)),
+                )
+            }
+        });
+
+        Box::new(fut)
     }
 
     pub fn handle_payment_intent_succeeded_or_amount_capturable_updated(
         self,
+        event_id: EventId,
         payment_intent: StripePaymentIntent,
     ) -> EventHandlerFuture<()> {
         if payment_intent.capture_method == CaptureMethod::Manual && payment_intent.amount != payment_intent.amount_capturable {
@@ -84,6 +165,7 @@ where
 
         let saga_client = self.saga_client.clone();
         let fee_config = self.fee.clone();
+        let capture_sla = self.capture_sla.clone();
 
         let amount_paid = payment_intent.amount.clone();
         let payment_intent_id = PaymentIntentId(payment_intent.id.clone());
@@ -116,6 +198,7 @@ where
                     &*payment_intent_fees_repo,
                     &*fees_repo,
                     fee_config,
+                    event_id,
                     payment_intent,
                 )
                 .map_err(ectx!(ErrorKind::Internal => payment_intent_id))
@@ -129,6 +212,7 @@ where
             move |payment_type| match payment_type {
                 Some(PaymentType::Invoice { invoice, orders, .. }) => {
                     let order_state_updates = orders
+                        .clone()
                         .into_iter()
                         .map(|order| OrderStateUpdate {
                             order_id: order.id,
@@ -144,23 +228,59 @@ where
 
                     let set_invoice_paid = spawn_on_pool(db_pool, cpu_pool, move |conn| {
                         let invoices_repo = repo_factory.create_invoices_v2_repo_with_sys_acl(&conn);
+                        let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
 
                         let invoice_set_amount_paid = InvoiceSetAmountPaid {
                             final_amount_paid: Amount::new(amount_paid as u128),
                             final_cashback_amount: Amount::new(0u128),
                             paid_at: Utc::now().naive_utc(),
+                            surplus_amount: Amount::zero(),
                         };
 
                         let invoice_id = invoice.id.clone();
-                        invoices_repo
+                        let already_paid = invoice.paid_at.is_some();
+                        let invoice = invoices_repo
                             .set_amount_paid_fiat(invoice_id.clone(), invoice_set_amount_paid.clone())
-                            .map_err(ectx!(convert => invoice_id, invoice_set_amount_paid))
+                            .map_err(ectx!(try convert => invoice_id, invoice_set_amount_paid))?;
+
+                        // both capturable and succeeded events pay the invoice, subscribers are notified and the orders are checked once
+                        if already_paid {
+                            return Ok(());
+                        }
+
+                        let event = Event::new(EventPayload::WebhookNotificationCreated {
+                            notification: WebhookNotification::for_invoice(WebhookEventType::InvoicePaid, &invoice, &orders),
+                        });
+                        event_store_repo.add_event(event.clone()).map_err(ectx!(try convert => event))?;
+
+                        add_invoice_receipts_event(&*event_store_repo, invoice_id)?;
+                        add_capture_sla_event(&*event_store_repo, &capture_sla, invoice_id)
                     });
 
                     future::Either::A(saga_update_states.and_then(|_| set_invoice_paid).map(|_| ()))
                 }
-                Some(PaymentType::Fee) => future::Either::B(future::ok(())),
-                None => future::Either::B(future::ok(())),
+                Some(PaymentType::Fee { fee }) => future::Either::B(future::Either::A(spawn_on_pool(db_pool, cpu_pool, move |conn| {
+                    let orders_repo = repo_factory.create_orders_repo_with_sys_acl(&conn);
+                    let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
+
+                    let order_id = fee.order_id;
+                    let order = orders_repo.get(order_id).map_err(ectx!(try convert => order_id))?.ok_or({
+                        let e = format_err!("Order {} not found", order_id);
+                        ectx!(try err e, ErrorKind::Internal)
+                    })?;
+
+                    let event = Event::new(EventPayload::WebhookNotificationCreated {
+                        notification: WebhookNotification::for_fee(&fee, order.store_id),
+                    });
+                    event_store_repo.add_event(event.clone()).map_err(ectx!(try convert => event))?;
+
+                    let event = Event::new(EventPayload::FeeInvoiceDue { fee_id: fee.id });
+                    event_store_repo
+                        .add_event(event.clone())
+                        .map_err(ectx!(convert => event))
+                        .map(|_| ())
+                }))),
+                None => future::Either::B(future::Either::B(future::ok(()))),
             }
         });
 
@@ -180,7 +300,19 @@ where
                             let self_ = self.clone();
                             move |_| self_.set_orders_status(invoice_id.clone(), OrderState::Paid)
                         })
-                        .and_then(move |_| self.create_fee_for_orders(invoice_id)),
+                        .and_then({
+                            let self_ = self.clone();
+                            move |_| self_.create_fee_for_orders(invoice_id)
+                        })
+                        .and_then({
+                            let self_ = self.clone();
+                            move |_| self_.schedule_capture_sla(invoice_id)
+                        })
+                        .and_then({
+                            let self_ = self.clone();
+                            move |_| self_.schedule_invoice_receipts(invoice_id)
+                        })
+                        .and_then(move |_| self.notify_invoice(invoice_id, WebhookEventType::InvoicePaid)),
                 )
             });
 
@@ -188,15 +320,90 @@ where
     }
 
     pub fn handle_payment_expired(self, invoice_id: InvoiceId) -> EventHandlerFuture<()> {
-        let fut = self.clone().get_invoice(invoice_id).and_then(move |invoice| match invoice.paid_at {
-            Some(_) => future::Either::A(future::ok(())), // do nothing if the invoice has already been paid
-            None => future::Either::B(future::lazy(move || self.process_payment_expired(invoice))),
+        let fut = self.clone().get_invoice(invoice_id).and_then(move |invoice| {
+            // do nothing if the invoice has already been paid or cancelled by the buyer
+            if invoice.paid_at.is_some() || invoice.status == OrderState::Cancelled {
+                future::Either::A(future::ok(()))
+            } else {
+                future::Either::B(future::lazy(move || {
+                    self.release_unpaid_invoice(invoice, OrderState::AmountExpired, WebhookEventType::InvoiceExpired)
+                }))
+            }
         });
 
         Box::new(fut)
     }
 
-    fn process_payment_expired(self, invoice: RawInvoice) -> EventHandlerFuture<()> {
+    pub fn handle_invoice_cancelled(self, invoice_id: InvoiceId) -> EventHandlerFuture<()> {
+        let fut = self.clone().get_invoice(invoice_id).and_then(move |invoice| {
+            // do nothing if the invoice got paid, even partly, before the cancellation
+            if invoice.paid_at.is_some() || invoice.amount_captured != Amount::zero() {
+                warn!(
+                    "Invoice {} has a captured amount, it is not released after the cancellation",
+                    invoice_id
+                );
+                future::Either::A(future::ok(()))
+            } else {
+                future::Either::B(future::lazy(move || {
+                    self.release_unpaid_invoice(invoice, OrderState::Cancelled, WebhookEventType::InvoiceCancelled)
+                }))
+            }
+        });
+
+        Box::new(fut)
+    }
+
+    pub fn handle_invoice_account_replaced(self, invoice_id: InvoiceId, account_id: AccountId) -> EventHandlerFuture<()> {
+        let fut = self
+            .clone()
+            .get_ture_context()
+            .into_future()
+            .and_then({
+                let self_ = self.clone();
+                move |ture_context| self_.is_account_free(account_id).map(move |is_free| (ture_context, is_free))
+            })
+            .and_then(move |((payments_client, account_service), is_free)| {
+                // a pooled account is free once replaced, a payment to it may already be expected by another invoice
+                if !is_free {
+                    warn!(
+                        "Account {} replaced on invoice {} is used by another invoice, it is not drained",
+                        account_id, invoice_id
+                    );
+                    return future::Either::A(future::ok(()));
+                }
+
+                future::Either::B(self.drain_account(payments_client, account_service, account_id))
+            });
+
+        Box::new(fut)
+    }
+
+    fn is_account_free(self, account_id: AccountId) -> EventHandlerFuture<bool> {
+        let EventHandler {
+            db_pool,
+            cpu_pool,
+            repo_factory,
+            ..
+        } = self;
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let invoices_repo = repo_factory.create_invoices_v2_repo_with_sys_acl(&conn);
+            invoices_repo
+                .get_by_account_id(account_id)
+                .map(|invoice| invoice.is_none())
+                .map_err(ectx!(convert => account_id))
+        })
+    }
+
+    /// Frees the account or cancels the payment intent of the unpaid invoice and sets its orders status
+    fn release_unpaid_invoice(
+        self,
+        invoice: RawInvoice,
+        status: OrderState,
+        webhook_event_type: WebhookEventType,
+    ) -> EventHandlerFuture<()> {
+        let self_ = self.clone();
+        let invoice_id = invoice.id;
         let db_pool = self.db_pool.clone();
         let cpu_pool = self.cpu_pool.clone();
         let stripe_client = self.stripe_client.clone();
@@ -214,17 +421,16 @@ where
                             self_.drain_and_unlink_account(payments_client, account_service, invoice_id)
                         }
                     })
-                    .and_then(move |_| self.set_orders_status(invoice.id.clone(), OrderState::AmountExpired))
+                    .and_then(move |_| self.set_orders_status(invoice.id.clone(), status))
             })),
             PaymentFlow::Fiat => future::Either::B(future::lazy(move || {
-                self.set_orders_status(invoice.id.clone(), OrderState::AmountExpired)
-                    .and_then(move |_| {
-                        cancel_payment_intent(db_pool, cpu_pool, stripe_client, repo_factory, invoice.id.clone())
-                            .map_err(ectx!(ErrorKind::Internal => invoice.id))
-                    })
+                self.set_orders_status(invoice.id.clone(), status).and_then(move |_| {
+                    cancel_payment_intent(db_pool, cpu_pool, stripe_client, repo_factory, invoice.id.clone())
+                        .map_err(ectx!(ErrorKind::Internal => invoice.id))
+                })
             })),
         }
-        .map(|_| ());
+        .and_then(move |_| self_.notify_invoice(invoice_id, webhook_event_type));
 
         Box::new(fut)
     }
@@ -278,6 +484,10 @@ where
                 }
             })
             .and_then(move |(account_id, balance, main_account_id)| {
+                if balance == Amount::zero() {
+                    return future::Either::A(future::ok(()));
+                }
+
                 let input = CreateInternalTransaction {
                     id: Uuid::new_v4(),
                     from: account_id,
@@ -285,9 +495,11 @@ where
                     amount: balance,
                 };
 
-                payments_client
-                    .create_internal_transaction(input.clone())
-                    .map_err(ectx!(ErrorKind::Internal => input))
+                future::Either::B(
+                    payments_client
+                        .create_internal_transaction(input.clone())
+                        .map_err(ectx!(ErrorKind::Internal => input)),
+                )
             });
 
         Box::new(fut)
@@ -406,6 +618,15 @@ where
                         let fees_repo = repo_factory.create_fees_repo_with_sys_acl(&conn);
 
                         for order in orders.iter() {
+                            // the event may be replayed, fees must not be created twice
+                            let existing_fee = fees_repo
+                                .get(SearchFee::OrderId(order.id))
+                                .map_err(ectx!(try ErrorKind::Internal => order.id))?;
+                            if existing_fee.is_some() {
+                                info!("Fee for order {} has already been created", order.id);
+                                continue;
+                            }
+
                             let new_fee =
                                 crate::services::invoice::create_crypto_fee(order_percent, &fee_currency, &currency_exchange_info, order)
                                     .map_err(ectx!(try ErrorKind::Internal => order.id))?;
@@ -424,7 +645,7 @@ where
         Box::new(fut)
     }
 
-    pub fn handle_payment_intent_capture(self, order_id: OrderId) -> EventHandlerFuture<()> {
+    pub fn handle_payment_intent_capture(self, event_id: EventId, order_id: OrderId, amount: Option<Amount>) -> EventHandlerFuture<()> {
         let db_pool_ = self.db_pool.clone();
         let cpu_pool_ = self.cpu_pool.clone();
         let repo_factory_ = self.repo_factory.clone();
@@ -434,16 +655,73 @@ where
             let payment_intent_repo = repo_factory_.create_payment_intent_repo_with_sys_acl(&conn);
             let orders_repo = repo_factory_.create_orders_repo_with_sys_acl(&conn);
             let payment_intent_invoices_repo = repo_factory_.create_payment_intent_invoices_repo_with_sys_acl(&conn);
+            let refunds_repo = repo_factory_.create_refunds_repo_with_sys_acl(&conn);
             let order = orders_repo.get(order_id).map_err(ectx!(try convert => order_id))?.ok_or({
                 let e = format_err!("Record order with id {} not found", order_id);
                 ectx!(try err e, ErrorKind::Internal)
             })?;
 
-            if order.state != PaymentState::Initial || order.stripe_fee.is_some() {
+            if order.state == PaymentState::Captured || order.stripe_fee.is_some() {
                 let e = format_err!("there is no need to perform capture payment intent");
                 return Err(ectx!(err e, ErrorKind::AlreadyDone));
             }
 
+            if order.state.transition_to(PaymentState::Captured).is_none() {
+                let e = format_err!("Order {} in state {} cannot be captured", order_id, order.state);
+                return Err(ectx!(err e, ErrorKind::Internal));
+            }
+
+            let captured_amount = amount.unwrap_or(order.total_amount);
+            let remainder = order.total_amount.checked_sub(captured_amount).ok_or({
+                let e = format_err!(
+                    "Captured amount {} exceeds total amount {} of order {}",
+                    captured_amount,
+                    order.total_amount,
+                    order_id
+                );
+                ectx!(try err e, ErrorKind::Internal)
+            })?;
+
+            // the release of the uncaptured remainder is recorded before Stripe is called,
+            // a retried capture finds the pending release and does not refund the remainder twice
+            let release = if remainder == Amount::zero() {
+                None
+            } else {
+                let pending_release = refunds_repo
+                    .list_by_order_id(order_id)
+                    .map_err(ectx!(try convert => order_id))?
+                    .into_iter()
+                    .find(|refund| refund.kind == RefundKind::CaptureRelease && refund.status == RefundStatus::Pending);
+
+                match pending_release {
+                    Some(ref release) if release.amount != remainder => {
+                        let e = format_err!(
+                            "Order {} has a pending release of {}, cannot release {}",
+                            order_id,
+                            release.amount,
+                            remainder
+                        );
+                        return Err(ectx!(err e, ErrorKind::Internal));
+                    }
+                    Some(release) => Some(release),
+                    None => {
+                        let new_refund = NewRefund {
+                            id: RefundId::generate(),
+                            order_id,
+                            amount: remainder,
+                            reason: Some("Uncaptured amount released".to_string()),
+                            status: RefundStatus::Pending,
+                            crypto_currency: None,
+                            crypto_amount: None,
+                            wallet_address: None,
+                            transaction_id: None,
+                            kind: RefundKind::CaptureRelease,
+                        };
+                        Some(refunds_repo.create(new_refund.clone()).map_err(ectx!(try convert => new_refund))?)
+                    }
+                }
+            };
+
             let order_invoice_id_cloned = order.invoice_id.clone();
             let payment_intent_invoice = payment_intent_invoices_repo
                 .get(SearchPaymentIntentInvoice::InvoiceId(order.invoice_id.clone()))
@@ -462,10 +740,11 @@ where
                     let e = format_err!("payment intent {:?} not found", search_clone);
                     ectx!(err e, ErrorKind::Internal)
                 })
-                .map(|payment_intent| (payment_intent, order.total_amount, order.seller_currency))
+                .map(|payment_intent| (payment_intent, captured_amount, release, order.seller_currency))
         })
-        .and_then(move |(payment_intent, total_amount, currency)| {
+        .and_then(move |(payment_intent, captured_amount, release, currency)| {
             let stripe_client_clone = stripe_client.clone();
+            let stripe_client_refund = stripe_client.clone();
             payment_intent
                 .charge_id
                 .ok_or({
@@ -473,40 +752,104 @@ where
                     ectx!(err e, ErrorKind::Internal)
                 })
                 .into_future()
-                .and_then(move |charge_id| stripe_client.get_charge(charge_id.clone()).map_err(ectx!(convert => charge_id)))
-                .and_then(move |charge| {
-                    charge.balance_transaction.ok_or({
-                        let e = format_err!("charge balance transaction id not found");
-                        ectx!(err e, ErrorKind::Internal)
-                    })
+                .and_then(move |charge_id| {
+                    stripe_client
+                        .get_charge(charge_id.clone())
+                        .map_err(ectx!(convert => charge_id))
+                        .map(move |charge| (charge_id, charge))
+                })
+                .and_then(move |(charge_id, charge)| {
+                    charge
+                        .balance_transaction
+                        .ok_or({
+                            let e = format_err!("charge balance transaction id not found");
+                            ectx!(err e, ErrorKind::Internal)
+                        })
+                        .map(move |balance_transaction| (charge_id, balance_transaction))
                 })
-                .and_then(move |balance_transaction| {
+                .and_then(move |(charge_id, balance_transaction)| {
                     stripe_client_clone
                         .retrieve_balance_transaction(balance_transaction.clone())
                         .map_err(ectx!(convert => balance_transaction))
+                        .map(move |balance_transaction| (charge_id, balance_transaction))
                 })
-                .map(move |balance_transaction| {
-                    let total_amount_super_unit = total_amount.to_super_unit(currency);
+                .map(move |(charge_id, balance_transaction)| {
+                    let captured_amount_super_unit = captured_amount.to_super_unit(currency);
                     let fee_procent = balance_transaction.fee as f64 / balance_transaction.amount as f64;
-                    let stripe_fee = Amount::from_super_unit(currency, total_amount_super_unit * BigDecimal::from(fee_procent));
-                    stripe_fee
+                    let stripe_fee = Amount::from_super_unit(currency, captured_amount_super_unit * BigDecimal::from(fee_procent));
+                    (charge_id, stripe_fee)
                 })
+                .and_then(move |(charge_id, stripe_fee)| {
+                    let release = match release {
+                        Some(release) => release,
+                        None => return future::Either::A(future::ok((stripe_fee, None))),
+                    };
+
+                    // the invoice is charged in full upfront, so the part the seller did not capture is released back to the buyer
+                    info!("Releasing uncaptured amount {} of order {}", release.amount, order_id);
+                    let (release_id, remainder) = (release.id, release.amount);
+                    future::Either::B(
+                        stripe_client_refund
+                            .refund_once(charge_id.clone(), remainder, order_id, release_id)
+                            .map_err(ectx!(convert => charge_id, remainder, order_id, release_id))
+                            .map(move |stripe_refund| (stripe_fee, Some((release_id, stripe_refund.id)))),
+                    )
+                })
+                .map(move |(stripe_fee, release)| (captured_amount, release, stripe_fee))
         })
         .and_then({
             let db_pool = self.db_pool.clone();
             let cpu_pool = self.cpu_pool.clone();
             let repo_factory = self.repo_factory.clone();
-            move |stripe_fee| {
+            let order_percent = self.fee.order_percent;
+            move |(captured_amount, release, stripe_fee)| {
                 spawn_on_pool(db_pool, cpu_pool, move |conn| {
                     let orders_repo = repo_factory.create_orders_repo_with_sys_acl(&conn);
+                    let refunds_repo = repo_factory.create_refunds_repo_with_sys_acl(&conn);
+                    let fees_repo = repo_factory.create_fees_repo_with_sys_acl(&conn);
+                    let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
                     info!("Setting order {} state \'Captured\'", order_id);
-                    orders_repo
-                        .update_state(order_id, PaymentState::Captured)
-                        .map_err(ectx!(try convert => order_id))?;
-                    orders_repo
-                        .update_stripe_fee(order_id, stripe_fee)
-                        .map_err(ectx!(convert => order_id, stripe_fee))
-                        .map(|_| ())
+                    conn.transaction::<_, Error, _>(move || {
+                        orders_repo
+                            .update_state(
+                                order_id,
+                                PaymentState::Captured,
+                                OrderStateChange::by_event(event_id, "Payment captured"),
+                            )
+                            .map_err(ectx!(try convert => order_id))?;
+                        let order = orders_repo
+                            .update_stripe_fee(order_id, stripe_fee)
+                            .map_err(ectx!(try convert => order_id, stripe_fee))?;
+
+                        let order = match release {
+                            None => order,
+                            Some((release_id, stripe_refund_id)) => {
+                                // the release is completed together with the capture, so it is never refunded again
+                                let update_refund = UpdateRefund {
+                                    stripe_refund_id: Some(stripe_refund_id),
+                                    status: Some(RefundStatus::Succeeded),
+                                    ..Default::default()
+                                };
+                                refunds_repo
+                                    .update(release_id, update_refund.clone())
+                                    .map_err(ectx!(try convert => release_id, update_refund))?;
+
+                                let order = orders_repo
+                                    .update_captured_amount(order_id, captured_amount)
+                                    .map_err(ectx!(try convert => order_id, captured_amount))?;
+                                update_fee_for_captured_amount(&*fees_repo, order_percent, &order)?;
+                                order
+                            }
+                        };
+
+                        let event = Event::new(EventPayload::WebhookNotificationCreated {
+                            notification: WebhookNotification::for_order(WebhookEventType::OrderCaptured, &order),
+                        });
+                        event_store_repo
+                            .add_event(event.clone())
+                            .map_err(ectx!(convert => event))
+                            .map(|_| ())
+                    })
                 })
             }
         })
@@ -582,10 +925,21 @@ where
 
         let fut = spawn_on_pool(db_pool, cpu_pool, move |conn| {
             let payouts_repo = repo_factory.create_payouts_repo_with_sys_acl(&conn);
+            let orders_repo = repo_factory.create_orders_repo_with_sys_acl(&conn);
+            let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
 
-            payouts_repo
+            let payout = payouts_repo
                 .mark_as_completed(payout_id.clone())
-                .map_err(ectx!(ErrorKind::Internal => payout_id))
+                .map_err(ectx!(try ErrorKind::Internal => payout_id))?;
+
+            let orders = orders_repo.get_many(&payout.order_ids).map_err(ectx!(try convert => payout_id))?;
+
+            let event = Event::new(EventPayload::WebhookNotificationCreated {
+                notification: WebhookNotification::for_payout(&payout, &orders),
+            });
+            event_store_repo
+                .add_event(event.clone())
+                .map_err(ectx!(convert => event))
                 .map(|_| ())
         });
 
@@ -637,3 +991,27 @@ where
 
     Box::new(fut)
 }
+
+/// Recalculates the unpaid fee of a partially captured order, paid fees are left untouched
+fn update_fee_for_captured_amount(fees_repo: &FeeRepo, order_percent: u64, order: &RawOrder) -> EventHandlerResult<()> {
+    let order_id = order.id;
+    let fee = fees_repo
+        .get(SearchFee::OrderId(order_id))
+        .map_err(ectx!(try convert => order_id))?;
+
+    match fee {
+        Some(ref fee) if fee.status == FeeStatus::NotPaid => {
+            let new_fee = services::stripe::create_fee(order_percent, order).map_err(ectx!(try ErrorKind::Internal => order_id))?;
+            let fee_id = fee.id;
+            let update_fee = UpdateFee {
+                amount: Some(new_fee.amount),
+                ..Default::default()
+            };
+            fees_repo
+                .update(fee_id, update_fee.clone())
+                .map_err(ectx!(convert => fee_id, update_fee))
+                .map(|_| ())
+        }
+        _ => Ok(()),
+    }
+}
@@ -1,6 +1,15 @@
+mod capture_sla;
+mod disputes;
+mod documents;
 pub mod error;
+mod escrow;
 mod handlers;
+mod refunds;
+mod retention;
+mod surpluses;
+mod webhooks;
 
+use chrono::Utc;
 use diesel::{
     connection::{AnsiTransactionManager, Connection},
     pg::Pg,
@@ -10,13 +19,16 @@ use futures::{future, Future, Stream};
 use futures_cpupool::CpuPool;
 use r2d2::{ManageConnection, Pool, PooledConnection};
 use sentry::integrations::failure::capture_error;
+use std::env;
+use std::process;
 use std::time::{Duration, Instant};
 use stq_http::client::HttpClient;
 use tokio_timer::Interval;
 
 use client::{payments::PaymentsClient, saga::SagaClient, stores::StoresClient, stripe::StripeClient};
 use config;
-use models::event_store::EventEntry;
+use models::event_store::{EventEntry, EventEntryId};
+use models::{Event, NewEventAttempt};
 use repos::repo_factory::ReposFactory;
 use services::accounts::AccountService;
 
@@ -47,6 +59,13 @@ where
     pub payments_client: Option<PC>,
     pub account_service: Option<AS>,
     pub fee: config::FeeValues,
+    pub crypto_refund: config::CryptoRefund,
+    pub capture_sla: config::CaptureSla,
+    pub escrow: config::Escrow,
+    pub invoice_payment: config::InvoicePayment,
+    pub documents: config::Documents,
+    pub batch_size: u32,
+    pub worker_instance: String,
 }
 
 impl<T, M, F, HC, PC, SC, STC, STRC, AS> Clone for EventHandler<T, M, F, HC, PC, SC, STC, STRC, AS>
@@ -73,6 +92,13 @@ where
             payments_client: self.payments_client.clone(),
             account_service: self.account_service.clone(),
             fee: self.fee.clone(),
+            crypto_refund: self.crypto_refund.clone(),
+            capture_sla: self.capture_sla.clone(),
+            escrow: self.escrow.clone(),
+            invoice_payment: self.invoice_payment.clone(),
+            documents: self.documents.clone(),
+            batch_size: self.batch_size,
+            worker_instance: self.worker_instance.clone(),
         }
     }
 }
@@ -127,58 +153,113 @@ where
             cpu_pool,
             db_pool,
             repo_factory,
+            batch_size,
             ..
         } = self.clone();
 
-        let fut = spawn_on_pool(db_pool.clone(), cpu_pool.clone(), {
-            let repo_factory = repo_factory.clone();
-            move |conn| {
-                let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
-
-                trace!("Resetting stuck events...");
-                let reset_events = event_store_repo.reset_stuck_events().map_err(ectx!(try convert))?;
-                trace!("{} events have been reset", reset_events.len());
-
-                trace!("Getting events for processing...");
-                event_store_repo
-                    .get_events_for_processing(1)
-                    .map(|event_entries| {
-                        trace!("Got {} events to process", event_entries.len());
-                        event_entries
-                            .into_iter()
-                            .next()
-                            .map(|EventEntry { id: entry_id, event, .. }| (entry_id, event))
-                    })
-                    .map_err(ectx!(convert))
-            }
+        let fut = spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
+
+            trace!("Resetting stuck events...");
+            let reset_events = event_store_repo.reset_stuck_events().map_err(ectx!(try convert))?;
+            trace!("{} events have been reset", reset_events.len());
+
+            trace!("Getting events for processing (batch size: {})...", batch_size);
+            event_store_repo
+                .get_events_for_processing(batch_size)
+                .map(|event_entries| {
+                    trace!("Got {} events to process", event_entries.len());
+                    event_entries
+                })
+                .map_err(ectx!(convert))
         })
-        .and_then(move |event| match event {
-            None => future::Either::A(future::ok(())),
-            Some((entry_id, event)) => future::Either::B(future::lazy(move || {
-                trace!("Started processing event #{} - {:?}", entry_id, event);
-                self.handle_event(event.clone()).then(move |result| {
-                    spawn_on_pool(db_pool, cpu_pool, move |conn| {
-                        let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
-
-                        match result {
-                            Ok(()) => {
-                                trace!("Finished processing event #{} - {:?}", entry_id, event);
-                                event_store_repo.complete_event(entry_id).map_err(ectx!(try convert => entry_id))?;
-                                Ok(())
-                            }
-                            Err(e) => {
-                                trace!("Failed to process event #{} - {:?}", entry_id, event);
-                                event_store_repo.fail_event(entry_id).map_err(ectx!(try convert => entry_id))?;
-                                Err(e)
-                            }
+        .and_then(move |event_entries| {
+            let event_futures = event_entries
+                .into_iter()
+                .map(|EventEntry { id: entry_id, event, .. }| {
+                    self.clone().process_event(entry_id, event).then(|res| {
+                        if let Err(err) = res {
+                            let err = FailureError::from(err.context("An error occurred while processing an event"));
+                            error!("{:?}", &err);
+                            capture_error(&err);
                         }
+
+                        future::ok::<_, Error>(())
                     })
                 })
-            })),
+                .collect::<Vec<_>>();
+
+            future::join_all(event_futures).map(|_| ())
         });
 
         Box::new(fut)
     }
+
+    fn process_event(self, entry_id: EventEntryId, event: Event) -> EventHandlerFuture<()> {
+        let EventHandler {
+            cpu_pool,
+            db_pool,
+            repo_factory,
+            worker_instance,
+            ..
+        } = self.clone();
+
+        let fut = future::lazy(move || {
+            trace!("Started processing event #{} - {:?}", entry_id, event);
+            let started_at = Utc::now().naive_utc();
+            self.handle_event(event.clone()).then(move |result| {
+                spawn_on_pool(db_pool, cpu_pool, move |conn| {
+                    let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
+
+                    let (error_kind, error_message) = match result {
+                        Ok(()) => (None, None),
+                        Err(ref e) => (Some(format!("{:?}", e.kind())), Some(error_chain(e))),
+                    };
+
+                    let new_event_attempt = NewEventAttempt {
+                        event_id: entry_id,
+                        worker_instance,
+                        started_at,
+                        finished_at: Utc::now().naive_utc(),
+                        error_kind,
+                        error_message,
+                    };
+
+                    // the attempt is recorded for audit only, failing to record it must not leave the event in progress
+                    if let Err(e) = event_store_repo.add_event_attempt(new_event_attempt) {
+                        let err = FailureError::from(e.context(format!("Failed to record an attempt of event #{}", entry_id)));
+                        error!("{:?}", &err);
+                        capture_error(&err);
+                    }
+
+                    match result {
+                        Ok(()) => {
+                            trace!("Finished processing event #{} - {:?}", entry_id, event);
+                            event_store_repo.complete_event(entry_id).map_err(ectx!(try convert => entry_id))?;
+                            Ok(())
+                        }
+                        Err(e) => {
+                            trace!("Failed to process event #{} - {:?}", entry_id, event);
+                            event_store_repo.fail_event(entry_id).map_err(ectx!(try convert => entry_id))?;
+                            Err(e)
+                        }
+                    }
+                })
+            })
+        });
+
+        Box::new(fut)
+    }
+}
+
+/// Identifies the process handling events, e.g. `billing-5d8f7c9b4-x2x7k:1`
+pub fn worker_instance() -> String {
+    let host = env::var("HOSTNAME").unwrap_or_else(|_| "localhost".to_string());
+    format!("{}:{}", host, process::id())
+}
+
+fn error_chain(err: &Error) -> String {
+    err.causes().map(|fail| fail.to_string()).collect::<Vec<_>>().join("\n")
 }
 
 pub fn spawn_on_pool<T, M, Func, R>(db_pool: Pool<M>, cpu_pool: CpuPool, f: Func) -> EventHandlerFuture<R>
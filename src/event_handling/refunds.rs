@@ -0,0 +1,561 @@
+use chrono::{Duration as ChronoDuration, Utc};
+use diesel::{connection::AnsiTransactionManager, pg::Pg, Connection};
+use failure::Fail;
+use futures::{future, Future};
+use r2d2::ManageConnection;
+use stq_http::client::HttpClient;
+use uuid::Uuid;
+
+use client::{
+    payments::{CreateExternalTransaction, FeesResponse, GetFees, PaymentsClient},
+    saga::SagaClient,
+    stores::StoresClient,
+    stripe::{ErrorKind as StripeErrorKind, StripeClient},
+};
+use models::{
+    AccountId, AccountWithBalance, Amount, ChargeId, Currency, Event, EventId, EventPayload, OrderStateChange, PaymentState, Refund,
+    RefundId, RefundStatus, TureCurrency, UpdateRefund, WalletAddress, WebhookEventType, WebhookNotification,
+};
+use repos::ReposFactory;
+use services::accounts::AccountService;
+use services::order::{adjust_fee_for_refund, get_order_charge_id, refund_balance_adjustment};
+
+use super::error::*;
+use super::{spawn_on_pool, EventHandler, EventHandlerFuture};
+
+impl<T, M, F, HC, PC, SC, STC, STRC, AS> EventHandler<T, M, F, HC, PC, SC, STC, STRC, AS>
+where
+    T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static,
+    M: ManageConnection<Connection = T>,
+    F: ReposFactory<T>,
+    HC: HttpClient + Clone,
+    PC: PaymentsClient + Clone,
+    SC: SagaClient + Clone,
+    STC: StoresClient + Clone,
+    STRC: StripeClient + Clone,
+    AS: AccountService + Clone + 'static,
+{
+    /// Sends the refund transaction if it hasn't been sent yet and completes the refund once the transaction is confirmed
+    pub fn handle_crypto_refund_initiated(self, event_id: EventId, refund_id: RefundId) -> EventHandlerFuture<()> {
+        let (payments_client, account_service) = match self.clone().get_ture_context() {
+            Ok((payments_client, account_service)) => (payments_client, account_service),
+            Err(e) => return Box::new(future::err(e)),
+        };
+
+        let fut = self
+            .clone()
+            .get_pending_crypto_refund(refund_id)
+            .and_then(move |refund| match refund {
+                None => future::Either::A(future::ok(())),
+                Some((refund, invoice_account_id)) => {
+                    future::Either::B(self.process_crypto_refund(event_id, payments_client, account_service, refund, invoice_account_id))
+                }
+            });
+
+        Box::new(fut)
+    }
+
+    /// Returns the refund together with the invoice account if the refund is still pending
+    fn get_pending_crypto_refund(self, refund_id: RefundId) -> EventHandlerFuture<Option<(Refund, Option<AccountId>)>> {
+        let EventHandler {
+            db_pool,
+            cpu_pool,
+            repo_factory,
+            ..
+        } = self;
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let refunds_repo = repo_factory.create_refunds_repo_with_sys_acl(&conn);
+            let orders_repo = repo_factory.create_orders_repo_with_sys_acl(&conn);
+            let invoices_repo = repo_factory.create_invoices_v2_repo_with_sys_acl(&conn);
+
+            let refund = match refunds_repo.get(refund_id).map_err(ectx!(try convert => refund_id))? {
+                Some(refund) => refund,
+                None => {
+                    info!("Crypto refund initiated handler: refund with ID {} not found", refund_id);
+                    return Ok(None);
+                }
+            };
+
+            if refund.status != RefundStatus::Pending {
+                info!(
+                    "Crypto refund initiated handler: refund with ID {} is already {}",
+                    refund_id, refund.status
+                );
+                return Ok(None);
+            }
+
+            let order_id = refund.order_id;
+            let order = orders_repo.get(order_id).map_err(ectx!(try convert => order_id))?.ok_or({
+                let e = format_err!("Order {} not found", order_id);
+                ectx!(try err e, ErrorKind::Internal)
+            })?;
+
+            if order.state.transition_to(PaymentState::Refunded).is_none() {
+                let e = format_err!("Order {} in state {} cannot be refunded", order_id, order.state);
+                return Err(ectx!(err e, ErrorKind::Internal));
+            }
+
+            let invoice_id = order.invoice_id;
+            let invoice = invoices_repo.get(invoice_id).map_err(ectx!(try convert => invoice_id))?.ok_or({
+                let e = format_err!("Invoice {} not found", invoice_id);
+                ectx!(try err e, ErrorKind::Internal)
+            })?;
+
+            Ok(Some((refund, invoice.account_id)))
+        })
+    }
+
+    fn process_crypto_refund(
+        self,
+        event_id: EventId,
+        payments_client: PC,
+        account_service: AS,
+        refund: Refund,
+        invoice_account_id: Option<AccountId>,
+    ) -> EventHandlerFuture<()> {
+        let refund_id = refund.id;
+        let tx_id = match refund.transaction_id {
+            Some(tx_id) => *tx_id.inner(),
+            None => {
+                let e = format_err!("Refund {} has no transaction ID", refund_id);
+                return Box::new(future::err(ectx!(err e, ErrorKind::Internal)));
+            }
+        };
+
+        let fut = payments_client
+            .clone()
+            .get_transaction(tx_id)
+            .map_err(ectx!(ErrorKind::Internal => tx_id))
+            .and_then(move |tx| match tx {
+                None => future::Either::A(
+                    create_crypto_refund_tx(payments_client, account_service, refund, invoice_account_id)
+                        .and_then(move |blockchain_fee| self.set_crypto_refund_blockchain_fee(refund_id, blockchain_fee)),
+                ),
+                Some(tx) => future::Either::B(match tx.status.as_str() {
+                    "completed" | "done" => future::Either::A(self.complete_crypto_refund(event_id, refund_id)),
+                    "failed" | "error" => future::Either::B(future::Either::A(self.fail_crypto_refund(refund_id))),
+                    status => {
+                        debug!("Refund {} transaction {} is {}, checking again later", refund_id, tx_id, status);
+                        future::Either::B(future::Either::B(self.schedule_crypto_refund_check(refund_id)))
+                    }
+                }),
+            });
+
+        Box::new(fut)
+    }
+
+    fn set_crypto_refund_blockchain_fee(self, refund_id: RefundId, blockchain_fee: Amount) -> EventHandlerFuture<()> {
+        let EventHandler {
+            db_pool,
+            cpu_pool,
+            repo_factory,
+            ..
+        } = self.clone();
+
+        let fut = spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let refunds_repo = repo_factory.create_refunds_repo_with_sys_acl(&conn);
+
+            let update_refund = UpdateRefund {
+                blockchain_fee: Some(blockchain_fee),
+                ..Default::default()
+            };
+            refunds_repo
+                .update(refund_id, update_refund.clone())
+                .map_err(ectx!(convert => refund_id, update_refund))
+                .map(|_| ())
+        })
+        .and_then(move |_| self.schedule_crypto_refund_check(refund_id));
+
+        Box::new(fut)
+    }
+
+    fn schedule_crypto_refund_check(self, refund_id: RefundId) -> EventHandlerFuture<()> {
+        let EventHandler {
+            db_pool,
+            cpu_pool,
+            repo_factory,
+            crypto_refund,
+            ..
+        } = self;
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
+
+            let scheduled_on = Utc::now().naive_utc() + ChronoDuration::seconds(crypto_refund.confirmation_check_interval_sec.into());
+            let event = Event::new(EventPayload::CryptoRefundInitiated { refund_id });
+            event_store_repo
+                .add_scheduled_event(event.clone(), scheduled_on)
+                .map_err(ectx!(convert => event, scheduled_on))
+                .map(|_| ())
+        })
+    }
+
+    fn complete_crypto_refund(self, event_id: EventId, refund_id: RefundId) -> EventHandlerFuture<()> {
+        let EventHandler {
+            db_pool,
+            cpu_pool,
+            repo_factory,
+            fee,
+            ..
+        } = self;
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let refunds_repo = repo_factory.create_refunds_repo_with_sys_acl(&conn);
+            let orders_repo = repo_factory.create_orders_repo_with_sys_acl(&conn);
+            let fees_repo = repo_factory.create_fees_repo_with_sys_acl(&conn);
+            let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
+
+            conn.transaction::<_, Error, _>(move || {
+                let refund = refunds_repo.get(refund_id).map_err(ectx!(try convert => refund_id))?.ok_or({
+                    let e = format_err!("Refund {} not found", refund_id);
+                    ectx!(try err e, ErrorKind::Internal)
+                })?;
+
+                let order_id = refund.order_id;
+                let order = orders_repo.get(order_id).map_err(ectx!(try convert => order_id))?.ok_or({
+                    let e = format_err!("Order {} not found", order_id);
+                    ectx!(try err e, ErrorKind::Internal)
+                })?;
+
+                let refunded_amount = order.refunded_amount.checked_add(refund.amount).ok_or({
+                    let e = format_err!("Overflow while adding refund {} to order {}", refund_id, order_id);
+                    ectx!(try err e, ErrorKind::Internal)
+                })?;
+                let refunded_order = orders_repo
+                    .update_refunded_amount(order_id, refunded_amount)
+                    .map_err(ectx!(try convert => order_id, refunded_amount))?;
+
+                let fee_adjustment = adjust_fee_for_refund(&*fees_repo, fee.order_percent, &order, &refunded_order)
+                    .map_err(ectx!(try ErrorKind::Internal => order_id))?;
+
+                let update_refund = UpdateRefund {
+                    status: Some(RefundStatus::Succeeded),
+                    fee_adjustment: Some(fee_adjustment),
+                    ..Default::default()
+                };
+                refunds_repo
+                    .update(refund_id, update_refund.clone())
+                    .map_err(ectx!(try convert => refund_id, update_refund))?;
+
+                info!("Refund {} is confirmed, setting order {} state \'Refunded\'", refund_id, order_id);
+                let refunded_order = orders_repo
+                    .update_state(
+                        order_id,
+                        PaymentState::Refunded,
+                        OrderStateChange::by_event(event_id, "Crypto refund confirmed"),
+                    )
+                    .map_err(ectx!(try convert => order_id))?;
+
+                let event = Event::new(EventPayload::WebhookNotificationCreated {
+                    notification: WebhookNotification::for_order(WebhookEventType::OrderRefunded, &refunded_order),
+                });
+                event_store_repo
+                    .add_event(event.clone())
+                    .map_err(ectx!(convert => event))
+                    .map(|_| ())
+            })
+        })
+    }
+
+    fn fail_crypto_refund(self, refund_id: RefundId) -> EventHandlerFuture<()> {
+        let EventHandler {
+            db_pool,
+            cpu_pool,
+            repo_factory,
+            ..
+        } = self;
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let refunds_repo = repo_factory.create_refunds_repo_with_sys_acl(&conn);
+
+            warn!("Refund {} transaction has failed, the order has to be refunded again", refund_id);
+            let update_refund = UpdateRefund {
+                status: Some(RefundStatus::Failed),
+                ..Default::default()
+            };
+            refunds_repo
+                .update(refund_id, update_refund.clone())
+                .map_err(ectx!(convert => refund_id, update_refund))
+                .map(|_| ())
+        })
+    }
+
+    /// Sends the refund of a fiat order to Stripe unless Stripe already has it and completes the refund.
+    /// Stripe being unreachable fails the event, so that it is retried until Stripe accepts or rejects the refund
+    pub fn handle_stripe_refund_initiated(self, event_id: EventId, refund_id: RefundId) -> EventHandlerFuture<()> {
+        let stripe_client = self.stripe_client.clone();
+
+        let fut = self
+            .clone()
+            .get_pending_stripe_refund(refund_id)
+            .and_then(move |refund| match refund {
+                None => future::Either::A(future::ok(())),
+                Some((refund, charge_id)) => future::Either::B(
+                    stripe_client
+                        .refund_once(charge_id, refund.amount, refund.order_id, refund_id)
+                        .then(move |result| match result {
+                            Ok(stripe_refund) => future::Either::A(self.complete_stripe_refund(event_id, refund_id, stripe_refund.id)),
+                            Err(e) => match e.kind() {
+                                StripeErrorKind::Validation(_) => {
+                                    warn!("Stripe rejected refund {}: {}", refund_id, e);
+                                    future::Either::B(future::Either::A(self.fail_stripe_refund(refund_id)))
+                                }
+                                _ => future::Either::B(future::Either::B(future::err(e).map_err(ectx!(convert => refund_id)))),
+                            },
+                        }),
+                ),
+            });
+
+        Box::new(fut)
+    }
+
+    /// Returns the refund together with the charge it is taken from if the refund is still pending
+    fn get_pending_stripe_refund(self, refund_id: RefundId) -> EventHandlerFuture<Option<(Refund, ChargeId)>> {
+        let EventHandler {
+            db_pool,
+            cpu_pool,
+            repo_factory,
+            ..
+        } = self;
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let refunds_repo = repo_factory.create_refunds_repo_with_sys_acl(&conn);
+            let orders_repo = repo_factory.create_orders_repo_with_sys_acl(&conn);
+            let payment_intent_repo = repo_factory.create_payment_intent_repo_with_sys_acl(&conn);
+            let payment_intent_invoices_repo = repo_factory.create_payment_intent_invoices_repo_with_sys_acl(&conn);
+
+            let refund = match refunds_repo.get(refund_id).map_err(ectx!(try convert => refund_id))? {
+                Some(refund) => refund,
+                None => {
+                    info!("Stripe refund initiated handler: refund with ID {} not found", refund_id);
+                    return Ok(None);
+                }
+            };
+
+            if refund.status != RefundStatus::Pending {
+                info!(
+                    "Stripe refund initiated handler: refund with ID {} is already {}",
+                    refund_id, refund.status
+                );
+                return Ok(None);
+            }
+
+            let order_id = refund.order_id;
+            let order = orders_repo.get(order_id).map_err(ectx!(try convert => order_id))?.ok_or({
+                let e = format_err!("Order {} not found", order_id);
+                ectx!(try err e, ErrorKind::Internal)
+            })?;
+
+            let charge_id = get_order_charge_id(&*payment_intent_invoices_repo, &*payment_intent_repo, &order)
+                .map_err(ectx!(try ErrorKind::Internal => order_id))?;
+
+            Ok(Some((refund, charge_id)))
+        })
+    }
+
+    fn complete_stripe_refund(self, event_id: EventId, refund_id: RefundId, stripe_refund_id: String) -> EventHandlerFuture<()> {
+        let EventHandler {
+            db_pool,
+            cpu_pool,
+            repo_factory,
+            fee,
+            ..
+        } = self;
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let refunds_repo = repo_factory.create_refunds_repo_with_sys_acl(&conn);
+            let orders_repo = repo_factory.create_orders_repo_with_sys_acl(&conn);
+            let fees_repo = repo_factory.create_fees_repo_with_sys_acl(&conn);
+            let payouts_repo = repo_factory.create_payouts_repo_with_sys_acl(&conn);
+            let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
+
+            conn.transaction::<_, Error, _>(move || {
+                let refund = refunds_repo.get(refund_id).map_err(ectx!(try convert => refund_id))?.ok_or({
+                    let e = format_err!("Refund {} not found", refund_id);
+                    ectx!(try err e, ErrorKind::Internal)
+                })?;
+
+                let order_id = refund.order_id;
+                let order = orders_repo.get(order_id).map_err(ectx!(try convert => order_id))?.ok_or({
+                    let e = format_err!("Order {} not found", order_id);
+                    ectx!(try err e, ErrorKind::Internal)
+                })?;
+
+                let refunded_amount = order.refunded_amount.checked_add(refund.amount).ok_or({
+                    let e = format_err!("Overflow while adding refund {} to order {}", refund_id, order_id);
+                    ectx!(try err e, ErrorKind::Internal)
+                })?;
+                let refunded_order = orders_repo
+                    .update_refunded_amount(order_id, refunded_amount)
+                    .map_err(ectx!(try convert => order_id, refunded_amount))?;
+
+                let fee_adjustment = adjust_fee_for_refund(&*fees_repo, fee.order_percent, &order, &refunded_order)
+                    .map_err(ectx!(try ErrorKind::Internal => order_id))?;
+
+                // the seller has already received the refunded amount, it is deducted from the next payout
+                let balance_adjustment =
+                    refund_balance_adjustment(&*payouts_repo, &order, refund.amount).map_err(ectx!(try ErrorKind::Internal => order_id))?;
+
+                let update_refund = UpdateRefund {
+                    stripe_refund_id: Some(stripe_refund_id),
+                    status: Some(RefundStatus::Succeeded),
+                    fee_adjustment: Some(fee_adjustment),
+                    balance_adjustment: Some(balance_adjustment),
+                    ..Default::default()
+                };
+                refunds_repo
+                    .update(refund_id, update_refund.clone())
+                    .map_err(ectx!(try convert => refund_id, update_refund))?;
+
+                if refunded_order.net_amount() != Amount::zero() {
+                    return Ok(());
+                }
+
+                info!("Order {} is fully refunded, setting state \'Refunded\'", order_id);
+                let refunded_order = orders_repo
+                    .update_state(
+                        order_id,
+                        PaymentState::Refunded,
+                        OrderStateChange::by_event(event_id, "Order fully refunded"),
+                    )
+                    .map_err(ectx!(try convert => order_id))?;
+
+                let event = Event::new(EventPayload::WebhookNotificationCreated {
+                    notification: WebhookNotification::for_order(WebhookEventType::OrderRefunded, &refunded_order),
+                });
+                event_store_repo
+                    .add_event(event.clone())
+                    .map_err(ectx!(convert => event))
+                    .map(|_| ())
+            })
+        })
+    }
+
+    fn fail_stripe_refund(self, refund_id: RefundId) -> EventHandlerFuture<()> {
+        let EventHandler {
+            db_pool,
+            cpu_pool,
+            repo_factory,
+            ..
+        } = self;
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let refunds_repo = repo_factory.create_refunds_repo_with_sys_acl(&conn);
+
+            let update_refund = UpdateRefund {
+                status: Some(RefundStatus::Failed),
+                ..Default::default()
+            };
+            refunds_repo
+                .update(refund_id, update_refund.clone())
+                .map_err(ectx!(convert => refund_id, update_refund))
+                .map(|_| ())
+        })
+    }
+}
+
+/// Sends the crypto refund to the buyer wallet, returns the blockchain fee of the transaction
+fn create_crypto_refund_tx<PC, AS>(
+    payments_client: PC,
+    account_service: AS,
+    refund: Refund,
+    invoice_account_id: Option<AccountId>,
+) -> EventHandlerFuture<Amount>
+where
+    PC: PaymentsClient + Clone,
+    AS: AccountService,
+{
+    let refund_id = refund.id;
+    let (currency, amount, wallet_address, tx_id) = match (
+        refund.crypto_currency,
+        refund.crypto_amount,
+        refund.wallet_address,
+        refund.transaction_id,
+    ) {
+        (Some(currency), Some(amount), Some(wallet_address), Some(tx_id)) => (currency, amount, wallet_address, *tx_id.inner()),
+        _ => {
+            let e = format_err!("Refund {} is not a crypto refund", refund_id);
+            return Box::new(future::err(ectx!(err e, ErrorKind::Internal)));
+        }
+    };
+
+    send_crypto_refund_tx(
+        payments_client,
+        account_service,
+        tx_id,
+        currency,
+        amount,
+        wallet_address,
+        invoice_account_id,
+    )
+}
+
+/// Sends the amount from the invoice account if it is still linked or from the main account otherwise,
+/// returns the blockchain fee of the transaction
+pub(super) fn send_crypto_refund_tx<PC, AS>(
+    payments_client: PC,
+    account_service: AS,
+    tx_id: Uuid,
+    currency: Currency,
+    amount: Amount,
+    wallet_address: WalletAddress,
+    invoice_account_id: Option<AccountId>,
+) -> EventHandlerFuture<Amount>
+where
+    PC: PaymentsClient + Clone,
+    AS: AccountService,
+{
+    let ture_currency = match TureCurrency::try_from_currency(currency) {
+        Ok(ture_currency) => ture_currency,
+        Err(_) => {
+            let e = format_err!("Unsupported currency: {}", currency);
+            return Box::new(future::err(ectx!(err e, ErrorKind::Internal)));
+        }
+    };
+
+    let from_account_id = match invoice_account_id {
+        Some(account_id) => future::Either::A(future::ok(account_id.into_inner())),
+        None => future::Either::B(
+            account_service
+                .get_main_account(ture_currency)
+                .map(|AccountWithBalance { account, .. }| account.id.into_inner())
+                .map_err(ectx!(ErrorKind::Internal => ture_currency)),
+        ),
+    };
+
+    let input = GetFees {
+        currency: ture_currency,
+        account_address: wallet_address.clone().into_inner(),
+    };
+
+    let fut = payments_client
+        .get_fees(input.clone())
+        .map_err(ectx!(ErrorKind::Internal => input))
+        .map(move |FeesResponse { fees, .. }| {
+            // the cheapest fee is used, a refund is not urgent
+            fees.into_iter()
+                .map(|fee| fee.value)
+                .min()
+                .map(|value| Amount::from_super_unit(currency, value))
+                .unwrap_or(Amount::zero())
+        })
+        .join(from_account_id)
+        .and_then(move |(blockchain_fee, from_account_id)| {
+            let tx = CreateExternalTransaction {
+                id: tx_id,
+                from: from_account_id,
+                to: wallet_address,
+                amount,
+                currency: ture_currency,
+                fee: blockchain_fee,
+            };
+
+            payments_client
+                .create_external_transaction(tx.clone())
+                .map_err(ectx!(ErrorKind::Internal => tx))
+                .map(move |_| blockchain_fee)
+        });
+
+    Box::new(fut)
+}
@@ -0,0 +1,102 @@
+use chrono::{Duration as ChronoDuration, Utc};
+use diesel::{connection::AnsiTransactionManager, pg::Pg, Connection};
+use failure::{Error as FailureError, Fail};
+use futures::{future, Future, Stream};
+use r2d2::ManageConnection;
+use sentry::integrations::failure::capture_error;
+use std::time::{Duration, Instant};
+use stq_http::client::HttpClient;
+use tokio_timer::Interval;
+
+use client::{payments::PaymentsClient, saga::SagaClient, stores::StoresClient, stripe::StripeClient};
+use config;
+use repos::ReposFactory;
+use services::accounts::AccountService;
+
+use super::error::*;
+use super::{spawn_on_pool, EventHandler, EventHandlerFuture};
+
+impl<T, M, F, HC, PC, SC, STC, STRC, AS> EventHandler<T, M, F, HC, PC, SC, STC, STRC, AS>
+where
+    T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static,
+    M: ManageConnection<Connection = T>,
+    F: ReposFactory<T>,
+    HC: HttpClient + Clone,
+    PC: PaymentsClient + Clone,
+    SC: SagaClient + Clone,
+    STC: StoresClient + Clone,
+    STRC: StripeClient + Clone,
+    AS: AccountService + Clone + 'static,
+{
+    /// Periodically archives or deletes completed events according to the retention policy
+    pub fn run_retention(self, retention: config::EventRetention) -> impl Future<Item = (), Error = FailureError> {
+        let interval = Duration::new(retention.interval_sec.into(), 0);
+
+        Interval::new(Instant::now(), interval)
+            .map_err(ectx!(ErrorSource::TokioTimer, ErrorKind::Internal))
+            .fold(self, move |event_handler, _| {
+                event_handler.clone().apply_retention_policy(retention.clone()).then(|res| {
+                    if let Err(err) = res {
+                        let err = FailureError::from(err.context("An error occurred while applying event retention policy"));
+                        error!("{:?}", &err);
+                        capture_error(&err);
+                    }
+
+                    future::ok::<_, FailureError>(event_handler)
+                })
+            })
+            .map(|_| ())
+    }
+
+    fn apply_retention_policy(self, retention: config::EventRetention) -> EventHandlerFuture<()> {
+        let EventHandler {
+            db_pool,
+            cpu_pool,
+            repo_factory,
+            ..
+        } = self;
+
+        let config::EventRetention {
+            retention_days,
+            archive,
+            batch_size,
+            ..
+        } = retention;
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
+
+            let completed_before = Utc::now().naive_utc() - ChronoDuration::days(retention_days.into());
+
+            // events are processed in batches to keep every statement short
+            let mut total = 0;
+            loop {
+                let count = if archive {
+                    event_store_repo.archive_completed_events(completed_before, batch_size)
+                } else {
+                    event_store_repo.delete_completed_events(completed_before, batch_size)
+                }
+                .map_err(ectx!(try convert => completed_before, batch_size))?;
+
+                total += count;
+
+                if count < batch_size.max(1) as usize {
+                    break;
+                }
+            }
+
+            if total > 0 {
+                info!(
+                    "Event store retention: {} {} events completed before {}",
+                    if archive { "archived" } else { "deleted" },
+                    total,
+                    completed_before
+                );
+            } else {
+                trace!("Event store retention: no events completed before {}", completed_before);
+            }
+
+            Ok(())
+        })
+    }
+}
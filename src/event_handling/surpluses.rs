@@ -0,0 +1,280 @@
+use chrono::{Duration as ChronoDuration, Utc};
+use diesel::{connection::AnsiTransactionManager, pg::Pg, Connection};
+use failure::Fail;
+use futures::{future, Future};
+use r2d2::ManageConnection;
+use stq_http::client::HttpClient;
+use uuid::Uuid;
+
+use client::{payments::PaymentsClient, saga::SagaClient, stores::StoresClient, stripe::StripeClient};
+use models::invoice_v2::InvoiceId;
+use models::{
+    AccountId, Amount, Event, EventPayload, InvoiceSurplus, InvoiceSurplusId, InvoiceSurplusStatus, NewInvoiceSurplus, SurplusHandling,
+    TransactionId, TureCurrency, UpdateInvoiceSurplus,
+};
+use repos::ReposFactory;
+use services::accounts::AccountService;
+
+use super::error::*;
+use super::refunds::send_crypto_refund_tx;
+use super::{spawn_on_pool, EventHandler, EventHandlerFuture};
+
+impl<T, M, F, HC, PC, SC, STC, STRC, AS> EventHandler<T, M, F, HC, PC, SC, STC, STRC, AS>
+where
+    T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static,
+    M: ManageConnection<Connection = T>,
+    F: ReposFactory<T>,
+    HC: HttpClient + Clone,
+    PC: PaymentsClient + Clone,
+    SC: SagaClient + Clone,
+    STC: StoresClient + Clone,
+    STRC: StripeClient + Clone,
+    AS: AccountService + Clone + 'static,
+{
+    /// Records the amount the buyer paid above the invoice price, a surplus to be refunded is kept as a credit
+    /// if the buyer has no wallet in the invoice currency
+    pub fn handle_invoice_surplus_received(self, invoice_id: InvoiceId) -> EventHandlerFuture<()> {
+        let EventHandler {
+            db_pool,
+            cpu_pool,
+            repo_factory,
+            invoice_payment,
+            ..
+        } = self;
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let invoices_repo = repo_factory.create_invoices_v2_repo_with_sys_acl(&conn);
+            let invoice_surpluses_repo = repo_factory.create_invoice_surpluses_repo_with_sys_acl(&conn);
+            let user_wallets_repo = repo_factory.create_user_wallets_repo_with_sys_acl(&conn);
+            let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
+
+            let invoice = invoices_repo.get(invoice_id).map_err(ectx!(try convert => invoice_id))?.ok_or({
+                let e = format_err!("Invoice {} not found", invoice_id);
+                ectx!(try err e, ErrorKind::Internal)
+            })?;
+
+            if invoice.surplus_amount == Amount::zero() {
+                return Ok(());
+            }
+
+            if let Some(surplus) = invoice_surpluses_repo
+                .get_by_invoice_id(invoice_id)
+                .map_err(ectx!(try convert => invoice_id))?
+            {
+                info!("Surplus of invoice {} has already been recorded as {}", invoice_id, surplus.id);
+                return Ok(());
+            }
+
+            let wallet_address = match (
+                invoice_payment.surplus_handling,
+                TureCurrency::try_from_currency(invoice.buyer_currency),
+            ) {
+                (SurplusHandling::Refund, Ok(currency)) => {
+                    let buyer_user_id = invoice.buyer_user_id;
+                    user_wallets_repo
+                        .get_currency_wallets_by_user_id(currency, buyer_user_id)
+                        .map_err(ectx!(try convert => currency, buyer_user_id))?
+                        .into_iter()
+                        .next()
+                        .map(|wallet| wallet.address)
+                }
+                _ => None,
+            };
+
+            let new_surplus = match wallet_address {
+                Some(wallet_address) => NewInvoiceSurplus {
+                    id: InvoiceSurplusId::generate(),
+                    invoice_id,
+                    buyer_user_id: invoice.buyer_user_id,
+                    currency: invoice.buyer_currency,
+                    amount: invoice.surplus_amount,
+                    handling: SurplusHandling::Refund,
+                    status: InvoiceSurplusStatus::Pending,
+                    wallet_address: Some(wallet_address),
+                    transaction_id: Some(TransactionId::new(Uuid::new_v4())),
+                },
+                None => NewInvoiceSurplus {
+                    id: InvoiceSurplusId::generate(),
+                    invoice_id,
+                    buyer_user_id: invoice.buyer_user_id,
+                    currency: invoice.buyer_currency,
+                    amount: invoice.surplus_amount,
+                    handling: SurplusHandling::StoreCredit,
+                    status: InvoiceSurplusStatus::Credited,
+                    wallet_address: None,
+                    transaction_id: None,
+                },
+            };
+
+            conn.transaction::<_, Error, _>(|| {
+                let surplus = invoice_surpluses_repo
+                    .create(new_surplus.clone())
+                    .map_err(ectx!(try convert => new_surplus))?;
+
+                info!(
+                    "Surplus {} of invoice {} is handled as {}",
+                    surplus.amount, invoice_id, surplus.handling
+                );
+                if surplus.status == InvoiceSurplusStatus::Pending {
+                    let event = Event::new(EventPayload::InvoiceSurplusRefundInitiated { surplus_id: surplus.id });
+                    event_store_repo.add_event(event.clone()).map_err(ectx!(try convert => event))?;
+                }
+
+                Ok(())
+            })
+        })
+    }
+
+    /// Sends the invoice surplus back to the buyer wallet and marks it refunded once the transaction is confirmed
+    pub fn handle_invoice_surplus_refund_initiated(self, surplus_id: InvoiceSurplusId) -> EventHandlerFuture<()> {
+        let (payments_client, account_service) = match self.clone().get_ture_context() {
+            Ok((payments_client, account_service)) => (payments_client, account_service),
+            Err(e) => return Box::new(future::err(e)),
+        };
+
+        let fut = self
+            .clone()
+            .get_pending_surplus_refund(surplus_id)
+            .and_then(move |surplus| match surplus {
+                None => future::Either::A(future::ok(())),
+                Some((surplus, invoice_account_id)) => {
+                    future::Either::B(self.process_surplus_refund(payments_client, account_service, surplus, invoice_account_id))
+                }
+            });
+
+        Box::new(fut)
+    }
+
+    /// Returns the surplus together with the invoice account if the surplus refund is still pending
+    fn get_pending_surplus_refund(self, surplus_id: InvoiceSurplusId) -> EventHandlerFuture<Option<(InvoiceSurplus, Option<AccountId>)>> {
+        let EventHandler {
+            db_pool,
+            cpu_pool,
+            repo_factory,
+            ..
+        } = self;
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let invoice_surpluses_repo = repo_factory.create_invoice_surpluses_repo_with_sys_acl(&conn);
+            let invoices_repo = repo_factory.create_invoices_v2_repo_with_sys_acl(&conn);
+
+            let surplus = match invoice_surpluses_repo.get(surplus_id).map_err(ectx!(try convert => surplus_id))? {
+                Some(surplus) => surplus,
+                None => {
+                    info!("Invoice surplus refund handler: surplus with ID {} not found", surplus_id);
+                    return Ok(None);
+                }
+            };
+
+            if surplus.handling != SurplusHandling::Refund || surplus.status != InvoiceSurplusStatus::Pending {
+                info!(
+                    "Invoice surplus refund handler: surplus with ID {} is {} and {}",
+                    surplus_id, surplus.handling, surplus.status
+                );
+                return Ok(None);
+            }
+
+            let invoice_id = surplus.invoice_id;
+            let invoice = invoices_repo.get(invoice_id).map_err(ectx!(try convert => invoice_id))?.ok_or({
+                let e = format_err!("Invoice {} not found", invoice_id);
+                ectx!(try err e, ErrorKind::Internal)
+            })?;
+
+            Ok(Some((surplus, invoice.account_id)))
+        })
+    }
+
+    fn process_surplus_refund(
+        self,
+        payments_client: PC,
+        account_service: AS,
+        surplus: InvoiceSurplus,
+        invoice_account_id: Option<AccountId>,
+    ) -> EventHandlerFuture<()> {
+        let surplus_id = surplus.id;
+        let (wallet_address, tx_id) = match (surplus.wallet_address, surplus.transaction_id) {
+            (Some(wallet_address), Some(tx_id)) => (wallet_address, *tx_id.inner()),
+            _ => {
+                let e = format_err!("Invoice surplus {} has no wallet address or transaction ID", surplus_id);
+                return Box::new(future::err(ectx!(err e, ErrorKind::Internal)));
+            }
+        };
+        let currency = surplus.currency;
+        let amount = surplus.amount;
+
+        let fut = payments_client
+            .clone()
+            .get_transaction(tx_id)
+            .map_err(ectx!(ErrorKind::Internal => tx_id))
+            .and_then(move |tx| match tx {
+                None => future::Either::A(
+                    send_crypto_refund_tx(
+                        payments_client,
+                        account_service,
+                        tx_id,
+                        currency,
+                        amount,
+                        wallet_address,
+                        invoice_account_id,
+                    )
+                    .and_then(move |_| self.schedule_surplus_refund_check(surplus_id)),
+                ),
+                Some(tx) => future::Either::B(match tx.status.as_str() {
+                    "completed" | "done" => future::Either::A(self.set_surplus_status(surplus_id, InvoiceSurplusStatus::Refunded)),
+                    "failed" | "error" => {
+                        warn!("Invoice surplus {} transaction has failed", surplus_id);
+                        future::Either::A(self.set_surplus_status(surplus_id, InvoiceSurplusStatus::Failed))
+                    }
+                    status => {
+                        debug!(
+                            "Invoice surplus {} transaction {} is {}, checking again later",
+                            surplus_id, tx_id, status
+                        );
+                        future::Either::B(self.schedule_surplus_refund_check(surplus_id))
+                    }
+                }),
+            });
+
+        Box::new(fut)
+    }
+
+    fn schedule_surplus_refund_check(self, surplus_id: InvoiceSurplusId) -> EventHandlerFuture<()> {
+        let EventHandler {
+            db_pool,
+            cpu_pool,
+            repo_factory,
+            crypto_refund,
+            ..
+        } = self;
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
+
+            let scheduled_on = Utc::now().naive_utc() + ChronoDuration::seconds(crypto_refund.confirmation_check_interval_sec.into());
+            let event = Event::new(EventPayload::InvoiceSurplusRefundInitiated { surplus_id });
+            event_store_repo
+                .add_scheduled_event(event.clone(), scheduled_on)
+                .map_err(ectx!(convert => event, scheduled_on))
+                .map(|_| ())
+        })
+    }
+
+    fn set_surplus_status(self, surplus_id: InvoiceSurplusId, status: InvoiceSurplusStatus) -> EventHandlerFuture<()> {
+        let EventHandler {
+            db_pool,
+            cpu_pool,
+            repo_factory,
+            ..
+        } = self;
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let invoice_surpluses_repo = repo_factory.create_invoice_surpluses_repo_with_sys_acl(&conn);
+
+            let update_surplus = UpdateInvoiceSurplus { status: Some(status) };
+            invoice_surpluses_repo
+                .update(surplus_id, update_surplus.clone())
+                .map_err(ectx!(convert => surplus_id, update_surplus))
+                .map(|_| ())
+        })
+    }
+}
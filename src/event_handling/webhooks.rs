@@ -0,0 +1,188 @@
+use diesel::{connection::AnsiTransactionManager, pg::Pg, Connection};
+use failure::Fail;
+use futures::{future, Future};
+use hyper::{Headers, Method};
+use r2d2::ManageConnection;
+use stq_http::client::HttpClient;
+
+use client::{payments::PaymentsClient, saga::SagaClient, stores::StoresClient, stripe::StripeClient};
+use models::{
+    invoice_v2::InvoiceId, sign_webhook_payload, Event, EventPayload, NewWebhookDelivery, WebhookDeliveryStatus, WebhookEventType,
+    WebhookNotification, WebhookSubscriptionId,
+};
+use repos::ReposFactory;
+use services::accounts::AccountService;
+
+use super::error::*;
+use super::{spawn_on_pool, EventHandler, EventHandlerFuture};
+
+impl<T, M, F, HC, PC, SC, STC, STRC, AS> EventHandler<T, M, F, HC, PC, SC, STC, STRC, AS>
+where
+    T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static,
+    M: ManageConnection<Connection = T>,
+    F: ReposFactory<T>,
+    HC: HttpClient + Clone,
+    PC: PaymentsClient + Clone,
+    SC: SagaClient + Clone,
+    STC: StoresClient + Clone,
+    STRC: StripeClient + Clone,
+    AS: AccountService + Clone + 'static,
+{
+    /// Fans the notification out into a separate delivery event per subscription,
+    /// so that every endpoint is retried independently
+    pub fn handle_webhook_notification_created(self, notification: WebhookNotification) -> EventHandlerFuture<()> {
+        let EventHandler {
+            db_pool,
+            cpu_pool,
+            repo_factory,
+            ..
+        } = self;
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let webhook_subscriptions_repo = repo_factory.create_webhook_subscriptions_repo_with_sys_acl(&conn);
+            let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
+
+            let notification_id = notification.id;
+            let subscriptions = webhook_subscriptions_repo
+                .list_active()
+                .map_err(ectx!(try convert => notification_id))?;
+
+            for subscription in subscriptions.into_iter().filter(|s| s.is_subscribed_to(&notification)) {
+                let event = Event::new(EventPayload::WebhookDelivery {
+                    subscription_id: subscription.id,
+                    notification: notification.clone(),
+                });
+                event_store_repo.add_event(event.clone()).map_err(ectx!(try convert => event))?;
+            }
+
+            Ok(())
+        })
+    }
+
+    pub fn handle_webhook_delivery(
+        self,
+        subscription_id: WebhookSubscriptionId,
+        notification: WebhookNotification,
+    ) -> EventHandlerFuture<()> {
+        let fut = spawn_on_pool(self.db_pool.clone(), self.cpu_pool.clone(), {
+            let repo_factory = self.repo_factory.clone();
+            move |conn| {
+                let webhook_subscriptions_repo = repo_factory.create_webhook_subscriptions_repo_with_sys_acl(&conn);
+                webhook_subscriptions_repo
+                    .get(subscription_id)
+                    .map_err(ectx!(convert => subscription_id))
+            }
+        })
+        .and_then(move |subscription| match subscription {
+            Some(ref subscription) if subscription.is_active => future::Either::A(future::lazy({
+                let subscription = subscription.clone();
+                move || self.deliver_webhook(subscription.id, subscription.url, subscription.secret, notification)
+            })),
+            _ => {
+                info!(
+                    "Webhook subscription {} has been deleted or deactivated, skipping notification {}",
+                    subscription_id, notification.id
+                );
+                future::Either::B(future::ok(()))
+            }
+        });
+
+        Box::new(fut)
+    }
+
+    fn deliver_webhook(
+        self,
+        subscription_id: WebhookSubscriptionId,
+        url: String,
+        secret: String,
+        notification: WebhookNotification,
+    ) -> EventHandlerFuture<()> {
+        let notification_id = notification.id;
+
+        let body = match serde_json::to_string(&notification) {
+            Ok(body) => body,
+            Err(e) => {
+                return Box::new(future::err(
+                    ectx!(err e, ErrorSource::SerdeJson, ErrorKind::Internal => notification_id),
+                ))
+            }
+        };
+
+        let mut headers = Headers::new();
+        headers.set_raw("content-type", "application/json");
+        headers.set_raw("x-billing-event", notification.event_type.to_string());
+        headers.set_raw("x-billing-delivery", notification_id.to_string());
+        headers.set_raw("x-billing-signature", format!("sha256={}", sign_webhook_payload(&secret, &body)));
+
+        let EventHandler {
+            db_pool,
+            cpu_pool,
+            repo_factory,
+            http_client,
+            ..
+        } = self;
+
+        let fut = http_client
+            .request(Method::Post, url.clone(), Some(body), Some(headers))
+            .map(|_| ())
+            .then(move |result| {
+                let (status, error_message) = match result {
+                    Ok(_) => (WebhookDeliveryStatus::Delivered, None),
+                    Err(ref e) => (WebhookDeliveryStatus::Failed, Some(e.to_string())),
+                };
+
+                spawn_on_pool(db_pool, cpu_pool, move |conn| {
+                    let webhook_deliveries_repo = repo_factory.create_webhook_deliveries_repo_with_sys_acl(&conn);
+                    let new_delivery = NewWebhookDelivery {
+                        subscription_id,
+                        notification_id,
+                        event_type: notification.event_type,
+                        payload: serde_json::to_value(&notification).unwrap_or_default(),
+                        status,
+                        error_message,
+                    };
+
+                    webhook_deliveries_repo
+                        .create(new_delivery.clone())
+                        .map_err(ectx!(convert => new_delivery))
+                        .map(|_| ())
+                })
+                // the failed attempt is returned as an error, so the event store retries the delivery
+                .and_then(move |_| result.map_err(ectx!(ErrorSource::StqHttp, ErrorKind::Internal => Method::Post, url)))
+            });
+
+        Box::new(fut)
+    }
+
+    pub(super) fn notify_invoice(self, invoice_id: InvoiceId, event_type: WebhookEventType) -> EventHandlerFuture<()> {
+        let EventHandler {
+            db_pool,
+            cpu_pool,
+            repo_factory,
+            ..
+        } = self;
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let invoices_repo = repo_factory.create_invoices_v2_repo_with_sys_acl(&conn);
+            let orders_repo = repo_factory.create_orders_repo_with_sys_acl(&conn);
+            let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
+
+            let invoice = invoices_repo.get(invoice_id).map_err(ectx!(try convert => invoice_id))?.ok_or({
+                let e = format_err!("Invoice {} not found", invoice_id);
+                ectx!(try err e, ErrorKind::Internal)
+            })?;
+
+            let orders = orders_repo
+                .get_many_by_invoice_id(invoice_id)
+                .map_err(ectx!(try convert => invoice_id))?;
+
+            let event = Event::new(EventPayload::WebhookNotificationCreated {
+                notification: WebhookNotification::for_invoice(event_type, &invoice, &orders),
+            });
+            event_store_repo
+                .add_event(event.clone())
+                .map_err(ectx!(convert => event))
+                .map(|_| ())
+        })
+    }
+}
@@ -86,7 +86,9 @@ use hyper::server::Http;
 use r2d2_diesel::ConnectionManager;
 use r2d2_redis::RedisConnectionManager;
 use stq_cache::cache::{redis::RedisCache, Cache, NullCache, TypedCache};
+use stq_http::client::ClientHandle;
 use stq_http::controller::Application;
+use stq_types::BillingRole;
 use tokio_core::reactor::Core;
 
 use client::{
@@ -99,11 +101,14 @@ use config::Config;
 use controller::context::StaticContext;
 use errors::Error;
 use event_handling::EventHandler;
+use models::EventRetryPolicies;
 use repos::acl::RolesCacheImpl;
-use repos::repo_factory::ReposFactoryImpl;
+use repos::repo_factory::{ReposFactory, ReposFactoryImpl};
 use services::accounts::{AccountService, AccountServiceImpl};
 use std::thread;
 
+type PaymentsContext = (Arc<dyn PaymentsClient>, Arc<dyn AccountService + Send + Sync>);
+
 /// Starts new web service from provided `Config`
 pub fn start_server<F: FnOnce() + 'static>(config: Config, port: &Option<String>, callback: F) {
     // Prepare reactor
@@ -125,43 +130,12 @@ pub fn start_server<F: FnOnce() + 'static>(config: Config, port: &Option<String>
     };
 
     // Prepare database pool
-    let database_url: String = config.server.database.parse().expect("Database URL must be set in configuration");
-    let db_manager = ConnectionManager::<PgConnection>::new(database_url);
-    let db_pool = r2d2::Pool::builder()
-        .build(db_manager)
-        .expect("Failed to create DB connection pool");
+    let db_pool = create_db_pool(&config);
 
     // Prepare CPU pool
     let cpu_pool = CpuPool::new(thread_count);
 
-    // Prepare cache
-    let roles_cache = match &config.server.redis {
-        Some(redis_url) => {
-            // Prepare Redis pool
-            let redis_url: String = redis_url.parse().expect("Redis URL must be set in configuration");
-            let redis_manager = RedisConnectionManager::new(redis_url.as_ref()).expect("Failed to create Redis connection manager");
-            let redis_pool = r2d2::Pool::builder()
-                .build(redis_manager)
-                .expect("Failed to create Redis connection pool");
-
-            let ttl = Duration::from_secs(config.server.cache_ttl_sec);
-
-            let roles_cache_backend = Box::new(TypedCache::new(
-                RedisCache::new(redis_pool.clone(), "roles".to_string()).with_ttl(ttl),
-            )) as Box<dyn Cache<_, Error = _> + Send + Sync>;
-
-            RolesCacheImpl::new(roles_cache_backend)
-        }
-        None => RolesCacheImpl::new(Box::new(NullCache::new()) as Box<_>),
-    };
-
-    let config::EventStore {
-        max_processing_attempts,
-        stuck_threshold_sec,
-        polling_rate_sec,
-    } = config.event_store.clone();
-
-    let repo_factory = ReposFactoryImpl::new(roles_cache, max_processing_attempts, stuck_threshold_sec);
+    let repo_factory = create_repo_factory(&config);
 
     let context = StaticContext::new(
         db_pool.clone(),
@@ -171,48 +145,13 @@ pub fn start_server<F: FnOnce() + 'static>(config: Config, port: &Option<String>
         repo_factory.clone(),
     );
 
-    let payments_ctx = config.payments.clone().map(|payments_config| {
-        let payments_client =
-            PaymentsClientImpl::create_from_config(client_handle.clone(), payments::Config::from(payments_config.clone()))
-                .expect("Failed to create Payments client");
-
-        let account_service = AccountServiceImpl::new(
-            db_pool.clone(),
-            cpu_pool.clone(),
-            repo_factory.clone(),
-            payments_config.min_pooled_accounts,
-            payments_client.clone(),
-            format!("{}{}", config.callback.url, controller::routes::PAYMENTS_CALLBACK_ENDPOINT),
-            payments_config.accounts.into(),
-        );
-
-        let payments_client = Arc::new(payments_client) as Arc<dyn PaymentsClient>;
-        let account_service = Arc::new(account_service) as Arc<dyn AccountService + Send + Sync>;
-
-        (payments_client, account_service)
-    });
-
-    let payments_mock_cfg = config.payments_mock.clone();
-    let payments_ctx = if payments_mock_cfg.use_mock {
-        let payments_client = MockPaymentsClient::default();
-
-        let account_service = AccountServiceImpl::new(
-            db_pool.clone(),
-            cpu_pool.clone(),
-            repo_factory.clone(),
-            payments_mock_cfg.min_pooled_accounts,
-            payments_client.clone(),
-            format!("{}{}", config.callback.url, controller::routes::PAYMENTS_CALLBACK_ENDPOINT),
-            payments_mock_cfg.accounts.into(),
-        );
-
-        let payments_client = Arc::new(payments_client) as Arc<dyn PaymentsClient>;
-        let account_service = Arc::new(account_service) as Arc<dyn AccountService + Send + Sync>;
-
-        Some((payments_client, account_service))
-    } else {
-        payments_ctx
-    };
+    let payments_ctx = create_payments_context(
+        &config,
+        db_pool.clone(),
+        cpu_pool.clone(),
+        repo_factory.clone(),
+        client_handle.clone(),
+    );
 
     match payments_ctx.as_ref() {
         None => {
@@ -231,26 +170,29 @@ pub fn start_server<F: FnOnce() + 'static>(config: Config, port: &Option<String>
         }
     };
 
-    let event_handler = EventHandler {
-        db_pool: db_pool.clone(),
-        cpu_pool: cpu_pool.clone(),
-        repo_factory: repo_factory.clone(),
-        http_client: client_handle.clone(),
-        payments_client: payments_ctx.as_ref().map(|(payments_client, _)| payments_client.clone()),
-        account_service: payments_ctx.as_ref().map(|(_, account_service)| account_service.clone()),
-        saga_client: SagaClientImpl::new(client_handle.clone(), config.saga_addr.url.clone()),
-        stores_client: StoresClientImpl::new(client_handle.clone(), config.stores_microservice.url.clone()),
-        stripe_client: StripeClientImpl::create_from_config(&config),
-        fee: config.fee,
-    };
-
-    thread::spawn(move || {
-        info!("Event processor is now running");
-        let mut core = Core::new().expect("Failed to create a Tokio core for the event processor");
-        let polling_rate = Duration::new(polling_rate_sec.into(), 0);
-        core.run(EventHandler::run(event_handler, polling_rate))
+    if config.server.process_events {
+        let config = config.clone();
+        let db_pool = db_pool.clone();
+        let cpu_pool = cpu_pool.clone();
+        let repo_factory = repo_factory.clone();
+        let client_handle = client_handle.clone();
+
+        thread::spawn(move || {
+            info!("Event processor is now running");
+            let mut core = Core::new().expect("Failed to create a Tokio core for the event processor");
+            core.run(create_event_processor(
+                &config,
+                db_pool,
+                cpu_pool,
+                repo_factory,
+                client_handle,
+                payments_ctx,
+            ))
             .expect("Fatal error occurred in the event processor");
-    });
+        });
+    } else {
+        info!("In-process event handling is disabled - events are expected to be processed by billing-worker");
+    }
 
     let serve = Http::new()
         .serve_addr_handle(&address, &handle, move || {
@@ -287,3 +229,174 @@ pub fn start_server<F: FnOnce() + 'static>(config: Config, port: &Option<String>
     }))
     .unwrap();
 }
+
+/// Starts the event processor without the HTTP server from provided `Config`.
+/// System accounts are initialized by the HTTP server, so it is expected to be deployed as well.
+pub fn start_event_worker(config: Config) {
+    let mut core = Core::new().expect("Unexpected error creating event loop core");
+    let handle = Arc::new(core.handle());
+
+    let client = stq_http::client::Client::new(&config.to_http_config(), &handle);
+    let client_handle = client.handle();
+    let client_stream = client.stream();
+    handle.spawn(client_stream.for_each(|_| Ok(())));
+
+    let db_pool = create_db_pool(&config);
+    let cpu_pool = CpuPool::new(config.server.thread_count);
+    let repo_factory = create_repo_factory(&config);
+    let payments_ctx = create_payments_context(
+        &config,
+        db_pool.clone(),
+        cpu_pool.clone(),
+        repo_factory.clone(),
+        client_handle.clone(),
+    );
+
+    info!("Event processor is now running");
+    let event_processor = create_event_processor(&config, db_pool, cpu_pool, repo_factory, client_handle, payments_ctx);
+    handle.spawn(event_processor.map_err(|err| {
+        error!("Fatal error occurred in the event processor: {:?}", err);
+        process::exit(1);
+    }));
+
+    core.run(tokio_signal::ctrl_c().flatten_stream().take(1u64).for_each(|()| {
+        info!("Ctrl+C received. Exit");
+        Ok(())
+    }))
+    .unwrap();
+}
+
+fn create_db_pool(config: &Config) -> r2d2::Pool<ConnectionManager<PgConnection>> {
+    let database_url: String = config.server.database.parse().expect("Database URL must be set in configuration");
+    let db_manager = ConnectionManager::<PgConnection>::new(database_url);
+    r2d2::Pool::builder()
+        .build(db_manager)
+        .expect("Failed to create DB connection pool")
+}
+
+fn create_repo_factory(config: &Config) -> ReposFactoryImpl<impl Cache<Vec<BillingRole>> + Send + Sync + 'static> {
+    // Prepare cache
+    let roles_cache = match &config.server.redis {
+        Some(redis_url) => {
+            // Prepare Redis pool
+            let redis_url: String = redis_url.parse().expect("Redis URL must be set in configuration");
+            let redis_manager = RedisConnectionManager::new(redis_url.as_ref()).expect("Failed to create Redis connection manager");
+            let redis_pool = r2d2::Pool::builder()
+                .build(redis_manager)
+                .expect("Failed to create Redis connection pool");
+
+            let ttl = Duration::from_secs(config.server.cache_ttl_sec);
+
+            let roles_cache_backend = Box::new(TypedCache::new(
+                RedisCache::new(redis_pool.clone(), "roles".to_string()).with_ttl(ttl),
+            )) as Box<dyn Cache<_, Error = _> + Send + Sync>;
+
+            RolesCacheImpl::new(roles_cache_backend)
+        }
+        None => RolesCacheImpl::new(Box::new(NullCache::new()) as Box<_>),
+    };
+
+    let config::EventStore {
+        max_processing_attempts,
+        stuck_threshold_sec,
+        ..
+    } = config.event_store.clone();
+
+    let event_retry_policies = EventRetryPolicies::from(config.event_store.clone());
+
+    ReposFactoryImpl::new(roles_cache, max_processing_attempts, stuck_threshold_sec, event_retry_policies)
+}
+
+fn create_payments_context<F>(
+    config: &Config,
+    db_pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    cpu_pool: CpuPool,
+    repo_factory: F,
+    client_handle: ClientHandle,
+) -> Option<PaymentsContext>
+where
+    F: ReposFactory<PgConnection>,
+{
+    let payments_mock_cfg = config.payments_mock.clone();
+    if payments_mock_cfg.use_mock {
+        let payments_client = MockPaymentsClient::default();
+
+        let account_service = AccountServiceImpl::new(
+            db_pool,
+            cpu_pool,
+            repo_factory,
+            payments_mock_cfg.min_pooled_accounts,
+            payments_client.clone(),
+            format!("{}{}", config.callback.url, controller::routes::PAYMENTS_CALLBACK_ENDPOINT),
+            payments_mock_cfg.accounts.into(),
+        );
+
+        let payments_client = Arc::new(payments_client) as Arc<dyn PaymentsClient>;
+        let account_service = Arc::new(account_service) as Arc<dyn AccountService + Send + Sync>;
+
+        return Some((payments_client, account_service));
+    }
+
+    config.payments.clone().map(|payments_config| {
+        let payments_client = PaymentsClientImpl::create_from_config(client_handle, payments::Config::from(payments_config.clone()))
+            .expect("Failed to create Payments client");
+
+        let account_service = AccountServiceImpl::new(
+            db_pool,
+            cpu_pool,
+            repo_factory,
+            payments_config.min_pooled_accounts,
+            payments_client.clone(),
+            format!("{}{}", config.callback.url, controller::routes::PAYMENTS_CALLBACK_ENDPOINT),
+            payments_config.accounts.into(),
+        );
+
+        let payments_client = Arc::new(payments_client) as Arc<dyn PaymentsClient>;
+        let account_service = Arc::new(account_service) as Arc<dyn AccountService + Send + Sync>;
+
+        (payments_client, account_service)
+    })
+}
+
+fn create_event_processor<F>(
+    config: &Config,
+    db_pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    cpu_pool: CpuPool,
+    repo_factory: F,
+    client_handle: ClientHandle,
+    payments_ctx: Option<PaymentsContext>,
+) -> impl Future<Item = (), Error = failure::Error>
+where
+    F: ReposFactory<PgConnection>,
+{
+    let event_handler = EventHandler {
+        db_pool,
+        cpu_pool,
+        repo_factory,
+        http_client: client_handle.clone(),
+        payments_client: payments_ctx.as_ref().map(|(payments_client, _)| payments_client.clone()),
+        account_service: payments_ctx.as_ref().map(|(_, account_service)| account_service.clone()),
+        saga_client: SagaClientImpl::new(client_handle.clone(), config.saga_addr.url.clone()),
+        stores_client: StoresClientImpl::new(client_handle.clone(), config.stores_microservice.url.clone()),
+        stripe_client: StripeClientImpl::create_from_config(config),
+        fee: config.fee.clone(),
+        crypto_refund: config.crypto_refund.clone(),
+        capture_sla: config.capture_sla.clone(),
+        escrow: config.escrow.clone(),
+        invoice_payment: config.invoice_payment.clone(),
+        documents: config.documents.clone(),
+        batch_size: config.event_store.batch_size,
+        worker_instance: event_handling::worker_instance(),
+    };
+
+    let polling_rate = Duration::new(config.event_store.polling_rate_sec.into(), 0);
+    let retention = config.event_store.retention.clone();
+
+    let retention_fut = if retention.enabled {
+        future::Either::A(event_handler.clone().run_retention(retention))
+    } else {
+        future::Either::B(future::ok(()))
+    };
+
+    EventHandler::run(event_handler, polling_rate).join(retention_fut).map(|_| ())
+}
@@ -22,6 +22,14 @@ pub enum Resource {
     PaymentIntentFee,
     UserWallet,
     Payout,
+    EventStore,
+    WebhookSubscription,
+    Refund,
+    OrderStateTransition,
+    StoreCapturePolicy,
+    Dispute,
+    InvoiceSurplus,
+    Document,
 }
 
 impl fmt::Display for Resource {
@@ -46,6 +54,14 @@ impl fmt::Display for Resource {
             Resource::PaymentIntentFee => write!(f, "payment_intent_fee"),
             Resource::UserWallet => write!(f, "user wallet"),
             Resource::Payout => write!(f, "payout"),
+            Resource::EventStore => write!(f, "event store"),
+            Resource::WebhookSubscription => write!(f, "webhook subscription"),
+            Resource::Refund => write!(f, "refund"),
+            Resource::OrderStateTransition => write!(f, "order state transition"),
+            Resource::StoreCapturePolicy => write!(f, "store capture policy"),
+            Resource::Dispute => write!(f, "dispute"),
+            Resource::InvoiceSurplus => write!(f, "invoice surplus"),
+            Resource::Document => write!(f, "document"),
         }
     }
 }
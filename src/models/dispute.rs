@@ -0,0 +1,193 @@
+use std::fmt::{self, Display};
+
+use bigdecimal::BigDecimal;
+use chrono::NaiveDateTime;
+use uuid::Uuid;
+
+use models::order_v2::OrderId;
+use models::{Amount, ChargeId, Currency, PaymentState, PayoutId};
+use schema::disputes;
+
+#[derive(Clone, Copy, Debug, Display, PartialEq, Eq, From, FromStr, Hash, Serialize, Deserialize, DieselTypes)]
+pub struct DisputeId(Uuid);
+
+impl DisputeId {
+    pub fn new(id: Uuid) -> Self {
+        DisputeId(id)
+    }
+
+    pub fn inner(&self) -> &Uuid {
+        &self.0
+    }
+
+    pub fn generate() -> Self {
+        DisputeId(Uuid::new_v4())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, DieselTypes, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DisputeStatus {
+    /// The dispute is not resolved yet, evidence may still be submitted
+    Open,
+    Won,
+    Lost,
+}
+
+impl Display for DisputeStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DisputeStatus::Open => write!(f, "Open"),
+            DisputeStatus::Won => write!(f, "Won"),
+            DisputeStatus::Lost => write!(f, "Lost"),
+        }
+    }
+}
+
+/// Part of a Stripe dispute related to one order, a charge paying for several orders is disputed for each of them
+#[derive(Clone, Debug, Deserialize, Serialize, Queryable)]
+pub struct Dispute {
+    pub id: DisputeId,
+    pub stripe_dispute_id: String,
+    pub order_id: OrderId,
+    pub charge_id: ChargeId,
+    /// Disputed amount of the order
+    pub amount: Amount,
+    pub currency: Currency,
+    pub reason: Option<String>,
+    pub status: DisputeStatus,
+    /// Evidence has to be submitted to Stripe before this time
+    pub evidence_due_by: Option<NaiveDateTime>,
+    /// State the order returns to once the dispute is closed
+    pub previous_state: PaymentState,
+    /// Amount taken back from the seller balance because the dispute was lost after the order had been paid out
+    pub balance_adjustment: Amount,
+    pub closed_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    /// Payout the balance adjustment was deducted from, the adjustment is taken from the balance until then
+    pub settled_payout_id: Option<PayoutId>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Insertable)]
+#[table_name = "disputes"]
+pub struct NewDispute {
+    pub id: DisputeId,
+    pub stripe_dispute_id: String,
+    pub order_id: OrderId,
+    pub charge_id: ChargeId,
+    pub amount: Amount,
+    pub currency: Currency,
+    pub reason: Option<String>,
+    pub status: DisputeStatus,
+    pub evidence_due_by: Option<NaiveDateTime>,
+    pub previous_state: PaymentState,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, AsChangeset)]
+#[table_name = "disputes"]
+pub struct UpdateDispute {
+    pub reason: Option<String>,
+    pub status: Option<DisputeStatus>,
+    pub evidence_due_by: Option<NaiveDateTime>,
+    pub balance_adjustment: Option<Amount>,
+    pub closed_at: Option<NaiveDateTime>,
+}
+
+/// Order paid by a disputed charge, its seller amount divided by `exchange_rate` is the amount in the charge currency
+#[derive(Clone, Debug)]
+pub struct DisputedOrder {
+    pub order_id: OrderId,
+    pub seller_currency: Currency,
+    pub charged_amount: Amount,
+    pub exchange_rate: BigDecimal,
+}
+
+/// Splits the amount disputed in the charge currency between the orders paid by the charge, in the given order,
+/// each order takes at most its charged amount. The shares are returned in the seller currency of each order
+pub fn split_disputed_amount(charge_currency: Currency, disputed_amount: Amount, orders: &[DisputedOrder]) -> Vec<(OrderId, Amount)> {
+    let mut remaining_amount = disputed_amount;
+    let mut shares = Vec::new();
+
+    for order in orders {
+        if remaining_amount == Amount::zero() {
+            break;
+        }
+
+        let charged_amount = Amount::from_super_unit(
+            charge_currency,
+            order.charged_amount.to_super_unit(order.seller_currency) / order.exchange_rate.clone(),
+        );
+
+        let (share, seller_share) = if charged_amount <= remaining_amount {
+            (charged_amount, order.charged_amount)
+        } else {
+            let seller_share = Amount::from_super_unit(
+                order.seller_currency,
+                remaining_amount.to_super_unit(charge_currency) * order.exchange_rate.clone(),
+            );
+            (remaining_amount, seller_share)
+        };
+
+        remaining_amount = remaining_amount.checked_sub(share).unwrap_or(Amount::zero());
+        if seller_share != Amount::zero() {
+            shares.push((order.order_id, seller_share));
+        }
+    }
+
+    shares
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn disputed_order(seller_currency: Currency, charged_amount: u128, exchange_rate: u32) -> DisputedOrder {
+        DisputedOrder {
+            order_id: OrderId::generate(),
+            seller_currency,
+            charged_amount: Amount::new(charged_amount),
+            exchange_rate: BigDecimal::from(exchange_rate),
+        }
+    }
+
+    #[test]
+    fn split_disputed_amount_takes_orders_in_turn() {
+        let orders = vec![disputed_order(Currency::Eur, 1000, 1), disputed_order(Currency::Eur, 500, 1)];
+
+        let shares = split_disputed_amount(Currency::Eur, Amount::new(1200), &orders);
+
+        assert_eq!(
+            shares,
+            vec![(orders[0].order_id, Amount::new(1000)), (orders[1].order_id, Amount::new(200))]
+        );
+    }
+
+    #[test]
+    fn split_disputed_amount_is_capped_by_charged_amounts() {
+        let orders = vec![disputed_order(Currency::Eur, 1000, 1), disputed_order(Currency::Eur, 500, 1)];
+
+        let shares = split_disputed_amount(Currency::Eur, Amount::new(2000), &orders);
+
+        assert_eq!(
+            shares,
+            vec![(orders[0].order_id, Amount::new(1000)), (orders[1].order_id, Amount::new(500))]
+        );
+    }
+
+    #[test]
+    fn split_disputed_amount_converts_charge_currency_to_seller_currency() {
+        // 100 EUR at 4000 EUR per BTC is charged as 0.025 BTC
+        let orders = vec![
+            disputed_order(Currency::Eur, 10000, 4000),
+            disputed_order(Currency::Eur, 10000, 4000),
+        ];
+
+        let shares = split_disputed_amount(Currency::Btc, Amount::new(3_500_000), &orders);
+
+        assert_eq!(
+            shares,
+            vec![(orders[0].order_id, Amount::new(10000)), (orders[1].order_id, Amount::new(4000))]
+        );
+    }
+}
@@ -0,0 +1,198 @@
+use std::fmt::{self, Display};
+
+use chrono::NaiveDateTime;
+use uuid::Uuid;
+
+use stq_types::StoreId;
+
+use models::invoice_v2::InvoiceId;
+use models::{FeeId, UserId};
+use schema::documents;
+
+#[derive(Clone, Copy, Debug, Display, PartialEq, Eq, From, FromStr, Hash, Serialize, Deserialize, DieselTypes)]
+pub struct DocumentId(Uuid);
+
+impl DocumentId {
+    pub fn new(id: Uuid) -> Self {
+        DocumentId(id)
+    }
+
+    pub fn inner(&self) -> &Uuid {
+        &self.0
+    }
+
+    pub fn generate() -> Self {
+        DocumentId(Uuid::new_v4())
+    }
+}
+
+/// Kind of a printable document, each kind is numbered separately for every issuer
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, DieselTypes, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DocumentKind {
+    /// Issued by the store to the buyer for the orders of a paid invoice
+    Receipt,
+    /// Issued by the marketplace to the store for a paid fee
+    FeeInvoice,
+}
+
+impl DocumentKind {
+    fn number_prefix(&self) -> &'static str {
+        match self {
+            DocumentKind::Receipt => "R",
+            DocumentKind::FeeInvoice => "F",
+        }
+    }
+
+    /// Documents issued by the marketplace are numbered in one sequence for all stores
+    pub fn is_issued_by_marketplace(&self) -> bool {
+        match self {
+            DocumentKind::Receipt => false,
+            DocumentKind::FeeInvoice => true,
+        }
+    }
+}
+
+impl Display for DocumentKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DocumentKind::Receipt => write!(f, "Receipt"),
+            DocumentKind::FeeInvoice => write!(f, "FeeInvoice"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, DieselTypes, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DocumentFormat {
+    Pdf,
+    /// Stored when the document could not be converted to PDF
+    Html,
+}
+
+impl DocumentFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            DocumentFormat::Pdf => "application/pdf",
+            DocumentFormat::Html => "text/html; charset=utf-8",
+        }
+    }
+}
+
+impl Display for DocumentFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DocumentFormat::Pdf => write!(f, "Pdf"),
+            DocumentFormat::Html => write!(f, "Html"),
+        }
+    }
+}
+
+/// Printable receipt or invoice, `number` is sequential per issuer and document kind
+#[derive(Clone, Queryable)]
+pub struct Document {
+    pub id: DocumentId,
+    pub kind: DocumentKind,
+    pub store_id: StoreId,
+    pub invoice_id: Option<InvoiceId>,
+    pub fee_id: Option<FeeId>,
+    pub buyer_user_id: Option<UserId>,
+    pub number: i64,
+    /// Number printed on the document, e.g. "R-42-000007"
+    pub document_number: String,
+    pub format: DocumentFormat,
+    pub content: Vec<u8>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+impl fmt::Debug for Document {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Document")
+            .field("id", &self.id)
+            .field("kind", &self.kind)
+            .field("store_id", &self.store_id)
+            .field("invoice_id", &self.invoice_id)
+            .field("fee_id", &self.fee_id)
+            .field("document_number", &self.document_number)
+            .field("format", &self.format)
+            .finish()
+    }
+}
+
+#[derive(Clone, Insertable)]
+#[table_name = "documents"]
+pub struct NewDocument {
+    pub id: DocumentId,
+    pub kind: DocumentKind,
+    pub store_id: StoreId,
+    pub invoice_id: Option<InvoiceId>,
+    pub fee_id: Option<FeeId>,
+    pub buyer_user_id: Option<UserId>,
+    pub number: i64,
+    pub document_number: String,
+    pub format: DocumentFormat,
+    pub content: Vec<u8>,
+}
+
+impl fmt::Debug for NewDocument {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("NewDocument")
+            .field("id", &self.id)
+            .field("kind", &self.kind)
+            .field("store_id", &self.store_id)
+            .field("invoice_id", &self.invoice_id)
+            .field("fee_id", &self.fee_id)
+            .field("document_number", &self.document_number)
+            .field("format", &self.format)
+            .finish()
+    }
+}
+
+/// Formats the number printed on the document, e.g. "R-42-000007" for the 7th receipt of store 42
+/// or "F-000007" for the 7th fee invoice of the marketplace
+pub fn format_document_number(kind: DocumentKind, store_id: StoreId, number: i64) -> String {
+    if kind.is_issued_by_marketplace() {
+        format!("{}-{:06}", kind.number_prefix(), number)
+    } else {
+        format!("{}-{}-{:06}", kind.number_prefix(), store_id, number)
+    }
+}
+
+/// The buyer and the managers of the store may read the document
+#[derive(Clone, Debug)]
+pub struct DocumentAccess {
+    pub store_id: StoreId,
+    pub buyer_user_id: Option<UserId>,
+}
+
+impl<'a> From<&'a Document> for DocumentAccess {
+    fn from(document: &'a Document) -> Self {
+        DocumentAccess {
+            store_id: document.store_id,
+            buyer_user_id: document.buyer_user_id,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn receipts_are_numbered_per_store() {
+        assert!(!DocumentKind::Receipt.is_issued_by_marketplace());
+        assert_eq!(format_document_number(DocumentKind::Receipt, StoreId(42), 7), "R-42-000007");
+        assert_eq!(format_document_number(DocumentKind::Receipt, StoreId(1), 1234567), "R-1-1234567");
+    }
+
+    #[test]
+    fn fee_invoices_are_numbered_by_the_marketplace() {
+        assert!(DocumentKind::FeeInvoice.is_issued_by_marketplace());
+        assert_eq!(format_document_number(DocumentKind::FeeInvoice, StoreId(42), 7), "F-000007");
+        assert_eq!(
+            format_document_number(DocumentKind::FeeInvoice, StoreId(42), 7),
+            format_document_number(DocumentKind::FeeInvoice, StoreId(43), 7)
+        );
+    }
+}
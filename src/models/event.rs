@@ -1,11 +1,11 @@
 use diesel::sql_types::Uuid as SqlUuid;
 use std::fmt;
-use stripe::PaymentIntent;
+use stripe::{Dispute as StripeDispute, PaymentIntent};
 use uuid::Uuid;
 
 use models::invoice_v2::InvoiceId;
 use models::order_v2::OrderId;
-use models::PayoutId;
+use models::{AccountId, Amount, EventEntryId, FeeId, InvoiceSurplusId, PayoutId, RefundId, WebhookNotification, WebhookSubscriptionId};
 
 #[derive(Debug, Serialize, Deserialize, FromSqlRow, AsExpression, Clone, Copy, PartialEq, Eq, FromStr)]
 #[sql_type = "SqlUuid"]
@@ -36,6 +36,9 @@ impl fmt::Display for EventId {
 pub struct Event {
     pub id: EventId,
     pub payload: EventPayload,
+    /// ID of the event store entry this event is a replay of
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replay_of: Option<EventEntryId>,
 }
 
 impl Event {
@@ -43,20 +46,142 @@ impl Event {
         Self {
             id: EventId::generate(),
             payload,
+            replay_of: None,
+        }
+    }
+
+    pub fn new_replay(original_event_entry_id: EventEntryId, payload: EventPayload) -> Self {
+        Self {
+            id: EventId::generate(),
+            payload,
+            replay_of: Some(original_event_entry_id),
         }
     }
 }
 
+/// Payload types that may be replayed. Handlers of the other payload types capture, refund, pay out
+/// or transfer money and do not check whether this has already been done
+pub const REPLAYABLE_PAYLOAD_TYPES: &[&str] = &[
+    "NoOp",
+    "InvoicePaid",
+    "PaymentIntentPaymentFailed",
+    "PaymentIntentAmountCapturableUpdated",
+    "PaymentIntentSucceeded",
+    "ChargeDisputeCreated",
+    "ChargeDisputeUpdated",
+    "InvoiceReceiptsDue",
+    "FeeInvoiceDue",
+    "WebhookNotificationCreated",
+    "WebhookDelivery",
+];
+
 #[derive(Clone, Serialize, Deserialize)]
 pub enum EventPayload {
     NoOp,
-    InvoicePaid { invoice_id: InvoiceId },
-    PaymentIntentPaymentFailed { payment_intent: PaymentIntent },
-    PaymentIntentAmountCapturableUpdated { payment_intent: PaymentIntent },
-    PaymentIntentSucceeded { payment_intent: PaymentIntent },
-    PaymentIntentCapture { order_id: OrderId },
-    PaymentExpired { invoice_id: InvoiceId },
-    PayoutInitiated { payout_id: PayoutId },
+    InvoicePaid {
+        invoice_id: InvoiceId,
+    },
+    PaymentIntentPaymentFailed {
+        payment_intent: PaymentIntent,
+        #[serde(default)]
+        stripe_event_id: Option<String>,
+    },
+    PaymentIntentAmountCapturableUpdated {
+        payment_intent: PaymentIntent,
+        #[serde(default)]
+        stripe_event_id: Option<String>,
+    },
+    PaymentIntentSucceeded {
+        payment_intent: PaymentIntent,
+        #[serde(default)]
+        stripe_event_id: Option<String>,
+    },
+    PaymentIntentCapture {
+        order_id: OrderId,
+        /// Amount to capture if the seller captures only a part of the order
+        #[serde(default)]
+        amount: Option<Amount>,
+    },
+    PaymentExpired {
+        invoice_id: InvoiceId,
+    },
+    /// The buyer cancelled the unpaid invoice, its account or payment intent is released like for `PaymentExpired`
+    InvoiceCancelled {
+        invoice_id: InvoiceId,
+    },
+    /// The buyer switched the invoice to another crypto currency, whatever has been sent to the replaced account
+    /// is moved to the main account unless the account is already used by another invoice
+    InvoiceAccountReplaced {
+        invoice_id: InvoiceId,
+        account_id: AccountId,
+    },
+    PayoutInitiated {
+        payout_id: PayoutId,
+    },
+    /// Sends the refund transaction of a crypto order, the event is rescheduled until the transaction is confirmed
+    CryptoRefundInitiated {
+        refund_id: RefundId,
+    },
+    /// Sends the refund of a fiat order to Stripe and completes it, retried until Stripe either accepts or rejects the refund
+    StripeRefundInitiated {
+        refund_id: RefundId,
+    },
+    /// The amount paid above the price of a crypto invoice is refunded or credited to the buyer
+    InvoiceSurplusReceived {
+        invoice_id: InvoiceId,
+    },
+    /// Sends the amount overpaid for a crypto invoice back to the buyer, rescheduled like `CryptoRefundInitiated`
+    InvoiceSurplusRefundInitiated {
+        surplus_id: InvoiceSurplusId,
+    },
+    /// The orders paid by the disputed charge are frozen from payout
+    ChargeDisputeCreated {
+        dispute: StripeDispute,
+        #[serde(default)]
+        stripe_event_id: Option<String>,
+    },
+    ChargeDisputeUpdated {
+        dispute: StripeDispute,
+        #[serde(default)]
+        stripe_event_id: Option<String>,
+    },
+    /// The dispute is resolved as won or lost, the seller balance is adjusted for lost disputes
+    ChargeDisputeClosed {
+        dispute: StripeDispute,
+        #[serde(default)]
+        stripe_event_id: Option<String>,
+    },
+    /// Scheduled when the invoice is paid, orders of the invoice still not captured are declined or captured
+    /// according to the capture policy of the store
+    CaptureSlaExpired {
+        invoice_id: InvoiceId,
+    },
+    /// Scheduled when the order is delivered, the captured order is released for payout to the seller
+    /// unless it has an open dispute or refund
+    EscrowReleaseDue {
+        order_id: OrderId,
+    },
+    /// Added when the invoice is paid, a receipt is issued by each store of the invoice
+    InvoiceReceiptsDue {
+        invoice_id: InvoiceId,
+    },
+    /// Added when the fee is paid, an invoice for the fee is issued to the store
+    FeeInvoiceDue {
+        fee_id: FeeId,
+    },
+    WebhookNotificationCreated {
+        notification: WebhookNotification,
+    },
+    WebhookDelivery {
+        subscription_id: WebhookSubscriptionId,
+        notification: WebhookNotification,
+    },
+}
+
+impl EventPayload {
+    pub fn is_replayable(&self) -> bool {
+        REPLAYABLE_PAYLOAD_TYPES.contains(&self.to_string().as_str())
+    }
 }
 
 impl fmt::Debug for EventPayload {
@@ -76,7 +201,22 @@ impl fmt::Display for EventPayload {
             EventPayload::PaymentIntentSucceeded { .. } => "PaymentIntentSucceeded",
             EventPayload::PaymentIntentCapture { .. } => "PaymentIntentCapture",
             EventPayload::PaymentExpired { .. } => "PaymentExpired",
+            EventPayload::InvoiceCancelled { .. } => "InvoiceCancelled",
+            EventPayload::InvoiceAccountReplaced { .. } => "InvoiceAccountReplaced",
             EventPayload::PayoutInitiated { .. } => "PayoutInitiated",
+            EventPayload::CryptoRefundInitiated { .. } => "CryptoRefundInitiated",
+            EventPayload::StripeRefundInitiated { .. } => "StripeRefundInitiated",
+            EventPayload::InvoiceSurplusReceived { .. } => "InvoiceSurplusReceived",
+            EventPayload::InvoiceSurplusRefundInitiated { .. } => "InvoiceSurplusRefundInitiated",
+            EventPayload::ChargeDisputeCreated { .. } => "ChargeDisputeCreated",
+            EventPayload::ChargeDisputeUpdated { .. } => "ChargeDisputeUpdated",
+            EventPayload::ChargeDisputeClosed { .. } => "ChargeDisputeClosed",
+            EventPayload::CaptureSlaExpired { .. } => "CaptureSlaExpired",
+            EventPayload::EscrowReleaseDue { .. } => "EscrowReleaseDue",
+            EventPayload::InvoiceReceiptsDue { .. } => "InvoiceReceiptsDue",
+            EventPayload::FeeInvoiceDue { .. } => "FeeInvoiceDue",
+            EventPayload::WebhookNotificationCreated { .. } => "WebhookNotificationCreated",
+            EventPayload::WebhookDelivery { .. } => "WebhookDelivery",
         };
 
         f.write_str(&s)
@@ -0,0 +1,53 @@
+use chrono::NaiveDateTime;
+use diesel::deserialize::{self, FromSql};
+use diesel::pg::Pg;
+use diesel::serialize::{self, Output, ToSql};
+use diesel::sql_types::BigInt;
+use std::io::Write;
+
+use models::event_store::{EventEntry, EventEntryId};
+use schema::event_attempts;
+
+#[derive(Debug, Serialize, Deserialize, FromSqlRow, AsExpression, Clone, Copy, PartialEq, Eq, FromStr, Display)]
+#[sql_type = "BigInt"]
+pub struct EventAttemptId(i64);
+newtype_from_to_sql!(BigInt, EventAttemptId, EventAttemptId);
+
+impl EventAttemptId {
+    pub fn new(id: i64) -> Self {
+        EventAttemptId(id)
+    }
+
+    pub fn inner(&self) -> i64 {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable)]
+pub struct EventAttempt {
+    pub id: EventAttemptId,
+    pub event_id: EventEntryId,
+    pub worker_instance: String,
+    pub started_at: NaiveDateTime,
+    pub finished_at: NaiveDateTime,
+    pub error_kind: Option<String>,
+    pub error_message: Option<String>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[table_name = "event_attempts"]
+pub struct NewEventAttempt {
+    pub event_id: EventEntryId,
+    pub worker_instance: String,
+    pub started_at: NaiveDateTime,
+    pub finished_at: NaiveDateTime,
+    pub error_kind: Option<String>,
+    pub error_message: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EventEntryWithAttempts {
+    #[serde(flatten)]
+    pub entry: EventEntry,
+    pub attempts: Vec<EventAttempt>,
+}
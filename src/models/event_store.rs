@@ -1,13 +1,16 @@
-use chrono::NaiveDateTime;
+use chrono::{Duration, NaiveDateTime};
 use diesel::deserialize::{self, FromSql};
 use diesel::pg::Pg;
 use diesel::serialize::{self, Output, ToSql};
 use diesel::sql_types::BigInt;
+use std::cmp;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 use std::io::Write;
 use std::str::FromStr;
 
-use models::event::Event;
+use config;
+use models::event::{Event, EventPayload};
 use schema::event_store;
 
 #[derive(Debug, Serialize, Deserialize, FromSqlRow, AsExpression, Clone, Copy, PartialEq, Eq, FromStr, Display)]
@@ -34,14 +37,17 @@ pub struct EventEntry {
     pub created_at: NaiveDateTime,
     pub status_updated_at: NaiveDateTime,
     pub scheduled_on: Option<NaiveDateTime>,
+    pub next_retry_at: Option<NaiveDateTime>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
 pub enum EventStatus {
     Pending,
     InProgress,
     Completed,
     Failed,
+    ManuallyResolved,
 }
 
 #[derive(Debug, Clone, Fail)]
@@ -57,6 +63,7 @@ impl FromStr for EventStatus {
             "in_progress" => Ok(EventStatus::InProgress),
             "completed" => Ok(EventStatus::Completed),
             "failed" => Ok(EventStatus::Failed),
+            "manually_resolved" => Ok(EventStatus::ManuallyResolved),
             _ => Err(ParseEventStatusError),
         }
     }
@@ -69,6 +76,7 @@ impl fmt::Display for EventStatus {
             EventStatus::InProgress => "in_progress",
             EventStatus::Completed => "completed",
             EventStatus::Failed => "failed",
+            EventStatus::ManuallyResolved => "manually_resolved",
         };
 
         f.write_str(s)
@@ -121,6 +129,11 @@ impl RawEventEntry {
             }
         };
 
+        let next_retry_at = match status {
+            EventStatus::Pending if attempt_count > 0 => scheduled_on,
+            _ => None,
+        };
+
         Ok(EventEntry {
             id,
             event,
@@ -129,6 +142,7 @@ impl RawEventEntry {
             created_at,
             status_updated_at,
             scheduled_on,
+            next_retry_at,
         })
     }
 }
@@ -161,3 +175,206 @@ impl RawNewEventEntry {
         })
     }
 }
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EventEntrySearch {
+    pub status: Option<EventStatus>,
+    pub payload_type: Option<String>,
+    pub created_from: Option<NaiveDateTime>,
+    pub created_to: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EventEntrySearchResults {
+    pub total_count: i64,
+    pub events: Vec<EventEntry>,
+}
+
+/// Selects completed or failed events to be replayed
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EventReplaySearch {
+    pub status: Option<EventStatus>,
+    pub id_from: Option<EventEntryId>,
+    pub id_to: Option<EventEntryId>,
+    pub payload_type: Option<String>,
+    pub created_from: Option<NaiveDateTime>,
+    pub created_to: Option<NaiveDateTime>,
+}
+
+impl EventReplaySearch {
+    pub fn is_empty(&self) -> bool {
+        self.id_from.is_none()
+            && self.id_to.is_none()
+            && self.payload_type.is_none()
+            && self.created_from.is_none()
+            && self.created_to.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EventReplayResults {
+    pub dry_run: bool,
+    pub total_count: usize,
+    pub count_by_payload_type: BTreeMap<String, usize>,
+    pub events: Vec<ReplayedEvent>,
+}
+
+impl EventReplayResults {
+    pub fn new(dry_run: bool, events: Vec<ReplayedEvent>) -> Self {
+        let mut count_by_payload_type = BTreeMap::new();
+        for event in events.iter() {
+            *count_by_payload_type.entry(event.payload_type.clone()).or_insert(0) += 1;
+        }
+
+        Self {
+            dry_run,
+            total_count: events.len(),
+            count_by_payload_type,
+            events,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayedEvent {
+    pub original_event_id: EventEntryId,
+    pub original_status: EventStatus,
+    pub payload_type: String,
+    /// ID of the enqueued copy, not set in dry-run mode
+    pub replay_event_id: Option<EventEntryId>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventRetryPolicy {
+    pub initial_delay_sec: u32,
+    pub max_delay_sec: u32,
+    pub jitter_percent: u32,
+    pub max_processing_attempts: Option<u32>,
+}
+
+impl EventRetryPolicy {
+    /// Exponential backoff delay after `attempt_count` failed attempts with up to `jitter_percent` of random jitter on top
+    pub fn retry_delay(&self, attempt_count: u32, jitter_seed: u32) -> Duration {
+        let exponent = cmp::min(attempt_count.saturating_sub(1), 31);
+        let delay_sec = cmp::min(
+            u64::from(self.initial_delay_sec).saturating_mul(1u64 << exponent),
+            u64::from(self.max_delay_sec),
+        );
+
+        let jitter_range_sec = delay_sec * u64::from(self.jitter_percent) / 100;
+        let jitter_sec = if jitter_range_sec == 0 {
+            0
+        } else {
+            u64::from(jitter_seed) % (jitter_range_sec + 1)
+        };
+
+        Duration::seconds((delay_sec + jitter_sec) as i64)
+    }
+}
+
+impl From<config::EventRetry> for EventRetryPolicy {
+    fn from(config: config::EventRetry) -> Self {
+        let config::EventRetry {
+            initial_delay_sec,
+            max_delay_sec,
+            jitter_percent,
+            max_processing_attempts,
+        } = config;
+
+        Self {
+            initial_delay_sec,
+            max_delay_sec,
+            jitter_percent,
+            max_processing_attempts,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EventRetryPolicies {
+    pub default: EventRetryPolicy,
+    pub by_payload: HashMap<String, EventRetryPolicy>,
+}
+
+impl EventRetryPolicies {
+    pub fn get(&self, payload: &EventPayload) -> &EventRetryPolicy {
+        self.by_payload.get(&payload.to_string()).unwrap_or(&self.default)
+    }
+}
+
+impl From<config::EventStore> for EventRetryPolicies {
+    fn from(config: config::EventStore) -> Self {
+        let config::EventStore {
+            retry, retry_by_payload, ..
+        } = config;
+
+        Self {
+            default: EventRetryPolicy::from(retry),
+            by_payload: retry_by_payload
+                .into_iter()
+                .map(|(payload, retry)| (payload, EventRetryPolicy::from(retry)))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> EventRetryPolicy {
+        EventRetryPolicy {
+            initial_delay_sec: 10,
+            max_delay_sec: 100,
+            jitter_percent: 0,
+            max_processing_attempts: None,
+        }
+    }
+
+    #[test]
+    fn retry_delay_grows_exponentially_up_to_max() {
+        let policy = policy();
+
+        assert_eq!(policy.retry_delay(1, 0), Duration::seconds(10));
+        assert_eq!(policy.retry_delay(2, 0), Duration::seconds(20));
+        assert_eq!(policy.retry_delay(3, 0), Duration::seconds(40));
+        assert_eq!(policy.retry_delay(5, 0), Duration::seconds(100));
+        assert_eq!(policy.retry_delay(100, 0), Duration::seconds(100));
+    }
+
+    #[test]
+    fn retry_delay_jitter_stays_within_range() {
+        let policy = EventRetryPolicy {
+            jitter_percent: 50,
+            ..policy()
+        };
+
+        for seed in 0..1000 {
+            let delay = policy.retry_delay(2, seed);
+            assert!(delay >= Duration::seconds(20) && delay <= Duration::seconds(30));
+        }
+    }
+
+    #[test]
+    fn replay_results_are_counted_by_payload_type() {
+        let replayed_event = |id: i64, payload_type: &str| ReplayedEvent {
+            original_event_id: EventEntryId::new(id),
+            original_status: EventStatus::Completed,
+            payload_type: payload_type.to_string(),
+            replay_event_id: None,
+        };
+
+        let results = EventReplayResults::new(
+            true,
+            vec![
+                replayed_event(1, "InvoicePaid"),
+                replayed_event(2, "PaymentExpired"),
+                replayed_event(3, "InvoicePaid"),
+            ],
+        );
+
+        assert_eq!(results.total_count, 3);
+        assert_eq!(results.count_by_payload_type.get("InvoicePaid"), Some(&2));
+        assert_eq!(results.count_by_payload_type.get("PaymentExpired"), Some(&1));
+    }
+}
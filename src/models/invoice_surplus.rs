@@ -0,0 +1,102 @@
+use std::fmt::{self, Display};
+
+use chrono::NaiveDateTime;
+use uuid::Uuid;
+
+use models::invoice_v2::InvoiceId;
+use models::{Amount, Currency, TransactionId, UserId, WalletAddress};
+use schema::invoice_surpluses;
+
+#[derive(Clone, Copy, Debug, Display, PartialEq, Eq, From, FromStr, Hash, Serialize, Deserialize, DieselTypes)]
+pub struct InvoiceSurplusId(Uuid);
+
+impl InvoiceSurplusId {
+    pub fn new(id: Uuid) -> Self {
+        InvoiceSurplusId(id)
+    }
+
+    pub fn inner(&self) -> &Uuid {
+        &self.0
+    }
+
+    pub fn generate() -> Self {
+        InvoiceSurplusId(Uuid::new_v4())
+    }
+}
+
+/// What happens to the amount a buyer paid above the invoice price
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, DieselTypes, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SurplusHandling {
+    /// Sent back to the buyer wallet
+    Refund,
+    /// Kept as a credit of the buyer
+    StoreCredit,
+}
+
+impl Display for SurplusHandling {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SurplusHandling::Refund => write!(f, "Refund"),
+            SurplusHandling::StoreCredit => write!(f, "StoreCredit"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, DieselTypes, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum InvoiceSurplusStatus {
+    Pending,
+    Refunded,
+    Credited,
+    Failed,
+}
+
+impl Display for InvoiceSurplusStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InvoiceSurplusStatus::Pending => write!(f, "Pending"),
+            InvoiceSurplusStatus::Refunded => write!(f, "Refunded"),
+            InvoiceSurplusStatus::Credited => write!(f, "Credited"),
+            InvoiceSurplusStatus::Failed => write!(f, "Failed"),
+        }
+    }
+}
+
+/// Amount a buyer paid above the price of a crypto invoice
+#[derive(Clone, Debug, Deserialize, Serialize, Queryable)]
+pub struct InvoiceSurplus {
+    pub id: InvoiceSurplusId,
+    pub invoice_id: InvoiceId,
+    pub buyer_user_id: UserId,
+    pub currency: Currency,
+    /// Surplus in the buyer currency
+    pub amount: Amount,
+    pub handling: SurplusHandling,
+    pub status: InvoiceSurplusStatus,
+    /// Buyer wallet the surplus is refunded to
+    pub wallet_address: Option<WalletAddress>,
+    pub transaction_id: Option<TransactionId>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Insertable)]
+#[table_name = "invoice_surpluses"]
+pub struct NewInvoiceSurplus {
+    pub id: InvoiceSurplusId,
+    pub invoice_id: InvoiceId,
+    pub buyer_user_id: UserId,
+    pub currency: Currency,
+    pub amount: Amount,
+    pub handling: SurplusHandling,
+    pub status: InvoiceSurplusStatus,
+    pub wallet_address: Option<WalletAddress>,
+    pub transaction_id: Option<TransactionId>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, AsChangeset)]
+#[table_name = "invoice_surpluses"]
+pub struct UpdateInvoiceSurplus {
+    pub status: Option<InvoiceSurplusStatus>,
+}
@@ -9,10 +9,10 @@ use stq_static_resources::OrderState;
 use stq_types::{InvoiceId as InvoiceV1Id, ProductPrice, SagaId};
 use uuid::{self, Uuid};
 
-use models::order_v2::{OrderId, RawOrder};
+use models::order_v2::{ExchangeId, OrderId, RawOrder};
 use models::{
-    AccountId, Amount, Currency, ExchangeRateStatus, Invoice as InvoiceV1, OrderExchangeRateId, RawOrderExchangeRate, TransactionId,
-    UserId, WalletAddress,
+    AccountId, Amount, Currency, ExchangeRateStatus, Fee, FeeStatus, Invoice as InvoiceV1, OrderExchangeRateId, RawOrderExchangeRate,
+    TransactionId, UserId, WalletAddress,
 };
 use schema::amounts_received;
 use schema::invoices_v2;
@@ -71,6 +71,9 @@ pub struct RawInvoice {
     pub updated_at: NaiveDateTime,
     pub buyer_user_id: UserId,
     pub status: OrderState,
+    pub payment_failed_at: Option<NaiveDateTime>,
+    /// Amount captured above the invoice price
+    pub surplus_amount: Amount,
 }
 
 impl RawInvoice {
@@ -81,6 +84,26 @@ impl RawInvoice {
             PaymentFlow::Crypto
         }
     }
+
+    pub fn payment_status(&self) -> InvoicePaymentStatus {
+        if self.paid_at.is_some() {
+            InvoicePaymentStatus::Paid
+        } else if self.payment_failed_at.is_some() {
+            InvoicePaymentStatus::Failed
+        } else {
+            InvoicePaymentStatus::Awaited
+        }
+    }
+}
+
+/// Payment progress shown to the buyer, `OrderState` has no state for a declined card payment that may be retried
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum InvoicePaymentStatus {
+    Awaited,
+    /// The last payment attempt was declined, the buyer may pay again
+    Failed,
+    Paid,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable)]
@@ -146,6 +169,7 @@ pub struct InvoiceSetAmountPaid {
     pub final_amount_paid: Amount,
     pub final_cashback_amount: Amount,
     pub paid_at: NaiveDateTime,
+    pub surplus_amount: Amount,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, AsChangeset)]
@@ -155,16 +179,26 @@ pub struct RawInvoiceSetAmountPaid {
     pub final_cashback_amount: Amount,
     pub paid_at: NaiveDateTime,
     pub status: OrderState,
+    pub surplus_amount: Amount,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, AsChangeset)]
 #[table_name = "invoices_v2"]
+#[changeset_options(treat_none_as_null = "true")]
 pub struct RawInvoiceSetAmountPaidFiat {
     pub amount_captured: Amount,
     pub final_amount_paid: Amount,
     pub final_cashback_amount: Amount,
     pub paid_at: NaiveDateTime,
     pub status: OrderState,
+    pub payment_failed_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, AsChangeset)]
+#[table_name = "invoices_v2"]
+pub struct RawInvoiceSetPaymentFailed {
+    pub payment_failed_at: NaiveDateTime,
+    pub status: OrderState,
 }
 
 impl From<InvoiceSetAmountPaid> for RawInvoiceSetAmountPaid {
@@ -173,12 +207,14 @@ impl From<InvoiceSetAmountPaid> for RawInvoiceSetAmountPaid {
             final_amount_paid,
             final_cashback_amount,
             paid_at,
+            surplus_amount,
         } = payload;
         Self {
             final_amount_paid,
             final_cashback_amount,
             paid_at,
             status: OrderState::Paid,
+            surplus_amount,
         }
     }
 }
@@ -189,6 +225,7 @@ impl From<InvoiceSetAmountPaid> for RawInvoiceSetAmountPaidFiat {
             final_amount_paid,
             final_cashback_amount,
             paid_at,
+            ..
         } = payload;
         Self {
             amount_captured: final_amount_paid,
@@ -196,6 +233,7 @@ impl From<InvoiceSetAmountPaid> for RawInvoiceSetAmountPaidFiat {
             final_cashback_amount,
             paid_at,
             status: OrderState::Paid,
+            payment_failed_at: None,
         }
     }
 }
@@ -246,6 +284,32 @@ pub struct OrderDump {
     pub rates: Vec<RateDump>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MarketplaceFeeDump {
+    pub currency: Currency,
+    pub amount: BigDecimal,
+    pub status: FeeStatus,
+}
+
+/// Breakdown of how the buyer price of an order is derived from the seller amount
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InvoiceLineDump {
+    pub order_id: OrderId,
+    pub seller_currency: Currency,
+    pub seller_amount: BigDecimal,
+    pub cashback: BigDecimal,
+    /// Active exchange rate, the seller amount divided by the rate is the buyer amount
+    pub exchange_rate: Option<BigDecimal>,
+    /// Exchange reserved through Payments gateway, not set for orders paid in the seller currency
+    pub exchange_id: Option<ExchangeId>,
+    pub buyer_currency: Currency,
+    /// Exact buyer amount, the lines add up to the price before it is rounded
+    pub buyer_amount: Option<BigDecimal>,
+    /// Stripe fee in the seller currency, known once the charge is captured
+    pub stripe_fee: Option<BigDecimal>,
+    pub marketplace_fee: Option<MarketplaceFeeDump>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InvoiceDump {
     pub id: InvoiceId,
@@ -254,11 +318,18 @@ pub struct InvoiceDump {
     pub total_price: BigDecimal,
     pub total_cashback: Option<BigDecimal>,
     pub orders: Vec<OrderDump>,
+    pub lines: Vec<InvoiceLineDump>,
+    /// Difference between the quoted price rounded to the smallest unit of the buyer currency and the sum of the lines,
+    /// the price is rounded as a whole when the invoice is charged. It does not depend on the amount actually paid,
+    /// so an invoice settled within the tolerance or with a surplus reports the rounding it was quoted with
+    pub rounding: Option<BigDecimal>,
     pub has_missing_rates: bool,
     pub created_at: NaiveDateTime,
     pub paid_at: Option<NaiveDateTime>,
     pub wallet_address: Option<WalletAddress>,
     pub status: OrderState,
+    pub payment_status: InvoicePaymentStatus,
+    pub payment_failed_at: Option<NaiveDateTime>,
 }
 
 #[derive(Debug, Clone, Fail)]
@@ -317,8 +388,10 @@ pub struct InvoiceDumpCalculationData {
 pub fn calculate_invoice_price(
     invoice: RawInvoice,
     orders: Vec<(RawOrder, Vec<RawOrderExchangeRate>)>,
+    fees: Vec<Fee>,
     wallet_address: Option<WalletAddress>,
 ) -> InvoiceDump {
+    let payment_status = invoice.payment_status();
     let RawInvoice {
         id,
         buyer_currency,
@@ -328,6 +401,7 @@ pub fn calculate_invoice_price(
         created_at,
         paid_at,
         status,
+        payment_failed_at,
         ..
     } = invoice;
 
@@ -335,7 +409,7 @@ pub fn calculate_invoice_price(
     let final_amount_paid = final_amount_paid.map(|amount| amount.to_super_unit(buyer_currency));
     let final_cashback_amount = final_cashback_amount.map(|amount| amount.to_super_unit(buyer_currency));
 
-    let orders = orders
+    let (orders, lines): (Vec<_>, Vec<_>) = orders
         .into_iter()
         .map(|(order, rates)| {
             let RawOrder {
@@ -343,20 +417,40 @@ pub fn calculate_invoice_price(
                 seller_currency,
                 cashback_amount,
                 total_amount,
+                stripe_fee,
                 ..
             } = order;
 
-            let exchange_rate = if buyer_currency == seller_currency {
-                Some(BigDecimal::from(1))
+            let (exchange_rate, exchange_id) = if buyer_currency == seller_currency {
+                (Some(BigDecimal::from(1)), None)
             } else {
                 rates
                     .iter()
                     .find(|rate| rate.status == ExchangeRateStatus::Active)
-                    .map(|RawOrderExchangeRate { ref exchange_rate, .. }| exchange_rate.clone())
+                    .map(|rate| (Some(rate.exchange_rate.clone()), rate.exchange_id))
+                    .unwrap_or((None, None))
             };
 
             let seller_price = total_amount.to_super_unit(seller_currency);
-            OrderDump {
+
+            let line = InvoiceLineDump {
+                order_id: id,
+                seller_currency,
+                seller_amount: seller_price.clone(),
+                cashback: cashback_amount.to_super_unit(seller_currency),
+                exchange_rate: exchange_rate.clone(),
+                exchange_id,
+                buyer_currency,
+                buyer_amount: exchange_rate.clone().map(|exchange_rate| seller_price.clone() / exchange_rate),
+                stripe_fee: stripe_fee.map(|stripe_fee| stripe_fee.to_super_unit(seller_currency)),
+                marketplace_fee: fees.iter().find(|fee| fee.order_id == id).map(|fee| MarketplaceFeeDump {
+                    currency: fee.currency,
+                    amount: fee.amount.to_super_unit(fee.currency),
+                    status: fee.status.clone(),
+                }),
+            };
+
+            let order = OrderDump {
                 id,
                 seller_currency,
                 seller_price: seller_price.clone(),
@@ -384,11 +478,26 @@ pub fn calculate_invoice_price(
                         }
                     })
                     .collect(),
-            }
+            };
+
+            (order, line)
         })
-        .collect::<Vec<_>>();
+        .unzip();
 
     let has_missing_rates = orders.iter().any(|op| op.buyer_amounts.is_none());
+    let quoted_price = orders
+        .iter()
+        .filter_map(|order| order.buyer_amounts.as_ref().map(|amounts| amounts.price.clone()))
+        .fold(BigDecimal::from(0), |acc, price| acc + price);
+    let rounding = if has_missing_rates {
+        None
+    } else {
+        let lines_total = lines
+            .iter()
+            .filter_map(|line| line.buyer_amount.clone())
+            .fold(BigDecimal::from(0), |acc, amount| acc + amount);
+        Some(Amount::from_super_unit(buyer_currency, quoted_price.clone()).to_super_unit(buyer_currency) - lines_total)
+    };
 
     // Check if the invoice has been paid. If it has, return the final prices.
     // Either all of the fields must contain a value or none of them,
@@ -401,32 +510,131 @@ pub fn calculate_invoice_price(
             total_price,
             total_cashback: Some(total_cashback),
             orders,
+            lines,
+            rounding,
             has_missing_rates,
             created_at,
             paid_at: Some(paid_at),
             wallet_address,
             status,
+            payment_status,
+            payment_failed_at,
         },
-        _ => orders.clone().into_iter().fold(
-            InvoiceDump {
-                id,
-                buyer_currency,
-                amount_captured,
-                total_price: BigDecimal::from(0),
-                total_cashback: Some(BigDecimal::from(0)),
-                orders,
-                has_missing_rates,
-                created_at,
-                paid_at: None,
-                wallet_address,
-                status,
-            },
-            |mut invoice, order_price| {
-                if let Some(BuyerAmounts { price, .. }) = order_price.buyer_amounts {
-                    invoice.total_price += price;
-                };
-                invoice
-            },
-        ),
+        _ => InvoiceDump {
+            id,
+            buyer_currency,
+            amount_captured,
+            total_price: quoted_price,
+            total_cashback: Some(BigDecimal::from(0)),
+            orders,
+            lines,
+            rounding,
+            has_missing_rates,
+            created_at,
+            paid_at: None,
+            wallet_address,
+            status,
+            payment_status,
+            payment_failed_at,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use repos::repo_factory::tests::{create_raw_invoice_v2, create_raw_order};
+
+    fn active_rate(order_id: OrderId, exchange_rate: BigDecimal) -> RawOrderExchangeRate {
+        RawOrderExchangeRate {
+            id: OrderExchangeRateId::new(1),
+            order_id,
+            exchange_id: None,
+            exchange_rate,
+            status: ExchangeRateStatus::Active,
+            created_at: NaiveDateTime::from_timestamp(0, 0),
+            updated_at: NaiveDateTime::from_timestamp(0, 0),
+        }
+    }
+
+    #[test]
+    fn test_lines_add_up_to_total_price() {
+        let invoice = create_raw_invoice_v2(Currency::Btc);
+        // 1 EUR at 3 EUR per BTC cannot be expressed in satoshis
+        let orders = (0..2)
+            .map(|_| {
+                let order = create_raw_order(invoice.id, Currency::Eur, Amount::new(100));
+                let rates = vec![active_rate(order.id, BigDecimal::from(3))];
+                (order, rates)
+            })
+            .collect::<Vec<_>>();
+
+        let invoice_dump = calculate_invoice_price(invoice, orders, vec![], None);
+
+        let exact_line_amount = Amount::new(100).to_super_unit(Currency::Eur) / BigDecimal::from(3);
+        assert!(invoice_dump
+            .lines
+            .iter()
+            .all(|line| line.buyer_amount == Some(exact_line_amount.clone())));
+
+        let lines_total = invoice_dump
+            .lines
+            .iter()
+            .filter_map(|line| line.buyer_amount.clone())
+            .fold(BigDecimal::from(0), |acc, amount| acc + amount);
+        assert_eq!(lines_total, invoice_dump.total_price);
+
+        let charged_price = Amount::from_super_unit(Currency::Btc, invoice_dump.total_price.clone());
+        assert_eq!(charged_price, Amount::new(66_666_666));
+        assert_eq!(
+            lines_total + invoice_dump.rounding.unwrap(),
+            charged_price.to_super_unit(Currency::Btc)
+        );
+    }
+
+    #[test]
+    fn test_no_rounding_with_missing_rates() {
+        let invoice = create_raw_invoice_v2(Currency::Btc);
+        let order = create_raw_order(invoice.id, Currency::Eur, Amount::new(100));
+
+        let invoice_dump = calculate_invoice_price(invoice, vec![(order, vec![])], vec![], None);
+
+        assert!(invoice_dump.has_missing_rates);
+        assert_eq!(invoice_dump.lines[0].buyer_amount, None);
+        assert_eq!(invoice_dump.rounding, None);
+    }
+
+    #[test]
+    fn test_rounding_of_underpaid_invoice() {
+        let mut invoice = create_raw_invoice_v2(Currency::Btc);
+        let orders = (0..2)
+            .map(|_| {
+                let order = create_raw_order(invoice.id, Currency::Eur, Amount::new(100));
+                let rates = vec![active_rate(order.id, BigDecimal::from(3))];
+                (order, rates)
+            })
+            .collect::<Vec<_>>();
+
+        let quoted_dump = calculate_invoice_price(invoice.clone(), orders.clone(), vec![], None);
+
+        // settled within the tolerance with less than the quoted 66_666_666 satoshis
+        invoice.amount_captured = Amount::new(66_660_000);
+        invoice.final_amount_paid = Some(Amount::new(66_660_000));
+        invoice.final_cashback_amount = Some(Amount::zero());
+        invoice.paid_at = Some(NaiveDateTime::from_timestamp(0, 0));
+        let paid_dump = calculate_invoice_price(invoice, orders, vec![], None);
+
+        assert_eq!(paid_dump.total_price, Amount::new(66_660_000).to_super_unit(Currency::Btc));
+        assert_eq!(paid_dump.rounding, quoted_dump.rounding);
+
+        let lines_total = paid_dump
+            .lines
+            .iter()
+            .filter_map(|line| line.buyer_amount.clone())
+            .fold(BigDecimal::from(0), |acc, amount| acc + amount);
+        assert_eq!(
+            lines_total + paid_dump.rounding.unwrap(),
+            Amount::new(66_666_666).to_super_unit(Currency::Btc)
+        );
     }
 }
@@ -9,17 +9,22 @@ pub mod currency;
 pub mod customer;
 pub mod customer_id;
 pub mod daily_limit_type;
+pub mod dispute;
+pub mod document;
 pub mod event;
+pub mod event_attempt;
 pub mod event_store;
 pub mod fee;
 pub mod international_billing_info;
 pub mod invoice;
+pub mod invoice_surplus;
 pub mod invoice_v2;
 pub mod merchant;
 pub mod order;
 pub mod order_billing;
 pub mod order_exchange_rate;
 pub mod order_info;
+pub mod order_state_transition;
 pub mod order_v2;
 pub mod payment_intent;
 pub mod payment_intents_fees;
@@ -27,14 +32,18 @@ pub mod payment_intents_invoices;
 pub mod payment_state;
 pub mod payout;
 pub mod proxy_companies_billing_info;
+pub mod refund;
 pub mod role;
 pub mod russia_billing_info;
 pub mod store_billing_type;
+pub mod store_capture_policy;
 pub mod stripe_payout_id;
+pub mod stripe_webhook_event;
 pub mod subscription;
 pub mod transaction_id;
 pub mod user;
 pub mod user_wallet;
+pub mod webhook;
 
 pub use self::account::*;
 pub use self::amount::*;
@@ -44,27 +53,36 @@ pub use self::currency::*;
 pub use self::customer::*;
 pub use self::customer_id::*;
 pub use self::daily_limit_type::*;
+pub use self::dispute::*;
+pub use self::document::*;
 pub use self::event::*;
+pub use self::event_attempt::*;
 pub use self::event_store::*;
 pub use self::fee::*;
 pub use self::international_billing_info::*;
 pub use self::invoice::*;
+pub use self::invoice_surplus::*;
 pub use self::merchant::*;
 pub use self::order::*;
 pub use self::order_billing::*;
 pub use self::order_exchange_rate::*;
 pub use self::order_info::*;
+pub use self::order_state_transition::*;
 pub use self::payment_intent::*;
 pub use self::payment_intents_fees::*;
 pub use self::payment_intents_invoices::*;
 pub use self::payment_state::*;
 pub use self::payout::*;
 pub use self::proxy_companies_billing_info::*;
+pub use self::refund::*;
 pub use self::role::*;
 pub use self::russia_billing_info::*;
 pub use self::store_billing_type::*;
+pub use self::store_capture_policy::*;
 pub use self::stripe_payout_id::*;
+pub use self::stripe_webhook_event::*;
 pub use self::subscription::*;
 pub use self::transaction_id::*;
 pub use self::user::*;
 pub use self::user_wallet::*;
+pub use self::webhook::*;
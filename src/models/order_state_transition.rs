@@ -0,0 +1,86 @@
+use chrono::NaiveDateTime;
+use diesel::deserialize::{self, FromSql};
+use diesel::pg::Pg;
+use diesel::serialize::{self, Output, ToSql};
+use diesel::sql_types::BigInt;
+use std::io::Write;
+
+use stq_types::UserId;
+
+use models::order_v2::OrderId;
+use models::{EventId, PaymentState};
+use schema::order_state_transitions;
+
+#[derive(Debug, Serialize, Deserialize, FromSqlRow, AsExpression, Clone, Copy, PartialEq, Eq, FromStr, Display)]
+#[sql_type = "BigInt"]
+pub struct OrderStateTransitionId(i64);
+newtype_from_to_sql!(BigInt, OrderStateTransitionId, OrderStateTransitionId);
+
+impl OrderStateTransitionId {
+    pub fn new(id: i64) -> Self {
+        OrderStateTransitionId(id)
+    }
+
+    pub fn inner(&self) -> i64 {
+        self.0
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Queryable)]
+pub struct OrderStateTransition {
+    pub id: OrderStateTransitionId,
+    pub order_id: OrderId,
+    pub previous_state: PaymentState,
+    pub new_state: PaymentState,
+    /// User who changed the state, not set for changes made by the system
+    pub user_id: Option<UserId>,
+    /// Event which caused the change if it was made while processing an event
+    pub event_id: Option<EventId>,
+    pub reason: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Insertable)]
+#[table_name = "order_state_transitions"]
+pub struct NewOrderStateTransition {
+    pub order_id: OrderId,
+    pub previous_state: PaymentState,
+    pub new_state: PaymentState,
+    pub user_id: Option<UserId>,
+    pub event_id: Option<EventId>,
+    pub reason: Option<String>,
+}
+
+/// Who or what changes the state of an order, recorded in the order state history
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct OrderStateChange {
+    pub user_id: Option<UserId>,
+    pub event_id: Option<EventId>,
+    pub reason: Option<String>,
+}
+
+impl OrderStateChange {
+    pub fn by_user(user_id: Option<UserId>, reason: &str) -> Self {
+        Self {
+            user_id,
+            event_id: None,
+            reason: Some(reason.to_string()),
+        }
+    }
+
+    pub fn by_event(event_id: EventId, reason: &str) -> Self {
+        Self {
+            user_id: None,
+            event_id: Some(event_id),
+            reason: Some(reason.to_string()),
+        }
+    }
+
+    pub fn by_system(reason: &str) -> Self {
+        Self {
+            user_id: None,
+            event_id: None,
+            reason: Some(reason.to_string()),
+        }
+    }
+}
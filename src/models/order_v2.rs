@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::fmt::{self, Display};
 use std::io::Write;
 use std::str::FromStr;
@@ -13,7 +14,7 @@ use diesel::{
 use uuid::{self, Uuid};
 
 use models::invoice_v2::InvoiceId;
-use models::{Amount, Currency, CurrencyChoice, FiatCurrency, PaymentState, TureCurrency};
+use models::{Amount, Currency, CurrencyChoice, FiatCurrency, PaymentState, TureCurrency, UserId};
 use schema::orders;
 
 #[derive(Debug, Serialize, Deserialize, FromSqlRow, AsExpression, Clone, Copy, PartialEq, Eq, Hash)]
@@ -97,6 +98,12 @@ pub struct RawOrder {
     pub store_id: StoreId,
     pub state: PaymentState,
     pub stripe_fee: Option<Amount>,
+    /// Set when the seller captured only a part of the order
+    pub captured_amount: Option<Amount>,
+    /// Sum of the successful refunds of the order
+    pub refunded_amount: Amount,
+    /// Set when the order is reported delivered, its release from escrow is scheduled only then
+    pub delivered_at: Option<NaiveDateTime>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -120,6 +127,16 @@ impl RawOrder {
             },
         }
     }
+
+    /// Amount the buyer is finally charged for the order
+    pub fn charged_amount(&self) -> Amount {
+        self.captured_amount.unwrap_or(self.total_amount)
+    }
+
+    /// Amount the seller finally receives for the order, i.e. the charged amount without refunds
+    pub fn net_amount(&self) -> Amount {
+        self.charged_amount().checked_sub(self.refunded_amount).unwrap_or(Amount::zero())
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Insertable)]
@@ -145,12 +162,47 @@ pub struct OrdersSearch {
     pub state: Option<PaymentState>,
     pub order_id: Option<OrderId>,
     pub order_ids: Option<Vec<OrderId>>,
+    pub created_from: Option<NaiveDateTime>,
+    pub created_to: Option<NaiveDateTime>,
+    pub updated_from: Option<NaiveDateTime>,
+    pub updated_to: Option<NaiveDateTime>,
+    pub seller_currency: Option<Currency>,
+    /// Bounds of the total amount of the order in the minor units of the seller currency
+    pub amount_from: Option<Amount>,
+    pub amount_to: Option<Amount>,
+    pub invoice_id: Option<InvoiceId>,
+    pub buyer_user_id: Option<UserId>,
+    #[serde(default)]
+    pub sort: OrdersSort,
+    /// ID of the last order of the previous page, orders up to and including it are skipped
+    pub cursor: Option<OrderId>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrdersSort {
+    CreatedAtDesc,
+    CreatedAtAsc,
+    UpdatedAtDesc,
+    UpdatedAtAsc,
+    TotalAmountDesc,
+    TotalAmountAsc,
+}
+
+impl Default for OrdersSort {
+    fn default() -> Self {
+        OrdersSort::CreatedAtDesc
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
 pub struct OrderSearchResults {
     pub total_count: i64,
     pub orders: Vec<RawOrder>,
+    /// Cursor of the next page, `None` on the last page
+    pub next_cursor: Option<OrderId>,
+    /// Sum of the total amounts of all the found orders
+    pub totals: HashMap<Currency, Amount>,
 }
 
 impl OrdersSearch {
@@ -18,6 +18,7 @@ pub struct PaymentIntent {
     pub status: PaymentIntentStatus,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
+    pub last_payment_error_code: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, Queryable, Insertable)]
@@ -32,6 +33,7 @@ pub struct NewPaymentIntent {
     pub receipt_email: Option<String>,
     pub charge_id: Option<ChargeId>,
     pub status: PaymentIntentStatus,
+    pub last_payment_error_code: Option<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, AsChangeset, Default)]
@@ -45,6 +47,7 @@ pub struct UpdatePaymentIntent {
     pub last_payment_error_message: Option<String>,
     pub receipt_email: Option<String>,
     pub charge_id: Option<ChargeId>,
+    pub last_payment_error_code: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, DieselTypes, PartialEq, Eq)]
@@ -85,6 +88,14 @@ impl<'r> From<&'r PaymentIntent> for PaymentIntentAccess {
     }
 }
 
+/// Decline code (or the generic error code if Stripe didn't provide one) and message of the last payment error
+pub fn last_payment_error_details(error: Option<&stripe::PaymentError>) -> (Option<String>, Option<String>) {
+    match error {
+        None => (None, None),
+        Some(error) => (error.decline_code.clone().or_else(|| error.code.clone()), error.message.clone()),
+    }
+}
+
 impl From<stripe::PaymentIntentStatus> for PaymentIntentStatus {
     fn from(status: stripe::PaymentIntentStatus) -> PaymentIntentStatus {
         use stripe::PaymentIntentStatus::*;
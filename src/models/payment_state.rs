@@ -8,6 +8,9 @@ use diesel::serialize::{self, IsNull, Output, ToSql};
 use diesel::sql_types::VarChar;
 use enum_iterator::IntoEnumIterator;
 use failure::Fail;
+use stq_types::BillingRole;
+
+use models::WebhookEventType;
 
 #[derive(Debug, Serialize, Deserialize, FromSqlRow, AsExpression, Clone, Copy, Eq, PartialEq, Hash, IntoEnumIterator)]
 #[sql_type = "VarChar"]
@@ -15,6 +18,8 @@ use failure::Fail;
 pub enum PaymentState {
     /// Order created and maybe paid by customer
     Initial,
+    /// Customer's payment attempt failed, the order is waiting for another attempt
+    PaymentFailed,
     /// Store manager declined the order
     Declined,
     /// Store manager confirmed the order, money was captured
@@ -27,6 +32,8 @@ pub enum PaymentState {
     PaidToSeller,
     /// Need money payment to seller
     PaymentToSellerNeeded,
+    /// Customer disputed the charge, the order is frozen from payout until the dispute is resolved
+    Disputed,
 }
 
 #[derive(Debug, Clone, Fail)]
@@ -39,12 +46,14 @@ impl FromStr for PaymentState {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s.to_ascii_lowercase().as_str() {
             "initial" => Ok(PaymentState::Initial),
+            "payment_failed" => Ok(PaymentState::PaymentFailed),
             "declined" => Ok(PaymentState::Declined),
             "captured" => Ok(PaymentState::Captured),
             "refunded" => Ok(PaymentState::Refunded),
             "refund_needed" => Ok(PaymentState::RefundNeeded),
             "paid_to_seller" => Ok(PaymentState::PaidToSeller),
             "payment_to_seller_needed" => Ok(PaymentState::PaymentToSellerNeeded),
+            "disputed" => Ok(PaymentState::Disputed),
             _ => Err(ParsePaymentStateError),
         }
     }
@@ -54,12 +63,14 @@ impl FromSql<VarChar, Pg> for PaymentState {
     fn from_sql(data: Option<&[u8]>) -> deserialize::Result<Self> {
         match data {
             Some(b"initial") => Ok(PaymentState::Initial),
+            Some(b"payment_failed") => Ok(PaymentState::PaymentFailed),
             Some(b"declined") => Ok(PaymentState::Declined),
             Some(b"captured") => Ok(PaymentState::Captured),
             Some(b"refunded") => Ok(PaymentState::Refunded),
             Some(b"refund_needed") => Ok(PaymentState::RefundNeeded),
             Some(b"paid_to_seller") => Ok(PaymentState::PaidToSeller),
             Some(b"payment_to_seller_needed") => Ok(PaymentState::PaymentToSellerNeeded),
+            Some(b"disputed") => Ok(PaymentState::Disputed),
             Some(v) => Err(format!(
                 "Unrecognized enum variant: {:?}",
                 String::from_utf8(v.to_vec()).unwrap_or_else(|_| "Non - UTF8 value".to_string()),
@@ -75,12 +86,14 @@ impl ToSql<VarChar, Pg> for PaymentState {
     fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
         match self {
             PaymentState::Initial => out.write_all(b"initial")?,
+            PaymentState::PaymentFailed => out.write_all(b"payment_failed")?,
             PaymentState::Declined => out.write_all(b"declined")?,
             PaymentState::Captured => out.write_all(b"captured")?,
             PaymentState::Refunded => out.write_all(b"refunded")?,
             PaymentState::RefundNeeded => out.write_all(b"refund_needed")?,
             PaymentState::PaidToSeller => out.write_all(b"paid_to_seller")?,
             PaymentState::PaymentToSellerNeeded => out.write_all(b"payment_to_seller_needed")?,
+            PaymentState::Disputed => out.write_all(b"disputed")?,
         };
         Ok(IsNull::No)
     }
@@ -90,12 +103,150 @@ impl Display for PaymentState {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             PaymentState::Initial => f.write_str("initial"),
+            PaymentState::PaymentFailed => f.write_str("payment_failed"),
             PaymentState::Declined => f.write_str("declined"),
             PaymentState::Captured => f.write_str("captured"),
             PaymentState::Refunded => f.write_str("refunded"),
             PaymentState::RefundNeeded => f.write_str("refund_needed"),
             PaymentState::PaidToSeller => f.write_str("paid_to_seller"),
             PaymentState::PaymentToSellerNeeded => f.write_str("payment_to_seller_needed"),
+            PaymentState::Disputed => f.write_str("disputed"),
+        }
+    }
+}
+
+/// Payment state change that can be requested through the API
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PaymentStateTransition {
+    pub from: PaymentState,
+    pub to: PaymentState,
+    /// Roles allowed to trigger the transition, the ACL still checks access to the order itself
+    pub roles: &'static [BillingRole],
+    /// Webhook notification enqueued once the transition is done
+    pub webhook_event: Option<WebhookEventType>,
+    /// Event enqueued to carry out the transition
+    pub event: Option<PaymentStateTransitionEvent>,
+}
+
+/// Event the billing enqueues for a transition, the order is changed further by its handler
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaymentStateTransitionEvent {
+    /// `PaymentIntentCapture` captures the charge of a fiat order, the handler sets the order `Captured`
+    PaymentIntentCapture,
+    /// `CryptoRefundInitiated` sends the coins of a crypto order back to the buyer wallet, the handler sets the order `Refunded`
+    CryptoRefundInitiated,
+}
+
+const SELLER_ROLES: &[BillingRole] = &[BillingRole::Superuser, BillingRole::StoreManager];
+
+/// Transitions that can be requested through the API, handlers doing one of them check it against this table as well
+pub const PAYMENT_STATE_TRANSITIONS: &[PaymentStateTransition] = &[
+    PaymentStateTransition {
+        from: PaymentState::Initial,
+        to: PaymentState::Captured,
+        roles: SELLER_ROLES,
+        webhook_event: Some(WebhookEventType::OrderCaptured),
+        event: Some(PaymentStateTransitionEvent::PaymentIntentCapture),
+    },
+    PaymentStateTransition {
+        from: PaymentState::Initial,
+        to: PaymentState::Declined,
+        roles: SELLER_ROLES,
+        webhook_event: Some(WebhookEventType::OrderDeclined),
+        event: None,
+    },
+    PaymentStateTransition {
+        from: PaymentState::Captured,
+        to: PaymentState::RefundNeeded,
+        roles: SELLER_ROLES,
+        webhook_event: None,
+        event: Some(PaymentStateTransitionEvent::CryptoRefundInitiated),
+    },
+    PaymentStateTransition {
+        from: PaymentState::Captured,
+        to: PaymentState::PaymentToSellerNeeded,
+        roles: SELLER_ROLES,
+        webhook_event: None,
+        event: None,
+    },
+    PaymentStateTransition {
+        from: PaymentState::RefundNeeded,
+        to: PaymentState::Refunded,
+        roles: SELLER_ROLES,
+        webhook_event: Some(WebhookEventType::OrderRefunded),
+        event: None,
+    },
+    PaymentStateTransition {
+        from: PaymentState::PaymentToSellerNeeded,
+        to: PaymentState::PaidToSeller,
+        roles: SELLER_ROLES,
+        webhook_event: None,
+        event: None,
+    },
+];
+
+/// Transitions done only by the billing itself while handling payment events, no user can request them
+pub const SYSTEM_STATE_TRANSITIONS: &[(PaymentState, PaymentState)] = &[
+    (PaymentState::Initial, PaymentState::PaymentFailed),
+    (PaymentState::PaymentFailed, PaymentState::Initial),
+];
+
+impl PaymentStateTransition {
+    pub fn is_allowed_for(&self, roles: &[BillingRole]) -> bool {
+        self.roles.iter().any(|role| roles.contains(role))
+    }
+}
+
+impl PaymentState {
+    /// Transitions from this state that can be requested through the API
+    pub fn transitions(self) -> impl Iterator<Item = &'static PaymentStateTransition> {
+        PAYMENT_STATE_TRANSITIONS.iter().filter(move |transition| transition.from == self)
+    }
+
+    pub fn transition_to(self, to: PaymentState) -> Option<&'static PaymentStateTransition> {
+        self.transitions().find(|transition| transition.to == to)
+    }
+
+    /// Checks a transition only the billing does, these are never listed by `transitions`
+    pub fn has_system_transition_to(self, to: PaymentState) -> bool {
+        SYSTEM_STATE_TRANSITIONS.contains(&(self, to))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transition_to() {
+        let transition = PaymentState::Initial.transition_to(PaymentState::Captured).unwrap();
+        assert!(transition.is_allowed_for(&[BillingRole::StoreManager]));
+        assert!(!transition.is_allowed_for(&[BillingRole::User]));
+
+        let transition = PaymentState::RefundNeeded.transition_to(PaymentState::Refunded).unwrap();
+        assert!(transition.is_allowed_for(&[BillingRole::StoreManager]));
+        assert!(transition.is_allowed_for(&[BillingRole::Superuser]));
+
+        // payment failures are set by the payment event handlers only, not even a superuser can request them
+        assert!(PaymentState::Initial.transition_to(PaymentState::PaymentFailed).is_none());
+        assert!(PaymentState::PaymentFailed.transition_to(PaymentState::Initial).is_none());
+        assert!(PaymentState::Initial.has_system_transition_to(PaymentState::PaymentFailed));
+        assert!(!PaymentState::Initial.has_system_transition_to(PaymentState::Captured));
+
+        assert!(PaymentState::Captured.transition_to(PaymentState::Initial).is_none());
+
+        let transition = PaymentState::Initial.transition_to(PaymentState::Captured).unwrap();
+        assert_eq!(transition.event, Some(PaymentStateTransitionEvent::PaymentIntentCapture));
+        assert!(PaymentState::Disputed.transitions().next().is_none());
+    }
+
+    #[test]
+    fn test_transitions_are_unique() {
+        for (i, transition) in PAYMENT_STATE_TRANSITIONS.iter().enumerate() {
+            assert!(PAYMENT_STATE_TRANSITIONS[i + 1..]
+                .iter()
+                .all(|other| (other.from, other.to) != (transition.from, transition.to)));
+            assert!(!transition.from.has_system_transition_to(transition.to));
         }
     }
 }
@@ -0,0 +1,112 @@
+use std::fmt::{self, Display};
+
+use chrono::NaiveDateTime;
+use uuid::Uuid;
+
+use models::order_v2::OrderId;
+use models::{Amount, Currency, PayoutId, TransactionId, WalletAddress};
+use schema::refunds;
+
+#[derive(Clone, Copy, Debug, Display, PartialEq, Eq, From, FromStr, Hash, Serialize, Deserialize, DieselTypes)]
+pub struct RefundId(Uuid);
+
+impl RefundId {
+    pub fn new(id: Uuid) -> Self {
+        RefundId(id)
+    }
+
+    pub fn inner(&self) -> &Uuid {
+        &self.0
+    }
+
+    pub fn generate() -> Self {
+        RefundId(Uuid::new_v4())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, DieselTypes, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RefundStatus {
+    Pending,
+    Succeeded,
+    Failed,
+}
+
+impl Display for RefundStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RefundStatus::Pending => write!(f, "Pending"),
+            RefundStatus::Succeeded => write!(f, "Succeeded"),
+            RefundStatus::Failed => write!(f, "Failed"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, DieselTypes, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RefundKind {
+    /// Requested for a captured order, it is deducted from the amount paid to the seller
+    Refund,
+    /// Part of the charge the seller did not capture, released back to the buyer when the order is captured
+    CaptureRelease,
+}
+
+impl Display for RefundKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RefundKind::Refund => write!(f, "Refund"),
+            RefundKind::CaptureRelease => write!(f, "CaptureRelease"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Queryable)]
+pub struct Refund {
+    pub id: RefundId,
+    pub order_id: OrderId,
+    pub amount: Amount,
+    pub reason: Option<String>,
+    pub stripe_refund_id: Option<String>,
+    pub status: RefundStatus,
+    /// Part of the platform fee returned to the seller because of the refund
+    pub fee_adjustment: Amount,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    /// Currency the buyer paid in, set for crypto refunds
+    pub crypto_currency: Option<Currency>,
+    /// Refunded amount in the buyer currency, set for crypto refunds
+    pub crypto_amount: Option<Amount>,
+    pub wallet_address: Option<WalletAddress>,
+    pub transaction_id: Option<TransactionId>,
+    pub blockchain_fee: Option<Amount>,
+    pub kind: RefundKind,
+    /// Amount taken back from the seller balance because the order had already been paid out when it was refunded
+    pub balance_adjustment: Amount,
+    /// Payout the balance adjustment was deducted from, the adjustment is taken from the balance until then
+    pub settled_payout_id: Option<PayoutId>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Insertable)]
+#[table_name = "refunds"]
+pub struct NewRefund {
+    pub id: RefundId,
+    pub order_id: OrderId,
+    pub amount: Amount,
+    pub reason: Option<String>,
+    pub status: RefundStatus,
+    pub crypto_currency: Option<Currency>,
+    pub crypto_amount: Option<Amount>,
+    pub wallet_address: Option<WalletAddress>,
+    pub transaction_id: Option<TransactionId>,
+    pub kind: RefundKind,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, AsChangeset)]
+#[table_name = "refunds"]
+pub struct UpdateRefund {
+    pub stripe_refund_id: Option<String>,
+    pub status: Option<RefundStatus>,
+    pub fee_adjustment: Option<Amount>,
+    pub blockchain_fee: Option<Amount>,
+    pub balance_adjustment: Option<Amount>,
+}
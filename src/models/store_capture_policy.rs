@@ -0,0 +1,39 @@
+use std::fmt::{self, Display};
+
+use chrono::NaiveDateTime;
+
+use stq_types::StoreId;
+
+use schema::store_capture_policies;
+
+/// What happens to a paid order the seller has neither captured nor declined in time
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, DieselTypes, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureSlaAction {
+    Decline,
+    Capture,
+}
+
+impl Display for CaptureSlaAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CaptureSlaAction::Decline => write!(f, "Decline"),
+            CaptureSlaAction::Capture => write!(f, "Capture"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Queryable)]
+pub struct StoreCapturePolicy {
+    pub store_id: StoreId,
+    pub action: CaptureSlaAction,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Insertable, AsChangeset)]
+#[table_name = "store_capture_policies"]
+pub struct NewStoreCapturePolicy {
+    pub store_id: StoreId,
+    pub action: CaptureSlaAction,
+}
@@ -0,0 +1,17 @@
+use chrono::NaiveDateTime;
+
+use schema::stripe_webhook_events;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable)]
+pub struct StripeWebhookEvent {
+    pub stripe_event_id: String,
+    pub event_type: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Insertable)]
+#[table_name = "stripe_webhook_events"]
+pub struct NewStripeWebhookEvent {
+    pub stripe_event_id: String,
+    pub event_type: String,
+}
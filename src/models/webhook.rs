@@ -0,0 +1,353 @@
+use std::fmt::{self, Display};
+use std::io::Write;
+use std::str::FromStr;
+
+use chrono::{NaiveDateTime, Utc};
+use diesel::deserialize::{self, FromSql};
+use diesel::pg::Pg;
+use diesel::serialize::{self, IsNull, Output, ToSql};
+use diesel::sql_types::VarChar;
+use failure::Fail;
+use hex;
+use serde_json;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use models::invoice_v2::RawInvoice;
+use models::order_v2::{RawOrder, StoreId};
+use models::{Fee, Payout};
+use schema::{webhook_deliveries, webhook_subscriptions};
+
+const HMAC_SHA256_BLOCK_SIZE: usize = 64;
+
+#[derive(Clone, Copy, Debug, Display, PartialEq, Eq, From, FromStr, Hash, Serialize, Deserialize, DieselTypes)]
+pub struct WebhookSubscriptionId(Uuid);
+
+impl WebhookSubscriptionId {
+    pub fn new(id: Uuid) -> Self {
+        WebhookSubscriptionId(id)
+    }
+
+    pub fn inner(&self) -> &Uuid {
+        &self.0
+    }
+
+    pub fn generate() -> Self {
+        WebhookSubscriptionId(Uuid::new_v4())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Display, PartialEq, Eq, From, FromStr, Hash, Serialize, Deserialize, DieselTypes)]
+pub struct WebhookDeliveryId(i64);
+
+impl WebhookDeliveryId {
+    pub fn inner(&self) -> i64 {
+        self.0
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, FromSqlRow, AsExpression, Clone, Copy, Eq, PartialEq, Hash)]
+#[sql_type = "VarChar"]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEventType {
+    InvoicePaid,
+    InvoiceExpired,
+    InvoiceCancelled,
+    OrderCaptured,
+    OrderDeclined,
+    OrderRefunded,
+    FeePaid,
+    PayoutCompleted,
+}
+
+#[derive(Debug, Clone, Fail)]
+#[fail(display = "failed to parse webhook event type")]
+pub struct ParseWebhookEventTypeError;
+
+impl FromStr for WebhookEventType {
+    type Err = ParseWebhookEventTypeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "invoice_paid" => Ok(WebhookEventType::InvoicePaid),
+            "invoice_expired" => Ok(WebhookEventType::InvoiceExpired),
+            "invoice_cancelled" => Ok(WebhookEventType::InvoiceCancelled),
+            "order_captured" => Ok(WebhookEventType::OrderCaptured),
+            "order_declined" => Ok(WebhookEventType::OrderDeclined),
+            "order_refunded" => Ok(WebhookEventType::OrderRefunded),
+            "fee_paid" => Ok(WebhookEventType::FeePaid),
+            "payout_completed" => Ok(WebhookEventType::PayoutCompleted),
+            _ => Err(ParseWebhookEventTypeError),
+        }
+    }
+}
+
+impl Display for WebhookEventType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WebhookEventType::InvoicePaid => f.write_str("invoice_paid"),
+            WebhookEventType::InvoiceExpired => f.write_str("invoice_expired"),
+            WebhookEventType::InvoiceCancelled => f.write_str("invoice_cancelled"),
+            WebhookEventType::OrderCaptured => f.write_str("order_captured"),
+            WebhookEventType::OrderDeclined => f.write_str("order_declined"),
+            WebhookEventType::OrderRefunded => f.write_str("order_refunded"),
+            WebhookEventType::FeePaid => f.write_str("fee_paid"),
+            WebhookEventType::PayoutCompleted => f.write_str("payout_completed"),
+        }
+    }
+}
+
+impl FromSql<VarChar, Pg> for WebhookEventType {
+    fn from_sql(data: Option<&[u8]>) -> deserialize::Result<Self> {
+        match data {
+            Some(v) => {
+                let s = String::from_utf8(v.to_vec()).map_err(|_| "Non - UTF8 value".to_string())?;
+                WebhookEventType::from_str(&s).map_err(|_| format!("Unrecognized enum variant: {:?}", s).into())
+            }
+            None => Err("Unexpected null for non-null column".into()),
+        }
+    }
+}
+
+impl ToSql<VarChar, Pg> for WebhookEventType {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        out.write_all(self.to_string().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Queryable)]
+pub struct WebhookSubscription {
+    pub id: WebhookSubscriptionId,
+    pub store_id: Option<StoreId>,
+    pub url: String,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub event_types: Vec<WebhookEventType>,
+    pub is_active: bool,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+impl WebhookSubscription {
+    /// Subscriptions without a store receive notifications for all stores,
+    /// subscriptions without event types receive all event types
+    pub fn is_subscribed_to(&self, notification: &WebhookNotification) -> bool {
+        self.is_active
+            && self
+                .store_id
+                .map(|store_id| notification.store_ids.contains(&store_id))
+                .unwrap_or(true)
+            && (self.event_types.is_empty() || self.event_types.contains(&notification.event_type))
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Insertable)]
+#[table_name = "webhook_subscriptions"]
+pub struct NewWebhookSubscription {
+    pub id: WebhookSubscriptionId,
+    pub store_id: Option<StoreId>,
+    pub url: String,
+    pub secret: String,
+    pub event_types: Vec<WebhookEventType>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, AsChangeset)]
+#[table_name = "webhook_subscriptions"]
+pub struct UpdateWebhookSubscription {
+    pub url: Option<String>,
+    pub secret: Option<String>,
+    pub event_types: Option<Vec<WebhookEventType>>,
+    pub is_active: Option<bool>,
+}
+
+#[derive(Clone, Debug)]
+pub struct WebhookSubscriptionAccess {
+    pub store_id: Option<StoreId>,
+}
+
+impl<'r> From<&'r WebhookSubscription> for WebhookSubscriptionAccess {
+    fn from(subscription: &WebhookSubscription) -> WebhookSubscriptionAccess {
+        WebhookSubscriptionAccess {
+            store_id: subscription.store_id,
+        }
+    }
+}
+
+impl<'r> From<&'r NewWebhookSubscription> for WebhookSubscriptionAccess {
+    fn from(subscription: &NewWebhookSubscription) -> WebhookSubscriptionAccess {
+        WebhookSubscriptionAccess {
+            store_id: subscription.store_id,
+        }
+    }
+}
+
+/// Billing domain event delivered to the subscribed webhooks
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WebhookNotification {
+    pub id: Uuid,
+    pub event_type: WebhookEventType,
+    pub store_ids: Vec<StoreId>,
+    pub data: serde_json::Value,
+    pub created_at: NaiveDateTime,
+}
+
+impl WebhookNotification {
+    pub fn new(event_type: WebhookEventType, store_ids: Vec<StoreId>, data: serde_json::Value) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            event_type,
+            store_ids,
+            data,
+            created_at: Utc::now().naive_utc(),
+        }
+    }
+
+    pub fn for_invoice(event_type: WebhookEventType, invoice: &RawInvoice, orders: &[RawOrder]) -> Self {
+        let data = json!({
+            "invoice_id": invoice.id,
+            "buyer_currency": invoice.buyer_currency,
+            "amount_paid": invoice.final_amount_paid,
+            "paid_at": invoice.paid_at,
+            "order_ids": orders.iter().map(|order| order.id).collect::<Vec<_>>(),
+        });
+
+        Self::new(event_type, store_ids(orders), data)
+    }
+
+    pub fn for_order(event_type: WebhookEventType, order: &RawOrder) -> Self {
+        let data = json!({
+            "order_id": order.id,
+            "invoice_id": order.invoice_id,
+            "state": order.state,
+            "seller_currency": order.seller_currency,
+            "total_amount": order.total_amount,
+            "captured_amount": order.captured_amount,
+            "refunded_amount": order.refunded_amount,
+        });
+
+        Self::new(event_type, vec![order.store_id], data)
+    }
+
+    pub fn for_fee(fee: &Fee, store_id: StoreId) -> Self {
+        let data = json!({
+            "fee_id": fee.id,
+            "order_id": fee.order_id,
+            "amount": fee.amount,
+            "currency": fee.currency,
+            "charge_id": fee.charge_id,
+        });
+
+        Self::new(WebhookEventType::FeePaid, vec![store_id], data)
+    }
+
+    pub fn for_payout(payout: &Payout, orders: &[RawOrder]) -> Self {
+        let data = json!({
+            "payout_id": payout.id,
+            "currency": payout.currency(),
+            "gross_amount": payout.gross_amount,
+            "net_amount": payout.net_amount,
+            "order_ids": payout.order_ids,
+        });
+
+        Self::new(WebhookEventType::PayoutCompleted, store_ids(orders), data)
+    }
+}
+
+fn store_ids(orders: &[RawOrder]) -> Vec<StoreId> {
+    let mut store_ids = orders.iter().map(|order| order.store_id).collect::<Vec<_>>();
+    store_ids.sort();
+    store_ids.dedup();
+    store_ids
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, DieselTypes, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookDeliveryStatus {
+    Delivered,
+    Failed,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Queryable)]
+pub struct WebhookDelivery {
+    pub id: WebhookDeliveryId,
+    pub subscription_id: WebhookSubscriptionId,
+    pub notification_id: Uuid,
+    pub event_type: WebhookEventType,
+    pub payload: serde_json::Value,
+    pub status: WebhookDeliveryStatus,
+    pub error_message: Option<String>,
+    pub attempted_at: NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Insertable)]
+#[table_name = "webhook_deliveries"]
+pub struct NewWebhookDelivery {
+    pub subscription_id: WebhookSubscriptionId,
+    pub notification_id: Uuid,
+    pub event_type: WebhookEventType,
+    pub payload: serde_json::Value,
+    pub status: WebhookDeliveryStatus,
+    pub error_message: Option<String>,
+}
+
+/// Hex-encoded HMAC-SHA256 of the webhook body, sent in the `X-Billing-Signature` header
+pub fn sign_webhook_payload(secret: &str, payload: &str) -> String {
+    let mut key = secret.as_bytes().to_vec();
+    if key.len() > HMAC_SHA256_BLOCK_SIZE {
+        key = Sha256::digest(&key).to_vec();
+    }
+    key.resize(HMAC_SHA256_BLOCK_SIZE, 0);
+
+    let inner_key_pad = key.iter().map(|byte| byte ^ 0x36).collect::<Vec<u8>>();
+    let outer_key_pad = key.iter().map(|byte| byte ^ 0x5c).collect::<Vec<u8>>();
+
+    let mut inner_hasher = Sha256::new();
+    inner_hasher.input(&inner_key_pad);
+    inner_hasher.input(payload.as_bytes());
+    let inner_hash = inner_hasher.result();
+
+    let mut outer_hasher = Sha256::new();
+    outer_hasher.input(&outer_key_pad);
+    outer_hasher.input(inner_hash.as_slice());
+    hex::encode(outer_hasher.result().as_slice())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_subscription(store_id: Option<StoreId>, event_types: Vec<WebhookEventType>) -> WebhookSubscription {
+        WebhookSubscription {
+            id: WebhookSubscriptionId::generate(),
+            store_id,
+            url: "https://example.com/webhooks".to_string(),
+            secret: "secret".to_string(),
+            event_types,
+            is_active: true,
+            created_at: NaiveDateTime::from_timestamp(0, 0),
+            updated_at: NaiveDateTime::from_timestamp(0, 0),
+        }
+    }
+
+    #[test]
+    fn test_sign_webhook_payload() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign_webhook_payload("Jefe", "what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_subscription_filters_by_store_and_event_type() {
+        // given
+        let notification = WebhookNotification::new(WebhookEventType::OrderCaptured, vec![StoreId::new(1)], json!({}));
+
+        // then
+        assert!(create_subscription(None, vec![]).is_subscribed_to(&notification));
+        assert!(create_subscription(Some(StoreId::new(1)), vec![WebhookEventType::OrderCaptured]).is_subscribed_to(&notification));
+        assert!(!create_subscription(Some(StoreId::new(2)), vec![]).is_subscribed_to(&notification));
+        assert!(!create_subscription(None, vec![WebhookEventType::InvoicePaid]).is_subscribed_to(&notification));
+    }
+}
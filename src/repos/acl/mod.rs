@@ -73,6 +73,14 @@ impl ApplicationAcl {
                 permission!(Resource::StoreSubscription),
                 permission!(Resource::StoreSubscriptionStatus),
                 permission!(Resource::SubscriptionPayment),
+                permission!(Resource::EventStore),
+                permission!(Resource::WebhookSubscription),
+                permission!(Resource::Refund),
+                permission!(Resource::OrderStateTransition),
+                permission!(Resource::StoreCapturePolicy),
+                permission!(Resource::Dispute),
+                permission!(Resource::InvoiceSurplus),
+                permission!(Resource::Document),
             ],
         );
         hash.insert(
@@ -95,6 +103,7 @@ impl ApplicationAcl {
                 permission!(Resource::UserWallet, Action::Write, Scope::Owned),
                 permission!(Resource::Payout, Action::Read, Scope::Owned),
                 permission!(Resource::Payout, Action::Write, Scope::Owned),
+                permission!(Resource::Document, Action::Read, Scope::Owned),
             ],
         );
         hash.insert(
@@ -120,6 +129,15 @@ impl ApplicationAcl {
                 permission!(Resource::Payout, Action::Write, Scope::Owned),
                 permission!(Resource::StoreSubscription, Action::Read, Scope::Owned),
                 permission!(Resource::StoreSubscription, Action::Write, Scope::Owned),
+                permission!(Resource::WebhookSubscription, Action::Read, Scope::Owned),
+                permission!(Resource::WebhookSubscription, Action::Write, Scope::Owned),
+                permission!(Resource::Refund, Action::Read, Scope::Owned),
+                permission!(Resource::Refund, Action::Write, Scope::Owned),
+                permission!(Resource::OrderStateTransition, Action::Read, Scope::Owned),
+                permission!(Resource::StoreCapturePolicy, Action::Read, Scope::Owned),
+                permission!(Resource::StoreCapturePolicy, Action::Write, Scope::Owned),
+                permission!(Resource::Dispute, Action::Read, Scope::Owned),
+                permission!(Resource::Document, Action::Read, Scope::Owned),
             ],
         );
         hash.insert(
@@ -144,6 +162,13 @@ impl ApplicationAcl {
                 permission!(Resource::StoreSubscriptionStatus, Action::Read),
                 permission!(Resource::StoreSubscriptionStatus, Action::Write),
                 permission!(Resource::SubscriptionPayment, Action::Read),
+                permission!(Resource::Refund, Action::Read),
+                permission!(Resource::Refund, Action::Write),
+                permission!(Resource::OrderStateTransition, Action::Read),
+                permission!(Resource::StoreCapturePolicy, Action::Read),
+                permission!(Resource::Dispute, Action::Read),
+                permission!(Resource::InvoiceSurplus, Action::Read),
+                permission!(Resource::Document, Action::Read),
             ],
         );
         ApplicationAcl {
@@ -286,4 +311,21 @@ mod tests {
         assert_eq!(acl.allows(Resource::UserRoles, Action::Read, &s, Some(&resource)).unwrap(), false);
         assert_eq!(acl.allows(Resource::UserRoles, Action::Write, &s, Some(&resource)).unwrap(), false);
     }
+
+    #[test]
+    fn test_event_store_is_available_to_super_user_only() {
+        let s = ScopeChecker::default();
+        let resource = create_order();
+
+        let acl = ApplicationAcl::new(vec![BillingRole::Superuser], UserId(1));
+        assert_eq!(acl.allows(Resource::EventStore, Action::Read, &s, Some(&resource)).unwrap(), true);
+        assert_eq!(acl.allows(Resource::EventStore, Action::Write, &s, Some(&resource)).unwrap(), true);
+
+        let acl = ApplicationAcl::new(
+            vec![BillingRole::User, BillingRole::StoreManager, BillingRole::FinancialManager],
+            UserId(2),
+        );
+        assert_eq!(acl.allows(Resource::EventStore, Action::Read, &s, Some(&resource)).unwrap(), false);
+        assert_eq!(acl.allows(Resource::EventStore, Action::Write, &s, Some(&resource)).unwrap(), false);
+    }
 }
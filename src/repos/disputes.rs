@@ -0,0 +1,220 @@
+use diesel;
+use diesel::connection::AnsiTransactionManager;
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::query_dsl::RunQueryDsl;
+use diesel::Connection;
+use failure::Error as FailureError;
+use failure::Fail;
+
+use stq_types::UserId;
+
+use models::authorization::*;
+use models::order_v2::{OrderId, StoreId};
+use models::{Amount, Currency, Dispute, DisputeId, DisputeStatus, NewDispute, PayoutId, UpdateDispute, UserRole};
+use repos::legacy_acl::*;
+
+use schema::disputes::dsl as DisputesDsl;
+use schema::orders::dsl as OrdersDsl;
+use schema::roles::dsl as UserRolesDsl;
+
+use super::acl;
+use super::error::*;
+use super::types::RepoResultV2;
+
+type DisputesRepoAcl = Box<Acl<Resource, Action, Scope, FailureError, OrderId>>;
+
+pub struct DisputesRepoImpl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> {
+    pub db_conn: &'a T,
+    pub acl: DisputesRepoAcl,
+}
+
+pub trait DisputesRepo {
+    fn create(&self, payload: NewDispute) -> RepoResultV2<Dispute>;
+    /// Lists the parts of a Stripe dispute, one for each disputed order
+    fn list_by_stripe_dispute_id(&self, stripe_dispute_id: String) -> RepoResultV2<Vec<Dispute>>;
+    fn list_by_order_id(&self, order_id: OrderId) -> RepoResultV2<Vec<Dispute>>;
+    /// Lists the lost disputes of the store orders whose balance adjustment has not been deducted from a payout yet
+    fn list_unsettled_adjustments_by_store_id(&self, store_id: StoreId, currency: Option<Currency>) -> RepoResultV2<Vec<Dispute>>;
+    /// Marks the balance adjustments of the disputes as deducted from the payout
+    fn settle_adjustments(&self, dispute_ids: Vec<DisputeId>, payout_id: PayoutId) -> RepoResultV2<Vec<Dispute>>;
+    fn update(&self, dispute_id: DisputeId, payload: UpdateDispute) -> RepoResultV2<Dispute>;
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> DisputesRepoImpl<'a, T> {
+    pub fn new(db_conn: &'a T, acl: DisputesRepoAcl) -> Self {
+        Self { db_conn, acl }
+    }
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> DisputesRepo for DisputesRepoImpl<'a, T> {
+    fn create(&self, payload: NewDispute) -> RepoResultV2<Dispute> {
+        debug!("Creating a dispute: {:?}", payload);
+
+        acl::check(&*self.acl, Resource::Dispute, Action::Write, self, Some(&payload.order_id)).map_err(ectx!(try ErrorKind::Forbidden))?;
+
+        let command = diesel::insert_into(DisputesDsl::disputes).values(&payload);
+
+        command.get_result::<Dispute>(self.db_conn).map_err(|e| {
+            let error_kind = ErrorKind::from(&e);
+            ectx!(err e, ErrorSource::Diesel, error_kind)
+        })
+    }
+
+    fn list_by_stripe_dispute_id(&self, stripe_dispute_id: String) -> RepoResultV2<Vec<Dispute>> {
+        debug!("Listing disputes with Stripe dispute ID: {}", stripe_dispute_id);
+
+        let disputes = DisputesDsl::disputes
+            .filter(DisputesDsl::stripe_dispute_id.eq(stripe_dispute_id))
+            .order(DisputesDsl::created_at)
+            .get_results::<Dispute>(self.db_conn)
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(try err e, ErrorSource::Diesel, error_kind)
+            })?;
+
+        for dispute in &disputes {
+            acl::check(&*self.acl, Resource::Dispute, Action::Read, self, Some(&dispute.order_id))
+                .map_err(ectx!(try ErrorKind::Forbidden))?;
+        }
+
+        Ok(disputes)
+    }
+
+    fn list_by_order_id(&self, order_id: OrderId) -> RepoResultV2<Vec<Dispute>> {
+        debug!("Listing disputes of order with ID: {}", order_id);
+
+        acl::check(&*self.acl, Resource::Dispute, Action::Read, self, Some(&order_id)).map_err(ectx!(try ErrorKind::Forbidden))?;
+
+        DisputesDsl::disputes
+            .filter(DisputesDsl::order_id.eq(order_id))
+            .order(DisputesDsl::created_at)
+            .get_results::<Dispute>(self.db_conn)
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(err e, ErrorSource::Diesel, error_kind)
+            })
+    }
+
+    fn list_unsettled_adjustments_by_store_id(&self, store_id: StoreId, currency: Option<Currency>) -> RepoResultV2<Vec<Dispute>> {
+        debug!("Listing unsettled balance adjustments of store with ID: {}", store_id);
+
+        let query = DisputesDsl::disputes
+            .inner_join(OrdersDsl::orders)
+            .filter(OrdersDsl::store_id.eq(store_id))
+            .filter(DisputesDsl::status.eq(DisputeStatus::Lost))
+            .filter(DisputesDsl::balance_adjustment.gt(Amount::zero()))
+            .filter(DisputesDsl::settled_payout_id.is_null())
+            .select(::schema::disputes::all_columns)
+            .into_boxed();
+
+        let query = match currency {
+            None => query,
+            Some(currency) => query.filter(DisputesDsl::currency.eq(currency)),
+        };
+
+        let disputes = query
+            .order(DisputesDsl::closed_at)
+            .get_results::<Dispute>(self.db_conn)
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(try err e, ErrorSource::Diesel, error_kind)
+            })?;
+
+        for dispute in &disputes {
+            acl::check(&*self.acl, Resource::Dispute, Action::Read, self, Some(&dispute.order_id))
+                .map_err(ectx!(try ErrorKind::Forbidden))?;
+        }
+
+        Ok(disputes)
+    }
+
+    fn settle_adjustments(&self, dispute_ids: Vec<DisputeId>, payout_id: PayoutId) -> RepoResultV2<Vec<Dispute>> {
+        debug!(
+            "Settling balance adjustments of disputes {:?} with payout {}",
+            dispute_ids, payout_id
+        );
+
+        let disputes = DisputesDsl::disputes
+            .filter(DisputesDsl::id.eq_any(dispute_ids.clone()))
+            .get_results::<Dispute>(self.db_conn)
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(try err e, ErrorSource::Diesel, error_kind)
+            })?;
+
+        for dispute in &disputes {
+            acl::check(&*self.acl, Resource::Dispute, Action::Write, self, Some(&dispute.order_id))
+                .map_err(ectx!(try ErrorKind::Forbidden))?;
+        }
+
+        // an adjustment already deducted from another payout is left as it is
+        let command = diesel::update(
+            DisputesDsl::disputes
+                .filter(DisputesDsl::id.eq_any(dispute_ids))
+                .filter(DisputesDsl::settled_payout_id.is_null()),
+        )
+        .set(DisputesDsl::settled_payout_id.eq(payout_id));
+
+        command.get_results::<Dispute>(self.db_conn).map_err(|e| {
+            let error_kind = ErrorKind::from(&e);
+            ectx!(err e, ErrorSource::Diesel, error_kind)
+        })
+    }
+
+    fn update(&self, dispute_id: DisputeId, payload: UpdateDispute) -> RepoResultV2<Dispute> {
+        debug!("Updating a dispute with ID: {} using payload: {:?}", dispute_id, payload);
+
+        let dispute = DisputesDsl::disputes
+            .filter(DisputesDsl::id.eq(dispute_id))
+            .get_result::<Dispute>(self.db_conn)
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(try err e, ErrorSource::Diesel, error_kind)
+            })?;
+
+        acl::check(&*self.acl, Resource::Dispute, Action::Write, self, Some(&dispute.order_id)).map_err(ectx!(try ErrorKind::Forbidden))?;
+
+        let command = diesel::update(DisputesDsl::disputes.filter(DisputesDsl::id.eq(dispute_id))).set(&payload);
+
+        command.get_result::<Dispute>(self.db_conn).map_err(|e| {
+            let error_kind = ErrorKind::from(&e);
+            ectx!(err e, ErrorSource::Diesel, error_kind)
+        })
+    }
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> CheckScope<Scope, OrderId>
+    for DisputesRepoImpl<'a, T>
+{
+    fn is_in_scope(&self, user_id: UserId, scope: &Scope, obj: Option<&OrderId>) -> bool {
+        match *scope {
+            Scope::All => true,
+            Scope::Owned => {
+                if let Some(order_id) = obj {
+                    let store_id = match OrdersDsl::orders
+                        .filter(OrdersDsl::id.eq(order_id))
+                        .select(OrdersDsl::store_id)
+                        .get_result::<StoreId>(self.db_conn)
+                    {
+                        Ok(store_id) => store_id,
+                        Err(_) => return false,
+                    };
+
+                    UserRolesDsl::roles
+                        .filter(UserRolesDsl::user_id.eq(user_id))
+                        .get_results::<UserRole>(self.db_conn)
+                        .map_err(From::from)
+                        .map(|user_roles_arg| {
+                            user_roles_arg
+                                .iter()
+                                .any(|user_role_arg| user_role_arg.data.clone().map(|data| data == store_id.inner()).unwrap_or_default())
+                        })
+                        .unwrap_or_else(|_: FailureError| false)
+                } else {
+                    false
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use diesel;
+use diesel::connection::AnsiTransactionManager;
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::query_dsl::RunQueryDsl;
+use diesel::Connection;
+use failure::Error as FailureError;
+use failure::Fail;
+
+use stq_types::{StoreId, UserId};
+
+use models::authorization::*;
+use models::invoice_v2::InvoiceId;
+use models::{Document, DocumentAccess, DocumentId, DocumentKind, FeeId, NewDocument, UserRole};
+use repos::legacy_acl::*;
+
+use schema::document_sequences::dsl as DocumentSequencesDsl;
+use schema::documents::dsl as DocumentsDsl;
+use schema::issuer_document_sequences::dsl as IssuerDocumentSequencesDsl;
+use schema::roles::dsl as UserRolesDsl;
+
+use super::acl;
+use super::error::*;
+use super::types::RepoResultV2;
+
+type DocumentsRepoAcl = Box<Acl<Resource, Action, Scope, FailureError, DocumentAccess>>;
+
+pub struct DocumentsRepoImpl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> {
+    pub db_conn: &'a T,
+    pub acl: DocumentsRepoAcl,
+}
+
+pub trait DocumentsRepo {
+    fn create(&self, payload: NewDocument) -> RepoResultV2<Document>;
+    fn get(&self, document_id: DocumentId) -> RepoResultV2<Option<Document>>;
+    /// Documents of the invoice the user may read, the others are left out
+    fn list_by_invoice_id(&self, invoice_id: InvoiceId) -> RepoResultV2<Vec<Document>>;
+    fn get_by_fee_id(&self, fee_id: FeeId) -> RepoResultV2<Option<Document>>;
+    /// Reserves the next number of the documents of this kind issued for the store, numbers start with 1
+    fn next_number(&self, store_id: StoreId, kind: DocumentKind) -> RepoResultV2<i64>;
+    /// Reserves the next number of the documents of this kind issued by the marketplace, one sequence for all stores
+    fn next_issuer_number(&self, kind: DocumentKind) -> RepoResultV2<i64>;
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> DocumentsRepoImpl<'a, T> {
+    pub fn new(db_conn: &'a T, acl: DocumentsRepoAcl) -> Self {
+        Self { db_conn, acl }
+    }
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> DocumentsRepo for DocumentsRepoImpl<'a, T> {
+    fn create(&self, payload: NewDocument) -> RepoResultV2<Document> {
+        debug!("Creating a document: {:?}", payload);
+
+        acl::check(
+            &*self.acl,
+            Resource::Document,
+            Action::Write,
+            self,
+            Some(&DocumentAccess {
+                store_id: payload.store_id,
+                buyer_user_id: payload.buyer_user_id,
+            }),
+        )
+        .map_err(ectx!(try ErrorKind::Forbidden))?;
+
+        let command = diesel::insert_into(DocumentsDsl::documents).values(&payload);
+
+        command.get_result::<Document>(self.db_conn).map_err(|e| {
+            let error_kind = ErrorKind::from(&e);
+            ectx!(err e, ErrorSource::Diesel, error_kind)
+        })
+    }
+
+    fn get(&self, document_id: DocumentId) -> RepoResultV2<Option<Document>> {
+        debug!("Getting a document with ID: {}", document_id);
+
+        let document = DocumentsDsl::documents
+            .filter(DocumentsDsl::id.eq(document_id))
+            .get_result::<Document>(self.db_conn)
+            .optional()
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(try err e, ErrorSource::Diesel, error_kind)
+            })?;
+
+        if let Some(ref document) = document {
+            acl::check(
+                &*self.acl,
+                Resource::Document,
+                Action::Read,
+                self,
+                Some(&DocumentAccess::from(document)),
+            )
+            .map_err(ectx!(try ErrorKind::Forbidden))?;
+        }
+
+        Ok(document)
+    }
+
+    fn list_by_invoice_id(&self, invoice_id: InvoiceId) -> RepoResultV2<Vec<Document>> {
+        debug!("Getting documents of invoice with ID: {}", invoice_id);
+
+        let documents = DocumentsDsl::documents
+            .filter(DocumentsDsl::invoice_id.eq(invoice_id))
+            .order(DocumentsDsl::created_at)
+            .get_results::<Document>(self.db_conn)
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(try err e, ErrorSource::Diesel, error_kind)
+            })?;
+
+        // a store manager sees only the receipt of their own store among those of the invoice
+        Ok(documents
+            .into_iter()
+            .filter(|document| {
+                acl::check(
+                    &*self.acl,
+                    Resource::Document,
+                    Action::Read,
+                    self,
+                    Some(&DocumentAccess::from(document)),
+                )
+                .is_ok()
+            })
+            .collect())
+    }
+
+    fn get_by_fee_id(&self, fee_id: FeeId) -> RepoResultV2<Option<Document>> {
+        debug!("Getting a document of fee with ID: {}", fee_id);
+
+        let document = DocumentsDsl::documents
+            .filter(DocumentsDsl::fee_id.eq(fee_id))
+            .get_result::<Document>(self.db_conn)
+            .optional()
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(try err e, ErrorSource::Diesel, error_kind)
+            })?;
+
+        if let Some(ref document) = document {
+            acl::check(
+                &*self.acl,
+                Resource::Document,
+                Action::Read,
+                self,
+                Some(&DocumentAccess::from(document)),
+            )
+            .map_err(ectx!(try ErrorKind::Forbidden))?;
+        }
+
+        Ok(document)
+    }
+
+    fn next_number(&self, store_id: StoreId, kind: DocumentKind) -> RepoResultV2<i64> {
+        debug!("Getting the next number of {} documents of store with ID: {}", kind, store_id);
+
+        acl::check(
+            &*self.acl,
+            Resource::Document,
+            Action::Write,
+            self,
+            Some(&DocumentAccess {
+                store_id,
+                buyer_user_id: None,
+            }),
+        )
+        .map_err(ectx!(try ErrorKind::Forbidden))?;
+
+        let command = diesel::insert_into(DocumentSequencesDsl::document_sequences)
+            .values((
+                DocumentSequencesDsl::store_id.eq(store_id),
+                DocumentSequencesDsl::kind.eq(kind),
+                DocumentSequencesDsl::last_number.eq(1i64),
+            ))
+            .on_conflict((DocumentSequencesDsl::store_id, DocumentSequencesDsl::kind))
+            .do_update()
+            .set(DocumentSequencesDsl::last_number.eq(DocumentSequencesDsl::last_number + 1i64))
+            .returning(DocumentSequencesDsl::last_number);
+
+        command.get_result::<i64>(self.db_conn).map_err(|e| {
+            let error_kind = ErrorKind::from(&e);
+            ectx!(err e, ErrorSource::Diesel, error_kind)
+        })
+    }
+
+    fn next_issuer_number(&self, kind: DocumentKind) -> RepoResultV2<i64> {
+        debug!("Getting the next number of {} documents of the marketplace", kind);
+
+        acl::check(&*self.acl, Resource::Document, Action::Write, self, None).map_err(ectx!(try ErrorKind::Forbidden))?;
+
+        let command = diesel::insert_into(IssuerDocumentSequencesDsl::issuer_document_sequences)
+            .values((
+                IssuerDocumentSequencesDsl::kind.eq(kind),
+                IssuerDocumentSequencesDsl::last_number.eq(1i64),
+            ))
+            .on_conflict(IssuerDocumentSequencesDsl::kind)
+            .do_update()
+            .set(IssuerDocumentSequencesDsl::last_number.eq(IssuerDocumentSequencesDsl::last_number + 1i64))
+            .returning(IssuerDocumentSequencesDsl::last_number);
+
+        command.get_result::<i64>(self.db_conn).map_err(|e| {
+            let error_kind = ErrorKind::from(&e);
+            ectx!(err e, ErrorSource::Diesel, error_kind)
+        })
+    }
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> CheckScope<Scope, DocumentAccess>
+    for DocumentsRepoImpl<'a, T>
+{
+    fn is_in_scope(&self, user_id: UserId, scope: &Scope, obj: Option<&DocumentAccess>) -> bool {
+        match *scope {
+            Scope::All => true,
+            Scope::Owned => {
+                if let Some(DocumentAccess { store_id, buyer_user_id }) = obj {
+                    if buyer_user_id
+                        .map(|buyer_user_id| buyer_user_id.inner() == user_id.0)
+                        .unwrap_or_default()
+                    {
+                        return true;
+                    }
+
+                    UserRolesDsl::roles
+                        .filter(UserRolesDsl::user_id.eq(user_id))
+                        .get_results::<UserRole>(self.db_conn)
+                        .map_err(From::from)
+                        .map(|user_roles_arg| {
+                            user_roles_arg
+                                .iter()
+                                .any(|user_role_arg| user_role_arg.data.clone().map(|data| data == store_id.0).unwrap_or_default())
+                        })
+                        .unwrap_or_else(|_: FailureError| false)
+                } else {
+                    false
+                }
+            }
+        }
+    }
+}
@@ -3,16 +3,33 @@ use diesel::connection::AnsiTransactionManager;
 use diesel::pg::Pg;
 use diesel::query_dsl::RunQueryDsl;
 use diesel::sql_types;
-use diesel::{sql_query, Connection, ExpressionMethods, QueryDsl};
-use failure::Fail;
+use diesel::{sql_query, Connection, ExpressionMethods, OptionalExtension, QueryDsl};
+use failure::{Error as FailureError, Fail};
 use std::str::FromStr;
-
-use models::{Event, EventEntry, EventEntryId, EventStatus, RawEventEntry, RawNewEventEntry};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use models::authorization::*;
+use models::{
+    Event, EventAttempt, EventEntry, EventEntryId, EventEntrySearch, EventEntrySearchResults, EventReplaySearch, EventRetryPolicies,
+    EventStatus, NewEventAttempt, RawEventEntry, RawNewEventEntry,
+};
+use repos::legacy_acl::*;
+use schema::event_attempts::dsl as EventAttempts;
 use schema::event_store::dsl as EventStore;
 
+use super::acl;
 use super::error::*;
 use super::types::RepoResultV2;
 
+type EventStoreRepoAcl = Box<Acl<Resource, Action, Scope, FailureError, EventEntry>>;
+
+#[derive(QueryableByName)]
+struct EventEntryCount {
+    #[sql_type = "sql_types::BigInt"]
+    count: i64,
+}
+
 pub trait EventStoreRepo {
     fn add_event(&self, event: Event) -> RepoResultV2<EventEntry>;
 
@@ -25,20 +42,74 @@ pub trait EventStoreRepo {
     fn complete_event(&self, event_entry_id: EventEntryId) -> RepoResultV2<EventEntry>;
 
     fn fail_event(&self, event_entry_id: EventEntryId) -> RepoResultV2<EventEntry>;
+
+    fn get_event(&self, event_entry_id: EventEntryId) -> RepoResultV2<Option<EventEntry>>;
+
+    fn search_events(&self, skip: i64, count: i64, search: EventEntrySearch) -> RepoResultV2<EventEntrySearchResults>;
+
+    /// Gets up to `limit` completed or failed events matching the search, ordered by ID
+    fn get_events_for_replay(&self, search: EventReplaySearch, limit: i64) -> RepoResultV2<Vec<EventEntry>>;
+
+    /// Resets failed or manually resolved events so that they are picked up again with a fresh attempt counter,
+    /// pending events keep their schedule and are left untouched
+    fn requeue_events(&self, event_entry_ids: Vec<EventEntryId>) -> RepoResultV2<Vec<EventEntry>>;
+
+    /// Marks a failed or pending event as resolved by hand so that it is never processed again
+    fn resolve_event(&self, event_entry_id: EventEntryId) -> RepoResultV2<Option<EventEntry>>;
+
+    fn add_event_attempt(&self, new_event_attempt: NewEventAttempt) -> RepoResultV2<EventAttempt>;
+
+    fn get_event_attempts(&self, event_entry_id: EventEntryId) -> RepoResultV2<Vec<EventAttempt>>;
+
+    /// Moves up to `limit` events completed before `completed_before` to `event_store_archive` keeping their attempts,
+    /// returns the number of moved events
+    fn archive_completed_events(&self, completed_before: NaiveDateTime, limit: u32) -> RepoResultV2<usize>;
+
+    /// Deletes up to `limit` events completed before `completed_before` with their attempts, returns the number of deleted events
+    fn delete_completed_events(&self, completed_before: NaiveDateTime, limit: u32) -> RepoResultV2<usize>;
 }
 
 pub struct EventStoreRepoImpl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> {
     pub db_conn: &'a T,
+    pub acl: EventStoreRepoAcl,
     pub max_processing_attempts: u32,
     pub stuck_threshold_sec: u32,
+    pub retry_policies: Arc<EventRetryPolicies>,
 }
 
 impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> EventStoreRepoImpl<'a, T> {
-    pub fn new(db_conn: &'a T, max_processing_attempts: u32, stuck_threshold_sec: u32) -> Self {
+    pub fn new(
+        db_conn: &'a T,
+        acl: EventStoreRepoAcl,
+        max_processing_attempts: u32,
+        stuck_threshold_sec: u32,
+        retry_policies: Arc<EventRetryPolicies>,
+    ) -> Self {
         Self {
             db_conn,
+            acl,
             max_processing_attempts,
             stuck_threshold_sec,
+            retry_policies,
+        }
+    }
+
+    /// Status and schedule of an event after an unfinished attempt: it is retried after the backoff delay
+    /// of its payload until it runs out of attempts
+    fn next_attempt(&self, event_entry: &EventEntry, now: NaiveDateTime) -> (EventStatus, Option<NaiveDateTime>) {
+        let retry_policy = self.retry_policies.get(&event_entry.event.payload);
+        let max_processing_attempts = retry_policy.max_processing_attempts.unwrap_or(self.max_processing_attempts);
+
+        if event_entry.attempt_count >= max_processing_attempts {
+            (EventStatus::Failed, event_entry.scheduled_on)
+        } else {
+            let jitter_seed = Uuid::new_v4()
+                .as_bytes()
+                .iter()
+                .take(4)
+                .fold(0u32, |seed, byte| (seed << 8) | u32::from(*byte));
+            let retry_delay = retry_policy.retry_delay(event_entry.attempt_count, jitter_seed);
+            (EventStatus::Pending, Some(now + retry_delay))
         }
     }
 }
@@ -47,6 +118,8 @@ impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager
     fn add_event(&self, event: Event) -> RepoResultV2<EventEntry> {
         trace!("Adding an event with ID: {}", event.id);
 
+        acl::check(&*self.acl, Resource::EventStore, Action::Write, self, None).map_err(ectx!(try ErrorKind::Forbidden))?;
+
         let new_event_entry =
             RawNewEventEntry::try_from_event(event.clone()).map_err(ectx!(try ErrorSource::SerdeJson, ErrorKind::Internal => event))?;
 
@@ -69,6 +142,8 @@ impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager
             scheduled_on.format("%Y-%m-%d %H:%M:%S")
         );
 
+        acl::check(&*self.acl, Resource::EventStore, Action::Write, self, None).map_err(ectx!(try ErrorKind::Forbidden))?;
+
         let new_event_entry = RawNewEventEntry::try_from_event_scheduled_on(event.clone(), scheduled_on)
             .map_err(ectx!(try ErrorSource::SerdeJson, ErrorKind::Internal => event))?;
 
@@ -87,6 +162,8 @@ impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager
     fn get_events_for_processing(&self, limit: u32) -> RepoResultV2<Vec<EventEntry>> {
         trace!("Getting events for processing (limit: {})", limit);
 
+        acl::check(&*self.acl, Resource::EventStore, Action::Write, self, None).map_err(ectx!(try ErrorKind::Forbidden))?;
+
         let now = Utc::now().naive_utc();
 
         let command = sql_query(
@@ -134,6 +211,8 @@ impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager
             self.stuck_threshold_sec
         );
 
+        acl::check(&*self.acl, Resource::EventStore, Action::Write, self, None).map_err(ectx!(try ErrorKind::Forbidden))?;
+
         let stuck_threshold = chrono::NaiveTime::from_num_seconds_from_midnight_opt(self.stuck_threshold_sec, 0).ok_or({
             let e = format_err!("Invalid number of seconds for stuck threshold: {}", self.stuck_threshold_sec);
             ectx!(try err e, ErrorKind::Internal)
@@ -141,46 +220,58 @@ impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager
 
         let now = chrono::Utc::now().naive_utc();
 
-        let command = sql_query(
-            "
-            UPDATE event_store
-            SET
-                status = CASE WHEN attempt_count >= $1 THEN $2 ELSE $3 END,
-                status_updated_at = $4
-            WHERE id IN (
-                SELECT id
+        self.db_conn.transaction(|| {
+            let raw_stuck_event_entries = sql_query(
+                "
+                SELECT *
                 FROM event_store
-                WHERE status = $5 AND status_updated_at + $6 < $7
+                WHERE status = $1 AND status_updated_at + $2 < $3
                 FOR UPDATE SKIP LOCKED
+            ",
             )
-            RETURNING *
-        ",
-        )
-        .bind::<sql_types::Integer, _>(self.max_processing_attempts as i32)
-        .bind::<sql_types::VarChar, _>(EventStatus::Failed.to_string())
-        .bind::<sql_types::VarChar, _>(EventStatus::Pending.to_string())
-        .bind::<sql_types::Timestamp, _>(now)
-        .bind::<sql_types::VarChar, _>(EventStatus::InProgress.to_string())
-        .bind::<sql_types::Time, _>(stuck_threshold)
-        .bind::<sql_types::Timestamp, _>(now);
-
-        let raw_event_entries = command.get_results::<RawEventEntry>(self.db_conn).map_err(|e| {
-            let error_kind = ErrorKind::from(&e);
-            ectx!(try err e, ErrorSource::Diesel, error_kind)
-        })?;
+            .bind::<sql_types::VarChar, _>(EventStatus::InProgress.to_string())
+            .bind::<sql_types::Time, _>(stuck_threshold)
+            .bind::<sql_types::Timestamp, _>(now)
+            .get_results::<RawEventEntry>(self.db_conn)
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(try err e, ErrorSource::Diesel, error_kind)
+            })?;
 
-        raw_event_entries
-            .into_iter()
-            .map(|raw_event_entry| {
-                RawEventEntry::try_into_event_entry(raw_event_entry.clone())
-                    .map_err(ectx!(ErrorSource::SerdeJson, ErrorKind::Internal => raw_event_entry))
-            })
-            .collect::<Result<Vec<_>, _>>()
+            raw_stuck_event_entries
+                .into_iter()
+                .map(|raw_event_entry| {
+                    let event_entry = RawEventEntry::try_into_event_entry(raw_event_entry.clone())
+                        .map_err(ectx!(try ErrorSource::SerdeJson, ErrorKind::Internal => raw_event_entry))?;
+
+                    // a handler that keeps hanging is retried with the same backoff as a failing one
+                    let (new_event_status, scheduled_on) = self.next_attempt(&event_entry, now);
+
+                    let raw_event_entry = diesel::update(EventStore::event_store)
+                        .filter(EventStore::id.eq(event_entry.id))
+                        .set((
+                            EventStore::status.eq(&new_event_status.to_string()),
+                            EventStore::status_updated_at.eq(now),
+                            EventStore::scheduled_on.eq(scheduled_on),
+                        ))
+                        .get_result::<RawEventEntry>(self.db_conn)
+                        .map_err(|e| {
+                            let error_kind = ErrorKind::from(&e);
+                            ectx!(try err e, ErrorSource::Diesel, error_kind)
+                        })?;
+
+                    RawEventEntry::try_into_event_entry(raw_event_entry.clone())
+                        .map_err(ectx!(ErrorSource::SerdeJson, ErrorKind::Internal => raw_event_entry))
+                })
+                .collect::<Result<Vec<_>, _>>()
+        })
     }
 
     fn complete_event(&self, event_entry_id: EventEntryId) -> RepoResultV2<EventEntry> {
         trace!("Completing an event with ID: {}", event_entry_id);
 
+        acl::check(&*self.acl, Resource::EventStore, Action::Write, self, None).map_err(ectx!(try ErrorKind::Forbidden))?;
+
         self.db_conn.transaction(|| {
             let event_status = EventStore::event_store
                 .filter(EventStore::id.eq(event_entry_id))
@@ -223,28 +314,28 @@ impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager
     fn fail_event(&self, event_entry_id: EventEntryId) -> RepoResultV2<EventEntry> {
         trace!("Failing an event with ID: {}", event_entry_id);
 
+        acl::check(&*self.acl, Resource::EventStore, Action::Write, self, None).map_err(ectx!(try ErrorKind::Forbidden))?;
+
         self.db_conn.transaction(|| {
-            let (event_status, attempt_count) = EventStore::event_store
+            let raw_event_entry = EventStore::event_store
                 .filter(EventStore::id.eq(event_entry_id))
-                .select((EventStore::status, EventStore::attempt_count))
-                .get_result::<(String, i32)>(self.db_conn)
+                .get_result::<RawEventEntry>(self.db_conn)
                 .map_err(|e| {
                     let error_kind = ErrorKind::from(&e);
                     ectx!(try err e, ErrorSource::Diesel, error_kind)
                 })?;
 
-            let event_status = EventStatus::from_str(event_status.as_str()).map_err(|_| ErrorKind::Internal)?;
+            let event_entry = RawEventEntry::try_into_event_entry(raw_event_entry.clone())
+                .map_err(ectx!(try ErrorSource::SerdeJson, ErrorKind::Internal => raw_event_entry))?;
 
-            let new_event_status = if attempt_count >= self.max_processing_attempts as i32 {
-                EventStatus::Failed
-            } else {
-                EventStatus::Pending
-            };
+            let now = chrono::Utc::now().naive_utc();
 
-            if event_status != EventStatus::InProgress {
+            let (new_event_status, scheduled_on) = self.next_attempt(&event_entry, now);
+
+            if event_entry.status != EventStatus::InProgress {
                 let e = format_err!(
                     "Cannot change status from \"{}\" to \"{}\" for event entry with ID: {}",
-                    event_status,
+                    event_entry.status,
                     new_event_status,
                     event_entry_id,
                 );
@@ -255,7 +346,8 @@ impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager
                 .filter(EventStore::id.eq(event_entry_id))
                 .set((
                     EventStore::status.eq(&new_event_status.to_string()),
-                    EventStore::status_updated_at.eq(chrono::Utc::now().naive_utc()),
+                    EventStore::status_updated_at.eq(now),
+                    EventStore::scheduled_on.eq(scheduled_on),
                 ))
                 .get_result::<RawEventEntry>(self.db_conn)
                 .map_err(|e| {
@@ -267,4 +359,304 @@ impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager
                 .map_err(ectx!(ErrorSource::SerdeJson, ErrorKind::Internal => raw_event_entry))
         })
     }
+
+    fn get_event(&self, event_entry_id: EventEntryId) -> RepoResultV2<Option<EventEntry>> {
+        trace!("Getting an event with ID: {}", event_entry_id);
+
+        acl::check(&*self.acl, Resource::EventStore, Action::Read, self, None).map_err(ectx!(try ErrorKind::Forbidden))?;
+
+        let raw_event_entry = EventStore::event_store
+            .filter(EventStore::id.eq(event_entry_id))
+            .get_result::<RawEventEntry>(self.db_conn)
+            .optional()
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(try err e, ErrorSource::Diesel, error_kind)
+            })?;
+
+        match raw_event_entry {
+            None => Ok(None),
+            Some(raw_event_entry) => RawEventEntry::try_into_event_entry(raw_event_entry.clone())
+                .map(Some)
+                .map_err(ectx!(ErrorSource::SerdeJson, ErrorKind::Internal => raw_event_entry)),
+        }
+    }
+
+    fn search_events(&self, skip: i64, count: i64, search: EventEntrySearch) -> RepoResultV2<EventEntrySearchResults> {
+        trace!("Searching events, skip={}, count={}, search {:?}", skip, count, search);
+
+        acl::check(&*self.acl, Resource::EventStore, Action::Read, self, None).map_err(ectx!(try ErrorKind::Forbidden))?;
+
+        let EventEntrySearch {
+            status,
+            payload_type,
+            created_from,
+            created_to,
+        } = search;
+
+        let status = status.map(|status| status.to_string());
+
+        // unit variants are serialized as plain strings, others as single-key objects
+        let filter = "
+            WHERE ($1::text IS NULL OR status = $1)
+                AND ($2::text IS NULL OR event->'payload' ? $2 OR event->>'payload' = $2)
+                AND ($3::timestamp IS NULL OR created_at >= $3)
+                AND ($4::timestamp IS NULL OR created_at <= $4)
+        ";
+
+        let raw_event_entries = sql_query(format!("SELECT * FROM event_store {} ORDER BY id DESC OFFSET $5 LIMIT $6", filter))
+            .bind::<sql_types::Nullable<sql_types::VarChar>, _>(status.clone())
+            .bind::<sql_types::Nullable<sql_types::VarChar>, _>(payload_type.clone())
+            .bind::<sql_types::Nullable<sql_types::Timestamp>, _>(created_from)
+            .bind::<sql_types::Nullable<sql_types::Timestamp>, _>(created_to)
+            .bind::<sql_types::BigInt, _>(skip)
+            .bind::<sql_types::BigInt, _>(count)
+            .get_results::<RawEventEntry>(self.db_conn)
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(try err e, ErrorSource::Diesel, error_kind)
+            })?;
+
+        let EventEntryCount { count: total_count } = sql_query(format!("SELECT COUNT(*) AS count FROM event_store {}", filter))
+            .bind::<sql_types::Nullable<sql_types::VarChar>, _>(status)
+            .bind::<sql_types::Nullable<sql_types::VarChar>, _>(payload_type)
+            .bind::<sql_types::Nullable<sql_types::Timestamp>, _>(created_from)
+            .bind::<sql_types::Nullable<sql_types::Timestamp>, _>(created_to)
+            .get_result::<EventEntryCount>(self.db_conn)
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(try err e, ErrorSource::Diesel, error_kind)
+            })?;
+
+        let events = raw_event_entries
+            .into_iter()
+            .map(|raw_event_entry| {
+                RawEventEntry::try_into_event_entry(raw_event_entry.clone())
+                    .map_err(ectx!(ErrorSource::SerdeJson, ErrorKind::Internal => raw_event_entry))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(EventEntrySearchResults { total_count, events })
+    }
+
+    fn get_events_for_replay(&self, search: EventReplaySearch, limit: i64) -> RepoResultV2<Vec<EventEntry>> {
+        trace!("Getting events for replay (limit: {}), search {:?}", limit, search);
+
+        acl::check(&*self.acl, Resource::EventStore, Action::Read, self, None).map_err(ectx!(try ErrorKind::Forbidden))?;
+
+        let EventReplaySearch {
+            status,
+            id_from,
+            id_to,
+            payload_type,
+            created_from,
+            created_to,
+        } = search;
+
+        let status = status.map(|status| status.to_string());
+
+        let raw_event_entries = sql_query(
+            "
+            SELECT *
+            FROM event_store
+            WHERE status IN ($1, $2)
+                AND ($3::text IS NULL OR status = $3)
+                AND ($4::bigint IS NULL OR id >= $4)
+                AND ($5::bigint IS NULL OR id <= $5)
+                AND ($6::text IS NULL OR event->'payload' ? $6 OR event->>'payload' = $6)
+                AND ($7::timestamp IS NULL OR created_at >= $7)
+                AND ($8::timestamp IS NULL OR created_at <= $8)
+            ORDER BY id
+            LIMIT $9
+        ",
+        )
+        .bind::<sql_types::VarChar, _>(EventStatus::Completed.to_string())
+        .bind::<sql_types::VarChar, _>(EventStatus::Failed.to_string())
+        .bind::<sql_types::Nullable<sql_types::VarChar>, _>(status)
+        .bind::<sql_types::Nullable<sql_types::BigInt>, _>(id_from)
+        .bind::<sql_types::Nullable<sql_types::BigInt>, _>(id_to)
+        .bind::<sql_types::Nullable<sql_types::VarChar>, _>(payload_type)
+        .bind::<sql_types::Nullable<sql_types::Timestamp>, _>(created_from)
+        .bind::<sql_types::Nullable<sql_types::Timestamp>, _>(created_to)
+        .bind::<sql_types::BigInt, _>(limit)
+        .get_results::<RawEventEntry>(self.db_conn)
+        .map_err(|e| {
+            let error_kind = ErrorKind::from(&e);
+            ectx!(try err e, ErrorSource::Diesel, error_kind)
+        })?;
+
+        raw_event_entries
+            .into_iter()
+            .map(|raw_event_entry| {
+                RawEventEntry::try_into_event_entry(raw_event_entry.clone())
+                    .map_err(ectx!(ErrorSource::SerdeJson, ErrorKind::Internal => raw_event_entry))
+            })
+            .collect::<Result<Vec<_>, _>>()
+    }
+
+    fn requeue_events(&self, event_entry_ids: Vec<EventEntryId>) -> RepoResultV2<Vec<EventEntry>> {
+        trace!("Requeueing events with IDs: {:?}", event_entry_ids);
+
+        acl::check(&*self.acl, Resource::EventStore, Action::Write, self, None).map_err(ectx!(try ErrorKind::Forbidden))?;
+
+        let raw_event_entries = diesel::update(EventStore::event_store)
+            .filter(EventStore::id.eq_any(event_entry_ids))
+            .filter(EventStore::status.eq_any(vec![EventStatus::Failed.to_string(), EventStatus::ManuallyResolved.to_string()]))
+            .set((
+                EventStore::status.eq(EventStatus::Pending.to_string()),
+                EventStore::attempt_count.eq(0),
+                EventStore::scheduled_on.eq(None::<NaiveDateTime>),
+                EventStore::status_updated_at.eq(chrono::Utc::now().naive_utc()),
+            ))
+            .get_results::<RawEventEntry>(self.db_conn)
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(try err e, ErrorSource::Diesel, error_kind)
+            })?;
+
+        raw_event_entries
+            .into_iter()
+            .map(|raw_event_entry| {
+                RawEventEntry::try_into_event_entry(raw_event_entry.clone())
+                    .map_err(ectx!(ErrorSource::SerdeJson, ErrorKind::Internal => raw_event_entry))
+            })
+            .collect::<Result<Vec<_>, _>>()
+    }
+
+    fn resolve_event(&self, event_entry_id: EventEntryId) -> RepoResultV2<Option<EventEntry>> {
+        trace!("Resolving an event with ID: {}", event_entry_id);
+
+        acl::check(&*self.acl, Resource::EventStore, Action::Write, self, None).map_err(ectx!(try ErrorKind::Forbidden))?;
+
+        let raw_event_entry = diesel::update(EventStore::event_store)
+            .filter(EventStore::id.eq(event_entry_id))
+            .filter(EventStore::status.eq_any(vec![EventStatus::Pending.to_string(), EventStatus::Failed.to_string()]))
+            .set((
+                EventStore::status.eq(EventStatus::ManuallyResolved.to_string()),
+                EventStore::status_updated_at.eq(chrono::Utc::now().naive_utc()),
+            ))
+            .get_result::<RawEventEntry>(self.db_conn)
+            .optional()
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(try err e, ErrorSource::Diesel, error_kind)
+            })?;
+
+        match raw_event_entry {
+            None => Ok(None),
+            Some(raw_event_entry) => RawEventEntry::try_into_event_entry(raw_event_entry.clone())
+                .map(Some)
+                .map_err(ectx!(ErrorSource::SerdeJson, ErrorKind::Internal => raw_event_entry)),
+        }
+    }
+
+    fn add_event_attempt(&self, new_event_attempt: NewEventAttempt) -> RepoResultV2<EventAttempt> {
+        trace!("Adding an attempt for event with ID: {}", new_event_attempt.event_id);
+
+        acl::check(&*self.acl, Resource::EventStore, Action::Write, self, None).map_err(ectx!(try ErrorKind::Forbidden))?;
+
+        diesel::insert_into(EventAttempts::event_attempts)
+            .values(&new_event_attempt)
+            .get_result::<EventAttempt>(self.db_conn)
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(err e, ErrorSource::Diesel, error_kind)
+            })
+    }
+
+    fn get_event_attempts(&self, event_entry_id: EventEntryId) -> RepoResultV2<Vec<EventAttempt>> {
+        trace!("Getting attempts for event with ID: {}", event_entry_id);
+
+        acl::check(&*self.acl, Resource::EventStore, Action::Read, self, None).map_err(ectx!(try ErrorKind::Forbidden))?;
+
+        EventAttempts::event_attempts
+            .filter(EventAttempts::event_id.eq(event_entry_id))
+            .order_by(EventAttempts::id)
+            .get_results::<EventAttempt>(self.db_conn)
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(err e, ErrorSource::Diesel, error_kind)
+            })
+    }
+
+    fn archive_completed_events(&self, completed_before: NaiveDateTime, limit: u32) -> RepoResultV2<usize> {
+        trace!("Archiving events completed before {} (limit: {})", completed_before, limit);
+
+        acl::check(&*self.acl, Resource::EventStore, Action::Write, self, None).map_err(ectx!(try ErrorKind::Forbidden))?;
+
+        // event attempts are kept, they still refer to the moved events by ID
+        let command = sql_query(
+            "
+            WITH archived AS (
+                DELETE FROM event_store
+                WHERE id IN (
+                    SELECT id
+                    FROM event_store
+                    WHERE status = $1 AND status_updated_at < $2
+                    ORDER BY id
+                    LIMIT $3
+                    FOR UPDATE SKIP LOCKED
+                )
+                RETURNING id, event, status, attempt_count, created_at, status_updated_at, scheduled_on
+            )
+            INSERT INTO event_store_archive (id, event, status, attempt_count, created_at, status_updated_at, scheduled_on)
+            SELECT id, event, status, attempt_count, created_at, status_updated_at, scheduled_on
+            FROM archived
+        ",
+        )
+        .bind::<sql_types::VarChar, _>(EventStatus::Completed.to_string())
+        .bind::<sql_types::Timestamp, _>(completed_before)
+        .bind::<sql_types::BigInt, _>(limit as i64);
+
+        command.execute(self.db_conn).map_err(|e| {
+            let error_kind = ErrorKind::from(&e);
+            ectx!(err e, ErrorSource::Diesel, error_kind)
+        })
+    }
+
+    fn delete_completed_events(&self, completed_before: NaiveDateTime, limit: u32) -> RepoResultV2<usize> {
+        trace!("Deleting events completed before {} (limit: {})", completed_before, limit);
+
+        acl::check(&*self.acl, Resource::EventStore, Action::Write, self, None).map_err(ectx!(try ErrorKind::Forbidden))?;
+
+        let command = sql_query(
+            "
+            WITH deleted AS (
+                DELETE FROM event_store
+                WHERE id IN (
+                    SELECT id
+                    FROM event_store
+                    WHERE status = $1 AND status_updated_at < $2
+                    ORDER BY id
+                    LIMIT $3
+                    FOR UPDATE SKIP LOCKED
+                )
+                RETURNING id
+            ), deleted_attempts AS (
+                DELETE FROM event_attempts
+                WHERE event_id IN (SELECT id FROM deleted)
+            )
+            SELECT id FROM deleted
+        ",
+        )
+        .bind::<sql_types::VarChar, _>(EventStatus::Completed.to_string())
+        .bind::<sql_types::Timestamp, _>(completed_before)
+        .bind::<sql_types::BigInt, _>(limit as i64);
+
+        command.execute(self.db_conn).map_err(|e| {
+            let error_kind = ErrorKind::from(&e);
+            ectx!(err e, ErrorSource::Diesel, error_kind)
+        })
+    }
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> CheckScope<Scope, EventEntry>
+    for EventStoreRepoImpl<'a, T>
+{
+    fn is_in_scope(&self, _user_id: stq_types::UserId, scope: &Scope, _obj: Option<&EventEntry>) -> bool {
+        match *scope {
+            Scope::All => true,
+            Scope::Owned => false,
+        }
+    }
 }
@@ -0,0 +1,162 @@
+use diesel;
+use diesel::connection::AnsiTransactionManager;
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::query_dsl::RunQueryDsl;
+use diesel::Connection;
+use failure::Error as FailureError;
+use failure::Fail;
+
+use stq_types;
+
+use models::authorization::*;
+use models::invoice_v2::InvoiceId;
+use models::{InvoiceSurplus, InvoiceSurplusId, NewInvoiceSurplus, UpdateInvoiceSurplus, UserId};
+use repos::legacy_acl::*;
+
+use schema::invoice_surpluses::dsl as InvoiceSurplusesDsl;
+
+use super::acl;
+use super::error::*;
+use super::types::RepoResultV2;
+
+type InvoiceSurplusesRepoAcl = Box<Acl<Resource, Action, Scope, FailureError, UserId>>;
+
+pub struct InvoiceSurplusesRepoImpl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> {
+    pub db_conn: &'a T,
+    pub acl: InvoiceSurplusesRepoAcl,
+}
+
+pub trait InvoiceSurplusesRepo {
+    fn create(&self, payload: NewInvoiceSurplus) -> RepoResultV2<InvoiceSurplus>;
+    fn get(&self, surplus_id: InvoiceSurplusId) -> RepoResultV2<Option<InvoiceSurplus>>;
+    fn get_by_invoice_id(&self, invoice_id: InvoiceId) -> RepoResultV2<Option<InvoiceSurplus>>;
+    fn update(&self, surplus_id: InvoiceSurplusId, payload: UpdateInvoiceSurplus) -> RepoResultV2<InvoiceSurplus>;
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> InvoiceSurplusesRepoImpl<'a, T> {
+    pub fn new(db_conn: &'a T, acl: InvoiceSurplusesRepoAcl) -> Self {
+        Self { db_conn, acl }
+    }
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> InvoiceSurplusesRepo
+    for InvoiceSurplusesRepoImpl<'a, T>
+{
+    fn create(&self, payload: NewInvoiceSurplus) -> RepoResultV2<InvoiceSurplus> {
+        debug!("Creating an invoice surplus: {:?}", payload);
+
+        acl::check(
+            &*self.acl,
+            Resource::InvoiceSurplus,
+            Action::Write,
+            self,
+            Some(&payload.buyer_user_id),
+        )
+        .map_err(ectx!(try ErrorKind::Forbidden))?;
+
+        let command = diesel::insert_into(InvoiceSurplusesDsl::invoice_surpluses).values(&payload);
+
+        command.get_result::<InvoiceSurplus>(self.db_conn).map_err(|e| {
+            let error_kind = ErrorKind::from(&e);
+            ectx!(err e, ErrorSource::Diesel, error_kind)
+        })
+    }
+
+    fn get(&self, surplus_id: InvoiceSurplusId) -> RepoResultV2<Option<InvoiceSurplus>> {
+        debug!("Getting an invoice surplus with ID: {}", surplus_id);
+
+        let surplus = InvoiceSurplusesDsl::invoice_surpluses
+            .filter(InvoiceSurplusesDsl::id.eq(surplus_id))
+            .get_result::<InvoiceSurplus>(self.db_conn)
+            .optional()
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(try err e, ErrorSource::Diesel, error_kind)
+            })?;
+
+        if let Some(ref surplus) = surplus {
+            acl::check(
+                &*self.acl,
+                Resource::InvoiceSurplus,
+                Action::Read,
+                self,
+                Some(&surplus.buyer_user_id),
+            )
+            .map_err(ectx!(try ErrorKind::Forbidden))?;
+        }
+
+        Ok(surplus)
+    }
+
+    fn get_by_invoice_id(&self, invoice_id: InvoiceId) -> RepoResultV2<Option<InvoiceSurplus>> {
+        debug!("Getting an invoice surplus of invoice with ID: {}", invoice_id);
+
+        let surplus = InvoiceSurplusesDsl::invoice_surpluses
+            .filter(InvoiceSurplusesDsl::invoice_id.eq(invoice_id))
+            .get_result::<InvoiceSurplus>(self.db_conn)
+            .optional()
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(try err e, ErrorSource::Diesel, error_kind)
+            })?;
+
+        if let Some(ref surplus) = surplus {
+            acl::check(
+                &*self.acl,
+                Resource::InvoiceSurplus,
+                Action::Read,
+                self,
+                Some(&surplus.buyer_user_id),
+            )
+            .map_err(ectx!(try ErrorKind::Forbidden))?;
+        }
+
+        Ok(surplus)
+    }
+
+    fn update(&self, surplus_id: InvoiceSurplusId, payload: UpdateInvoiceSurplus) -> RepoResultV2<InvoiceSurplus> {
+        debug!("Updating an invoice surplus with ID: {} using payload: {:?}", surplus_id, payload);
+
+        let surplus = InvoiceSurplusesDsl::invoice_surpluses
+            .filter(InvoiceSurplusesDsl::id.eq(surplus_id))
+            .get_result::<InvoiceSurplus>(self.db_conn)
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(try err e, ErrorSource::Diesel, error_kind)
+            })?;
+
+        acl::check(
+            &*self.acl,
+            Resource::InvoiceSurplus,
+            Action::Write,
+            self,
+            Some(&surplus.buyer_user_id),
+        )
+        .map_err(ectx!(try ErrorKind::Forbidden))?;
+
+        let command = diesel::update(InvoiceSurplusesDsl::invoice_surpluses.filter(InvoiceSurplusesDsl::id.eq(surplus_id))).set(&payload);
+
+        command.get_result::<InvoiceSurplus>(self.db_conn).map_err(|e| {
+            let error_kind = ErrorKind::from(&e);
+            ectx!(err e, ErrorSource::Diesel, error_kind)
+        })
+    }
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> CheckScope<Scope, UserId>
+    for InvoiceSurplusesRepoImpl<'a, T>
+{
+    fn is_in_scope(&self, user_id: stq_types::UserId, scope: &Scope, obj: Option<&UserId>) -> bool {
+        match *scope {
+            Scope::All => true,
+            Scope::Owned => {
+                if let Some(buyer_user_id) = obj {
+                    buyer_user_id.inner() == user_id.0
+                } else {
+                    false
+                }
+            }
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use chrono::NaiveDateTime;
 use diesel;
 use diesel::connection::AnsiTransactionManager;
 use diesel::pg::Pg;
@@ -12,9 +13,10 @@ use repos::legacy_acl::*;
 
 use models::authorization::*;
 use models::invoice_v2::*;
-use models::{AccountId, TransactionId, UserId};
+use models::{AccountId, Currency, TransactionId, UserId};
 use schema::amounts_received::dsl as AmountsReceived;
 use schema::invoices_v2::dsl as InvoicesV2;
+use stq_static_resources::OrderState;
 
 use super::acl;
 use super::error::*;
@@ -39,7 +41,16 @@ pub trait InvoicesV2Repo {
     ) -> RepoResultV2<RawInvoice>;
     fn set_amount_paid(&self, invoice_id: InvoiceId, input: InvoiceSetAmountPaid) -> RepoResultV2<RawInvoice>;
     fn set_amount_paid_fiat(&self, invoice_id: InvoiceId, input: InvoiceSetAmountPaid) -> RepoResultV2<RawInvoice>;
+    fn set_payment_failed(&self, invoice_id: InvoiceId, failed_at: NaiveDateTime) -> RepoResultV2<RawInvoice>;
     fn unlink_account(&self, invoice_id: InvoiceId) -> RepoResultV2<RawInvoice>;
+    /// Cancels the invoice unless something has been captured for it, returns `None` in that case
+    fn set_cancelled(&self, invoice_id: InvoiceId) -> RepoResultV2<Option<RawInvoice>>;
+    fn set_buyer_currency(
+        &self,
+        invoice_id: InvoiceId,
+        buyer_currency: Currency,
+        account_id: Option<AccountId>,
+    ) -> RepoResultV2<RawInvoice>;
     fn delete(&self, invoice_id: InvoiceId) -> RepoResultV2<Option<RawInvoice>>;
 }
 
@@ -254,6 +265,41 @@ impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager
         })
     }
 
+    fn set_payment_failed(&self, invoice_id: InvoiceId, failed_at: NaiveDateTime) -> RepoResultV2<RawInvoice> {
+        debug!("Setting payment failed for invoice with ID = {} at {}", &invoice_id, &failed_at);
+
+        let query = InvoicesV2::invoices_v2.filter(InvoicesV2::id.eq(invoice_id));
+
+        query
+            .get_result::<RawInvoice>(self.db_conn)
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(try err e, ErrorSource::Diesel, error_kind)
+            })
+            .and_then(|invoice| {
+                acl::check(
+                    &*self.acl,
+                    Resource::Invoice,
+                    Action::Write,
+                    self,
+                    Some(&InvoiceAccess::from(invoice.clone())),
+                )
+                .map_err(ectx!(try ErrorKind::Forbidden))
+            })?;
+
+        let changeset = RawInvoiceSetPaymentFailed {
+            payment_failed_at: failed_at,
+            status: OrderState::PaymentAwaited,
+        };
+
+        let command = diesel::update(InvoicesV2::invoices_v2.filter(InvoicesV2::id.eq(invoice_id))).set(&changeset);
+
+        command.get_result::<RawInvoice>(self.db_conn).map_err(|e| {
+            let error_kind = ErrorKind::from(&e);
+            ectx!(err e, ErrorSource::Diesel, error_kind)
+        })
+    }
+
     fn unlink_account(&self, invoice_id: InvoiceId) -> RepoResultV2<RawInvoice> {
         debug!("Unlinking account for invoice with ID = {}", invoice_id);
 
@@ -285,6 +331,81 @@ impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager
         })
     }
 
+    fn set_cancelled(&self, invoice_id: InvoiceId) -> RepoResultV2<Option<RawInvoice>> {
+        debug!("Setting invoice with ID = {} cancelled", invoice_id);
+
+        let query = InvoicesV2::invoices_v2.filter(InvoicesV2::id.eq(invoice_id));
+
+        query
+            .get_result::<RawInvoice>(self.db_conn)
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(try err e, ErrorSource::Diesel, error_kind)
+            })
+            .and_then(|invoice| {
+                acl::check(
+                    &*self.acl,
+                    Resource::Invoice,
+                    Action::Write,
+                    self,
+                    Some(&InvoiceAccess::from(invoice.clone())),
+                )
+                .map_err(ectx!(try ErrorKind::Forbidden))
+            })?;
+
+        // the payment may be captured concurrently, the invoice is only cancelled if it is still unpaid
+        let filter = InvoicesV2::invoices_v2
+            .filter(InvoicesV2::id.eq(invoice_id))
+            .filter(InvoicesV2::amount_captured.eq(Amount::zero()))
+            .filter(InvoicesV2::paid_at.is_null());
+
+        let command = diesel::update(filter).set(InvoicesV2::status.eq(OrderState::Cancelled));
+
+        command.get_result::<RawInvoice>(self.db_conn).optional().map_err(|e| {
+            let error_kind = ErrorKind::from(&e);
+            ectx!(err e, ErrorSource::Diesel, error_kind)
+        })
+    }
+
+    fn set_buyer_currency(
+        &self,
+        invoice_id: InvoiceId,
+        buyer_currency: Currency,
+        account_id: Option<AccountId>,
+    ) -> RepoResultV2<RawInvoice> {
+        debug!(
+            "Setting buyer currency {} and account {:?} for invoice with ID = {}",
+            buyer_currency, account_id, invoice_id
+        );
+
+        let query = InvoicesV2::invoices_v2.filter(InvoicesV2::id.eq(invoice_id));
+
+        query
+            .get_result::<RawInvoice>(self.db_conn)
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(try err e, ErrorSource::Diesel, error_kind)
+            })
+            .and_then(|invoice| {
+                acl::check(
+                    &*self.acl,
+                    Resource::Invoice,
+                    Action::Write,
+                    self,
+                    Some(&InvoiceAccess::from(invoice.clone())),
+                )
+                .map_err(ectx!(try ErrorKind::Forbidden))
+            })?;
+
+        let command = diesel::update(InvoicesV2::invoices_v2.filter(InvoicesV2::id.eq(invoice_id)))
+            .set((InvoicesV2::buyer_currency.eq(buyer_currency), InvoicesV2::account_id.eq(account_id)));
+
+        command.get_result::<RawInvoice>(self.db_conn).map_err(|e| {
+            let error_kind = ErrorKind::from(&e);
+            ectx!(err e, ErrorSource::Diesel, error_kind)
+        })
+    }
+
     fn delete(&self, invoice_id: InvoiceId) -> RepoResultV2<Option<RawInvoice>> {
         debug!("Deleting an invoice with ID: {}", invoice_id);
 
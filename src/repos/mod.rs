@@ -4,53 +4,71 @@ pub mod accounts;
 #[macro_use]
 pub mod acl;
 pub mod customer;
+pub mod disputes;
+pub mod documents;
 pub mod error;
 pub mod event_store;
 pub mod fee;
 pub mod international_billing_info;
 pub mod invoice;
+pub mod invoice_surpluses;
 pub mod invoices_v2;
 pub mod order_exchange_rates;
 pub mod order_info;
+pub mod order_state_transitions;
 pub mod orders;
 pub mod payment_intent;
 pub mod payment_intents_fees;
 pub mod payment_intents_invoices;
 pub mod payouts;
 pub mod proxy_companies_billing_info;
+pub mod refunds;
 pub mod repo_factory;
 pub mod russia_billing_info;
 pub mod store_billing_type;
+pub mod store_capture_policies;
 pub mod store_subscription;
+pub mod stripe_webhook_events;
 pub mod subscription;
 pub mod subscription_payment;
 pub mod types;
 pub mod user_roles;
 pub mod user_wallets;
+pub mod webhook_deliveries;
+pub mod webhook_subscriptions;
 
 pub use self::accounts::*;
 pub use self::acl::*;
 pub use self::customer::*;
+pub use self::disputes::*;
+pub use self::documents::*;
 pub use self::error::*;
 pub use self::event_store::*;
 pub use self::fee::*;
 pub use self::international_billing_info::*;
 pub use self::invoice::*;
+pub use self::invoice_surpluses::*;
 pub use self::invoices_v2::*;
 pub use self::order_exchange_rates::*;
 pub use self::order_info::*;
+pub use self::order_state_transitions::*;
 pub use self::orders::*;
 pub use self::payment_intent::*;
 pub use self::payment_intents_fees::*;
 pub use self::payment_intents_invoices::*;
 pub use self::payouts::*;
 pub use self::proxy_companies_billing_info::*;
+pub use self::refunds::*;
 pub use self::repo_factory::*;
 pub use self::russia_billing_info::*;
 pub use self::store_billing_type::*;
+pub use self::store_capture_policies::*;
 pub use self::store_subscription::*;
+pub use self::stripe_webhook_events::*;
 pub use self::subscription::*;
 pub use self::subscription_payment::*;
 pub use self::types::*;
 pub use self::user_roles::*;
 pub use self::user_wallets::*;
+pub use self::webhook_deliveries::*;
+pub use self::webhook_subscriptions::*;
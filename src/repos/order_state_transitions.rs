@@ -0,0 +1,94 @@
+use diesel::connection::AnsiTransactionManager;
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::query_dsl::RunQueryDsl;
+use diesel::Connection;
+use failure::Error as FailureError;
+use failure::Fail;
+
+use stq_types::UserId;
+
+use models::authorization::*;
+use models::order_v2::{OrderId, StoreId};
+use models::{OrderStateTransition, UserRole};
+use repos::legacy_acl::*;
+
+use schema::order_state_transitions::dsl as OrderStateTransitionsDsl;
+use schema::orders::dsl as OrdersDsl;
+use schema::roles::dsl as UserRolesDsl;
+
+use super::acl;
+use super::error::*;
+use super::types::RepoResultV2;
+
+type OrderStateTransitionsRepoAcl = Box<Acl<Resource, Action, Scope, FailureError, OrderId>>;
+
+pub struct OrderStateTransitionsRepoImpl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> {
+    pub db_conn: &'a T,
+    pub acl: OrderStateTransitionsRepoAcl,
+}
+
+pub trait OrderStateTransitionsRepo {
+    fn list_by_order_id(&self, order_id: OrderId) -> RepoResultV2<Vec<OrderStateTransition>>;
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> OrderStateTransitionsRepoImpl<'a, T> {
+    pub fn new(db_conn: &'a T, acl: OrderStateTransitionsRepoAcl) -> Self {
+        Self { db_conn, acl }
+    }
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> OrderStateTransitionsRepo
+    for OrderStateTransitionsRepoImpl<'a, T>
+{
+    fn list_by_order_id(&self, order_id: OrderId) -> RepoResultV2<Vec<OrderStateTransition>> {
+        debug!("Listing state transitions of order with ID: {}", order_id);
+
+        acl::check(&*self.acl, Resource::OrderStateTransition, Action::Read, self, Some(&order_id))
+            .map_err(ectx!(try ErrorKind::Forbidden))?;
+
+        OrderStateTransitionsDsl::order_state_transitions
+            .filter(OrderStateTransitionsDsl::order_id.eq(order_id))
+            .order(OrderStateTransitionsDsl::id)
+            .get_results::<OrderStateTransition>(self.db_conn)
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(err e, ErrorSource::Diesel, error_kind)
+            })
+    }
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> CheckScope<Scope, OrderId>
+    for OrderStateTransitionsRepoImpl<'a, T>
+{
+    fn is_in_scope(&self, user_id: UserId, scope: &Scope, obj: Option<&OrderId>) -> bool {
+        match *scope {
+            Scope::All => true,
+            Scope::Owned => {
+                if let Some(order_id) = obj {
+                    let store_id = match OrdersDsl::orders
+                        .filter(OrdersDsl::id.eq(order_id))
+                        .select(OrdersDsl::store_id)
+                        .get_result::<StoreId>(self.db_conn)
+                    {
+                        Ok(store_id) => store_id,
+                        Err(_) => return false,
+                    };
+
+                    UserRolesDsl::roles
+                        .filter(UserRolesDsl::user_id.eq(user_id))
+                        .get_results::<UserRole>(self.db_conn)
+                        .map_err(From::from)
+                        .map(|user_roles_arg| {
+                            user_roles_arg
+                                .iter()
+                                .any(|user_role_arg| user_role_arg.data.clone().map(|data| data == store_id.inner()).unwrap_or_default())
+                        })
+                        .unwrap_or_else(|_: FailureError| false)
+                } else {
+                    false
+                }
+            }
+        }
+    }
+}
@@ -1,3 +1,6 @@
+use std::collections::HashMap;
+
+use chrono::Utc;
 use diesel;
 use diesel::connection::AnsiTransactionManager;
 use diesel::pg::{expression::dsl::any, Pg};
@@ -13,9 +16,9 @@ use repos::user_roles::user_is_store_manager;
 
 use models::authorization::*;
 use models::invoice_v2::InvoiceId;
-use models::order_v2::{NewOrder, OrderAccess, OrderId, OrderSearchResults, OrdersSearch, RawOrder, StoreId};
-use models::{Amount, Currency, PaymentState, UserId};
-use schema::{invoices_v2::dsl as InvoicesV2, orders::dsl as Orders};
+use models::order_v2::{NewOrder, OrderAccess, OrderId, OrderSearchResults, OrdersSearch, OrdersSort, RawOrder, StoreId};
+use models::{Amount, Currency, NewOrderStateTransition, OrderStateChange, PaymentState, UserId};
+use schema::{invoices_v2::dsl as InvoicesV2, order_state_transitions::dsl as OrderStateTransitions, orders::dsl as Orders};
 
 use super::acl;
 use super::error::*;
@@ -40,8 +43,13 @@ pub trait OrdersRepo {
     fn create(&self, payload: NewOrder) -> RepoResultV2<RawOrder>;
     fn delete(&self, order_id: OrderId) -> RepoResultV2<Option<RawOrder>>;
     fn delete_by_invoice_id(&self, invoice_id: InvoiceId) -> RepoResultV2<Vec<RawOrder>>;
-    fn update_state(&self, order_id: OrderId, state: PaymentState) -> RepoResultV2<RawOrder>;
+    /// Updates the state and records the transition in the order state history
+    fn update_state(&self, order_id: OrderId, state: PaymentState, change: OrderStateChange) -> RepoResultV2<RawOrder>;
     fn update_stripe_fee(&self, order_id: OrderId, stripe_fee: Amount) -> RepoResultV2<RawOrder>;
+    fn update_captured_amount(&self, order_id: OrderId, captured_amount: Amount) -> RepoResultV2<RawOrder>;
+    fn update_refunded_amount(&self, order_id: OrderId, refunded_amount: Amount) -> RepoResultV2<RawOrder>;
+    /// Marks the order delivered, returns `None` if it has already been marked
+    fn set_delivered(&self, order_id: OrderId) -> RepoResultV2<Option<RawOrder>>;
 }
 
 impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> OrdersRepoImpl<'a, T> {
@@ -202,19 +210,59 @@ impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager
 
     fn search(&self, skip: i64, count: i64, search_params: OrdersSearch) -> RepoResultV2<OrderSearchResults> {
         debug!("Searching orders, skip={}, count={}, search {:?}", skip, count, search_params);
+        let sort = search_params.sort;
+        let cursor = search_params.cursor;
         let query: BoxedExpr = into_expr(search_params).unwrap_or(Box::new(true.into_sql::<Bool>()));
 
-        let orders = Orders::orders
-            .filter(&query)
+        let cursor_order = match cursor {
+            None => None,
+            Some(cursor) => {
+                let cursor_order = Orders::orders
+                    .filter(Orders::id.eq(cursor))
+                    .get_result::<RawOrder>(self.db_conn)
+                    .optional()
+                    .map_err(|e| {
+                        let error_kind = ErrorKind::from(&e);
+                        ectx!(try err e, ErrorSource::Diesel, error_kind)
+                    })?
+                    .ok_or({
+                        let e = format_err!("Cursor order {} not found", cursor);
+                        ectx!(try err e, ErrorKind::NotFound)
+                    })?;
+                Some(cursor_order)
+            }
+        };
+
+        let mut orders_query = Orders::orders.filter(&query).into_boxed();
+        if let Some(cursor_order) = cursor_order {
+            orders_query = orders_query.filter(after_cursor(sort, &cursor_order));
+        }
+        orders_query = match sort {
+            OrdersSort::CreatedAtDesc => orders_query.order_by((Orders::created_at.desc(), Orders::id.desc())),
+            OrdersSort::CreatedAtAsc => orders_query.order_by((Orders::created_at.asc(), Orders::id.asc())),
+            OrdersSort::UpdatedAtDesc => orders_query.order_by((Orders::updated_at.desc(), Orders::id.desc())),
+            OrdersSort::UpdatedAtAsc => orders_query.order_by((Orders::updated_at.asc(), Orders::id.asc())),
+            OrdersSort::TotalAmountDesc => orders_query.order_by((Orders::total_amount.desc(), Orders::id.desc())),
+            OrdersSort::TotalAmountAsc => orders_query.order_by((Orders::total_amount.asc(), Orders::id.asc())),
+        };
+
+        // one more order is requested to find out whether there is a next page
+        let mut orders = orders_query
             .offset(skip)
-            .limit(count)
-            .order_by(Orders::created_at.desc())
+            .limit(count + 1)
             .get_results::<RawOrder>(self.db_conn)
             .map_err(|e| {
                 let error_kind = ErrorKind::from(&e);
                 ectx!(try err e, ErrorSource::Diesel, error_kind)
             })?;
 
+        let next_cursor = if orders.len() as i64 > count {
+            orders.truncate(count as usize);
+            orders.last().map(|order| order.id)
+        } else {
+            None
+        };
+
         let total_count = Orders::orders.filter(&query).count().get_result::<i64>(self.db_conn).map_err(|e| {
             let error_kind = ErrorKind::from(&e);
             ectx!(try err e, ErrorSource::Diesel, error_kind)
@@ -234,7 +282,35 @@ impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager
             .map_err(ectx!(try ErrorKind::Forbidden))?;
         }
 
-        Ok(OrderSearchResults { total_count, orders })
+        let amounts = Orders::orders
+            .filter(&query)
+            .select((Orders::seller_currency, Orders::total_amount))
+            .get_results::<(Currency, Amount)>(self.db_conn)
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(try err e, ErrorSource::Diesel, error_kind)
+            })?;
+
+        let totals = amounts
+            .into_iter()
+            .try_fold(HashMap::new(), |mut totals, (currency, amount)| {
+                {
+                    let total = totals.entry(currency).or_insert(Amount::zero());
+                    *total = total.checked_add(amount)?;
+                }
+                Some(totals)
+            })
+            .ok_or({
+                let e = format_err!("Overflow while calculating the totals of the found orders");
+                ectx!(try err e, ErrorKind::Internal)
+            })?;
+
+        Ok(OrderSearchResults {
+            total_count,
+            orders,
+            next_cursor,
+            totals,
+        })
     }
 
     fn create(&self, payload: NewOrder) -> RepoResultV2<RawOrder> {
@@ -322,32 +398,102 @@ impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager
         Ok(deleted_orders)
     }
 
-    fn update_state(&self, order_id: OrderId, state: PaymentState) -> RepoResultV2<RawOrder> {
-        debug!("Updating state of order with ID: {} - {}", order_id, state);
+    fn update_state(&self, order_id: OrderId, state: PaymentState, change: OrderStateChange) -> RepoResultV2<RawOrder> {
+        debug!("Updating state of order with ID: {} - {} ({:?})", order_id, state, change);
+
+        acl::check(&*self.acl, Resource::OrderInfo, Action::Write, self, None).map_err(ectx!(try ErrorKind::Forbidden))?;
+
+        self.db_conn
+            .transaction(|| {
+                let previous_state = Orders::orders
+                    .filter(Orders::id.eq(order_id))
+                    .select(Orders::state)
+                    .get_result::<PaymentState>(self.db_conn)?;
+
+                let filter = Orders::orders.filter(Orders::id.eq(order_id));
+                let order = diesel::update(filter)
+                    .set(Orders::state.eq(state))
+                    .get_result::<RawOrder>(self.db_conn)?;
+
+                if previous_state != state {
+                    let OrderStateChange { user_id, event_id, reason } = change;
+                    let new_transition = NewOrderStateTransition {
+                        order_id,
+                        previous_state,
+                        new_state: state,
+                        user_id,
+                        event_id,
+                        reason,
+                    };
+                    diesel::insert_into(OrderStateTransitions::order_state_transitions)
+                        .values(&new_transition)
+                        .execute(self.db_conn)?;
+                }
+
+                Ok(order)
+            })
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(err e, ErrorSource::Diesel, error_kind)
+            })
+    }
+    fn update_stripe_fee(&self, order_id: OrderId, stripe_fee: Amount) -> RepoResultV2<RawOrder> {
+        debug!("Updating stripe_fee of order with ID: {} - {}", order_id, stripe_fee);
 
         acl::check(&*self.acl, Resource::OrderInfo, Action::Write, self, None).map_err(ectx!(try ErrorKind::Forbidden))?;
 
         let filter = Orders::orders.filter(Orders::id.eq(order_id));
 
-        let query = diesel::update(filter).set(Orders::state.eq(state));
+        let query = diesel::update(filter).set(Orders::stripe_fee.eq(stripe_fee));
         query.get_result::<RawOrder>(self.db_conn).map_err(|e| {
             let error_kind = ErrorKind::from(&e);
             ectx!(err e, ErrorSource::Diesel, error_kind)
         })
     }
-    fn update_stripe_fee(&self, order_id: OrderId, stripe_fee: Amount) -> RepoResultV2<RawOrder> {
-        debug!("Updating stripe_fee of order with ID: {} - {}", order_id, stripe_fee);
+
+    fn update_captured_amount(&self, order_id: OrderId, captured_amount: Amount) -> RepoResultV2<RawOrder> {
+        debug!("Updating captured_amount of order with ID: {} - {}", order_id, captured_amount);
 
         acl::check(&*self.acl, Resource::OrderInfo, Action::Write, self, None).map_err(ectx!(try ErrorKind::Forbidden))?;
 
         let filter = Orders::orders.filter(Orders::id.eq(order_id));
 
-        let query = diesel::update(filter).set(Orders::stripe_fee.eq(stripe_fee));
+        let query = diesel::update(filter).set(Orders::captured_amount.eq(captured_amount));
         query.get_result::<RawOrder>(self.db_conn).map_err(|e| {
             let error_kind = ErrorKind::from(&e);
             ectx!(err e, ErrorSource::Diesel, error_kind)
         })
     }
+
+    fn update_refunded_amount(&self, order_id: OrderId, refunded_amount: Amount) -> RepoResultV2<RawOrder> {
+        debug!("Updating refunded_amount of order with ID: {} - {}", order_id, refunded_amount);
+
+        acl::check(&*self.acl, Resource::OrderInfo, Action::Write, self, None).map_err(ectx!(try ErrorKind::Forbidden))?;
+
+        let filter = Orders::orders.filter(Orders::id.eq(order_id));
+
+        let query = diesel::update(filter).set(Orders::refunded_amount.eq(refunded_amount));
+        query.get_result::<RawOrder>(self.db_conn).map_err(|e| {
+            let error_kind = ErrorKind::from(&e);
+            ectx!(err e, ErrorSource::Diesel, error_kind)
+        })
+    }
+
+    fn set_delivered(&self, order_id: OrderId) -> RepoResultV2<Option<RawOrder>> {
+        debug!("Setting order with ID: {} delivered", order_id);
+
+        acl::check(&*self.acl, Resource::OrderInfo, Action::Write, self, None).map_err(ectx!(try ErrorKind::Forbidden))?;
+
+        let filter = Orders::orders
+            .filter(Orders::id.eq(order_id))
+            .filter(Orders::delivered_at.is_null());
+
+        let query = diesel::update(filter).set(Orders::delivered_at.eq(Some(Utc::now().naive_utc())));
+        query.get_result::<RawOrder>(self.db_conn).optional().map_err(|e| {
+            let error_kind = ErrorKind::from(&e);
+            ectx!(err e, ErrorSource::Diesel, error_kind)
+        })
+    }
 }
 
 impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> CheckScope<Scope, OrderAccess>
@@ -392,6 +538,16 @@ fn into_expr(search: OrdersSearch) -> Option<BoxedExpr> {
         state,
         order_id,
         order_ids,
+        created_from,
+        created_to,
+        updated_from,
+        updated_to,
+        seller_currency,
+        amount_from,
+        amount_to,
+        invoice_id,
+        buyer_user_id,
+        ..
     } = search;
 
     if let Some(store_id_filter) = store_id {
@@ -414,9 +570,95 @@ fn into_expr(search: OrdersSearch) -> Option<BoxedExpr> {
         query = Some(and(query, Box::new(new_condition)));
     }
 
+    if let Some(created_from_filter) = created_from {
+        let new_condition = Orders::created_at.ge(created_from_filter);
+        query = Some(and(query, Box::new(new_condition)));
+    }
+
+    if let Some(created_to_filter) = created_to {
+        let new_condition = Orders::created_at.le(created_to_filter);
+        query = Some(and(query, Box::new(new_condition)));
+    }
+
+    if let Some(updated_from_filter) = updated_from {
+        let new_condition = Orders::updated_at.ge(updated_from_filter);
+        query = Some(and(query, Box::new(new_condition)));
+    }
+
+    if let Some(updated_to_filter) = updated_to {
+        let new_condition = Orders::updated_at.le(updated_to_filter);
+        query = Some(and(query, Box::new(new_condition)));
+    }
+
+    if let Some(seller_currency_filter) = seller_currency {
+        let new_condition = Orders::seller_currency.eq(seller_currency_filter);
+        query = Some(and(query, Box::new(new_condition)));
+    }
+
+    if let Some(amount_from_filter) = amount_from {
+        let new_condition = Orders::total_amount.ge(amount_from_filter);
+        query = Some(and(query, Box::new(new_condition)));
+    }
+
+    if let Some(amount_to_filter) = amount_to {
+        let new_condition = Orders::total_amount.le(amount_to_filter);
+        query = Some(and(query, Box::new(new_condition)));
+    }
+
+    if let Some(invoice_id_filter) = invoice_id {
+        let new_condition = Orders::invoice_id.eq(invoice_id_filter);
+        query = Some(and(query, Box::new(new_condition)));
+    }
+
+    if let Some(buyer_user_id_filter) = buyer_user_id {
+        let buyer_invoice_ids = InvoicesV2::invoices_v2
+            .filter(InvoicesV2::buyer_user_id.eq(buyer_user_id_filter))
+            .select(InvoicesV2::id);
+        let new_condition = Orders::invoice_id.eq_any(buyer_invoice_ids);
+        query = Some(and(query, Box::new(new_condition)));
+    }
+
     query
 }
 
+/// Condition selecting the orders that follow the cursor order in the given sort order,
+/// the order ID breaks ties so that pages never overlap
+fn after_cursor(sort: OrdersSort, cursor_order: &RawOrder) -> BoxedExpr {
+    let id = cursor_order.id;
+    match sort {
+        OrdersSort::CreatedAtDesc => Box::new(
+            Orders::created_at
+                .lt(cursor_order.created_at)
+                .or(Orders::created_at.eq(cursor_order.created_at).and(Orders::id.lt(id))),
+        ),
+        OrdersSort::CreatedAtAsc => Box::new(
+            Orders::created_at
+                .gt(cursor_order.created_at)
+                .or(Orders::created_at.eq(cursor_order.created_at).and(Orders::id.gt(id))),
+        ),
+        OrdersSort::UpdatedAtDesc => Box::new(
+            Orders::updated_at
+                .lt(cursor_order.updated_at)
+                .or(Orders::updated_at.eq(cursor_order.updated_at).and(Orders::id.lt(id))),
+        ),
+        OrdersSort::UpdatedAtAsc => Box::new(
+            Orders::updated_at
+                .gt(cursor_order.updated_at)
+                .or(Orders::updated_at.eq(cursor_order.updated_at).and(Orders::id.gt(id))),
+        ),
+        OrdersSort::TotalAmountDesc => Box::new(
+            Orders::total_amount
+                .lt(cursor_order.total_amount)
+                .or(Orders::total_amount.eq(cursor_order.total_amount).and(Orders::id.lt(id))),
+        ),
+        OrdersSort::TotalAmountAsc => Box::new(
+            Orders::total_amount
+                .gt(cursor_order.total_amount)
+                .or(Orders::total_amount.eq(cursor_order.total_amount).and(Orders::id.gt(id))),
+        ),
+    }
+}
+
 fn and(old_condition: Option<BoxedExpr>, new_condition: BoxedExpr) -> BoxedExpr {
     if let Some(old_condition) = old_condition {
         Box::new(old_condition.and(new_condition))
@@ -12,7 +12,7 @@ use stq_types::stripe::PaymentIntentId;
 use repos::legacy_acl::*;
 
 use models::authorization::*;
-use models::{NewPaymentIntent, PaymentIntent, PaymentIntentAccess, UpdatePaymentIntent};
+use models::{ChargeId, NewPaymentIntent, PaymentIntent, PaymentIntentAccess, UpdatePaymentIntent};
 
 use schema::payment_intent::dsl as PaymentIntentDsl;
 
@@ -25,6 +25,7 @@ type PaymentIntentRepoAcl = Box<Acl<Resource, Action, Scope, FailureError, Payme
 #[derive(Debug, Clone)]
 pub enum SearchPaymentIntent {
     Id(PaymentIntentId),
+    ChargeId(ChargeId),
 }
 
 pub struct PaymentIntentRepoImpl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> {
@@ -53,6 +54,7 @@ impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager
 
         let search_exp: Box<BoxableExpression<PaymentIntentDsl::payment_intent, _, SqlType = Bool>> = match search {
             SearchPaymentIntent::Id(payment_intent_id) => Box::new(PaymentIntentDsl::id.eq(payment_intent_id)),
+            SearchPaymentIntent::ChargeId(charge_id) => Box::new(PaymentIntentDsl::charge_id.eq(charge_id)),
         };
 
         let query = PaymentIntentDsl::payment_intent.filter(search_exp);
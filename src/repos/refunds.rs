@@ -0,0 +1,225 @@
+use diesel;
+use diesel::connection::AnsiTransactionManager;
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::query_dsl::RunQueryDsl;
+use diesel::Connection;
+use failure::Error as FailureError;
+use failure::Fail;
+
+use stq_types::UserId;
+
+use models::authorization::*;
+use models::order_v2::{OrderId, StoreId};
+use models::{Amount, Currency, NewRefund, PayoutId, Refund, RefundId, RefundStatus, UpdateRefund, UserRole};
+use repos::legacy_acl::*;
+
+use schema::orders::dsl as OrdersDsl;
+use schema::refunds::dsl as RefundsDsl;
+use schema::roles::dsl as UserRolesDsl;
+
+use super::acl;
+use super::error::*;
+use super::types::RepoResultV2;
+
+type RefundsRepoAcl = Box<Acl<Resource, Action, Scope, FailureError, OrderId>>;
+
+pub struct RefundsRepoImpl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> {
+    pub db_conn: &'a T,
+    pub acl: RefundsRepoAcl,
+}
+
+pub trait RefundsRepo {
+    fn create(&self, payload: NewRefund) -> RepoResultV2<Refund>;
+    fn get(&self, refund_id: RefundId) -> RepoResultV2<Option<Refund>>;
+    fn list_by_order_id(&self, order_id: OrderId) -> RepoResultV2<Vec<Refund>>;
+    /// Lists the refunds of paid out store orders whose balance adjustment has not been deducted from a payout yet,
+    /// together with the seller currency of their orders
+    fn list_unsettled_adjustments_by_store_id(
+        &self,
+        store_id: StoreId,
+        currency: Option<Currency>,
+    ) -> RepoResultV2<Vec<(Refund, Currency)>>;
+    /// Marks the balance adjustments of the refunds as deducted from the payout
+    fn settle_adjustments(&self, refund_ids: Vec<RefundId>, payout_id: PayoutId) -> RepoResultV2<Vec<Refund>>;
+    fn update(&self, refund_id: RefundId, payload: UpdateRefund) -> RepoResultV2<Refund>;
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> RefundsRepoImpl<'a, T> {
+    pub fn new(db_conn: &'a T, acl: RefundsRepoAcl) -> Self {
+        Self { db_conn, acl }
+    }
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> RefundsRepo for RefundsRepoImpl<'a, T> {
+    fn create(&self, payload: NewRefund) -> RepoResultV2<Refund> {
+        debug!("Creating a refund: {:?}", payload);
+
+        acl::check(&*self.acl, Resource::Refund, Action::Write, self, Some(&payload.order_id)).map_err(ectx!(try ErrorKind::Forbidden))?;
+
+        let command = diesel::insert_into(RefundsDsl::refunds).values(&payload);
+
+        command.get_result::<Refund>(self.db_conn).map_err(|e| {
+            let error_kind = ErrorKind::from(&e);
+            ectx!(err e, ErrorSource::Diesel, error_kind)
+        })
+    }
+
+    fn get(&self, refund_id: RefundId) -> RepoResultV2<Option<Refund>> {
+        debug!("Getting a refund with ID: {}", refund_id);
+
+        let refund = RefundsDsl::refunds
+            .filter(RefundsDsl::id.eq(refund_id))
+            .get_result::<Refund>(self.db_conn)
+            .optional()
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(try err e, ErrorSource::Diesel, error_kind)
+            })?;
+
+        if let Some(ref refund) = refund {
+            acl::check(&*self.acl, Resource::Refund, Action::Read, self, Some(&refund.order_id))
+                .map_err(ectx!(try ErrorKind::Forbidden))?;
+        }
+
+        Ok(refund)
+    }
+
+    fn list_by_order_id(&self, order_id: OrderId) -> RepoResultV2<Vec<Refund>> {
+        debug!("Listing refunds of order with ID: {}", order_id);
+
+        acl::check(&*self.acl, Resource::Refund, Action::Read, self, Some(&order_id)).map_err(ectx!(try ErrorKind::Forbidden))?;
+
+        RefundsDsl::refunds
+            .filter(RefundsDsl::order_id.eq(order_id))
+            .order(RefundsDsl::created_at)
+            .get_results::<Refund>(self.db_conn)
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(err e, ErrorSource::Diesel, error_kind)
+            })
+    }
+
+    fn list_unsettled_adjustments_by_store_id(
+        &self,
+        store_id: StoreId,
+        currency: Option<Currency>,
+    ) -> RepoResultV2<Vec<(Refund, Currency)>> {
+        debug!("Listing unsettled refund balance adjustments of store with ID: {}", store_id);
+
+        let query = RefundsDsl::refunds
+            .inner_join(OrdersDsl::orders)
+            .filter(OrdersDsl::store_id.eq(store_id))
+            .filter(RefundsDsl::status.eq(RefundStatus::Succeeded))
+            .filter(RefundsDsl::balance_adjustment.gt(Amount::zero()))
+            .filter(RefundsDsl::settled_payout_id.is_null())
+            .select((::schema::refunds::all_columns, OrdersDsl::seller_currency))
+            .into_boxed();
+
+        let query = match currency {
+            None => query,
+            Some(currency) => query.filter(OrdersDsl::seller_currency.eq(currency)),
+        };
+
+        let refunds = query
+            .order(RefundsDsl::updated_at)
+            .get_results::<(Refund, Currency)>(self.db_conn)
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(try err e, ErrorSource::Diesel, error_kind)
+            })?;
+
+        for (refund, _) in &refunds {
+            acl::check(&*self.acl, Resource::Refund, Action::Read, self, Some(&refund.order_id))
+                .map_err(ectx!(try ErrorKind::Forbidden))?;
+        }
+
+        Ok(refunds)
+    }
+
+    fn settle_adjustments(&self, refund_ids: Vec<RefundId>, payout_id: PayoutId) -> RepoResultV2<Vec<Refund>> {
+        debug!("Settling balance adjustments of refunds {:?} with payout {}", refund_ids, payout_id);
+
+        let refunds = RefundsDsl::refunds
+            .filter(RefundsDsl::id.eq_any(refund_ids.clone()))
+            .get_results::<Refund>(self.db_conn)
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(try err e, ErrorSource::Diesel, error_kind)
+            })?;
+
+        for refund in &refunds {
+            acl::check(&*self.acl, Resource::Refund, Action::Write, self, Some(&refund.order_id))
+                .map_err(ectx!(try ErrorKind::Forbidden))?;
+        }
+
+        // an adjustment already deducted from another payout is left as it is
+        let command = diesel::update(
+            RefundsDsl::refunds
+                .filter(RefundsDsl::id.eq_any(refund_ids))
+                .filter(RefundsDsl::settled_payout_id.is_null()),
+        )
+        .set(RefundsDsl::settled_payout_id.eq(payout_id));
+
+        command.get_results::<Refund>(self.db_conn).map_err(|e| {
+            let error_kind = ErrorKind::from(&e);
+            ectx!(err e, ErrorSource::Diesel, error_kind)
+        })
+    }
+
+    fn update(&self, refund_id: RefundId, payload: UpdateRefund) -> RepoResultV2<Refund> {
+        debug!("Updating a refund with ID: {} using payload: {:?}", refund_id, payload);
+
+        let refund = RefundsDsl::refunds
+            .filter(RefundsDsl::id.eq(refund_id))
+            .get_result::<Refund>(self.db_conn)
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(try err e, ErrorSource::Diesel, error_kind)
+            })?;
+
+        acl::check(&*self.acl, Resource::Refund, Action::Write, self, Some(&refund.order_id)).map_err(ectx!(try ErrorKind::Forbidden))?;
+
+        let command = diesel::update(RefundsDsl::refunds.filter(RefundsDsl::id.eq(refund_id))).set(&payload);
+
+        command.get_result::<Refund>(self.db_conn).map_err(|e| {
+            let error_kind = ErrorKind::from(&e);
+            ectx!(err e, ErrorSource::Diesel, error_kind)
+        })
+    }
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> CheckScope<Scope, OrderId>
+    for RefundsRepoImpl<'a, T>
+{
+    fn is_in_scope(&self, user_id: UserId, scope: &Scope, obj: Option<&OrderId>) -> bool {
+        match *scope {
+            Scope::All => true,
+            Scope::Owned => {
+                if let Some(order_id) = obj {
+                    let store_id = match OrdersDsl::orders
+                        .filter(OrdersDsl::id.eq(order_id))
+                        .select(OrdersDsl::store_id)
+                        .get_result::<StoreId>(self.db_conn)
+                    {
+                        Ok(store_id) => store_id,
+                        Err(_) => return false,
+                    };
+
+                    UserRolesDsl::roles
+                        .filter(UserRolesDsl::user_id.eq(user_id))
+                        .get_results::<UserRole>(self.db_conn)
+                        .map_err(From::from)
+                        .map(|user_roles_arg| {
+                            user_roles_arg
+                                .iter()
+                                .any(|user_role_arg| user_role_arg.data.clone().map(|data| data == store_id.inner()).unwrap_or_default())
+                        })
+                        .unwrap_or_else(|_: FailureError| false)
+                } else {
+                    false
+                }
+            }
+        }
+    }
+}
@@ -27,6 +27,7 @@ where
     fn create_orders_repo<'a>(&self, db_conn: &'a C, user_id: Option<UserId>) -> Box<OrdersRepo + 'a>;
     fn create_order_exchange_rates_repo_with_sys_acl<'a>(&self, db_conn: &'a C) -> Box<OrderExchangeRatesRepo + 'a>;
     fn create_order_exchange_rates_repo<'a>(&self, db_conn: &'a C, user_id: Option<UserId>) -> Box<OrderExchangeRatesRepo + 'a>;
+    fn create_event_store_repo<'a>(&self, db_conn: &'a C, user_id: Option<UserId>) -> Box<EventStoreRepo + 'a>;
     fn create_event_store_repo_with_sys_acl<'a>(&self, db_conn: &'a C) -> Box<EventStoreRepo + 'a>;
     fn create_payment_intent_repo<'a>(&self, db_conn: &'a C, user_id: Option<UserId>) -> Box<PaymentIntentRepo + 'a>;
     fn create_payment_intent_repo_with_sys_acl<'a>(&self, db_conn: &'a C) -> Box<PaymentIntentRepo + 'a>;
@@ -61,6 +62,21 @@ where
     fn create_store_subscription_with_sys_acl<'a>(&self, db_conn: &'a C) -> Box<StoreSubscriptionRepo + 'a>;
     fn create_subscription_payment_repo<'a>(&self, db_conn: &'a C, user_id: Option<UserId>) -> Box<SubscriptionPaymentRepo + 'a>;
     fn create_subscription_payment_with_sys_acl<'a>(&self, db_conn: &'a C) -> Box<SubscriptionPaymentRepo + 'a>;
+    fn create_stripe_webhook_events_repo_with_sys_acl<'a>(&self, db_conn: &'a C) -> Box<StripeWebhookEventsRepo + 'a>;
+    fn create_webhook_subscriptions_repo<'a>(&self, db_conn: &'a C, user_id: Option<UserId>) -> Box<WebhookSubscriptionsRepo + 'a>;
+    fn create_webhook_subscriptions_repo_with_sys_acl<'a>(&self, db_conn: &'a C) -> Box<WebhookSubscriptionsRepo + 'a>;
+    fn create_webhook_deliveries_repo_with_sys_acl<'a>(&self, db_conn: &'a C) -> Box<WebhookDeliveriesRepo + 'a>;
+    fn create_refunds_repo<'a>(&self, db_conn: &'a C, user_id: Option<UserId>) -> Box<RefundsRepo + 'a>;
+    fn create_refunds_repo_with_sys_acl<'a>(&self, db_conn: &'a C) -> Box<RefundsRepo + 'a>;
+    fn create_order_state_transitions_repo<'a>(&self, db_conn: &'a C, user_id: Option<UserId>) -> Box<OrderStateTransitionsRepo + 'a>;
+    fn create_store_capture_policies_repo<'a>(&self, db_conn: &'a C, user_id: Option<UserId>) -> Box<StoreCapturePoliciesRepo + 'a>;
+    fn create_store_capture_policies_repo_with_sys_acl<'a>(&self, db_conn: &'a C) -> Box<StoreCapturePoliciesRepo + 'a>;
+    fn create_disputes_repo<'a>(&self, db_conn: &'a C, user_id: Option<UserId>) -> Box<DisputesRepo + 'a>;
+    fn create_disputes_repo_with_sys_acl<'a>(&self, db_conn: &'a C) -> Box<DisputesRepo + 'a>;
+    fn create_invoice_surpluses_repo<'a>(&self, db_conn: &'a C, user_id: Option<UserId>) -> Box<InvoiceSurplusesRepo + 'a>;
+    fn create_invoice_surpluses_repo_with_sys_acl<'a>(&self, db_conn: &'a C) -> Box<InvoiceSurplusesRepo + 'a>;
+    fn create_documents_repo<'a>(&self, db_conn: &'a C, user_id: Option<UserId>) -> Box<DocumentsRepo + 'a>;
+    fn create_documents_repo_with_sys_acl<'a>(&self, db_conn: &'a C) -> Box<DocumentsRepo + 'a>;
 }
 
 pub struct ReposFactoryImpl<C1>
@@ -70,6 +86,7 @@ where
     roles_cache: Arc<RolesCacheImpl<C1>>,
     max_processing_attempts: u32,
     stuck_threshold_sec: u32,
+    event_retry_policies: Arc<EventRetryPolicies>,
 }
 
 impl<C1> Clone for ReposFactoryImpl<C1>
@@ -81,6 +98,7 @@ where
             roles_cache: self.roles_cache.clone(),
             max_processing_attempts: self.max_processing_attempts.clone(),
             stuck_threshold_sec: self.stuck_threshold_sec.clone(),
+            event_retry_policies: self.event_retry_policies.clone(),
         }
     }
 }
@@ -89,11 +107,17 @@ impl<C1> ReposFactoryImpl<C1>
 where
     C1: Cache<Vec<BillingRole>> + Send + Sync + 'static,
 {
-    pub fn new(roles_cache: RolesCacheImpl<C1>, max_processing_attempts: u32, stuck_threshold_sec: u32) -> Self {
+    pub fn new(
+        roles_cache: RolesCacheImpl<C1>,
+        max_processing_attempts: u32,
+        stuck_threshold_sec: u32,
+        event_retry_policies: EventRetryPolicies,
+    ) -> Self {
         Self {
             roles_cache: Arc::new(roles_cache),
             max_processing_attempts,
             stuck_threshold_sec,
+            event_retry_policies: Arc::new(event_retry_policies),
         }
     }
 
@@ -199,11 +223,24 @@ where
         Box::new(OrderExchangeRatesRepoImpl::new(db_conn, acl)) as Box<OrderExchangeRatesRepo>
     }
 
+    fn create_event_store_repo<'a>(&self, db_conn: &'a C, user_id: Option<UserId>) -> Box<EventStoreRepo + 'a> {
+        let acl = self.get_acl(db_conn, user_id);
+        Box::new(EventStoreRepoImpl::new(
+            db_conn,
+            acl,
+            self.max_processing_attempts,
+            self.stuck_threshold_sec,
+            self.event_retry_policies.clone(),
+        )) as Box<EventStoreRepo>
+    }
+
     fn create_event_store_repo_with_sys_acl<'a>(&self, db_conn: &'a C) -> Box<EventStoreRepo + 'a> {
         Box::new(EventStoreRepoImpl::new(
             db_conn,
+            Box::new(SystemACL::default()) as Box<Acl<Resource, Action, Scope, FailureError, EventEntry>>,
             self.max_processing_attempts,
             self.stuck_threshold_sec,
+            self.event_retry_policies.clone(),
         )) as Box<EventStoreRepo>
     }
 
@@ -354,6 +391,81 @@ where
         let acl = Box::new(SystemACL::default());
         Box::new(SubscriptionPaymentRepoImpl::new(db_conn, acl))
     }
+
+    fn create_stripe_webhook_events_repo_with_sys_acl<'a>(&self, db_conn: &'a C) -> Box<StripeWebhookEventsRepo + 'a> {
+        let acl = Box::new(SystemACL::default());
+        Box::new(StripeWebhookEventsRepoImpl::new(db_conn, acl))
+    }
+
+    fn create_webhook_subscriptions_repo<'a>(&self, db_conn: &'a C, user_id: Option<UserId>) -> Box<WebhookSubscriptionsRepo + 'a> {
+        let acl = self.get_acl(db_conn, user_id);
+        Box::new(WebhookSubscriptionsRepoImpl::new(db_conn, acl))
+    }
+
+    fn create_webhook_subscriptions_repo_with_sys_acl<'a>(&self, db_conn: &'a C) -> Box<WebhookSubscriptionsRepo + 'a> {
+        let acl = Box::new(SystemACL::default());
+        Box::new(WebhookSubscriptionsRepoImpl::new(db_conn, acl))
+    }
+
+    fn create_webhook_deliveries_repo_with_sys_acl<'a>(&self, db_conn: &'a C) -> Box<WebhookDeliveriesRepo + 'a> {
+        let acl = Box::new(SystemACL::default());
+        Box::new(WebhookDeliveriesRepoImpl::new(db_conn, acl))
+    }
+
+    fn create_refunds_repo<'a>(&self, db_conn: &'a C, user_id: Option<UserId>) -> Box<RefundsRepo + 'a> {
+        let acl = self.get_acl(db_conn, user_id);
+        Box::new(RefundsRepoImpl::new(db_conn, acl))
+    }
+
+    fn create_refunds_repo_with_sys_acl<'a>(&self, db_conn: &'a C) -> Box<RefundsRepo + 'a> {
+        let acl = Box::new(SystemACL::default());
+        Box::new(RefundsRepoImpl::new(db_conn, acl))
+    }
+
+    fn create_order_state_transitions_repo<'a>(&self, db_conn: &'a C, user_id: Option<UserId>) -> Box<OrderStateTransitionsRepo + 'a> {
+        let acl = self.get_acl(db_conn, user_id);
+        Box::new(OrderStateTransitionsRepoImpl::new(db_conn, acl))
+    }
+
+    fn create_store_capture_policies_repo<'a>(&self, db_conn: &'a C, user_id: Option<UserId>) -> Box<StoreCapturePoliciesRepo + 'a> {
+        let acl = self.get_acl(db_conn, user_id);
+        Box::new(StoreCapturePoliciesRepoImpl::new(db_conn, acl))
+    }
+
+    fn create_store_capture_policies_repo_with_sys_acl<'a>(&self, db_conn: &'a C) -> Box<StoreCapturePoliciesRepo + 'a> {
+        let acl = Box::new(SystemACL::default());
+        Box::new(StoreCapturePoliciesRepoImpl::new(db_conn, acl))
+    }
+
+    fn create_disputes_repo<'a>(&self, db_conn: &'a C, user_id: Option<UserId>) -> Box<DisputesRepo + 'a> {
+        let acl = self.get_acl(db_conn, user_id);
+        Box::new(DisputesRepoImpl::new(db_conn, acl))
+    }
+
+    fn create_disputes_repo_with_sys_acl<'a>(&self, db_conn: &'a C) -> Box<DisputesRepo + 'a> {
+        let acl = Box::new(SystemACL::default());
+        Box::new(DisputesRepoImpl::new(db_conn, acl))
+    }
+
+    fn create_invoice_surpluses_repo<'a>(&self, db_conn: &'a C, user_id: Option<UserId>) -> Box<InvoiceSurplusesRepo + 'a> {
+        let acl = self.get_acl(db_conn, user_id);
+        Box::new(InvoiceSurplusesRepoImpl::new(db_conn, acl))
+    }
+
+    fn create_invoice_surpluses_repo_with_sys_acl<'a>(&self, db_conn: &'a C) -> Box<InvoiceSurplusesRepo + 'a> {
+        let acl = Box::new(SystemACL::default());
+        Box::new(InvoiceSurplusesRepoImpl::new(db_conn, acl))
+    }
+
+    fn create_documents_repo<'a>(&self, db_conn: &'a C, user_id: Option<UserId>) -> Box<DocumentsRepo + 'a> {
+        let acl = self.get_acl(db_conn, user_id);
+        Box::new(DocumentsRepoImpl::new(db_conn, acl))
+    }
+
+    fn create_documents_repo_with_sys_acl<'a>(&self, db_conn: &'a C) -> Box<DocumentsRepo + 'a> {
+        let acl = Box::new(SystemACL::default());
+        Box::new(DocumentsRepoImpl::new(db_conn, acl))
+    }
 }
 
 #[cfg(test)]
@@ -371,6 +483,7 @@ pub mod tests {
     use futures::Future;
     use hyper::Headers;
     use services::accounts::AccountService;
+    use services::error::{Error as ServiceError, ErrorKind as ServiceErrorKind};
     use services::types::ServiceFutureV2;
     use std::error::Error;
     use std::fmt;
@@ -473,6 +586,10 @@ pub mod tests {
             Box::new(OrderExchangeRatesRepoMock::default())
         }
 
+        fn create_event_store_repo<'a>(&self, _db_conn: &'a C, _user_id: Option<UserId>) -> Box<EventStoreRepo + 'a> {
+            Box::new(EventStoreRepoMock::default())
+        }
+
         fn create_event_store_repo_with_sys_acl<'a>(&self, _db_conn: &'a C) -> Box<EventStoreRepo + 'a> {
             Box::new(EventStoreRepoMock::default())
         }
@@ -596,6 +713,277 @@ pub mod tests {
         fn create_subscription_payment_with_sys_acl<'a>(&self, _db_conn: &'a C) -> Box<SubscriptionPaymentRepo + 'a> {
             unimplemented!()
         }
+
+        fn create_stripe_webhook_events_repo_with_sys_acl<'a>(&self, _db_conn: &'a C) -> Box<StripeWebhookEventsRepo + 'a> {
+            Box::new(StripeWebhookEventsRepoMock::default())
+        }
+
+        fn create_webhook_subscriptions_repo<'a>(&self, _db_conn: &'a C, _user_id: Option<UserId>) -> Box<WebhookSubscriptionsRepo + 'a> {
+            Box::new(WebhookSubscriptionsRepoMock::default())
+        }
+
+        fn create_webhook_subscriptions_repo_with_sys_acl<'a>(&self, _db_conn: &'a C) -> Box<WebhookSubscriptionsRepo + 'a> {
+            Box::new(WebhookSubscriptionsRepoMock::default())
+        }
+
+        fn create_webhook_deliveries_repo_with_sys_acl<'a>(&self, _db_conn: &'a C) -> Box<WebhookDeliveriesRepo + 'a> {
+            Box::new(WebhookDeliveriesRepoMock::default())
+        }
+
+        fn create_refunds_repo<'a>(&self, _db_conn: &'a C, _user_id: Option<UserId>) -> Box<RefundsRepo + 'a> {
+            Box::new(RefundsRepoMock::default())
+        }
+
+        fn create_refunds_repo_with_sys_acl<'a>(&self, _db_conn: &'a C) -> Box<RefundsRepo + 'a> {
+            Box::new(RefundsRepoMock::default())
+        }
+
+        fn create_order_state_transitions_repo<'a>(
+            &self,
+            _db_conn: &'a C,
+            _user_id: Option<UserId>,
+        ) -> Box<OrderStateTransitionsRepo + 'a> {
+            Box::new(OrderStateTransitionsRepoMock::default())
+        }
+
+        fn create_store_capture_policies_repo<'a>(&self, _db_conn: &'a C, _user_id: Option<UserId>) -> Box<StoreCapturePoliciesRepo + 'a> {
+            Box::new(StoreCapturePoliciesRepoMock::default())
+        }
+
+        fn create_store_capture_policies_repo_with_sys_acl<'a>(&self, _db_conn: &'a C) -> Box<StoreCapturePoliciesRepo + 'a> {
+            Box::new(StoreCapturePoliciesRepoMock::default())
+        }
+
+        fn create_disputes_repo<'a>(&self, _db_conn: &'a C, _user_id: Option<UserId>) -> Box<DisputesRepo + 'a> {
+            Box::new(DisputesRepoMock::default())
+        }
+
+        fn create_disputes_repo_with_sys_acl<'a>(&self, _db_conn: &'a C) -> Box<DisputesRepo + 'a> {
+            Box::new(DisputesRepoMock::default())
+        }
+
+        fn create_invoice_surpluses_repo<'a>(&self, _db_conn: &'a C, _user_id: Option<UserId>) -> Box<InvoiceSurplusesRepo + 'a> {
+            Box::new(InvoiceSurplusesRepoMock::default())
+        }
+
+        fn create_invoice_surpluses_repo_with_sys_acl<'a>(&self, _db_conn: &'a C) -> Box<InvoiceSurplusesRepo + 'a> {
+            Box::new(InvoiceSurplusesRepoMock::default())
+        }
+
+        fn create_documents_repo<'a>(&self, _db_conn: &'a C, _user_id: Option<UserId>) -> Box<DocumentsRepo + 'a> {
+            Box::new(DocumentsRepoMock::default())
+        }
+
+        fn create_documents_repo_with_sys_acl<'a>(&self, _db_conn: &'a C) -> Box<DocumentsRepo + 'a> {
+            Box::new(DocumentsRepoMock::default())
+        }
+    }
+
+    #[derive(Clone, Default)]
+    pub struct StripeWebhookEventsRepoMock;
+
+    impl StripeWebhookEventsRepo for StripeWebhookEventsRepoMock {
+        fn get(&self, _stripe_event_id: String) -> RepoResultV2<Option<StripeWebhookEvent>> {
+            Ok(None)
+        }
+
+        fn register(&self, _payload: NewStripeWebhookEvent) -> RepoResultV2<bool> {
+            Ok(true)
+        }
+    }
+
+    #[derive(Clone, Default)]
+    pub struct WebhookSubscriptionsRepoMock;
+
+    impl WebhookSubscriptionsRepo for WebhookSubscriptionsRepoMock {
+        fn create(&self, _payload: NewWebhookSubscription) -> RepoResultV2<WebhookSubscription> {
+            unimplemented!()
+        }
+
+        fn get(&self, _subscription_id: WebhookSubscriptionId) -> RepoResultV2<Option<WebhookSubscription>> {
+            Ok(None)
+        }
+
+        fn list(&self, _store_id: Option<StoreV2Id>) -> RepoResultV2<Vec<WebhookSubscription>> {
+            Ok(vec![])
+        }
+
+        fn list_active(&self) -> RepoResultV2<Vec<WebhookSubscription>> {
+            Ok(vec![])
+        }
+
+        fn update(
+            &self,
+            _subscription_id: WebhookSubscriptionId,
+            _payload: UpdateWebhookSubscription,
+        ) -> RepoResultV2<WebhookSubscription> {
+            unimplemented!()
+        }
+
+        fn delete(&self, _subscription_id: WebhookSubscriptionId) -> RepoResultV2<Option<WebhookSubscription>> {
+            Ok(None)
+        }
+    }
+
+    #[derive(Clone, Default)]
+    pub struct WebhookDeliveriesRepoMock;
+
+    impl WebhookDeliveriesRepo for WebhookDeliveriesRepoMock {
+        fn create(&self, _payload: NewWebhookDelivery) -> RepoResultV2<WebhookDelivery> {
+            unimplemented!()
+        }
+
+        fn list_by_subscription_id(
+            &self,
+            _subscription_id: WebhookSubscriptionId,
+            _skip: i64,
+            _count: i64,
+        ) -> RepoResultV2<Vec<WebhookDelivery>> {
+            Ok(vec![])
+        }
+    }
+
+    #[derive(Clone, Default)]
+    pub struct RefundsRepoMock;
+
+    impl RefundsRepo for RefundsRepoMock {
+        fn create(&self, _payload: NewRefund) -> RepoResultV2<Refund> {
+            unimplemented!()
+        }
+
+        fn get(&self, _refund_id: RefundId) -> RepoResultV2<Option<Refund>> {
+            Ok(None)
+        }
+
+        fn list_by_order_id(&self, _order_id: OrderV2Id) -> RepoResultV2<Vec<Refund>> {
+            Ok(vec![])
+        }
+
+        fn list_unsettled_adjustments_by_store_id(
+            &self,
+            _store_id: StoreV2Id,
+            _currency: Option<::models::Currency>,
+        ) -> RepoResultV2<Vec<(Refund, ::models::Currency)>> {
+            Ok(vec![])
+        }
+
+        fn settle_adjustments(&self, _refund_ids: Vec<RefundId>, _payout_id: PayoutId) -> RepoResultV2<Vec<Refund>> {
+            Ok(vec![])
+        }
+
+        fn update(&self, _refund_id: RefundId, _payload: UpdateRefund) -> RepoResultV2<Refund> {
+            unimplemented!()
+        }
+    }
+
+    #[derive(Clone, Default)]
+    pub struct OrderStateTransitionsRepoMock;
+
+    impl OrderStateTransitionsRepo for OrderStateTransitionsRepoMock {
+        fn list_by_order_id(&self, _order_id: OrderV2Id) -> RepoResultV2<Vec<OrderStateTransition>> {
+            Ok(vec![])
+        }
+    }
+
+    #[derive(Clone, Default)]
+    pub struct StoreCapturePoliciesRepoMock;
+
+    impl StoreCapturePoliciesRepo for StoreCapturePoliciesRepoMock {
+        fn get(&self, _store_id: StoreId) -> RepoResultV2<Option<StoreCapturePolicy>> {
+            Ok(None)
+        }
+
+        fn upsert(&self, payload: NewStoreCapturePolicy) -> RepoResultV2<StoreCapturePolicy> {
+            Ok(StoreCapturePolicy {
+                store_id: payload.store_id,
+                action: payload.action,
+                created_at: NaiveDateTime::from_timestamp(0, 0),
+                updated_at: NaiveDateTime::from_timestamp(0, 0),
+            })
+        }
+    }
+
+    #[derive(Clone, Default)]
+    pub struct DisputesRepoMock;
+
+    impl DisputesRepo for DisputesRepoMock {
+        fn create(&self, _payload: NewDispute) -> RepoResultV2<Dispute> {
+            unimplemented!()
+        }
+
+        fn list_by_stripe_dispute_id(&self, _stripe_dispute_id: String) -> RepoResultV2<Vec<Dispute>> {
+            Ok(vec![])
+        }
+
+        fn list_by_order_id(&self, _order_id: OrderV2Id) -> RepoResultV2<Vec<Dispute>> {
+            Ok(vec![])
+        }
+
+        fn list_unsettled_adjustments_by_store_id(
+            &self,
+            _store_id: StoreV2Id,
+            _currency: Option<::models::Currency>,
+        ) -> RepoResultV2<Vec<Dispute>> {
+            Ok(vec![])
+        }
+
+        fn settle_adjustments(&self, _dispute_ids: Vec<DisputeId>, _payout_id: PayoutId) -> RepoResultV2<Vec<Dispute>> {
+            Ok(vec![])
+        }
+
+        fn update(&self, _dispute_id: DisputeId, _payload: UpdateDispute) -> RepoResultV2<Dispute> {
+            unimplemented!()
+        }
+    }
+
+    #[derive(Clone, Default)]
+    pub struct InvoiceSurplusesRepoMock;
+
+    impl InvoiceSurplusesRepo for InvoiceSurplusesRepoMock {
+        fn create(&self, _payload: NewInvoiceSurplus) -> RepoResultV2<InvoiceSurplus> {
+            unimplemented!()
+        }
+
+        fn get(&self, _surplus_id: InvoiceSurplusId) -> RepoResultV2<Option<InvoiceSurplus>> {
+            Ok(None)
+        }
+
+        fn get_by_invoice_id(&self, _invoice_id: InvoiceV2Id) -> RepoResultV2<Option<InvoiceSurplus>> {
+            Ok(None)
+        }
+
+        fn update(&self, _surplus_id: InvoiceSurplusId, _payload: UpdateInvoiceSurplus) -> RepoResultV2<InvoiceSurplus> {
+            unimplemented!()
+        }
+    }
+
+    #[derive(Clone, Default)]
+    pub struct DocumentsRepoMock;
+
+    impl DocumentsRepo for DocumentsRepoMock {
+        fn create(&self, _payload: NewDocument) -> RepoResultV2<Document> {
+            unimplemented!()
+        }
+
+        fn get(&self, _document_id: DocumentId) -> RepoResultV2<Option<Document>> {
+            Ok(None)
+        }
+
+        fn list_by_invoice_id(&self, _invoice_id: InvoiceV2Id) -> RepoResultV2<Vec<Document>> {
+            Ok(vec![])
+        }
+
+        fn get_by_fee_id(&self, _fee_id: FeeId) -> RepoResultV2<Option<Document>> {
+            Ok(None)
+        }
+
+        fn next_number(&self, _store_id: StoreId, _kind: DocumentKind) -> RepoResultV2<i64> {
+            Ok(1)
+        }
+
+        fn next_issuer_number(&self, _kind: DocumentKind) -> RepoResultV2<i64> {
+            Ok(1)
+        }
     }
 
     #[derive(Clone, Default)]
@@ -1069,6 +1457,8 @@ pub mod tests {
                 updated_at: NaiveDateTime::from_timestamp(0, 0),
                 buyer_user_id,
                 status: OrderState::New,
+                payment_failed_at: None,
+                surplus_amount: Amount::zero(),
             })
         }
 
@@ -1084,6 +1474,19 @@ pub mod tests {
             unimplemented!()
         }
 
+        fn set_cancelled(&self, _invoice_id: InvoiceV2Id) -> RepoResultV2<Option<RawInvoiceV2>> {
+            unimplemented!()
+        }
+
+        fn set_buyer_currency(
+            &self,
+            _invoice_id: InvoiceV2Id,
+            _buyer_currency: Currency,
+            _account_id: Option<AccountId>,
+        ) -> RepoResultV2<RawInvoiceV2> {
+            unimplemented!()
+        }
+
         fn increase_amount_captured(
             &self,
             _account_id: AccountId,
@@ -1100,6 +1503,10 @@ pub mod tests {
         fn set_amount_paid_fiat(&self, _invoice_id: InvoiceV2Id, _input: InvoiceSetAmountPaid) -> RepoResultV2<RawInvoiceV2> {
             unimplemented!()
         }
+
+        fn set_payment_failed(&self, _invoice_id: InvoiceV2Id, _failed_at: NaiveDateTime) -> RepoResultV2<RawInvoiceV2> {
+            unimplemented!()
+        }
     }
 
     #[derive(Debug, Default)]
@@ -1130,6 +1537,8 @@ pub mod tests {
             Ok(OrderSearchResults {
                 total_count: 0,
                 orders: vec![],
+                next_cursor: None,
+                totals: HashMap::new(),
             })
         }
 
@@ -1154,6 +1563,9 @@ pub mod tests {
                 store_id,
                 state: PaymentState::Initial,
                 stripe_fee: None,
+                captured_amount: None,
+                refunded_amount: Amount::zero(),
+                delivered_at: None,
             })
         }
 
@@ -1164,7 +1576,7 @@ pub mod tests {
         fn delete_by_invoice_id(&self, _invoice_id: InvoiceV2Id) -> RepoResultV2<Vec<RawOrder>> {
             Ok(vec![])
         }
-        fn update_state(&self, order_id: OrderV2Id, _state: PaymentState) -> RepoResultV2<RawOrder> {
+        fn update_state(&self, order_id: OrderV2Id, _state: PaymentState, _change: OrderStateChange) -> RepoResultV2<RawOrder> {
             Ok(RawOrder {
                 id: order_id,
                 seller_currency: BillingCurrency::Btc,
@@ -1176,6 +1588,9 @@ pub mod tests {
                 store_id: StoreV2Id::new(1),
                 state: PaymentState::Initial,
                 stripe_fee: None,
+                captured_amount: None,
+                refunded_amount: Amount::zero(),
+                delivered_at: None,
             })
         }
         fn update_stripe_fee(&self, order_id: OrderV2Id, stripe_fee: Amount) -> RepoResultV2<RawOrder> {
@@ -1190,8 +1605,48 @@ pub mod tests {
                 store_id: StoreV2Id::new(1),
                 state: PaymentState::Initial,
                 stripe_fee: Some(stripe_fee),
+                captured_amount: None,
+                refunded_amount: Amount::zero(),
+                delivered_at: None,
+            })
+        }
+        fn update_captured_amount(&self, order_id: OrderV2Id, captured_amount: Amount) -> RepoResultV2<RawOrder> {
+            Ok(RawOrder {
+                id: order_id,
+                seller_currency: BillingCurrency::Btc,
+                total_amount: Amount::new(0),
+                cashback_amount: Amount::new(0),
+                invoice_id: InvoiceV2Id::generate(),
+                created_at: NaiveDateTime::from_timestamp(0, 0),
+                updated_at: NaiveDateTime::from_timestamp(0, 0),
+                store_id: StoreV2Id::new(1),
+                state: PaymentState::Initial,
+                stripe_fee: None,
+                captured_amount: Some(captured_amount),
+                refunded_amount: Amount::zero(),
+                delivered_at: None,
             })
         }
+        fn update_refunded_amount(&self, order_id: OrderV2Id, refunded_amount: Amount) -> RepoResultV2<RawOrder> {
+            Ok(RawOrder {
+                id: order_id,
+                seller_currency: BillingCurrency::Btc,
+                total_amount: Amount::new(0),
+                cashback_amount: Amount::new(0),
+                invoice_id: InvoiceV2Id::generate(),
+                created_at: NaiveDateTime::from_timestamp(0, 0),
+                updated_at: NaiveDateTime::from_timestamp(0, 0),
+                store_id: StoreV2Id::new(1),
+                state: PaymentState::Initial,
+                stripe_fee: None,
+                captured_amount: None,
+                refunded_amount,
+                delivered_at: None,
+            })
+        }
+        fn set_delivered(&self, _order_id: OrderV2Id) -> RepoResultV2<Option<RawOrder>> {
+            Ok(None)
+        }
     }
 
     #[derive(Debug, Default)]
@@ -1257,6 +1712,7 @@ pub mod tests {
                 created_at: chrono::Utc::now().naive_utc(),
                 status_updated_at: chrono::Utc::now().naive_utc(),
                 scheduled_on: None,
+                next_retry_at: None,
             })
         }
 
@@ -1269,6 +1725,7 @@ pub mod tests {
                 created_at: chrono::Utc::now().naive_utc(),
                 status_updated_at: chrono::Utc::now().naive_utc(),
                 scheduled_on: Some(scheduled_on),
+                next_retry_at: None,
             })
         }
 
@@ -1276,15 +1733,13 @@ pub mod tests {
             Ok((0..limit)
                 .map(|i| EventEntry {
                     id: EventEntryId::new(i as i64),
-                    event: Event {
-                        id: EventId::generate(),
-                        payload: EventPayload::NoOp,
-                    },
+                    event: Event::new(EventPayload::NoOp),
                     status: EventStatus::InProgress,
                     attempt_count: 1,
                     created_at: chrono::Utc::now().naive_utc(),
                     status_updated_at: chrono::Utc::now().naive_utc(),
                     scheduled_on: None,
+                    next_retry_at: None,
                 })
                 .collect::<Vec<_>>())
         }
@@ -1296,32 +1751,84 @@ pub mod tests {
         fn complete_event(&self, event_entry_id: EventEntryId) -> RepoResultV2<EventEntry> {
             Ok(EventEntry {
                 id: event_entry_id,
-                event: Event {
-                    id: EventId::generate(),
-                    payload: EventPayload::NoOp,
-                },
+                event: Event::new(EventPayload::NoOp),
                 status: EventStatus::Completed,
                 attempt_count: 1,
                 created_at: chrono::Utc::now().naive_utc(),
                 status_updated_at: chrono::Utc::now().naive_utc(),
                 scheduled_on: None,
+                next_retry_at: None,
             })
         }
 
         fn fail_event(&self, event_entry_id: EventEntryId) -> RepoResultV2<EventEntry> {
             Ok(EventEntry {
                 id: event_entry_id,
-                event: Event {
-                    id: EventId::generate(),
-                    payload: EventPayload::NoOp,
-                },
+                event: Event::new(EventPayload::NoOp),
                 status: EventStatus::Failed,
                 attempt_count: 5,
                 created_at: chrono::Utc::now().naive_utc(),
                 status_updated_at: chrono::Utc::now().naive_utc(),
                 scheduled_on: None,
+                next_retry_at: None,
+            })
+        }
+
+        fn get_event(&self, _event_entry_id: EventEntryId) -> RepoResultV2<Option<EventEntry>> {
+            Ok(None)
+        }
+
+        fn search_events(&self, _skip: i64, _count: i64, _search: EventEntrySearch) -> RepoResultV2<EventEntrySearchResults> {
+            Ok(EventEntrySearchResults {
+                total_count: 0,
+                events: vec![],
             })
         }
+
+        fn get_events_for_replay(&self, _search: EventReplaySearch, _limit: i64) -> RepoResultV2<Vec<EventEntry>> {
+            Ok(vec![])
+        }
+
+        fn requeue_events(&self, _event_entry_ids: Vec<EventEntryId>) -> RepoResultV2<Vec<EventEntry>> {
+            Ok(vec![])
+        }
+
+        fn resolve_event(&self, _event_entry_id: EventEntryId) -> RepoResultV2<Option<EventEntry>> {
+            Ok(None)
+        }
+
+        fn add_event_attempt(&self, new_event_attempt: NewEventAttempt) -> RepoResultV2<EventAttempt> {
+            let NewEventAttempt {
+                event_id,
+                worker_instance,
+                started_at,
+                finished_at,
+                error_kind,
+                error_message,
+            } = new_event_attempt;
+
+            Ok(EventAttempt {
+                id: EventAttemptId::new(1),
+                event_id,
+                worker_instance,
+                started_at,
+                finished_at,
+                error_kind,
+                error_message,
+            })
+        }
+
+        fn get_event_attempts(&self, _event_entry_id: EventEntryId) -> RepoResultV2<Vec<EventAttempt>> {
+            Ok(vec![])
+        }
+
+        fn archive_completed_events(&self, _completed_before: NaiveDateTime, _limit: u32) -> RepoResultV2<usize> {
+            Ok(0)
+        }
+
+        fn delete_completed_events(&self, _completed_before: NaiveDateTime, _limit: u32) -> RepoResultV2<usize> {
+            Ok(0)
+        }
     }
 
     #[derive(Debug, Default)]
@@ -1513,6 +2020,7 @@ pub mod tests {
             status: PaymentIntentStatus::Other,
             created_at: now,
             updated_at: now,
+            last_payment_error_code: None,
         }
     }
 
@@ -1546,6 +2054,72 @@ pub mod tests {
         }
     }
 
+    pub fn create_raw_invoice_v2(buyer_currency: BillingCurrency) -> RawInvoiceV2 {
+        RawInvoiceV2 {
+            id: InvoiceV2Id::generate(),
+            account_id: None,
+            buyer_currency,
+            amount_captured: Amount::zero(),
+            final_amount_paid: None,
+            final_cashback_amount: None,
+            paid_at: None,
+            created_at: NaiveDateTime::from_timestamp(0, 0),
+            updated_at: NaiveDateTime::from_timestamp(0, 0),
+            buyer_user_id: ::models::UserId::new(1),
+            status: OrderState::PaymentAwaited,
+            payment_failed_at: None,
+            surplus_amount: Amount::zero(),
+        }
+    }
+
+    pub fn create_raw_order(invoice_id: InvoiceV2Id, seller_currency: BillingCurrency, total_amount: Amount) -> RawOrder {
+        RawOrder {
+            id: OrderV2Id::generate(),
+            seller_currency,
+            total_amount,
+            cashback_amount: Amount::zero(),
+            invoice_id,
+            created_at: NaiveDateTime::from_timestamp(0, 0),
+            updated_at: NaiveDateTime::from_timestamp(0, 0),
+            store_id: StoreV2Id::new(1),
+            state: PaymentState::Initial,
+            stripe_fee: None,
+            captured_amount: None,
+            refunded_amount: Amount::zero(),
+            delivered_at: None,
+        }
+    }
+
+    pub fn create_refund(order_id: OrderV2Id, amount: Amount, status: RefundStatus) -> Refund {
+        Refund {
+            id: RefundId::generate(),
+            order_id,
+            amount,
+            reason: None,
+            stripe_refund_id: None,
+            status,
+            fee_adjustment: Amount::zero(),
+            created_at: NaiveDateTime::from_timestamp(0, 0),
+            updated_at: NaiveDateTime::from_timestamp(0, 0),
+            crypto_currency: None,
+            crypto_amount: None,
+            wallet_address: None,
+            transaction_id: None,
+            blockchain_fee: None,
+            kind: RefundKind::Refund,
+            balance_adjustment: Amount::zero(),
+            settled_payout_id: None,
+        }
+    }
+
+    /// Fields of the validation error returned by a service, panics on success or any other error
+    pub fn validation_error_fields<T: fmt::Debug>(result: Result<T, ServiceError>) -> Vec<String> {
+        match result.map_err(|e| e.kind()) {
+            Err(ServiceErrorKind::Validation(serde_json::Value::Object(errors))) => errors.keys().cloned().collect(),
+            result => panic!("expected a validation error, got {:?}", result),
+        }
+    }
+
     #[derive(Default)]
     pub struct MockConnection {
         tr: AnsiTransactionManager,
@@ -0,0 +1,123 @@
+use diesel;
+use diesel::connection::AnsiTransactionManager;
+use diesel::pg::upsert::excluded;
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::query_dsl::RunQueryDsl;
+use diesel::Connection;
+use failure::Error as FailureError;
+use failure::Fail;
+
+use stq_types::{StoreId, UserId};
+
+use models::authorization::*;
+use models::{NewStoreCapturePolicy, StoreCapturePolicy, UserRole};
+use repos::legacy_acl::*;
+
+use schema::roles::dsl as UserRolesDsl;
+use schema::store_capture_policies::dsl as StoreCapturePoliciesDsl;
+
+use super::acl;
+use super::error::*;
+use super::types::RepoResultV2;
+
+type StoreCapturePoliciesRepoAcl = Box<Acl<Resource, Action, Scope, FailureError, StoreCapturePolicyAccess>>;
+
+pub struct StoreCapturePolicyAccess {
+    store_id: StoreId,
+}
+
+pub struct StoreCapturePoliciesRepoImpl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> {
+    pub db_conn: &'a T,
+    pub acl: StoreCapturePoliciesRepoAcl,
+}
+
+pub trait StoreCapturePoliciesRepo {
+    fn get(&self, store_id: StoreId) -> RepoResultV2<Option<StoreCapturePolicy>>;
+    /// Creates the policy of the store or replaces the existing one
+    fn upsert(&self, payload: NewStoreCapturePolicy) -> RepoResultV2<StoreCapturePolicy>;
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> StoreCapturePoliciesRepoImpl<'a, T> {
+    pub fn new(db_conn: &'a T, acl: StoreCapturePoliciesRepoAcl) -> Self {
+        Self { db_conn, acl }
+    }
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> StoreCapturePoliciesRepo
+    for StoreCapturePoliciesRepoImpl<'a, T>
+{
+    fn get(&self, store_id: StoreId) -> RepoResultV2<Option<StoreCapturePolicy>> {
+        debug!("Getting capture policy of store with ID: {}", store_id);
+
+        acl::check(
+            &*self.acl,
+            Resource::StoreCapturePolicy,
+            Action::Read,
+            self,
+            Some(&StoreCapturePolicyAccess { store_id }),
+        )
+        .map_err(ectx!(try ErrorKind::Forbidden))?;
+
+        StoreCapturePoliciesDsl::store_capture_policies
+            .filter(StoreCapturePoliciesDsl::store_id.eq(store_id))
+            .get_result::<StoreCapturePolicy>(self.db_conn)
+            .optional()
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(err e, ErrorSource::Diesel, error_kind)
+            })
+    }
+
+    fn upsert(&self, payload: NewStoreCapturePolicy) -> RepoResultV2<StoreCapturePolicy> {
+        debug!("Setting store capture policy: {:?}", payload);
+
+        acl::check(
+            &*self.acl,
+            Resource::StoreCapturePolicy,
+            Action::Write,
+            self,
+            Some(&StoreCapturePolicyAccess {
+                store_id: payload.store_id,
+            }),
+        )
+        .map_err(ectx!(try ErrorKind::Forbidden))?;
+
+        let command = diesel::insert_into(StoreCapturePoliciesDsl::store_capture_policies)
+            .values(&payload)
+            .on_conflict(StoreCapturePoliciesDsl::store_id)
+            .do_update()
+            .set(StoreCapturePoliciesDsl::action.eq(excluded(StoreCapturePoliciesDsl::action)));
+
+        command.get_result::<StoreCapturePolicy>(self.db_conn).map_err(|e| {
+            let error_kind = ErrorKind::from(&e);
+            ectx!(err e, ErrorSource::Diesel, error_kind)
+        })
+    }
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> CheckScope<Scope, StoreCapturePolicyAccess>
+    for StoreCapturePoliciesRepoImpl<'a, T>
+{
+    fn is_in_scope(&self, user_id: UserId, scope: &Scope, obj: Option<&StoreCapturePolicyAccess>) -> bool {
+        match *scope {
+            Scope::All => true,
+            Scope::Owned => {
+                if let Some(StoreCapturePolicyAccess { store_id }) = obj {
+                    UserRolesDsl::roles
+                        .filter(UserRolesDsl::user_id.eq(user_id))
+                        .get_results::<UserRole>(self.db_conn)
+                        .map_err(From::from)
+                        .map(|user_roles_arg| {
+                            user_roles_arg
+                                .iter()
+                                .any(|user_role_arg| user_role_arg.data.clone().map(|data| data == store_id.0).unwrap_or_default())
+                        })
+                        .unwrap_or_else(|_: FailureError| false)
+                } else {
+                    false
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use diesel;
+use diesel::connection::AnsiTransactionManager;
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::query_dsl::RunQueryDsl;
+use diesel::Connection;
+use failure::Error as FailureError;
+use failure::Fail;
+
+use repos::legacy_acl::*;
+
+use models::authorization::*;
+use models::{NewStripeWebhookEvent, StripeWebhookEvent};
+use schema::stripe_webhook_events::dsl as StripeWebhookEvents;
+
+use super::acl;
+use super::error::*;
+use super::types::RepoResultV2;
+
+type StripeWebhookEventsRepoAcl = Box<Acl<Resource, Action, Scope, FailureError, StripeWebhookEvent>>;
+
+pub struct StripeWebhookEventsRepoImpl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> {
+    pub db_conn: &'a T,
+    pub acl: StripeWebhookEventsRepoAcl,
+}
+
+pub trait StripeWebhookEventsRepo {
+    fn get(&self, stripe_event_id: String) -> RepoResultV2<Option<StripeWebhookEvent>>;
+
+    /// Returns `false` if the event has already been registered
+    fn register(&self, payload: NewStripeWebhookEvent) -> RepoResultV2<bool>;
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> StripeWebhookEventsRepoImpl<'a, T> {
+    pub fn new(db_conn: &'a T, acl: StripeWebhookEventsRepoAcl) -> Self {
+        Self { db_conn, acl }
+    }
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> StripeWebhookEventsRepo
+    for StripeWebhookEventsRepoImpl<'a, T>
+{
+    fn get(&self, stripe_event_id: String) -> RepoResultV2<Option<StripeWebhookEvent>> {
+        debug!("Getting a Stripe webhook event with ID: {}", stripe_event_id);
+
+        acl::check(&*self.acl, Resource::EventStore, Action::Read, self, None).map_err(ectx!(try ErrorKind::Forbidden))?;
+
+        StripeWebhookEvents::stripe_webhook_events
+            .filter(StripeWebhookEvents::stripe_event_id.eq(stripe_event_id))
+            .get_result::<StripeWebhookEvent>(self.db_conn)
+            .optional()
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(err e, ErrorSource::Diesel, error_kind)
+            })
+    }
+
+    fn register(&self, payload: NewStripeWebhookEvent) -> RepoResultV2<bool> {
+        debug!("Registering a Stripe webhook event: {:?}", payload);
+
+        acl::check(&*self.acl, Resource::EventStore, Action::Write, self, None).map_err(ectx!(try ErrorKind::Forbidden))?;
+
+        let command = diesel::insert_into(StripeWebhookEvents::stripe_webhook_events)
+            .values(&payload)
+            .on_conflict_do_nothing();
+
+        command.execute(self.db_conn).map(|inserted| inserted > 0).map_err(|e| {
+            let error_kind = ErrorKind::from(&e);
+            ectx!(err e, ErrorSource::Diesel, error_kind)
+        })
+    }
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> CheckScope<Scope, StripeWebhookEvent>
+    for StripeWebhookEventsRepoImpl<'a, T>
+{
+    fn is_in_scope(&self, _user_id: stq_types::UserId, scope: &Scope, _obj: Option<&StripeWebhookEvent>) -> bool {
+        match *scope {
+            Scope::All => true,
+            Scope::Owned => false,
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use diesel;
+use diesel::connection::AnsiTransactionManager;
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::query_dsl::RunQueryDsl;
+use diesel::Connection;
+use failure::Error as FailureError;
+use failure::Fail;
+
+use models::authorization::*;
+use models::{NewWebhookDelivery, WebhookDelivery, WebhookSubscriptionId};
+use repos::legacy_acl::*;
+use schema::webhook_deliveries::dsl as WebhookDeliveries;
+
+use super::acl;
+use super::error::*;
+use super::types::RepoResultV2;
+
+type WebhookDeliveriesRepoAcl = Box<Acl<Resource, Action, Scope, FailureError, WebhookDelivery>>;
+
+pub struct WebhookDeliveriesRepoImpl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> {
+    pub db_conn: &'a T,
+    pub acl: WebhookDeliveriesRepoAcl,
+}
+
+pub trait WebhookDeliveriesRepo {
+    fn create(&self, payload: NewWebhookDelivery) -> RepoResultV2<WebhookDelivery>;
+    fn list_by_subscription_id(&self, subscription_id: WebhookSubscriptionId, skip: i64, count: i64) -> RepoResultV2<Vec<WebhookDelivery>>;
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> WebhookDeliveriesRepoImpl<'a, T> {
+    pub fn new(db_conn: &'a T, acl: WebhookDeliveriesRepoAcl) -> Self {
+        Self { db_conn, acl }
+    }
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> WebhookDeliveriesRepo
+    for WebhookDeliveriesRepoImpl<'a, T>
+{
+    fn create(&self, payload: NewWebhookDelivery) -> RepoResultV2<WebhookDelivery> {
+        debug!("Creating a webhook delivery: {:?}", payload);
+
+        acl::check(&*self.acl, Resource::WebhookSubscription, Action::Write, self, None).map_err(ectx!(try ErrorKind::Forbidden))?;
+
+        let command = diesel::insert_into(WebhookDeliveries::webhook_deliveries).values(&payload);
+
+        command.get_result::<WebhookDelivery>(self.db_conn).map_err(|e| {
+            let error_kind = ErrorKind::from(&e);
+            ectx!(err e, ErrorSource::Diesel, error_kind)
+        })
+    }
+
+    fn list_by_subscription_id(&self, subscription_id: WebhookSubscriptionId, skip: i64, count: i64) -> RepoResultV2<Vec<WebhookDelivery>> {
+        debug!(
+            "Listing webhook deliveries for subscription with ID: {}, skip: {}, count: {}",
+            subscription_id, skip, count
+        );
+
+        acl::check(&*self.acl, Resource::WebhookSubscription, Action::Read, self, None).map_err(ectx!(try ErrorKind::Forbidden))?;
+
+        WebhookDeliveries::webhook_deliveries
+            .filter(WebhookDeliveries::subscription_id.eq(subscription_id))
+            .order(WebhookDeliveries::id.desc())
+            .offset(skip)
+            .limit(count)
+            .get_results::<WebhookDelivery>(self.db_conn)
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(err e, ErrorSource::Diesel, error_kind)
+            })
+    }
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> CheckScope<Scope, WebhookDelivery>
+    for WebhookDeliveriesRepoImpl<'a, T>
+{
+    fn is_in_scope(&self, _user_id: stq_types::UserId, scope: &Scope, _obj: Option<&WebhookDelivery>) -> bool {
+        match *scope {
+            Scope::All => true,
+            Scope::Owned => false,
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use diesel;
+use diesel::connection::AnsiTransactionManager;
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::query_dsl::RunQueryDsl;
+use diesel::Connection;
+use failure::Error as FailureError;
+use failure::Fail;
+
+use stq_types::UserId;
+
+use models::authorization::*;
+use models::order_v2::StoreId;
+use models::{
+    NewWebhookSubscription, UpdateWebhookSubscription, UserRole, WebhookSubscription, WebhookSubscriptionAccess, WebhookSubscriptionId,
+};
+use repos::legacy_acl::*;
+
+use schema::roles::dsl as UserRolesDsl;
+use schema::webhook_subscriptions::dsl as WebhookSubscriptions;
+
+use super::acl;
+use super::error::*;
+use super::types::RepoResultV2;
+
+type WebhookSubscriptionsRepoAcl = Box<Acl<Resource, Action, Scope, FailureError, WebhookSubscriptionAccess>>;
+
+pub struct WebhookSubscriptionsRepoImpl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> {
+    pub db_conn: &'a T,
+    pub acl: WebhookSubscriptionsRepoAcl,
+}
+
+pub trait WebhookSubscriptionsRepo {
+    fn create(&self, payload: NewWebhookSubscription) -> RepoResultV2<WebhookSubscription>;
+    fn get(&self, subscription_id: WebhookSubscriptionId) -> RepoResultV2<Option<WebhookSubscription>>;
+    fn list(&self, store_id: Option<StoreId>) -> RepoResultV2<Vec<WebhookSubscription>>;
+    fn list_active(&self) -> RepoResultV2<Vec<WebhookSubscription>>;
+    fn update(&self, subscription_id: WebhookSubscriptionId, payload: UpdateWebhookSubscription) -> RepoResultV2<WebhookSubscription>;
+    fn delete(&self, subscription_id: WebhookSubscriptionId) -> RepoResultV2<Option<WebhookSubscription>>;
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> WebhookSubscriptionsRepoImpl<'a, T> {
+    pub fn new(db_conn: &'a T, acl: WebhookSubscriptionsRepoAcl) -> Self {
+        Self { db_conn, acl }
+    }
+
+    fn check_access(&self, action: Action, subscriptions: &[WebhookSubscription]) -> RepoResultV2<()> {
+        for subscription in subscriptions {
+            acl::check(
+                &*self.acl,
+                Resource::WebhookSubscription,
+                action,
+                self,
+                Some(&WebhookSubscriptionAccess::from(subscription)),
+            )
+            .map_err(ectx!(try ErrorKind::Forbidden))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> WebhookSubscriptionsRepo
+    for WebhookSubscriptionsRepoImpl<'a, T>
+{
+    fn create(&self, payload: NewWebhookSubscription) -> RepoResultV2<WebhookSubscription> {
+        debug!("Creating a webhook subscription: {:?}", payload);
+
+        acl::check(
+            &*self.acl,
+            Resource::WebhookSubscription,
+            Action::Write,
+            self,
+            Some(&WebhookSubscriptionAccess::from(&payload)),
+        )
+        .map_err(ectx!(try ErrorKind::Forbidden))?;
+
+        let command = diesel::insert_into(WebhookSubscriptions::webhook_subscriptions).values(&payload);
+
+        command.get_result::<WebhookSubscription>(self.db_conn).map_err(|e| {
+            let error_kind = ErrorKind::from(&e);
+            ectx!(err e, ErrorSource::Diesel, error_kind)
+        })
+    }
+
+    fn get(&self, subscription_id: WebhookSubscriptionId) -> RepoResultV2<Option<WebhookSubscription>> {
+        debug!("Getting a webhook subscription with ID: {}", subscription_id);
+
+        let subscription = WebhookSubscriptions::webhook_subscriptions
+            .filter(WebhookSubscriptions::id.eq(subscription_id))
+            .get_result::<WebhookSubscription>(self.db_conn)
+            .optional()
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(try err e, ErrorSource::Diesel, error_kind)
+            })?;
+
+        if let Some(ref subscription) = subscription {
+            self.check_access(Action::Read, &[subscription.clone()])?;
+        }
+
+        Ok(subscription)
+    }
+
+    fn list(&self, store_id: Option<StoreId>) -> RepoResultV2<Vec<WebhookSubscription>> {
+        debug!("Listing webhook subscriptions, store ID: {:?}", store_id);
+
+        let query = WebhookSubscriptions::webhook_subscriptions
+            .order(WebhookSubscriptions::created_at.desc())
+            .into_boxed();
+
+        let query = match store_id {
+            Some(store_id) => query.filter(WebhookSubscriptions::store_id.eq(store_id)),
+            None => query,
+        };
+
+        let subscriptions = query.get_results::<WebhookSubscription>(self.db_conn).map_err(|e| {
+            let error_kind = ErrorKind::from(&e);
+            ectx!(try err e, ErrorSource::Diesel, error_kind)
+        })?;
+
+        self.check_access(Action::Read, &subscriptions)?;
+
+        Ok(subscriptions)
+    }
+
+    fn list_active(&self) -> RepoResultV2<Vec<WebhookSubscription>> {
+        debug!("Listing active webhook subscriptions");
+
+        let subscriptions = WebhookSubscriptions::webhook_subscriptions
+            .filter(WebhookSubscriptions::is_active.eq(true))
+            .get_results::<WebhookSubscription>(self.db_conn)
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(try err e, ErrorSource::Diesel, error_kind)
+            })?;
+
+        self.check_access(Action::Read, &subscriptions)?;
+
+        Ok(subscriptions)
+    }
+
+    fn update(&self, subscription_id: WebhookSubscriptionId, payload: UpdateWebhookSubscription) -> RepoResultV2<WebhookSubscription> {
+        debug!(
+            "Updating a webhook subscription with ID: {} using payload: {:?}",
+            subscription_id, payload
+        );
+
+        let subscription = WebhookSubscriptions::webhook_subscriptions
+            .filter(WebhookSubscriptions::id.eq(subscription_id))
+            .get_result::<WebhookSubscription>(self.db_conn)
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(try err e, ErrorSource::Diesel, error_kind)
+            })?;
+
+        self.check_access(Action::Write, &[subscription])?;
+
+        let command =
+            diesel::update(WebhookSubscriptions::webhook_subscriptions.filter(WebhookSubscriptions::id.eq(subscription_id))).set(&payload);
+
+        command.get_result::<WebhookSubscription>(self.db_conn).map_err(|e| {
+            let error_kind = ErrorKind::from(&e);
+            ectx!(err e, ErrorSource::Diesel, error_kind)
+        })
+    }
+
+    fn delete(&self, subscription_id: WebhookSubscriptionId) -> RepoResultV2<Option<WebhookSubscription>> {
+        debug!("Deleting a webhook subscription with ID: {}", subscription_id);
+
+        let subscription = WebhookSubscriptions::webhook_subscriptions
+            .filter(WebhookSubscriptions::id.eq(subscription_id))
+            .get_result::<WebhookSubscription>(self.db_conn)
+            .optional()
+            .map_err(|e| {
+                let error_kind = ErrorKind::from(&e);
+                ectx!(try err e, ErrorSource::Diesel, error_kind)
+            })?;
+
+        let subscription = match subscription {
+            None => {
+                return Ok(None);
+            }
+            Some(subscription) => subscription,
+        };
+
+        self.check_access(Action::Write, &[subscription])?;
+
+        let command = diesel::delete(WebhookSubscriptions::webhook_subscriptions.filter(WebhookSubscriptions::id.eq(subscription_id)));
+
+        command.get_result::<WebhookSubscription>(self.db_conn).optional().map_err(|e| {
+            let error_kind = ErrorKind::from(&e);
+            ectx!(err e, ErrorSource::Diesel, error_kind)
+        })
+    }
+}
+
+impl<'a, T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static> CheckScope<Scope, WebhookSubscriptionAccess>
+    for WebhookSubscriptionsRepoImpl<'a, T>
+{
+    fn is_in_scope(&self, user_id: UserId, scope: &Scope, obj: Option<&WebhookSubscriptionAccess>) -> bool {
+        match *scope {
+            Scope::All => true,
+            Scope::Owned => {
+                if let Some(WebhookSubscriptionAccess { store_id: Some(store_id) }) = obj {
+                    UserRolesDsl::roles
+                        .filter(UserRolesDsl::user_id.eq(user_id))
+                        .get_results::<UserRole>(self.db_conn)
+                        .map_err(From::from)
+                        .map(|user_roles_arg| {
+                            user_roles_arg
+                                .iter()
+                                .any(|user_role_arg| user_role_arg.data.clone().map(|data| data == store_id.inner()).unwrap_or_default())
+                        })
+                        .unwrap_or_else(|_: FailureError| false)
+                } else {
+                    false
+                }
+            }
+        }
+    }
+}
@@ -27,6 +27,63 @@ table! {
     }
 }
 
+table! {
+    disputes (id) {
+        id -> Uuid,
+        stripe_dispute_id -> Varchar,
+        order_id -> Uuid,
+        charge_id -> Varchar,
+        amount -> Numeric,
+        currency -> Varchar,
+        reason -> Nullable<Varchar>,
+        status -> Varchar,
+        evidence_due_by -> Nullable<Timestamp>,
+        previous_state -> Varchar,
+        balance_adjustment -> Numeric,
+        closed_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        settled_payout_id -> Nullable<Uuid>,
+    }
+}
+
+table! {
+    document_sequences (store_id, kind) {
+        store_id -> Int4,
+        kind -> Varchar,
+        last_number -> Int8,
+    }
+}
+
+table! {
+    documents (id) {
+        id -> Uuid,
+        kind -> Varchar,
+        store_id -> Int4,
+        invoice_id -> Nullable<Uuid>,
+        fee_id -> Nullable<Int4>,
+        buyer_user_id -> Nullable<Int4>,
+        number -> Int8,
+        document_number -> Varchar,
+        format -> Varchar,
+        content -> Bytea,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    event_attempts (id) {
+        id -> Int8,
+        event_id -> Int8,
+        worker_instance -> Text,
+        started_at -> Timestamp,
+        finished_at -> Timestamp,
+        error_kind -> Nullable<Text>,
+        error_message -> Nullable<Text>,
+    }
+}
+
 table! {
     event_store (id) {
         id -> Int8,
@@ -39,6 +96,19 @@ table! {
     }
 }
 
+table! {
+    event_store_archive (id) {
+        id -> Int8,
+        event -> Jsonb,
+        status -> Text,
+        attempt_count -> Int4,
+        created_at -> Timestamp,
+        status_updated_at -> Timestamp,
+        scheduled_on -> Nullable<Timestamp>,
+        archived_at -> Timestamp,
+    }
+}
+
 table! {
     fees (id) {
         id -> Int4,
@@ -71,6 +141,22 @@ table! {
     }
 }
 
+table! {
+    invoice_surpluses (id) {
+        id -> Uuid,
+        invoice_id -> Uuid,
+        buyer_user_id -> Int4,
+        currency -> Varchar,
+        amount -> Numeric,
+        handling -> Varchar,
+        status -> Varchar,
+        wallet_address -> Nullable<Varchar>,
+        transaction_id -> Nullable<Uuid>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
 table! {
     invoices (id) {
         id -> Uuid,
@@ -100,6 +186,15 @@ table! {
         updated_at -> Timestamp,
         buyer_user_id -> Int4,
         status -> Text,
+        payment_failed_at -> Nullable<Timestamp>,
+        surplus_amount -> Numeric,
+    }
+}
+
+table! {
+    issuer_document_sequences (kind) {
+        kind -> Varchar,
+        last_number -> Int8,
     }
 }
 
@@ -134,6 +229,19 @@ table! {
     }
 }
 
+table! {
+    order_state_transitions (id) {
+        id -> Int8,
+        order_id -> Uuid,
+        previous_state -> Varchar,
+        new_state -> Varchar,
+        user_id -> Nullable<Int4>,
+        event_id -> Nullable<Uuid>,
+        reason -> Nullable<Text>,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     orders (id) {
         id -> Uuid,
@@ -146,6 +254,9 @@ table! {
         store_id -> Int4,
         state -> Varchar,
         stripe_fee -> Nullable<Numeric>,
+        captured_amount -> Nullable<Numeric>,
+        refunded_amount -> Numeric,
+        delivered_at -> Nullable<Timestamp>,
     }
 }
 
@@ -176,6 +287,7 @@ table! {
         status -> Varchar,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        last_payment_error_code -> Nullable<Varchar>,
     }
 }
 
@@ -230,6 +342,28 @@ table! {
     }
 }
 
+table! {
+    refunds (id) {
+        id -> Uuid,
+        order_id -> Uuid,
+        amount -> Numeric,
+        reason -> Nullable<Text>,
+        stripe_refund_id -> Nullable<Varchar>,
+        status -> Varchar,
+        fee_adjustment -> Numeric,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        crypto_currency -> Nullable<Varchar>,
+        crypto_amount -> Nullable<Numeric>,
+        wallet_address -> Nullable<Varchar>,
+        transaction_id -> Nullable<Uuid>,
+        blockchain_fee -> Nullable<Numeric>,
+        kind -> Varchar,
+        balance_adjustment -> Numeric,
+        settled_payout_id -> Nullable<Uuid>,
+    }
+}
+
 table! {
     roles (id) {
         id -> Uuid,
@@ -262,6 +396,15 @@ table! {
     }
 }
 
+table! {
+    store_capture_policies (store_id) {
+        store_id -> Int4,
+        action -> Varchar,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
 table! {
     store_subscription (store_id) {
         store_id -> Int4,
@@ -275,6 +418,14 @@ table! {
     }
 }
 
+table! {
+    stripe_webhook_events (stripe_event_id) {
+        stripe_event_id -> Varchar,
+        event_type -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     subscription (id) {
         id -> Int4,
@@ -309,31 +460,74 @@ table! {
     }
 }
 
+table! {
+    webhook_deliveries (id) {
+        id -> Int8,
+        subscription_id -> Uuid,
+        notification_id -> Uuid,
+        event_type -> Varchar,
+        payload -> Jsonb,
+        status -> Varchar,
+        error_message -> Nullable<Text>,
+        attempted_at -> Timestamp,
+    }
+}
+
+table! {
+    webhook_subscriptions (id) {
+        id -> Uuid,
+        store_id -> Nullable<Int4>,
+        url -> Varchar,
+        secret -> Varchar,
+        event_types -> Array<Varchar>,
+        is_active -> Bool,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
 joinable!(amounts_received -> invoices_v2 (invoice_id));
+joinable!(disputes -> orders (order_id));
+joinable!(disputes -> payouts (settled_payout_id));
+joinable!(documents -> fees (fee_id));
+joinable!(documents -> invoices_v2 (invoice_id));
 joinable!(fees -> orders (order_id));
+joinable!(invoice_surpluses -> invoices_v2 (invoice_id));
 joinable!(invoices_v2 -> accounts (account_id));
 joinable!(order_exchange_rates -> orders (order_id));
 joinable!(order_payouts -> orders (order_id));
+joinable!(order_state_transitions -> orders (order_id));
 joinable!(order_payouts -> payouts (payout_id));
 joinable!(orders -> invoices_v2 (invoice_id));
 joinable!(payment_intents_fees -> fees (fee_id));
 joinable!(payment_intents_fees -> payment_intent (payment_intent_id));
 joinable!(payment_intents_invoices -> invoices_v2 (invoice_id));
 joinable!(payment_intents_invoices -> payment_intent (payment_intent_id));
+joinable!(refunds -> orders (order_id));
+joinable!(refunds -> payouts (settled_payout_id));
 joinable!(subscription -> subscription_payment (subscription_payment_id));
+joinable!(webhook_deliveries -> webhook_subscriptions (subscription_id));
 
 allow_tables_to_appear_in_same_query!(
     accounts,
     amounts_received,
     customers,
+    disputes,
+    document_sequences,
+    documents,
+    event_attempts,
     event_store,
+    event_store_archive,
     fees,
     international_billing_info,
+    invoice_surpluses,
     invoices,
     invoices_v2,
+    issuer_document_sequences,
     merchants,
     order_exchange_rates,
     order_payouts,
+    order_state_transitions,
     orders,
     orders_info,
     payment_intent,
@@ -341,11 +535,16 @@ allow_tables_to_appear_in_same_query!(
     payment_intents_invoices,
     payouts,
     proxy_companies_billing_info,
+    refunds,
     roles,
     russia_billing_info,
     store_billing_type,
+    store_capture_policies,
     store_subscription,
+    stripe_webhook_events,
     subscription,
     subscription_payment,
     user_wallets,
+    webhook_deliveries,
+    webhook_subscriptions,
 );
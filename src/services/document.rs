@@ -0,0 +1,328 @@
+//! Printable receipts of paid invoices and invoices of paid fees
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+
+use bigdecimal::BigDecimal;
+use diesel::connection::AnsiTransactionManager;
+use diesel::pg::Pg;
+use diesel::Connection;
+use failure::{err_msg, Error as FailureError, Fail};
+use futures_cpupool::CpuPool;
+use r2d2::{ManageConnection, Pool};
+
+use stq_http::client::HttpClient;
+use stq_types::StoreId;
+
+use super::types::ServiceFutureV2;
+use client::payments::PaymentsClient;
+use config;
+use controller::context::DynamicContext;
+use controller::responses::{DocumentContentResponse, DocumentResponse};
+use models::invoice_v2::{InvoiceId, RawInvoice};
+use models::order_v2::RawOrder;
+use models::{DocumentFormat, DocumentId, Fee, FeeId, InternationalBillingInfo, RussiaBillingInfo};
+use repos::repo_factory::ReposFactory;
+use services::accounts::AccountService;
+use services::types::spawn_on_pool;
+
+pub trait DocumentService {
+    /// Receipts issued for the invoice, one for each store of the invoice the user may read
+    fn list_invoice_documents(&self, invoice_id: InvoiceId) -> ServiceFutureV2<Vec<DocumentResponse>>;
+    /// Invoice issued to the store for the fee
+    fn get_fee_document(&self, fee_id: FeeId) -> ServiceFutureV2<Option<DocumentResponse>>;
+    /// Document with its content
+    fn get_document(&self, document_id: DocumentId) -> ServiceFutureV2<Option<DocumentContentResponse>>;
+}
+
+pub struct DocumentServiceImpl<
+    T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static,
+    M: ManageConnection<Connection = T>,
+    F: ReposFactory<T>,
+    C: HttpClient + Clone,
+    PC: PaymentsClient + Clone,
+    AS: AccountService + Clone,
+> {
+    pub db_pool: Pool<M>,
+    pub cpu_pool: CpuPool,
+    pub repo_factory: F,
+    pub dynamic_context: DynamicContext<C, PC, AS>,
+}
+
+impl<
+        T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static,
+        M: ManageConnection<Connection = T>,
+        F: ReposFactory<T>,
+        C: HttpClient + Clone,
+        PC: PaymentsClient + Clone,
+        AS: AccountService + Clone,
+    > DocumentService for DocumentServiceImpl<T, M, F, C, PC, AS>
+{
+    fn list_invoice_documents(&self, invoice_id: InvoiceId) -> ServiceFutureV2<Vec<DocumentResponse>> {
+        let repo_factory = self.repo_factory.clone();
+        let user_id = self.dynamic_context.user_id;
+
+        let db_pool = self.db_pool.clone();
+        let cpu_pool = self.cpu_pool.clone();
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let documents_repo = repo_factory.create_documents_repo(&conn, user_id);
+            documents_repo
+                .list_by_invoice_id(invoice_id)
+                .map_err(ectx!(convert => invoice_id))
+                .map(|documents| documents.iter().map(DocumentResponse::from).collect())
+        })
+    }
+
+    fn get_fee_document(&self, fee_id: FeeId) -> ServiceFutureV2<Option<DocumentResponse>> {
+        let repo_factory = self.repo_factory.clone();
+        let user_id = self.dynamic_context.user_id;
+
+        let db_pool = self.db_pool.clone();
+        let cpu_pool = self.cpu_pool.clone();
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let documents_repo = repo_factory.create_documents_repo(&conn, user_id);
+            documents_repo
+                .get_by_fee_id(fee_id)
+                .map_err(ectx!(convert => fee_id))
+                .map(|document| document.as_ref().map(DocumentResponse::from))
+        })
+    }
+
+    fn get_document(&self, document_id: DocumentId) -> ServiceFutureV2<Option<DocumentContentResponse>> {
+        let repo_factory = self.repo_factory.clone();
+        let user_id = self.dynamic_context.user_id;
+
+        let db_pool = self.db_pool.clone();
+        let cpu_pool = self.cpu_pool.clone();
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let documents_repo = repo_factory.create_documents_repo(&conn, user_id);
+            documents_repo
+                .get(document_id)
+                .map_err(ectx!(convert => document_id))
+                .map(|document| document.map(DocumentContentResponse::from))
+        })
+    }
+}
+
+/// Legal details of the store printed on its documents, taken from the billing info matching the store billing type
+#[derive(Clone, Debug)]
+pub enum SellerDetails {
+    Russia(RussiaBillingInfo),
+    International(InternationalBillingInfo),
+}
+
+impl SellerDetails {
+    fn name(&self) -> &str {
+        match self {
+            SellerDetails::Russia(info) => &info.beneficiary_full_name,
+            SellerDetails::International(info) => &info.name,
+        }
+    }
+
+    fn rows(&self) -> Vec<(&'static str, String)> {
+        match self {
+            SellerDetails::Russia(info) => {
+                let mut rows = vec![
+                    ("Name", info.beneficiary_full_name.clone()),
+                    ("Tax ID", info.tax_id.clone()),
+                    ("Bank", info.bank_name.clone()),
+                ];
+                if let Some(ref branch_name) = info.branch_name {
+                    rows.push(("Branch", branch_name.clone()));
+                }
+                rows.push(("BIC", info.swift_bic.0.clone()));
+                rows.push(("Correspondent account", info.correspondent_account.clone()));
+                rows.push(("Current account", info.current_account.clone()));
+                if let Some(ref personal_account) = info.personal_account {
+                    rows.push(("Personal account", personal_account.clone()));
+                }
+                rows
+            }
+            SellerDetails::International(info) => vec![
+                ("Name", info.name.clone()),
+                ("Address", info.recipient_address.clone()),
+                ("City", info.city.clone()),
+                ("Country", info.country.clone()),
+                ("Bank", info.bank.clone()),
+                ("Bank address", info.bank_address.clone()),
+                ("SWIFT", info.swift.0.clone()),
+                ("Account", info.account.clone()),
+                ("Account currency", format!("{:?}", info.currency)),
+            ],
+        }
+    }
+}
+
+/// Renders the receipt the store issues to the buyer for its orders of the paid invoice
+pub fn render_receipt(document_number: &str, invoice: &RawInvoice, orders: &[RawOrder], seller: &SellerDetails) -> String {
+    let mut totals: BTreeMap<String, BigDecimal> = BTreeMap::new();
+    let mut lines = String::new();
+    for order in orders {
+        let currency = order.seller_currency.to_string().to_uppercase();
+        let amount = order.total_amount.to_super_unit(order.seller_currency);
+        lines.push_str(&format!(
+            "<tr><td>Order {}</td><td class=\"amount\">{} {}</td></tr>\n",
+            escape_html(&order.id.to_string()),
+            amount,
+            escape_html(&currency)
+        ));
+        let total = totals.entry(currency).or_insert_with(|| BigDecimal::from(0));
+        *total = total.clone() + amount;
+    }
+    for (currency, total) in &totals {
+        lines.push_str(&format!(
+            "<tr class=\"total\"><td>Total</td><td class=\"amount\">{} {}</td></tr>\n",
+            total,
+            escape_html(currency)
+        ));
+    }
+
+    let paid_at = invoice
+        .paid_at
+        .map(|paid_at| paid_at.format("%Y-%m-%d").to_string())
+        .unwrap_or_default();
+
+    let body = format!(
+        "<h1>Receipt {number}</h1>\n\
+         <p>Date: {paid_at}</p>\n\
+         <h2>Seller</h2>\n{seller}\n\
+         <h2>Buyer</h2>\n<p>Customer {buyer}</p>\n\
+         <p>Invoice {invoice_id}, paid in {buyer_currency}</p>\n\
+         <table>\n{lines}</table>\n",
+        number = escape_html(document_number),
+        paid_at = paid_at,
+        seller = render_details(seller),
+        buyer = invoice.buyer_user_id,
+        invoice_id = escape_html(&invoice.id.to_string()),
+        buyer_currency = escape_html(&invoice.buyer_currency.to_string().to_uppercase()),
+        lines = lines,
+    );
+
+    render_page(&format!("Receipt {}", document_number), &body)
+}
+
+/// Renders the invoice the marketplace issues to the store for the paid fee
+pub fn render_fee_invoice(document_number: &str, issuer_name: &str, fee: &Fee, store_id: StoreId, recipient: &SellerDetails) -> String {
+    let currency = fee.currency.to_string().to_uppercase();
+    let mut lines = format!(
+        "<tr><td>Marketplace fee for order {}</td><td class=\"amount\">{} {}</td></tr>\n",
+        escape_html(&fee.order_id.to_string()),
+        fee.amount.to_super_unit(fee.currency),
+        escape_html(&currency)
+    );
+    if let (Some(crypto_currency), Some(crypto_amount)) = (fee.crypto_currency, fee.crypto_amount) {
+        lines.push_str(&format!(
+            "<tr><td>Paid as</td><td class=\"amount\">{} {}</td></tr>\n",
+            crypto_amount.to_super_unit(crypto_currency),
+            escape_html(&crypto_currency.to_string().to_uppercase())
+        ));
+    }
+
+    let body = format!(
+        "<h1>Invoice {number}</h1>\n\
+         <p>Date: {paid_at}</p>\n\
+         <h2>Issuer</h2>\n<p>{issuer}</p>\n\
+         <h2>Recipient</h2>\n<p>Store {store_id}, {recipient_name}</p>\n{recipient}\n\
+         <table>\n{lines}</table>\n\
+         <p>Paid</p>\n",
+        number = escape_html(document_number),
+        paid_at = fee.updated_at.format("%Y-%m-%d"),
+        issuer = escape_html(issuer_name),
+        store_id = store_id,
+        recipient_name = escape_html(recipient.name()),
+        recipient = render_details(recipient),
+        lines = lines,
+    );
+
+    render_page(&format!("Invoice {}", document_number), &body)
+}
+
+/// Converts the HTML document to PDF with the configured converter, the HTML is kept if there is none or it fails
+pub fn to_printable(documents_config: &config::Documents, html: String) -> (DocumentFormat, Vec<u8>) {
+    let converter = match documents_config.pdf_converter {
+        Some(ref converter) => converter,
+        None => return (DocumentFormat::Html, html.into_bytes()),
+    };
+
+    match convert_to_pdf(converter, &html) {
+        Ok(pdf) => (DocumentFormat::Pdf, pdf),
+        Err(e) => {
+            warn!("Could not convert document to PDF, storing it as HTML: {}", e);
+            (DocumentFormat::Html, html.into_bytes())
+        }
+    }
+}
+
+fn convert_to_pdf(converter: &[String], html: &str) -> Result<Vec<u8>, FailureError> {
+    let (program, args) = converter.split_first().ok_or_else(|| err_msg("PDF converter command is empty"))?;
+
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| e.context(format!("Could not start PDF converter {}", program)))?;
+
+    // the input is written from another thread so that the converter never blocks on a full stdout pipe
+    let mut stdin = child.stdin.take().ok_or_else(|| err_msg("PDF converter stdin is not available"))?;
+    let input = html.as_bytes().to_vec();
+    let writer = thread::spawn(move || stdin.write_all(&input));
+
+    let output = child.wait_with_output()?;
+    writer.join().map_err(|_| err_msg("PDF converter input thread panicked"))??;
+
+    if !output.status.success() {
+        return Err(format_err!(
+            "PDF converter exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    Ok(output.stdout)
+}
+
+fn render_details(details: &SellerDetails) -> String {
+    let rows: String = details
+        .rows()
+        .into_iter()
+        .map(|(label, value)| format!("<tr><th>{}</th><td>{}</td></tr>\n", label, escape_html(&value)))
+        .collect();
+    format!("<table class=\"details\">\n{}</table>", rows)
+}
+
+fn render_page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n\
+         <html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n\
+         <style>\n\
+         body {{ font-family: sans-serif; font-size: 12px; margin: 40px; }}\n\
+         table {{ border-collapse: collapse; width: 100%; margin-bottom: 16px; }}\n\
+         th, td {{ text-align: left; padding: 4px 8px; border-bottom: 1px solid #ddd; }}\n\
+         .amount {{ text-align: right; }}\n\
+         .total td {{ font-weight: bold; }}\n\
+         </style>\n</head>\n<body>\n{body}</body>\n</html>\n",
+        title = escape_html(title),
+        body = body,
+    )
+}
+
+fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
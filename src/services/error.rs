@@ -40,6 +40,8 @@ pub enum ErrorContext {
     OrderState,
     #[fail(display = "service context - wrong fee state")]
     FeeState,
+    #[fail(display = "service context - wrong invoice state")]
+    InvoiceState,
     #[fail(display = "service context - billing info error")]
     BillingInfo,
     #[fail(display = "service error context - public key has wrong format")]
@@ -52,6 +54,14 @@ pub enum ErrorContext {
     VerifySign,
     #[fail(display = "service error context - stripe error")]
     StripeClient,
+    #[fail(display = "service context - wrong event state")]
+    EventState,
+    #[fail(display = "service context - event replay error")]
+    EventReplay,
+    #[fail(display = "service context - webhook subscription error")]
+    WebhookSubscription,
+    #[fail(display = "service context - refund error")]
+    Refund,
 }
 
 derive_error_impls!();
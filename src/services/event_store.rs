@@ -0,0 +1,376 @@
+//! EventStore Service, presents admin operations with event store entries
+use diesel::connection::AnsiTransactionManager;
+use diesel::pg::Pg;
+use diesel::Connection;
+use futures::{Future, IntoFuture};
+use futures_cpupool::CpuPool;
+use r2d2::{ManageConnection, Pool};
+use validator::{ValidationError, ValidationErrors};
+
+use failure::Fail;
+
+use stq_http::client::HttpClient;
+
+use client::payments::PaymentsClient;
+use controller::context::DynamicContext;
+use models::{
+    Event, EventEntry, EventEntryId, EventEntrySearch, EventEntrySearchResults, EventEntryWithAttempts, EventReplayResults,
+    EventReplaySearch, EventStatus, ReplayedEvent, REPLAYABLE_PAYLOAD_TYPES,
+};
+use repos::{EventStoreRepo, ReposFactory};
+use services::accounts::AccountService;
+use services::types::{spawn_on_pool, ServiceResultV2};
+
+use super::types::ServiceFutureV2;
+use super::{Error, ErrorContext, ErrorKind};
+
+/// Upper bound on the number of events re-enqueued by a single replay request
+const MAX_REPLAYED_EVENTS: i64 = 1000;
+
+pub trait EventStoreService {
+    fn search_events(&self, skip: i64, count: i64, search: EventEntrySearch) -> ServiceFutureV2<EventEntrySearchResults>;
+    fn get_event(&self, event_entry_id: EventEntryId) -> ServiceFutureV2<EventEntryWithAttempts>;
+    fn requeue_event(&self, event_entry_id: EventEntryId) -> ServiceFutureV2<EventEntry>;
+    fn requeue_events(&self, event_entry_ids: Vec<EventEntryId>) -> ServiceFutureV2<Vec<EventEntry>>;
+    fn resolve_event(&self, event_entry_id: EventEntryId) -> ServiceFutureV2<EventEntry>;
+    /// Re-enqueues copies of completed or failed events, only reports the matching events in dry-run mode
+    fn replay_events(&self, search: EventReplaySearch, dry_run: bool) -> ServiceFutureV2<EventReplayResults>;
+}
+
+pub struct EventStoreServiceImpl<
+    T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static,
+    M: ManageConnection<Connection = T>,
+    F: ReposFactory<T>,
+    C: HttpClient + Clone,
+    PC: PaymentsClient + Clone,
+    AS: AccountService + Clone,
+> {
+    pub db_pool: Pool<M>,
+    pub cpu_pool: CpuPool,
+    pub repo_factory: F,
+    pub dynamic_context: DynamicContext<C, PC, AS>,
+}
+
+impl<
+        T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static,
+        M: ManageConnection<Connection = T>,
+        F: ReposFactory<T>,
+        C: HttpClient + Clone,
+        PC: PaymentsClient + Clone,
+        AS: AccountService + Clone,
+    > EventStoreService for EventStoreServiceImpl<T, M, F, C, PC, AS>
+{
+    fn search_events(&self, skip: i64, count: i64, search: EventEntrySearch) -> ServiceFutureV2<EventEntrySearchResults> {
+        let repo_factory = self.repo_factory.clone();
+        let user_id = self.dynamic_context.user_id;
+
+        let db_pool = self.db_pool.clone();
+        let cpu_pool = self.cpu_pool.clone();
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let event_store_repo = repo_factory.create_event_store_repo(&conn, user_id);
+
+            event_store_repo
+                .search_events(skip, count, search.clone())
+                .map_err(ectx!(convert => skip, count, search))
+        })
+    }
+
+    fn get_event(&self, event_entry_id: EventEntryId) -> ServiceFutureV2<EventEntryWithAttempts> {
+        let repo_factory = self.repo_factory.clone();
+        let user_id = self.dynamic_context.user_id;
+
+        let db_pool = self.db_pool.clone();
+        let cpu_pool = self.cpu_pool.clone();
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let event_store_repo = repo_factory.create_event_store_repo(&conn, user_id);
+
+            let entry = get_event_entry(&*event_store_repo, event_entry_id)?;
+
+            let attempts = event_store_repo
+                .get_event_attempts(event_entry_id)
+                .map_err(ectx!(try convert => event_entry_id))?;
+
+            Ok(EventEntryWithAttempts { entry, attempts })
+        })
+    }
+
+    fn requeue_event(&self, event_entry_id: EventEntryId) -> ServiceFutureV2<EventEntry> {
+        let repo_factory = self.repo_factory.clone();
+        let user_id = self.dynamic_context.user_id;
+
+        let db_pool = self.db_pool.clone();
+        let cpu_pool = self.cpu_pool.clone();
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let event_store_repo = repo_factory.create_event_store_repo(&conn, user_id);
+
+            let requeued_event = event_store_repo
+                .requeue_events(vec![event_entry_id])
+                .map_err(ectx!(try convert => event_entry_id))?
+                .into_iter()
+                .next();
+
+            match requeued_event {
+                Some(event_entry) => Ok(event_entry),
+                None => get_event_entry(&*event_store_repo, event_entry_id).and_then(|event_entry| Err(wrong_state_error(&event_entry))),
+            }
+        })
+    }
+
+    fn requeue_events(&self, event_entry_ids: Vec<EventEntryId>) -> ServiceFutureV2<Vec<EventEntry>> {
+        let repo_factory = self.repo_factory.clone();
+        let user_id = self.dynamic_context.user_id;
+
+        let db_pool = self.db_pool.clone();
+        let cpu_pool = self.cpu_pool.clone();
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let event_store_repo = repo_factory.create_event_store_repo(&conn, user_id);
+
+            event_store_repo
+                .requeue_events(event_entry_ids.clone())
+                .map_err(ectx!(convert => event_entry_ids))
+        })
+    }
+
+    fn resolve_event(&self, event_entry_id: EventEntryId) -> ServiceFutureV2<EventEntry> {
+        let repo_factory = self.repo_factory.clone();
+        let user_id = self.dynamic_context.user_id;
+
+        let db_pool = self.db_pool.clone();
+        let cpu_pool = self.cpu_pool.clone();
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let event_store_repo = repo_factory.create_event_store_repo(&conn, user_id);
+
+            let resolved_event = event_store_repo
+                .resolve_event(event_entry_id)
+                .map_err(ectx!(try convert => event_entry_id))?;
+
+            match resolved_event {
+                Some(event_entry) => Ok(event_entry),
+                None => get_event_entry(&*event_store_repo, event_entry_id).and_then(|event_entry| Err(wrong_state_error(&event_entry))),
+            }
+        })
+    }
+
+    fn replay_events(&self, search: EventReplaySearch, dry_run: bool) -> ServiceFutureV2<EventReplayResults> {
+        let repo_factory = self.repo_factory.clone();
+        let user_id = self.dynamic_context.user_id;
+
+        let db_pool = self.db_pool.clone();
+        let cpu_pool = self.cpu_pool.clone();
+
+        let fut = validate_event_replay_search(&search).into_future().and_then(move |_| {
+            spawn_on_pool(db_pool, cpu_pool, move |conn| {
+                let event_store_repo = repo_factory.create_event_store_repo(&conn, user_id);
+
+                conn.transaction::<_, Error, _>(move || {
+                    let event_entries = event_store_repo
+                        .get_events_for_replay(search.clone(), MAX_REPLAYED_EVENTS + 1)
+                        .map_err(ectx!(try convert => search))?;
+
+                    if event_entries.len() as i64 > MAX_REPLAYED_EVENTS {
+                        return Err(too_many_events_error());
+                    }
+
+                    validate_replayed_events(&event_entries)?;
+
+                    let replayed_events = event_entries
+                        .into_iter()
+                        .map(|event_entry| {
+                            let EventEntry { id, event, status, .. } = event_entry;
+                            let payload_type = event.payload.to_string();
+
+                            let replay_event_id = if dry_run {
+                                None
+                            } else {
+                                let replay_event = Event::new_replay(id, event.payload);
+                                let replay_event_entry = event_store_repo
+                                    .add_event(replay_event.clone())
+                                    .map_err(ectx!(try convert => replay_event))?;
+                                Some(replay_event_entry.id)
+                            };
+
+                            Ok(ReplayedEvent {
+                                original_event_id: id,
+                                original_status: status,
+                                payload_type,
+                                replay_event_id,
+                            })
+                        })
+                        .collect::<Result<Vec<_>, Error>>()?;
+
+                    Ok(EventReplayResults::new(dry_run, replayed_events))
+                })
+            })
+        });
+
+        Box::new(fut)
+    }
+}
+
+fn get_event_entry(event_store_repo: &EventStoreRepo, event_entry_id: EventEntryId) -> ServiceResultV2<EventEntry> {
+    event_store_repo
+        .get_event(event_entry_id)
+        .map_err(ectx!(try convert => event_entry_id))?
+        .ok_or_else(|| {
+            let e = format_err!("Event entry with ID {} not found", event_entry_id);
+            ectx!(err e, ErrorKind::NotFound)
+        })
+}
+
+fn wrong_state_error(event_entry: &EventEntry) -> Error {
+    let mut errors = ValidationErrors::new();
+    let mut error = ValidationError::new("wrong_state");
+    error.message = Some(format!("Operation is not allowed for event in status \"{}\"", event_entry.status).into());
+    errors.add("event", error);
+    ectx!(err ErrorContext::EventState, ErrorKind::Validation(serde_json::to_value(errors).unwrap_or_default()))
+}
+
+fn validate_event_replay_search(search: &EventReplaySearch) -> ServiceResultV2<()> {
+    let mut errors = ValidationErrors::new();
+
+    if search.is_empty() {
+        let mut error = ValidationError::new("empty_search");
+        error.message = Some("At least one of ID range, payload type or time window must be specified".into());
+        errors.add("search", error);
+    }
+
+    if let Some(ref payload_type) = search.payload_type {
+        if !REPLAYABLE_PAYLOAD_TYPES.contains(&payload_type.as_str()) {
+            let mut error = ValidationError::new("not_replayable");
+            error.message = Some(format!("Events of type \"{}\" cannot be replayed", payload_type).into());
+            errors.add("payload_type", error);
+        }
+    }
+
+    match search.status {
+        None | Some(EventStatus::Completed) | Some(EventStatus::Failed) => {}
+        Some(ref status) => {
+            let mut error = ValidationError::new("wrong_status");
+            error.message = Some(format!("Only completed or failed events can be replayed, got \"{}\"", status).into());
+            errors.add("status", error);
+        }
+    }
+
+    if errors.errors().is_empty() {
+        Ok(())
+    } else {
+        Err(ectx!(err ErrorContext::EventReplay, ErrorKind::Validation(serde_json::to_value(errors).unwrap_or_default())))
+    }
+}
+
+/// Events found by ID range or time window may still be of a type that cannot be replayed, the whole replay is rejected then
+fn validate_replayed_events(event_entries: &[EventEntry]) -> ServiceResultV2<()> {
+    let mut payload_types = event_entries
+        .iter()
+        .filter(|event_entry| !event_entry.event.payload.is_replayable())
+        .map(|event_entry| event_entry.event.payload.to_string())
+        .collect::<Vec<_>>();
+
+    if payload_types.is_empty() {
+        return Ok(());
+    }
+
+    payload_types.sort();
+    payload_types.dedup();
+
+    let mut errors = ValidationErrors::new();
+    let mut error = ValidationError::new("not_replayable");
+    error.message = Some(
+        format!(
+            "Events of types {} cannot be replayed, exclude them from the search",
+            payload_types.join(", ")
+        )
+        .into(),
+    );
+    errors.add("search", error);
+    Err(ectx!(err ErrorContext::EventReplay, ErrorKind::Validation(serde_json::to_value(errors).unwrap_or_default())))
+}
+
+fn too_many_events_error() -> Error {
+    let mut errors = ValidationErrors::new();
+    let mut error = ValidationError::new("too_many_events");
+    error.message = Some(format!("More than {} events match the search, narrow it down", MAX_REPLAYED_EVENTS).into());
+    errors.add("search", error);
+    ectx!(err ErrorContext::EventReplay, ErrorKind::Validation(serde_json::to_value(errors).unwrap_or_default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDateTime;
+
+    use models::invoice_v2::InvoiceId;
+    use models::order_v2::OrderId;
+    use models::EventPayload;
+
+    use super::*;
+
+    #[test]
+    fn test_validate_event_replay_search() {
+        let search = EventReplaySearch {
+            payload_type: Some("InvoicePaid".to_string()),
+            ..Default::default()
+        };
+        assert!(validate_event_replay_search(&search).is_ok());
+
+        assert!(validate_event_replay_search(&EventReplaySearch::default()).is_err());
+
+        let search = EventReplaySearch {
+            status: Some(EventStatus::Pending),
+            payload_type: Some("InvoicePaid".to_string()),
+            ..Default::default()
+        };
+        assert!(validate_event_replay_search(&search).is_err());
+    }
+
+    #[test]
+    fn test_validate_event_replay_search_rejects_money_moving_payloads() {
+        for payload_type in &[
+            "PaymentIntentCapture",
+            "CryptoRefundInitiated",
+            "StripeRefundInitiated",
+            "PayoutInitiated",
+            "InvoiceSurplusRefundInitiated",
+        ] {
+            let search = EventReplaySearch {
+                payload_type: Some(payload_type.to_string()),
+                ..Default::default()
+            };
+            assert!(
+                validate_event_replay_search(&search).is_err(),
+                "{} must not be replayable",
+                payload_type
+            );
+        }
+    }
+
+    #[test]
+    fn test_validate_replayed_events() {
+        let event_entry = |payload| EventEntry {
+            id: EventEntryId::new(1),
+            event: Event::new(payload),
+            status: EventStatus::Completed,
+            attempt_count: 1,
+            created_at: NaiveDateTime::from_timestamp(0, 0),
+            status_updated_at: NaiveDateTime::from_timestamp(0, 0),
+            scheduled_on: None,
+            next_retry_at: None,
+        };
+
+        let replayable = event_entry(EventPayload::InvoicePaid {
+            invoice_id: InvoiceId::generate(),
+        });
+        assert!(validate_replayed_events(&[replayable.clone()]).is_ok());
+
+        // a capture found by ID range would be captured again
+        let capture = event_entry(EventPayload::PaymentIntentCapture {
+            order_id: OrderId::generate(),
+            amount: None,
+        });
+        assert!(validate_replayed_events(&[replayable, capture]).is_err());
+    }
+}
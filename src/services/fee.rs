@@ -22,7 +22,7 @@ use services::accounts::AccountService;
 
 use models::{
     order_v2::{OrderId, OrdersSearch, StoreId},
-    Amount, ChargeId, Currency, Fee, FeeStatus, UpdateFee,
+    Amount, ChargeId, Currency, Event, EventPayload, Fee, FeeStatus, UpdateFee, WebhookNotification,
 };
 use repos::{ReposFactory, SearchCustomer, SearchFee, SearchFeeParams};
 
@@ -249,6 +249,7 @@ impl<
             move |(fees, charge)| {
                 spawn_on_pool(db_pool, cpu_pool, move |conn| {
                     let fees_repo = repo_factory.create_fees_repo(&conn, user_id);
+                    let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
                     conn.transaction(|| {
                         let status = if charge.paid {
                             Some(FeeStatus::Paid)
@@ -265,10 +266,21 @@ impl<
                             .into_iter()
                             .map(|fee| {
                                 let fee_id_cloned = fee.id.clone();
-                                fees_repo
+                                let fee = fees_repo
                                     .update(fee.id, update_fee.clone())
-                                    .map_err(ectx!(convert => fee_id_cloned))
-                                    .and_then(|res| FeeResponse::try_from_fee(res))
+                                    .map_err(ectx!(try convert => fee_id_cloned))?;
+
+                                if fee.status == FeeStatus::Paid {
+                                    let event = Event::new(EventPayload::WebhookNotificationCreated {
+                                        notification: WebhookNotification::for_fee(&fee, store_id),
+                                    });
+                                    event_store_repo.add_event(event.clone()).map_err(ectx!(try convert => event))?;
+
+                                    let event = Event::new(EventPayload::FeeInvoiceDue { fee_id: fee.id });
+                                    event_store_repo.add_event(event.clone()).map_err(ectx!(try convert => event))?;
+                                }
+
+                                FeeResponse::try_from_fee(fee)
                             })
                             .collect();
                         fee_result
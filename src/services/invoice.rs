@@ -20,17 +20,20 @@ use serde_json;
 use sha2::digest::Digest;
 use sha2::Sha256;
 use uuid::Uuid;
+use validator::{ValidationError, ValidationErrors};
 
 use stq_http::client::HttpClient;
 use stq_http::request_util::Sign as TureSignature;
+use stq_static_resources::OrderState;
 use stq_types::stripe::PaymentIntentId;
 use stq_types::{InvoiceId, OrderId, SagaId};
 
 use client::payments::{GetRate, PaymentsClient, Rate, RateRefresh};
 use client::stores::CurrencyExchangeInfo;
 use client::stripe::{NewPaymentIntent as StripeClientNewPaymentIntent, StripeClient};
-use config::ExternalBilling;
+use config::{ExternalBilling, InvoicePayment};
 use controller::context::DynamicContext;
+use controller::requests::SetInvoiceBuyerCurrencyRequest;
 use errors::Error;
 use models::invoice_v2::{calculate_invoice_price, InvoiceDump, InvoiceId as InvoiceV2Id, NewInvoice, RawInvoice as InvoiceV2};
 use models::order_v2::{ExchangeId, NewOrder, OrderId as OrderV2Id, RawOrder};
@@ -38,8 +41,8 @@ use models::*;
 use repos::error::ErrorKind as RepoErrorKind;
 use repos::repo_factory::ReposFactory;
 use repos::{
-    AccountsRepo, EventStoreRepo, InvoicesV2Repo, OrderExchangeRatesRepo, OrdersRepo, PaymentIntentInvoiceRepo, PaymentIntentRepo,
-    SearchPaymentIntentInvoice,
+    AccountsRepo, EventStoreRepo, FeeRepo, InvoicesV2Repo, OrderExchangeRatesRepo, OrdersRepo, PaymentIntentInvoiceRepo, PaymentIntentRepo,
+    SearchFeeParams, SearchPaymentIntentInvoice,
 };
 use services::accounts::AccountService;
 use services::types::spawn_on_pool;
@@ -65,6 +68,11 @@ pub trait InvoiceService {
     fn recalc_invoice(&self, id: InvoiceId) -> ServiceFuture<Invoice>;
     fn recalc_invoice_v1(&self, id: InvoiceId) -> ServiceFuture<Invoice>;
     fn recalc_invoice_v2(&self, id: InvoiceV2Id) -> ServiceFutureV2<Option<InvoiceDump>>;
+    /// Cancels the unpaid invoice, the invoice account or payment intent and the orders are released in the background
+    fn cancel_invoice_v2(&self, id: InvoiceV2Id) -> ServiceFutureV2<()>;
+    /// Switches the crypto currency of the unpaid invoice, rates are reserved again and the invoice account
+    /// is replaced by an account in the new currency, the replaced account is drained in the background
+    fn set_invoice_buyer_currency_v2(&self, id: InvoiceV2Id, payload: SetInvoiceBuyerCurrencyRequest) -> ServiceFutureV2<InvoiceDump>;
     /// Get orders ids by invoice id
     fn get_invoice_orders_ids(&self, id: InvoiceId) -> ServiceFuture<Vec<OrderId>>;
     fn get_invoice_orders_ids_v1(&self, id: InvoiceId) -> ServiceFuture<Vec<OrderId>>;
@@ -274,7 +282,8 @@ impl<
                                     })
                                     .collect::<Result<Vec<_>, ServiceError>>()?;
 
-                                Ok(calculate_invoice_price(invoice, orders_with_rates, wallet_address))
+                                // marketplace fees are created once the invoice is paid
+                                Ok(calculate_invoice_price(invoice, orders_with_rates, Vec::new(), wallet_address))
                             })
                         })
                     })
@@ -497,6 +506,7 @@ impl<
                 let orders_repo = repo_factory.create_orders_repo(&conn, user_id);
                 let rates_repo = repo_factory.create_order_exchange_rates_repo(&conn, user_id);
                 let accounts_repo = repo_factory.create_accounts_repo_with_sys_acl(&conn);
+                let fees_repo = repo_factory.create_fees_repo_with_sys_acl(&conn);
 
                 let id_clone = id.clone();
                 let invoice = invoices_repo.get(id_clone.clone()).map_err(ectx!(try convert => id_clone))?;
@@ -509,6 +519,7 @@ impl<
                 };
 
                 let current_order_rates = get_order_active_rates(&*orders_repo, &*rates_repo, id)?;
+                let fees = get_order_fees(&*fees_repo, current_order_rates.iter().map(|(order, _)| order.id).collect())?;
 
                 let wallet_address = if let Some(account_id) = invoice.account_id {
                     Some(
@@ -528,7 +539,7 @@ impl<
                     None
                 };
 
-                Ok(Some((invoice, current_order_rates, wallet_address)))
+                Ok(Some((invoice, current_order_rates, fees, wallet_address)))
             }
         })
         .and_then({
@@ -536,18 +547,24 @@ impl<
             let cpu_pool = self.static_context.cpu_pool.clone();
             let repo_factory = self.static_context.repo_factory.clone();
             let user_id = self.dynamic_context.user_id;
+            let invoice_payment = self.static_context.config.invoice_payment.clone();
             let self_ = self.clone();
 
             move |invoice_data| match invoice_data {
                 None => future::Either::A(future::ok(None)),
-                Some((invoice, current_order_rates, wallet_address)) => future::Either::B(Some(future::lazy(move || {
+                Some((invoice, current_order_rates, fees, wallet_address)) => future::Either::B(Some(future::lazy(move || {
                     // Calculate invoice price without refreshing rates if the invoice has already been paid
                     if invoice.paid_at.is_some() {
                         let current_order_rates = current_order_rates
                             .into_iter()
                             .map(|(order, rate)| (order, rate.into_iter().collect::<Vec<_>>()))
                             .collect::<Vec<_>>();
-                        return future::Either::A(future::ok(calculate_invoice_price(invoice, current_order_rates, wallet_address)));
+                        return future::Either::A(future::ok(calculate_invoice_price(
+                            invoice,
+                            current_order_rates,
+                            fees,
+                            wallet_address,
+                        )));
                     }
 
                     // Get missing rates from Payments gateway and refresh existing rates
@@ -570,6 +587,7 @@ impl<
                                 let orders_repo = repo_factory.create_orders_repo(&conn, user_id);
                                 let rates_repo = repo_factory.create_order_exchange_rates_repo(&conn, user_id);
                                 let accounts_repo = repo_factory.create_accounts_repo_with_sys_acl(&conn);
+                                let fees_repo = repo_factory.create_fees_repo_with_sys_acl(&conn);
                                 let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
 
                                 calculate_invoice_price_and_set_final_price_if_paid(
@@ -578,7 +596,9 @@ impl<
                                     &*orders_repo,
                                     &*rates_repo,
                                     &*accounts_repo,
+                                    &*fees_repo,
                                     &*event_store_repo,
+                                    &invoice_payment,
                                     invoice.id.clone(),
                                 )
                             })
@@ -593,6 +613,145 @@ impl<
         Box::new(fut)
     }
 
+    fn cancel_invoice_v2(&self, id: InvoiceV2Id) -> ServiceFutureV2<()> {
+        let db_pool = self.static_context.db_pool.clone();
+        let cpu_pool = self.static_context.cpu_pool.clone();
+        let repo_factory = self.static_context.repo_factory.clone();
+        let user_id = self.dynamic_context.user_id;
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let invoices_repo = repo_factory.create_invoices_v2_repo(&conn, user_id);
+            let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
+
+            conn.transaction::<_, ServiceError, _>(move || {
+                let invoice = invoices_repo.get(id).map_err(ectx!(try convert => id))?.ok_or({
+                    let e = format_err!("Invoice {} not found", id);
+                    ectx!(try err e, ErrorKind::NotFound)
+                })?;
+
+                validate_invoice_cancel(&invoice)?;
+
+                // the update checks the captured amount again in case the payment comes in meanwhile
+                if invoices_repo.set_cancelled(id).map_err(ectx!(try convert => id))?.is_none() {
+                    return Err(cancel_invoice_error("Cannot cancel an invoice with a captured amount"));
+                }
+                info!("Invoice {} is cancelled by the buyer", id);
+
+                let event = Event::new(EventPayload::InvoiceCancelled { invoice_id: id });
+                event_store_repo
+                    .add_event(event.clone())
+                    .map_err(ectx!(convert => event))
+                    .map(|_| ())
+            })
+        })
+    }
+
+    fn set_invoice_buyer_currency_v2(&self, id: InvoiceV2Id, payload: SetInvoiceBuyerCurrencyRequest) -> ServiceFutureV2<InvoiceDump> {
+        let db_pool = self.static_context.db_pool.clone();
+        let cpu_pool = self.static_context.cpu_pool.clone();
+        let repo_factory = self.static_context.repo_factory.clone();
+        let DynamicContext {
+            user_id,
+            payments_client,
+            account_service,
+            ..
+        } = self.dynamic_context.clone();
+
+        let (payments_client, account_service) = if let (Some(payments_client), Some(account_service)) = (payments_client, account_service)
+        {
+            (payments_client, account_service)
+        } else {
+            let e = err_msg("payments integration has not been configured");
+            return Box::new(future::err::<_, ServiceError>(ectx!(err e, ErrorKind::Internal)));
+        };
+
+        let SetInvoiceBuyerCurrencyRequest { currency: buyer_currency } = payload;
+
+        let fut = spawn_on_pool(db_pool.clone(), cpu_pool.clone(), {
+            let repo_factory = repo_factory.clone();
+            move |conn| {
+                let invoices_repo = repo_factory.create_invoices_v2_repo(&conn, user_id);
+                let orders_repo = repo_factory.create_orders_repo(&conn, user_id);
+
+                let invoice = invoices_repo.get(id).map_err(ectx!(try convert => id))?.ok_or({
+                    let e = format_err!("Invoice {} not found", id);
+                    ectx!(try err e, ErrorKind::NotFound)
+                })?;
+                let orders = orders_repo.get_many_by_invoice_id(id).map_err(ectx!(try convert => id))?;
+
+                validate_buyer_currency_switch(&invoice, &orders, buyer_currency)?;
+
+                Ok(orders)
+            }
+        })
+        .and_then(move |orders| {
+            to_ture_currency(buyer_currency).and_then(move |ture_currency| {
+                let new_rates = stream::iter_ok::<_, ServiceError>(orders.into_iter().map({
+                    let payments_client = payments_client.clone();
+                    move |order| (payments_client.clone(), order)
+                }))
+                .and_then(move |(payments_client, order)| reserve_or_refresh_rate(payments_client, ture_currency, order, None))
+                .filter_map(|new_rate| new_rate)
+                .collect();
+
+                account_service
+                    .get_or_create_free_pooled_account(ture_currency)
+                    .map_err(ectx!(convert => ture_currency))
+                    .join(new_rates)
+                    .map(move |(account, new_rates)| (account.id, new_rates))
+            })
+        })
+        .and_then(move |(account_id, new_rates)| {
+            spawn_on_pool(db_pool, cpu_pool, move |conn| {
+                let invoices_repo = repo_factory.create_invoices_v2_repo(&conn, user_id);
+                let orders_repo = repo_factory.create_orders_repo(&conn, user_id);
+                let rates_repo = repo_factory.create_order_exchange_rates_repo(&conn, user_id);
+                let accounts_repo = repo_factory.create_accounts_repo_with_sys_acl(&conn);
+                let fees_repo = repo_factory.create_fees_repo_with_sys_acl(&conn);
+                let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
+
+                conn.transaction::<InvoiceDump, ServiceError, _>(move || {
+                    // the invoice may have received a payment while the rates were being reserved
+                    let invoice = invoices_repo.get(id).map_err(ectx!(try convert => id))?.ok_or({
+                        let e = format_err!("Invoice {} not found", id);
+                        ectx!(try err e, ErrorKind::NotFound)
+                    })?;
+                    let orders = orders_repo.get_many_by_invoice_id(id).map_err(ectx!(try convert => id))?;
+                    validate_buyer_currency_switch(&invoice, &orders, buyer_currency)?;
+
+                    info!(
+                        "Invoice {} buyer currency is switched from {} to {}",
+                        id, invoice.buyer_currency, buyer_currency
+                    );
+                    let replaced_account_id = invoice.account_id;
+                    let account_id = Some(account_id);
+                    let invoice = invoices_repo
+                        .set_buyer_currency(id, buyer_currency, account_id)
+                        .map_err(ectx!(try convert => id, buyer_currency, account_id))?;
+
+                    // whatever has been sent to the replaced account is moved to the main account once the switch is stored
+                    if let Some(replaced_account_id) = replaced_account_id {
+                        let event = Event::new(EventPayload::InvoiceAccountReplaced {
+                            invoice_id: id,
+                            account_id: replaced_account_id,
+                        });
+                        event_store_repo.add_event(event.clone()).map_err(ectx!(try convert => event))?;
+                    }
+
+                    for new_rate in new_rates {
+                        rates_repo
+                            .add_new_active_rate(new_rate.clone())
+                            .map_err(ectx!(try convert => new_rate))?;
+                    }
+
+                    get_invoice_price(&*orders_repo, &*rates_repo, &*accounts_repo, &*fees_repo, invoice)
+                })
+            })
+        });
+
+        Box::new(fut)
+    }
+
     /// Get orders ids by invoice id
 
     fn get_invoice_orders_ids(&self, id: InvoiceId) -> ServiceFuture<Vec<OrderId>> {
@@ -780,6 +939,7 @@ impl<
         let db_pool = self.static_context.db_pool.clone();
         let cpu_pool = self.static_context.cpu_pool.clone();
         let repo_factory = self.static_context.repo_factory.clone();
+        let invoice_payment = self.static_context.config.invoice_payment.clone();
 
         let PaymentsCallback {
             transaction_id,
@@ -902,6 +1062,7 @@ impl<
                                     let orders_repo = repo_factory.create_orders_repo_with_sys_acl(&conn);
                                     let rates_repo = repo_factory.create_order_exchange_rates_repo_with_sys_acl(&conn);
                                     let accounts_repo = repo_factory.create_accounts_repo_with_sys_acl(&conn);
+                                    let fees_repo = repo_factory.create_fees_repo_with_sys_acl(&conn);
                                     let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
 
                                     calculate_invoice_price_and_set_final_price_if_paid(
@@ -910,7 +1071,9 @@ impl<
                                         &*orders_repo,
                                         &*rates_repo,
                                         &*accounts_repo,
+                                        &*fees_repo,
                                         &*event_store_repo,
+                                        &invoice_payment,
                                         invoice.id.clone(),
                                     )?;
 
@@ -1036,6 +1199,46 @@ fn create_payment_intent(
     Box::new(fut)
 }
 
+/// Checks that the invoice is not paid yet and can be paid in the new buyer currency
+fn validate_buyer_currency_switch(invoice: &InvoiceV2, orders: &[RawOrder], buyer_currency: Currency) -> Result<(), ServiceError> {
+    let state_error_message = if invoice.status == OrderState::Cancelled {
+        Some("Invoice has been cancelled")
+    } else if invoice.paid_at.is_some() || invoice.amount_captured != Amount::zero() {
+        Some("Cannot switch the currency of an invoice with a captured amount")
+    } else {
+        None
+    };
+    if let Some(error_message) = state_error_message {
+        let mut errors = ValidationErrors::new();
+        let mut error = ValidationError::new("wrong_state");
+        error.message = Some(error_message.into());
+        errors.add("invoice", error);
+        return Err(ectx!(err ErrorContext::InvoiceState, ErrorKind::Validation(serde_json::to_value(errors).unwrap_or_default())));
+    }
+
+    // fiat invoices are paid in the seller currency, so only crypto invoices have a currency to switch to
+    let currency_error_message = if invoice.buyer_currency == buyer_currency {
+        Some(format!("Invoice is already in {}", buyer_currency))
+    } else if invoice.buyer_currency.is_fiat() || buyer_currency.is_fiat() {
+        Some("Only crypto invoices can switch to another crypto currency".to_string())
+    } else if TureCurrency::try_from_currency(buyer_currency).is_err() {
+        Some(format!("Unsupported currency: {}", buyer_currency))
+    } else if orders.iter().any(|order| order.seller_currency.is_fiat()) {
+        Some("fiat - crypto payments are not supported yet".to_string())
+    } else {
+        None
+    };
+    if let Some(error_message) = currency_error_message {
+        let mut errors = ValidationErrors::new();
+        let mut error = ValidationError::new("not_supported");
+        error.message = Some(error_message.into());
+        errors.add("currency", error);
+        return Err(ectx!(err ErrorContext::CurrencyConversion, ErrorKind::Validation(serde_json::to_value(errors).unwrap_or_default())));
+    }
+
+    Ok(())
+}
+
 pub fn payment_intent_success<C>(
     conn: &C,
     orders_repo: &OrdersRepo,
@@ -1120,19 +1323,26 @@ pub fn get_order_active_rates(
         .collect::<Result<Vec<_>, _>>()
 }
 
+/// Gets the marketplace fees charged for the orders
+pub fn get_order_fees(fees_repo: &FeeRepo, order_ids: Vec<OrderV2Id>) -> Result<Vec<Fee>, ServiceError> {
+    let search = SearchFeeParams::by_order_ids(order_ids);
+    fees_repo.search(search).map_err(ectx!(convert))
+}
+
 /// Gets all of the invoice data by invoice ID from the DB and calculates the total price
 pub fn get_invoice_price_by_invoice_id(
     invoices_repo: &InvoicesV2Repo,
     orders_repo: &OrdersRepo,
     rates_repo: &OrderExchangeRatesRepo,
     accounts_repo: &AccountsRepo,
+    fees_repo: &FeeRepo,
     invoice_id: InvoiceV2Id,
 ) -> Result<Option<InvoiceDump>, ServiceError> {
     let invoice = invoices_repo.get(invoice_id.clone()).map_err(ectx!(try convert => invoice_id))?;
 
     match invoice {
         None => Ok(None),
-        Some(invoice) => get_invoice_price(orders_repo, rates_repo, accounts_repo, invoice).map(Some),
+        Some(invoice) => get_invoice_price(orders_repo, rates_repo, accounts_repo, fees_repo, invoice).map(Some),
     }
 }
 
@@ -1141,6 +1351,7 @@ pub fn get_invoice_price(
     orders_repo: &OrdersRepo,
     rates_repo: &OrderExchangeRatesRepo,
     accounts_repo: &AccountsRepo,
+    fees_repo: &FeeRepo,
     invoice: RawInvoice,
 ) -> Result<InvoiceDump, ServiceError> {
     let invoice_id = invoice.id.clone();
@@ -1156,6 +1367,7 @@ pub fn get_invoice_price(
                 .map(|rates| (order, rates))
         })
         .collect::<Result<Vec<_>, ServiceError>>()?;
+    let fees = get_order_fees(fees_repo, orders_with_rates.iter().map(|(order, _)| order.id).collect())?;
 
     let wallet_address = if let Some(account_id) = invoice.account_id {
         Some(
@@ -1175,7 +1387,7 @@ pub fn get_invoice_price(
         None
     };
 
-    Ok(calculate_invoice_price(invoice, orders_with_rates, wallet_address))
+    Ok(calculate_invoice_price(invoice, orders_with_rates, fees, wallet_address))
 }
 
 /// Returns new and updated active rates which then have to be saved in the database. Rates that remained the same get filetered out
@@ -1250,13 +1462,18 @@ pub fn reserve_or_refresh_rate<PC: PaymentsClient + Send + Clone + 'static>(
     Box::new(fut)
 }
 
+/// Marks the invoice paid once the captured amount reaches its price minus the underpayment tolerance,
+/// the amount captured above the price is recorded as the invoice surplus
+#[allow(clippy::too_many_arguments)]
 pub fn calculate_invoice_price_and_set_final_price_if_paid<C>(
     conn: &C,
     invoices_repo: &InvoicesV2Repo,
     orders_repo: &OrdersRepo,
     rates_repo: &OrderExchangeRatesRepo,
     accounts_repo: &AccountsRepo,
+    fees_repo: &FeeRepo,
     event_store_repo: &EventStoreRepo,
+    invoice_payment: &InvoicePayment,
     invoice_id: InvoiceV2Id,
 ) -> Result<InvoiceDump, ServiceError>
 where
@@ -1271,25 +1488,29 @@ where
                 ectx!(try err e, ErrorKind::Internal => invoice_id)
             })?;
 
-        let invoice_dump = get_invoice_price(&*orders_repo, &*rates_repo, &*accounts_repo, invoice.clone())?;
+        let invoice_dump = get_invoice_price(&*orders_repo, &*rates_repo, &*accounts_repo, &*fees_repo, invoice.clone())?;
 
         // Do not update anything in DB if the invoice is already marked as paid
         if invoice.paid_at.is_some() {
             Ok(invoice_dump)
         } else {
-            let has_become_paid = !invoice_dump.has_missing_rates
-                && invoice.amount_captured.clone().to_super_unit(invoice_dump.buyer_currency.clone()) >= invoice_dump.total_price;
-            // If the invoice became paid, save the total values and mark is as paid in the DB
-            if !has_become_paid {
-                Ok(invoice_dump)
+            let buyer_currency = invoice_dump.buyer_currency;
+            let tolerance = invoice_payment.underpayment_tolerance(buyer_currency, &invoice_dump.total_price);
+            let settlement = if invoice_dump.has_missing_rates {
+                None
             } else {
+                settle_invoice_payment(buyer_currency, invoice.amount_captured, &invoice_dump.total_price, tolerance)
+            };
+            // If the invoice became paid, save the total values and mark is as paid in the DB
+            if let Some((final_amount_paid, surplus_amount)) = settlement {
                 let input = InvoiceSetAmountPaid {
-                    final_amount_paid: Amount::from_super_unit(invoice_dump.buyer_currency.clone(), invoice_dump.total_price.clone()),
+                    final_amount_paid,
                     final_cashback_amount: Amount::from_super_unit(
                         Currency::Stq,
                         invoice_dump.total_cashback.clone().unwrap_or(BigDecimal::from(0)),
                     ),
                     paid_at: chrono::Utc::now().naive_utc(),
+                    surplus_amount,
                 };
 
                 let invoice_id = invoice.id.clone();
@@ -1302,12 +1523,60 @@ where
                 let event = Event::new(EventPayload::InvoicePaid { invoice_id: invoice.id });
                 event_store_repo.add_event(event.clone()).map_err(ectx!(try convert => event))?;
 
+                if surplus_amount > Amount::zero() {
+                    info!("Invoice {} is overpaid by {}", invoice.id, surplus_amount);
+                    let event = Event::new(EventPayload::InvoiceSurplusReceived { invoice_id: invoice.id });
+                    event_store_repo.add_event(event.clone()).map_err(ectx!(try convert => event))?;
+                }
+
+                Ok(invoice_dump)
+            } else {
                 Ok(invoice_dump)
             }
         }
     })
 }
 
+/// Checks that nothing has been captured on the invoice yet, so that it can be cancelled
+fn validate_invoice_cancel(invoice: &InvoiceV2) -> Result<(), ServiceError> {
+    if invoice.status == OrderState::Cancelled {
+        return Err(cancel_invoice_error("Invoice has already been cancelled"));
+    }
+
+    if invoice.paid_at.is_some() || invoice.amount_captured != Amount::zero() {
+        return Err(cancel_invoice_error("Cannot cancel an invoice with a captured amount"));
+    }
+
+    Ok(())
+}
+
+fn cancel_invoice_error(message: &'static str) -> ServiceError {
+    let mut errors = ValidationErrors::new();
+    let mut error = ValidationError::new("wrong_state");
+    error.message = Some(message.into());
+    errors.add("invoice", error);
+    ectx!(err ErrorContext::InvoiceState, ErrorKind::Validation(serde_json::to_value(errors).unwrap_or_default()))
+}
+
+/// Returns the final amount paid and the surplus once the captured amount covers the price within the tolerance,
+/// an underpaid invoice is settled with the amount actually captured
+fn settle_invoice_payment(
+    buyer_currency: Currency,
+    amount_captured: Amount,
+    total_price: &BigDecimal,
+    tolerance: BigDecimal,
+) -> Option<(Amount, Amount)> {
+    if amount_captured.to_super_unit(buyer_currency) + tolerance < *total_price {
+        return None;
+    }
+
+    let price = Amount::from_super_unit(buyer_currency, total_price.clone());
+    match amount_captured.checked_sub(price) {
+        Some(surplus_amount) => Some((price, surplus_amount)),
+        None => Some((amount_captured, Amount::zero())),
+    }
+}
+
 fn payment_intent_create_params(
     orders: &[(NewOrder, Option<ExchangeId>, BigDecimal)],
     invoice_id: InvoiceV2Id,
@@ -1343,6 +1612,9 @@ fn new_payment_intent(
     invoice_id: InvoiceV2Id,
     stripe_payment_intent: stripe::PaymentIntent,
 ) -> Result<(NewPaymentIntent, NewPaymentIntentInvoice), ServiceError> {
+    let (last_payment_error_code, last_payment_error_message) =
+        last_payment_error_details(stripe_payment_intent.last_payment_error.as_ref());
+
     let payment_intent = NewPaymentIntent {
         id: PaymentIntentId(stripe_payment_intent.id.clone()),
         amount: stripe_payment_intent.amount.into(),
@@ -1356,7 +1628,7 @@ fn new_payment_intent(
             );
             move |_| ectx!(try err e, ErrorKind::Internal)
         })?,
-        last_payment_error_message: stripe_payment_intent.last_payment_error.map(|err| format!("{:?}", err)),
+        last_payment_error_message,
         receipt_email: stripe_payment_intent.receipt_email,
         charge_id: stripe_payment_intent
             .charges
@@ -1365,6 +1637,7 @@ fn new_payment_intent(
             .next()
             .map(|charge| ChargeId::new(charge.id)),
         status: stripe_payment_intent.status.into(),
+        last_payment_error_code,
     };
 
     let payment_intent_invoice = NewPaymentIntentInvoice {
@@ -1460,22 +1733,24 @@ pub mod tests {
 
     use bigdecimal::BigDecimal;
     use chrono::NaiveDateTime;
+    use std::str::FromStr;
     use std::sync::Arc;
     use std::time::SystemTime;
     use tokio_core::reactor::Core;
     use uuid::Uuid;
 
     use models::currency::Currency as StqCurrency;
-    use stq_static_resources::Currency;
+    use stq_static_resources::{Currency, OrderState};
     use stq_types::*;
 
     use client::stores::*;
     use models::invoice_v2::InvoiceId as InvoiceIdv2;
-    use models::order_v2::{OrderId as OrderIdv2, RawOrder, StoreId as StoreIdv2};
     use models::*;
     use repos::repo_factory::tests::*;
     use services::invoice::create_crypto_fee;
+    use services::invoice::settle_invoice_payment;
     use services::invoice::InvoiceService;
+    use services::invoice::{validate_buyer_currency_switch, validate_invoice_cancel};
     use services::merchant::MerchantService;
 
     #[test]
@@ -1555,18 +1830,11 @@ pub mod tests {
             data,
         };
 
-        let order = RawOrder {
-            id: OrderIdv2::new(Uuid::new_v4()),
-            seller_currency: crypto_currency,
-            total_amount: Amount::from_super_unit(crypto_currency, BigDecimal::from(100)),
-            cashback_amount: Amount::new(0),
-            invoice_id: InvoiceIdv2::new(Uuid::new_v4()),
-            created_at: NaiveDateTime::from_timestamp(0, 0),
-            updated_at: NaiveDateTime::from_timestamp(0, 0),
-            store_id: StoreIdv2::new(1),
-            state: PaymentState::Initial,
-            stripe_fee: None,
-        };
+        let order = create_raw_order(
+            InvoiceIdv2::generate(),
+            crypto_currency,
+            Amount::from_super_unit(crypto_currency, BigDecimal::from(100)),
+        );
 
         // then
         let new_fee = create_crypto_fee(order_percent, &fee_currency, &currency_exchange_info, &order).expect("cannot get new fee");
@@ -1574,4 +1842,99 @@ pub mod tests {
         assert_eq!(new_fee.amount, Amount::from_super_unit(fee_currency, BigDecimal::from(1)));
     }
 
+    #[test]
+    fn test_settle_invoice_payment() {
+        let price = BigDecimal::from(1);
+        let tolerance = || BigDecimal::from_str("0.001").unwrap();
+
+        // underpaid within the tolerance, settled with the captured amount
+        let amount_captured = Amount::new(99_950_000);
+        assert_eq!(
+            settle_invoice_payment(StqCurrency::Btc, amount_captured, &price, tolerance()),
+            Some((amount_captured, Amount::zero()))
+        );
+
+        // underpaid beyond the tolerance
+        assert_eq!(
+            settle_invoice_payment(StqCurrency::Btc, Amount::new(99_800_000), &price, tolerance()),
+            None
+        );
+
+        // paid exactly
+        assert_eq!(
+            settle_invoice_payment(StqCurrency::Btc, Amount::new(100_000_000), &price, tolerance()),
+            Some((Amount::new(100_000_000), Amount::zero()))
+        );
+
+        // overpaid, the final amount is the price and the rest is the surplus
+        assert_eq!(
+            settle_invoice_payment(StqCurrency::Btc, Amount::new(100_250_000), &price, tolerance()),
+            Some((Amount::new(100_000_000), Amount::new(250_000)))
+        );
+    }
+
+    #[test]
+    fn test_validate_invoice_cancel() {
+        assert!(validate_invoice_cancel(&create_raw_invoice_v2(StqCurrency::Btc)).is_ok());
+
+        let mut captured_invoice = create_raw_invoice_v2(StqCurrency::Btc);
+        captured_invoice.amount_captured = Amount::new(1);
+        assert_eq!(validation_error_fields(validate_invoice_cancel(&captured_invoice)), vec!["invoice"]);
+
+        let mut paid_invoice = create_raw_invoice_v2(StqCurrency::Eur);
+        paid_invoice.paid_at = Some(NaiveDateTime::from_timestamp(0, 0));
+        assert_eq!(validation_error_fields(validate_invoice_cancel(&paid_invoice)), vec!["invoice"]);
+
+        let mut cancelled_invoice = create_raw_invoice_v2(StqCurrency::Btc);
+        cancelled_invoice.status = OrderState::Cancelled;
+        assert_eq!(
+            validation_error_fields(validate_invoice_cancel(&cancelled_invoice)),
+            vec!["invoice"]
+        );
+    }
+
+    #[test]
+    fn test_validate_buyer_currency_switch() {
+        let orders = vec![create_raw_order(
+            InvoiceIdv2::generate(),
+            StqCurrency::Stq,
+            Amount::from_super_unit(StqCurrency::Stq, BigDecimal::from(1)),
+        )];
+
+        assert!(validate_buyer_currency_switch(&create_raw_invoice_v2(StqCurrency::Stq), &orders, StqCurrency::Btc).is_ok());
+
+        // the invoice is already in the currency
+        let invoice = create_raw_invoice_v2(StqCurrency::Btc);
+        assert_eq!(
+            validation_error_fields(validate_buyer_currency_switch(&invoice, &orders, StqCurrency::Btc)),
+            vec!["currency"]
+        );
+
+        // fiat invoices cannot switch and crypto invoices cannot switch to fiat
+        let invoice = create_raw_invoice_v2(StqCurrency::Eur);
+        assert_eq!(
+            validation_error_fields(validate_buyer_currency_switch(&invoice, &orders, StqCurrency::Btc)),
+            vec!["currency"]
+        );
+        let invoice = create_raw_invoice_v2(StqCurrency::Btc);
+        assert_eq!(
+            validation_error_fields(validate_buyer_currency_switch(&invoice, &orders, StqCurrency::Eur)),
+            vec!["currency"]
+        );
+
+        // a captured amount stays in the account of the current currency
+        let mut invoice = create_raw_invoice_v2(StqCurrency::Btc);
+        invoice.amount_captured = Amount::new(1);
+        assert_eq!(
+            validation_error_fields(validate_buyer_currency_switch(&invoice, &orders, StqCurrency::Eth)),
+            vec!["invoice"]
+        );
+
+        let mut invoice = create_raw_invoice_v2(StqCurrency::Btc);
+        invoice.status = OrderState::Cancelled;
+        assert_eq!(
+            validation_error_fields(validate_buyer_currency_switch(&invoice, &orders, StqCurrency::Eth)),
+            vec!["invoice"]
+        );
+    }
 }
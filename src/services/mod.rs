@@ -5,7 +5,9 @@ pub mod accounts;
 pub mod billing_info;
 pub mod billing_type;
 pub mod customer;
+pub mod document;
 pub mod error;
+pub mod event_store;
 pub mod fee;
 pub mod invoice;
 pub mod merchant;
@@ -13,12 +15,14 @@ pub mod order;
 pub mod order_billing;
 pub mod payment_intent;
 pub mod payout;
+pub mod store_capture_policy;
 pub mod store_subscription;
 pub mod stripe;
 pub mod subscription;
 pub mod subscription_payment;
 pub mod types;
 pub mod user_roles;
+pub mod webhook_subscription;
 
 pub use self::error::*;
 pub use self::types::Service;
@@ -1,41 +1,74 @@
 //! Order Services, presents CRUD operations with orders
 
+use std::collections::HashSet;
+
+use bigdecimal::BigDecimal;
+use chrono::{Duration as ChronoDuration, Utc};
 use diesel::connection::AnsiTransactionManager;
 use diesel::pg::Pg;
 use diesel::Connection;
 use failure::Fail;
 use future::Either;
-use futures::Future;
+use futures::{future, stream, Future, Stream};
 use futures_cpupool::CpuPool;
 use r2d2::{ManageConnection, Pool};
 use validator::{ValidationError, ValidationErrors};
 
 use stq_http::client::HttpClient;
-use stq_types::UserId;
+use stq_types::{BillingRole, UserId};
 
 use super::error::{ErrorContext, ErrorKind};
 use super::types::ServiceFutureV2;
 use client::payments::PaymentsClient;
 use client::stripe::StripeClient;
-use controller::responses::{OrderResponse, OrderSearchResultsResponse};
+use controller::requests::{BulkOrdersRequest, CaptureOrderRequest, CreateCryptoRefundRequest, CreateRefundRequest};
+use controller::responses::{BulkOrderResultResponse, OrderResponse, OrderSearchResultsResponse, OrderTransitionsResponse, RefundResponse};
+use models::invoice_v2::RawInvoice;
 use models::order_v2::{OrderId, OrdersSearch, RawOrder};
-use models::PaymentState;
-use models::{Event, EventPayload};
-use repos::{ReposFactory, SearchPaymentIntent, SearchPaymentIntentInvoice};
+use models::{
+    Amount, ChargeId, Dispute, FeeStatus, OrderStateChange, OrderStateTransition, PaymentState, PaymentStateTransition,
+    PaymentStateTransitionEvent, TransactionId, TureCurrency, UpdateFee, WalletAddress,
+};
+use models::{Event, EventPayload, WebhookEventType, WebhookNotification};
+use models::{NewRefund, Refund, RefundId, RefundKind, RefundStatus};
+use repos::{
+    EventStoreRepo, FeeRepo, OrdersRepo, PaymentIntentInvoiceRepo, PaymentIntentRepo, PayoutsRepo, ReposFactory, SearchFee,
+    SearchPaymentIntent, SearchPaymentIntentInvoice, UserRolesRepo, UserWalletsRepo,
+};
 use services::accounts::AccountService;
 use services::error::Error as ServiceError;
+use services::stripe::create_fee;
 use services::types::spawn_on_pool;
 use services::Service;
 
 pub trait OrderService {
-    /// Capturing charge on order and setting order state to InProgress
-    fn order_capture(&self, order_id: OrderId) -> ServiceFutureV2<()>;
+    /// Capturing charge on order and setting order state to InProgress, fiat orders can be captured partially
+    fn order_capture(&self, order_id: OrderId, payload: CaptureOrderRequest) -> ServiceFutureV2<()>;
     /// Refunding charge on order and setting order state to Cancel
     fn order_decline(&self, order_id: OrderId) -> ServiceFutureV2<()>;
+    /// Capturing the whole charge of every order, the result is reported per order
+    fn orders_bulk_capture(&self, payload: BulkOrdersRequest) -> ServiceFutureV2<Vec<BulkOrderResultResponse>>;
+    /// Declining every order, the result is reported per order
+    fn orders_bulk_decline(&self, payload: BulkOrdersRequest) -> ServiceFutureV2<Vec<BulkOrderResultResponse>>;
     /// Update order payment state
     fn update_order_state(&self, order_id: OrderId, state: PaymentState) -> ServiceFutureV2<()>;
     // Search orders
     fn search_orders(&self, skip: i64, count: i64, payload: OrdersSearch) -> ServiceFutureV2<OrderSearchResultsResponse>;
+    /// Refunding a part of the charge on a captured order, the refund stays pending until Stripe accepts it
+    /// and the order is set to Refunded once fully refunded
+    fn order_refund(&self, order_id: OrderId, payload: CreateRefundRequest) -> ServiceFutureV2<RefundResponse>;
+    /// Get refunds of the order
+    fn get_order_refunds(&self, order_id: OrderId) -> ServiceFutureV2<Vec<RefundResponse>>;
+    /// Sending the paid coins of a declined crypto order back to the buyer, the order is set to Refunded once the transaction is confirmed
+    fn order_refund_crypto(&self, order_id: OrderId, payload: CreateCryptoRefundRequest) -> ServiceFutureV2<RefundResponse>;
+    /// Get the payment state history of the order
+    fn get_order_history(&self, order_id: OrderId) -> ServiceFutureV2<Vec<OrderStateTransition>>;
+    /// Get the charge disputes of the order
+    fn get_order_disputes(&self, order_id: OrderId) -> ServiceFutureV2<Vec<Dispute>>;
+    /// Get the payment states the current user may move the order to
+    fn get_order_transitions(&self, order_id: OrderId) -> ServiceFutureV2<OrderTransitionsResponse>;
+    /// Called once the order is delivered, the captured order is released for payout after the escrow window
+    fn order_delivered(&self, order_id: OrderId) -> ServiceFutureV2<()>;
 }
 
 impl<
@@ -47,7 +80,7 @@ impl<
         AS: AccountService + Clone,
     > OrderService for Service<T, M, F, C, PC, AS>
 {
-    fn order_capture(&self, order_id: OrderId) -> ServiceFutureV2<()> {
+    fn order_capture(&self, order_id: OrderId, payload: CaptureOrderRequest) -> ServiceFutureV2<()> {
         let repo_factory = self.static_context.repo_factory.clone();
         let user_id = self.dynamic_context.user_id;
 
@@ -57,33 +90,37 @@ impl<
         Box::new(
             spawn_on_pool(db_pool, cpu_pool, move |conn| {
                 let orders_repo = repo_factory.create_orders_repo(&conn, user_id);
+                let user_roles_repo = repo_factory.create_user_roles_repo_with_sys_acl(&conn);
                 debug!("Requesting order by id: {}", order_id);
                 let order = orders_repo.get(order_id).map_err(ectx!(try convert => order_id))?.ok_or({
                     let e = format_err!("Order {} not found", order_id);
                     ectx!(try err e, ErrorKind::Internal)
                 })?;
 
-                if order.state != PaymentState::Initial {
-                    let mut errors = ValidationErrors::new();
-                    let mut error = ValidationError::new("wrong_state");
-                    error.message = Some(format!("Cannot capture order in state is \"{}\"", order.state,).into());
-                    errors.add("order", error);
-                    return Err(
-                        ectx!(err ErrorContext::OrderState ,ErrorKind::Validation(serde_json::to_value(errors).unwrap_or_default())),
-                    );
-                }
+                validate_state_transition(&*user_roles_repo, user_id, order.state, PaymentState::Captured)?;
 
-                Ok(order)
+                let captured_amount = match payload.amount {
+                    Some(amount) => validate_captured_amount(&order, amount)?,
+                    None => None,
+                };
+
+                Ok((order, captured_amount))
             })
             .and_then({
                 let repo_factory = self.static_context.repo_factory.clone();
                 let db_pool = self.static_context.db_pool.clone();
                 let cpu_pool = self.static_context.cpu_pool.clone();
-                move |order| {
+                move |(order, captured_amount)| {
                     if order.seller_currency.is_fiat() {
-                        Either::A(order_capture_fiat(cpu_pool, db_pool, repo_factory, order))
+                        Either::A(order_capture_fiat(cpu_pool, db_pool, repo_factory, order, captured_amount))
                     } else {
-                        Either::B(order_capture_crypto(cpu_pool, db_pool, repo_factory, user_id, order))
+                        Either::B(order_capture_crypto(
+                            cpu_pool,
+                            db_pool,
+                            repo_factory,
+                            order,
+                            OrderStateChange::by_user(user_id, "Captured by the seller"),
+                        ))
                     }
                 }
             }),
@@ -101,21 +138,14 @@ impl<
         Box::new(
             spawn_on_pool(db_pool, cpu_pool, move |conn| {
                 let orders_repo = repo_factory.create_orders_repo(&conn, user_id);
+                let user_roles_repo = repo_factory.create_user_roles_repo_with_sys_acl(&conn);
                 debug!("Requesting order by id: {}", order_id);
                 let order = orders_repo.get(order_id).map_err(ectx!(try convert => order_id))?.ok_or({
                     let e = format_err!("Order {} not found", order_id);
                     ectx!(try err e, ErrorKind::Internal)
                 })?;
 
-                if order.state != PaymentState::Initial {
-                    let mut errors = ValidationErrors::new();
-                    let mut error = ValidationError::new("wrong_state");
-                    error.message = Some(format!("Cannot capture order in state is \"{}\"", order.state,).into());
-                    errors.add("order", error);
-                    return Err(
-                        ectx!(err ErrorContext::OrderState ,ErrorKind::Validation(serde_json::to_value(errors).unwrap_or_default())),
-                    );
-                }
+                validate_state_transition(&*user_roles_repo, user_id, order.state, PaymentState::Declined)?;
 
                 Ok(order)
             })
@@ -124,16 +154,143 @@ impl<
                 let db_pool = self.static_context.db_pool.clone();
                 let cpu_pool = self.static_context.cpu_pool.clone();
                 move |order| {
+                    let change = OrderStateChange::by_user(user_id, "Declined by the seller");
                     if order.seller_currency.is_fiat() {
-                        Either::A(order_decline_fiat(cpu_pool, db_pool, repo_factory, user_id, stripe_client, order))
+                        Either::A(order_decline_fiat(cpu_pool, db_pool, repo_factory, stripe_client, order, change))
                     } else {
-                        Either::B(order_decline_crypto(cpu_pool, db_pool, repo_factory, user_id, order))
+                        Either::B(order_decline_crypto(cpu_pool, db_pool, repo_factory, order, change))
                     }
                 }
             }),
         )
     }
 
+    fn orders_bulk_capture(&self, payload: BulkOrdersRequest) -> ServiceFutureV2<Vec<BulkOrderResultResponse>> {
+        let repo_factory = self.static_context.repo_factory.clone();
+        let user_id = self.dynamic_context.user_id;
+
+        let db_pool = self.static_context.db_pool.clone();
+        let cpu_pool = self.static_context.cpu_pool.clone();
+
+        let fut = spawn_on_pool(db_pool.clone(), cpu_pool.clone(), {
+            let repo_factory = repo_factory.clone();
+            move |conn| {
+                let orders_repo = repo_factory.create_orders_repo(&conn, user_id);
+                let user_roles_repo = repo_factory.create_user_roles_repo_with_sys_acl(&conn);
+                let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
+
+                let orders = dedup_order_ids(payload.order_ids)
+                    .into_iter()
+                    .map(|order_id| {
+                        let order = get_order_for_transition(&*orders_repo, &*user_roles_repo, user_id, order_id, PaymentState::Captured);
+                        (order_id, order)
+                    })
+                    .collect::<Vec<_>>();
+
+                // fiat orders are captured by the event handler, the capture events of all of them are enqueued at once
+                let fiat_order_ids = orders
+                    .iter()
+                    .filter_map(|(_, order)| order.as_ref().ok())
+                    .filter(|order| order.seller_currency.is_fiat())
+                    .map(|order| order.id)
+                    .collect::<Vec<_>>();
+                conn.transaction::<_, ServiceError, _>(|| {
+                    for order_id in fiat_order_ids {
+                        let event = Event::new(EventPayload::PaymentIntentCapture { order_id, amount: None });
+                        event_store_repo.add_event(event.clone()).map_err(ectx!(try convert => event))?;
+                    }
+                    Ok(())
+                })?;
+
+                Ok(orders)
+            }
+        })
+        .and_then(move |orders| {
+            stream::iter_ok::<_, ServiceError>(orders)
+                .and_then(move |(order_id, order)| -> ServiceFutureV2<BulkOrderResultResponse> {
+                    match order {
+                        Ok(ref order) if order.seller_currency.is_fiat() => {
+                            Box::new(future::ok(BulkOrderResultResponse::from_result(order_id, Ok(()))))
+                        }
+                        Ok(order) => Box::new(
+                            order_capture_crypto(
+                                cpu_pool.clone(),
+                                db_pool.clone(),
+                                repo_factory.clone(),
+                                order,
+                                OrderStateChange::by_user(user_id, "Captured by the seller"),
+                            )
+                            .then(move |result| Ok::<_, ServiceError>(BulkOrderResultResponse::from_result(order_id, result))),
+                        ),
+                        Err(e) => Box::new(future::ok(BulkOrderResultResponse::from_result(order_id, Err(e)))),
+                    }
+                })
+                .collect()
+        });
+
+        Box::new(fut)
+    }
+
+    fn orders_bulk_decline(&self, payload: BulkOrdersRequest) -> ServiceFutureV2<Vec<BulkOrderResultResponse>> {
+        let repo_factory = self.static_context.repo_factory.clone();
+        let stripe_client = self.static_context.stripe_client.clone();
+        let user_id = self.dynamic_context.user_id;
+
+        let db_pool = self.static_context.db_pool.clone();
+        let cpu_pool = self.static_context.cpu_pool.clone();
+
+        let fut = spawn_on_pool(db_pool.clone(), cpu_pool.clone(), {
+            let repo_factory = repo_factory.clone();
+            move |conn| {
+                let orders_repo = repo_factory.create_orders_repo(&conn, user_id);
+                let user_roles_repo = repo_factory.create_user_roles_repo_with_sys_acl(&conn);
+
+                Ok(dedup_order_ids(payload.order_ids)
+                    .into_iter()
+                    .map(|order_id| {
+                        let order = get_order_for_transition(&*orders_repo, &*user_roles_repo, user_id, order_id, PaymentState::Declined);
+                        (order_id, order)
+                    })
+                    .collect::<Vec<_>>())
+            }
+        })
+        .and_then(move |orders| {
+            // declines are done one by one, a failed refund of one order does not affect the others
+            stream::iter_ok::<_, ServiceError>(orders)
+                .and_then(move |(order_id, order)| -> ServiceFutureV2<BulkOrderResultResponse> {
+                    let order = match order {
+                        Ok(order) => order,
+                        Err(e) => return Box::new(future::ok(BulkOrderResultResponse::from_result(order_id, Err(e)))),
+                    };
+
+                    let change = OrderStateChange::by_user(user_id, "Declined by the seller");
+                    let fut = if order.seller_currency.is_fiat() {
+                        Either::A(order_decline_fiat(
+                            cpu_pool.clone(),
+                            db_pool.clone(),
+                            repo_factory.clone(),
+                            stripe_client.clone(),
+                            order,
+                            change,
+                        ))
+                    } else {
+                        Either::B(order_decline_crypto(
+                            cpu_pool.clone(),
+                            db_pool.clone(),
+                            repo_factory.clone(),
+                            order,
+                            change,
+                        ))
+                    };
+
+                    Box::new(fut.then(move |result| Ok::<_, ServiceError>(BulkOrderResultResponse::from_result(order_id, result))))
+                })
+                .collect()
+        });
+
+        Box::new(fut)
+    }
+
     fn update_order_state(&self, order_id: OrderId, state: PaymentState) -> ServiceFutureV2<()> {
         let repo_factory = self.static_context.repo_factory.clone();
         let user_id = self.dynamic_context.user_id;
@@ -143,6 +300,8 @@ impl<
 
         let fut = spawn_on_pool(db_pool, cpu_pool, move |conn| {
             let orders_repo = repo_factory.create_orders_repo(&conn, user_id);
+            let user_roles_repo = repo_factory.create_user_roles_repo_with_sys_acl(&conn);
+            let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
             info!("Set new payment state order by id: {}, payment_state: {:?}", order_id, state);
 
             let order = orders_repo.get(order_id).map_err(ectx!(try convert => order_id))?.ok_or({
@@ -150,18 +309,33 @@ impl<
                 ectx!(try err e, ErrorKind::Internal)
             })?;
 
-            if check_change_order_payment_state(order.state, state) {
-                orders_repo
-                    .update_state(order_id, state)
-                    .map_err(ectx!(convert => order_id, state))
-                    .map(|_| ())
-            } else {
-                let mut errors = ValidationErrors::new();
-                let mut error = ValidationError::new("wrong_state");
-                error.message = Some(format!("Cannot change order state from \"{}\" to \"{}\"", order.state, state).into());
-                errors.add("order", error);
-                return Err(ectx!(err ErrorContext::OrderState ,ErrorKind::Validation(serde_json::to_value(errors).unwrap_or_default())));
-            }
+            let transition = validate_state_transition(&*user_roles_repo, user_id, order.state, state)?;
+
+            conn.transaction::<_, ServiceError, _>(|| {
+                // fiat orders are captured by the event handler, it also sets the state and notifies the webhooks
+                if transition.event == Some(PaymentStateTransitionEvent::PaymentIntentCapture) && order.seller_currency.is_fiat() {
+                    let event = Event::new(EventPayload::PaymentIntentCapture { order_id, amount: None });
+                    return event_store_repo
+                        .add_event(event.clone())
+                        .map_err(ectx!(convert => event))
+                        .map(|_| ());
+                }
+
+                let order = orders_repo
+                    .update_state(order_id, state, OrderStateChange::by_user(user_id, "Payment state set manually"))
+                    .map_err(ectx!(try convert => order_id, state))?;
+
+                if let Some(event_type) = transition.webhook_event {
+                    add_order_webhook_notification(&*event_store_repo, event_type, &order)?;
+                }
+
+                match transition.event {
+                    Some(PaymentStateTransitionEvent::CryptoRefundInitiated) => {
+                        start_crypto_refund_if_possible(&*conn, &repo_factory, &order, Some("refund requested".to_string()))
+                    }
+                    _ => Ok(()),
+                }
+            })
         });
 
         Box::new(fut)
@@ -184,15 +358,274 @@ impl<
                 .into_iter()
                 .map(OrderResponse::try_from_raw_order)
                 .collect::<Result<Vec<_>, ServiceError>>()?;
+            let totals = search_result
+                .totals
+                .into_iter()
+                .map(|(currency, total)| (currency.into(), total.to_super_unit(currency)))
+                .collect();
             Ok(OrderSearchResultsResponse {
                 total_count: search_result.total_count,
                 orders,
+                next_cursor: search_result.next_cursor,
+                totals,
+            })
+        })
+    }
+
+    fn order_refund(&self, order_id: OrderId, payload: CreateRefundRequest) -> ServiceFutureV2<RefundResponse> {
+        let repo_factory = self.static_context.repo_factory.clone();
+        let user_id = self.dynamic_context.user_id;
+
+        let db_pool = self.static_context.db_pool.clone();
+        let cpu_pool = self.static_context.cpu_pool.clone();
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let orders_repo = repo_factory.create_orders_repo(&conn, user_id);
+            let refunds_repo = repo_factory.create_refunds_repo(&conn, user_id);
+            let payment_intent_repo = repo_factory.create_payment_intent_repo(&conn, user_id);
+            let payment_intent_invoices_repo = repo_factory.create_payment_intent_invoices_repo(&conn, user_id);
+            let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
+
+            debug!("Requesting order by id: {}", order_id);
+            let order = orders_repo.get(order_id).map_err(ectx!(try convert => order_id))?.ok_or({
+                let e = format_err!("Order {} not found", order_id);
+                ectx!(try err e, ErrorKind::Internal)
+            })?;
+
+            let refunds = refunds_repo.list_by_order_id(order_id).map_err(ectx!(try convert => order_id))?;
+            let amount = validate_refund(&order, &refunds, payload.amount)?;
+
+            // the charge is checked upfront, the refund itself is sent by the event handler
+            get_order_charge_id(&*payment_intent_invoices_repo, &*payment_intent_repo, &order)?;
+
+            // the refund and its event are stored together, so that a refund accepted by Stripe is always completed
+            let refund = conn.transaction::<_, ServiceError, _>(|| {
+                let new_refund = NewRefund {
+                    id: RefundId::generate(),
+                    order_id,
+                    amount,
+                    reason: payload.reason,
+                    status: RefundStatus::Pending,
+                    crypto_currency: None,
+                    crypto_amount: None,
+                    wallet_address: None,
+                    transaction_id: None,
+                    kind: RefundKind::Refund,
+                };
+                let refund = refunds_repo.create(new_refund.clone()).map_err(ectx!(try convert => new_refund))?;
+
+                let event = Event::new(EventPayload::StripeRefundInitiated { refund_id: refund.id });
+                event_store_repo.add_event(event.clone()).map_err(ectx!(try convert => event))?;
+
+                Ok(refund)
+            })?;
+
+            RefundResponse::try_from_refund(refund, order.seller_currency)
+        })
+    }
+
+    fn get_order_refunds(&self, order_id: OrderId) -> ServiceFutureV2<Vec<RefundResponse>> {
+        let repo_factory = self.static_context.repo_factory.clone();
+        let user_id = self.dynamic_context.user_id;
+
+        let db_pool = self.static_context.db_pool.clone();
+        let cpu_pool = self.static_context.cpu_pool.clone();
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let orders_repo = repo_factory.create_orders_repo_with_sys_acl(&conn);
+            let refunds_repo = repo_factory.create_refunds_repo(&conn, user_id);
+
+            let order = orders_repo.get(order_id).map_err(ectx!(try convert => order_id))?.ok_or({
+                let e = format_err!("Order {} not found", order_id);
+                ectx!(try err e, ErrorKind::NotFound)
+            })?;
+
+            refunds_repo
+                .list_by_order_id(order_id)
+                .map_err(ectx!(try convert => order_id))?
+                .into_iter()
+                .map(|refund| RefundResponse::try_from_refund(refund, order.seller_currency))
+                .collect()
+        })
+    }
+
+    fn order_refund_crypto(&self, order_id: OrderId, payload: CreateCryptoRefundRequest) -> ServiceFutureV2<RefundResponse> {
+        let repo_factory = self.static_context.repo_factory.clone();
+        let user_id = self.dynamic_context.user_id;
+
+        let db_pool = self.static_context.db_pool.clone();
+        let cpu_pool = self.static_context.cpu_pool.clone();
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let orders_repo = repo_factory.create_orders_repo(&conn, user_id);
+            let invoices_repo = repo_factory.create_invoices_v2_repo_with_sys_acl(&conn);
+            let user_wallets_repo = repo_factory.create_user_wallets_repo_with_sys_acl(&conn);
+
+            debug!("Requesting order by id: {}", order_id);
+            let order = orders_repo.get(order_id).map_err(ectx!(try convert => order_id))?.ok_or({
+                let e = format_err!("Order {} not found", order_id);
+                ectx!(try err e, ErrorKind::NotFound)
+            })?;
+
+            let invoice_id = order.invoice_id;
+            let invoice = invoices_repo.get(invoice_id).map_err(ectx!(try convert => invoice_id))?.ok_or({
+                let e = format_err!("Invoice {} not found", invoice_id);
+                ectx!(try err e, ErrorKind::Internal)
+            })?;
+
+            validate_crypto_refund(&order, &invoice)?;
+
+            let CreateCryptoRefundRequest { wallet_address, reason } = payload;
+            let wallet_address = match wallet_address {
+                Some(wallet_address) => wallet_address,
+                None => find_buyer_wallet(&*user_wallets_repo, &invoice)?.ok_or_else(|| {
+                    let mut errors = ValidationErrors::new();
+                    let mut error = ValidationError::new("missing_wallet_address");
+                    error.message = Some("The buyer has no active wallet, a refund address has to be provided".into());
+                    errors.add("wallet_address", error);
+                    ectx!(err ErrorContext::Refund, ErrorKind::Validation(serde_json::to_value(errors).unwrap_or_default()))
+                })?,
+            };
+
+            let refund = conn.transaction::<_, ServiceError, _>(|| {
+                start_crypto_refund(&*conn, &repo_factory, &order, &invoice, wallet_address, reason)
+            })?;
+
+            RefundResponse::try_from_refund(refund, order.seller_currency)
+        })
+    }
+
+    fn get_order_history(&self, order_id: OrderId) -> ServiceFutureV2<Vec<OrderStateTransition>> {
+        let repo_factory = self.static_context.repo_factory.clone();
+        let user_id = self.dynamic_context.user_id;
+
+        let db_pool = self.static_context.db_pool.clone();
+        let cpu_pool = self.static_context.cpu_pool.clone();
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let orders_repo = repo_factory.create_orders_repo_with_sys_acl(&conn);
+            let order_state_transitions_repo = repo_factory.create_order_state_transitions_repo(&conn, user_id);
+
+            orders_repo.get(order_id).map_err(ectx!(try convert => order_id))?.ok_or({
+                let e = format_err!("Order {} not found", order_id);
+                ectx!(try err e, ErrorKind::NotFound)
+            })?;
+
+            order_state_transitions_repo
+                .list_by_order_id(order_id)
+                .map_err(ectx!(convert => order_id))
+        })
+    }
+
+    fn get_order_disputes(&self, order_id: OrderId) -> ServiceFutureV2<Vec<Dispute>> {
+        let repo_factory = self.static_context.repo_factory.clone();
+        let user_id = self.dynamic_context.user_id;
+
+        let db_pool = self.static_context.db_pool.clone();
+        let cpu_pool = self.static_context.cpu_pool.clone();
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let orders_repo = repo_factory.create_orders_repo_with_sys_acl(&conn);
+            let disputes_repo = repo_factory.create_disputes_repo(&conn, user_id);
+
+            orders_repo.get(order_id).map_err(ectx!(try convert => order_id))?.ok_or({
+                let e = format_err!("Order {} not found", order_id);
+                ectx!(try err e, ErrorKind::NotFound)
+            })?;
+
+            disputes_repo.list_by_order_id(order_id).map_err(ectx!(convert => order_id))
+        })
+    }
+
+    fn get_order_transitions(&self, order_id: OrderId) -> ServiceFutureV2<OrderTransitionsResponse> {
+        let repo_factory = self.static_context.repo_factory.clone();
+        let user_id = self.dynamic_context.user_id;
+
+        let db_pool = self.static_context.db_pool.clone();
+        let cpu_pool = self.static_context.cpu_pool.clone();
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let orders_repo = repo_factory.create_orders_repo(&conn, user_id);
+            let user_roles_repo = repo_factory.create_user_roles_repo_with_sys_acl(&conn);
+
+            let order = orders_repo.get(order_id).map_err(ectx!(try convert => order_id))?.ok_or({
+                let e = format_err!("Order {} not found", order_id);
+                ectx!(try err e, ErrorKind::NotFound)
+            })?;
+
+            let roles = list_user_roles(&*user_roles_repo, user_id)?;
+            let transitions = order
+                .state
+                .transitions()
+                .filter(|transition| transition.is_allowed_for(&roles))
+                .map(|transition| transition.to)
+                .collect();
+
+            Ok(OrderTransitionsResponse {
+                state: order.state,
+                transitions,
+            })
+        })
+    }
+
+    fn order_delivered(&self, order_id: OrderId) -> ServiceFutureV2<()> {
+        let repo_factory = self.static_context.repo_factory.clone();
+        let release_after_days = self.static_context.config.escrow.release_after_days;
+        let user_id = self.dynamic_context.user_id;
+
+        let db_pool = self.static_context.db_pool.clone();
+        let cpu_pool = self.static_context.cpu_pool.clone();
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let orders_repo = repo_factory.create_orders_repo(&conn, user_id);
+            let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
+
+            let order = orders_repo.get(order_id).map_err(ectx!(try convert => order_id))?.ok_or({
+                let e = format_err!("Order {} not found", order_id);
+                ectx!(try err e, ErrorKind::NotFound)
+            })?;
+
+            if order.state != PaymentState::Captured {
+                let mut errors = ValidationErrors::new();
+                let mut error = ValidationError::new("wrong_state");
+                error.message = Some(format!("Cannot release order in state \"{}\" from escrow", order.state).into());
+                errors.add("order", error);
+                return Err(ectx!(err ErrorContext::OrderState, ErrorKind::Validation(serde_json::to_value(errors).unwrap_or_default())));
+            }
+
+            conn.transaction::<_, ServiceError, _>(|| {
+                // the release is scheduled for the first delivery report only
+                if orders_repo
+                    .set_delivered(order_id)
+                    .map_err(ectx!(try convert => order_id))?
+                    .is_none()
+                {
+                    info!("Order {} has already been delivered", order_id);
+                    return Ok(());
+                }
+
+                info!(
+                    "Order {} is delivered, it is released from escrow in {} days",
+                    order_id, release_after_days
+                );
+                let scheduled_on = Utc::now().naive_utc() + ChronoDuration::days(release_after_days.into());
+                let event = Event::new(EventPayload::EscrowReleaseDue { order_id });
+                event_store_repo
+                    .add_scheduled_event(event.clone(), scheduled_on)
+                    .map_err(ectx!(convert => event, scheduled_on))
+                    .map(|_| ())
             })
         })
     }
 }
 
-fn order_capture_fiat<T, F, M>(cpu_pool: CpuPool, db_pool: Pool<M>, repo_factory: F, order: RawOrder) -> ServiceFutureV2<()>
+pub fn order_capture_fiat<T, F, M>(
+    cpu_pool: CpuPool,
+    db_pool: Pool<M>,
+    repo_factory: F,
+    order: RawOrder,
+    captured_amount: Option<Amount>,
+) -> ServiceFutureV2<()>
 where
     T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static,
     F: ReposFactory<T>,
@@ -200,20 +633,24 @@ where
 {
     let fut = spawn_on_pool(db_pool, cpu_pool, move |conn| {
         let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
-        let event = Event::new(EventPayload::PaymentIntentCapture { order_id: order.id });
+        let event = Event::new(EventPayload::PaymentIntentCapture {
+            order_id: order.id,
+            amount: captured_amount,
+        });
         event_store_repo.add_event(event.clone()).map_err(ectx!(try convert => event))?;
         Ok(())
     });
     Box::new(fut)
 }
 
-fn order_decline_fiat<T, F, M>(
+/// Refunds the authorised charge, `change` tells whether the order is declined by a user or by the system
+pub fn order_decline_fiat<T, F, M>(
     cpu_pool: CpuPool,
     db_pool: Pool<M>,
     repo_factory: F,
-    user_id: Option<UserId>,
     stripe_client: std::sync::Arc<dyn StripeClient>,
     order: RawOrder,
+    change: OrderStateChange,
 ) -> ServiceFutureV2<()>
 where
     T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static,
@@ -223,39 +660,22 @@ where
     let db_pool_ = db_pool.clone();
     let cpu_pool_ = cpu_pool.clone();
     let repo_factory_ = repo_factory.clone();
+    let change_ = change.clone();
     let order_id = order.id;
 
     let fut = spawn_on_pool(db_pool_, cpu_pool_, move |conn| {
-        let payment_intent_repo = repo_factory_.create_payment_intent_repo(&conn, user_id);
-        let payment_intent_invoices_repo = repo_factory_.create_payment_intent_invoices_repo(&conn, user_id);
+        let (payment_intent_repo, payment_intent_invoices_repo) = match change_.event_id {
+            Some(_) => (
+                repo_factory_.create_payment_intent_repo_with_sys_acl(&conn),
+                repo_factory_.create_payment_intent_invoices_repo_with_sys_acl(&conn),
+            ),
+            None => (
+                repo_factory_.create_payment_intent_repo(&conn, change_.user_id),
+                repo_factory_.create_payment_intent_invoices_repo(&conn, change_.user_id),
+            ),
+        };
 
-        let order_invoice_id_cloned = order.invoice_id.clone();
-        let payment_intent_invoice = payment_intent_invoices_repo
-            .get(SearchPaymentIntentInvoice::InvoiceId(order.invoice_id))
-            .map_err(ectx!(try convert => order_invoice_id_cloned))?
-            .ok_or({
-                let e = format_err!("Record payment_intent_invoice by invoice id {} not found", order.invoice_id);
-                ectx!(try err e, ErrorKind::Internal)
-            })?;
-
-        let search = SearchPaymentIntent::Id(payment_intent_invoice.payment_intent_id);
-        let search_clone = search.clone();
-        let payment_intent = payment_intent_repo
-            .get(search.clone())
-            .map_err(ectx!(try convert => search))?
-            .ok_or({
-                let e = format_err!("payment intent {:?} not found", search_clone);
-                ectx!(try err e, ErrorKind::Internal)
-            })?;
-
-        let payment_intent_id = payment_intent.id;
-        payment_intent
-            .charge_id
-            .ok_or({
-                let e = format_err!("charge is absent in payment intent {:?}", payment_intent_id);
-                ectx!(err e, ErrorKind::Internal)
-            })
-            .map(|charge_id| (charge_id, order.total_amount))
+        get_order_charge_id(&*payment_intent_invoices_repo, &*payment_intent_repo, &order).map(|charge_id| (charge_id, order.total_amount))
     })
     .and_then(move |(charge_id, total_amount)| {
         stripe_client
@@ -269,24 +689,27 @@ where
         let repo_factory = repo_factory.clone();
         move |_| {
             spawn_on_pool(db_pool, cpu_pool, move |conn| {
-                let orders_repo = repo_factory.create_orders_repo(&conn, user_id);
+                let orders_repo = create_orders_repo_for_change(&repo_factory, &*conn, &change);
+                let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
                 info!("Setting order {} state \'Declined\'", order_id);
-                orders_repo
-                    .update_state(order_id, PaymentState::Declined)
-                    .map_err(ectx!(convert => order_id))
-                    .map(|_| ())
+                conn.transaction::<_, ServiceError, _>(move || {
+                    let order = orders_repo
+                        .update_state(order_id, PaymentState::Declined, change)
+                        .map_err(ectx!(try convert => order_id))?;
+                    add_order_webhook_notification(&*event_store_repo, WebhookEventType::OrderDeclined, &order)
+                })
             })
         }
     });
     Box::new(fut)
 }
 
-fn order_capture_crypto<T, F, M>(
+pub fn order_capture_crypto<T, F, M>(
     cpu_pool: CpuPool,
     db_pool: Pool<M>,
     repo_factory: F,
-    user_id: Option<UserId>,
     order: RawOrder,
+    change: OrderStateChange,
 ) -> ServiceFutureV2<()>
 where
     T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static,
@@ -296,22 +719,26 @@ where
     let order_id = order.id;
 
     let fut = spawn_on_pool(db_pool, cpu_pool, move |conn| {
-        let orders_repo = repo_factory.create_orders_repo(&conn, user_id);
+        let orders_repo = create_orders_repo_for_change(&repo_factory, &*conn, &change);
+        let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
         info!("Setting order {} state \'Captured\'", order_id);
-        orders_repo
-            .update_state(order_id, PaymentState::Captured)
-            .map_err(ectx!(convert => order_id))
-            .map(|_| ())
+        conn.transaction::<_, ServiceError, _>(move || {
+            let order = orders_repo
+                .update_state(order_id, PaymentState::Captured, change)
+                .map_err(ectx!(try convert => order_id))?;
+            add_order_webhook_notification(&*event_store_repo, WebhookEventType::OrderCaptured, &order)
+        })
     });
     Box::new(fut)
 }
 
-fn order_decline_crypto<T, F, M>(
+/// Sets the order to `RefundNeeded`, the coins are sent back right away if the buyer has a wallet
+pub fn order_decline_crypto<T, F, M>(
     cpu_pool: CpuPool,
     db_pool: Pool<M>,
     repo_factory: F,
-    user_id: Option<UserId>,
     order: RawOrder,
+    change: OrderStateChange,
 ) -> ServiceFutureV2<()>
 where
     T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static,
@@ -321,29 +748,493 @@ where
     let order_id = order.id;
 
     let fut = spawn_on_pool(db_pool, cpu_pool, move |conn| {
-        let orders_repo = repo_factory.create_orders_repo(&conn, user_id);
+        let orders_repo = create_orders_repo_for_change(&repo_factory, &*conn, &change);
+        let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
         info!("Setting order {} state \'RefundNeeded\'", order_id);
-        orders_repo
-            .update_state(order_id, PaymentState::RefundNeeded)
-            .map_err(ectx!(convert => order_id))
-            .map(|_| ())
+        conn.transaction::<_, ServiceError, _>(|| {
+            let order = orders_repo
+                .update_state(order_id, PaymentState::RefundNeeded, change.clone())
+                .map_err(ectx!(try convert => order_id))?;
+            add_order_webhook_notification(&*event_store_repo, WebhookEventType::OrderDeclined, &order)?;
+
+            start_crypto_refund_if_possible(&*conn, &repo_factory, &order, Some("declined".to_string()))
+        })
     });
     Box::new(fut)
 }
 
-fn check_change_order_payment_state(current_state: PaymentState, new_state: PaymentState) -> bool {
-    use models::PaymentState::*;
-
-    match (current_state, new_state) {
-        (Initial, Captured)
-        | (Initial, Declined)
-        | (Captured, RefundNeeded)
-        | (Captured, PaymentToSellerNeeded)
-        | (RefundNeeded, Refunded)
-        | (PaymentToSellerNeeded, PaidToSeller) => true,
-        _ => {
-            error!("Change state from {} to {} unreachable.", current_state, new_state);
-            false
+/// Converts the requested capture amount, returns `None` if the whole order is captured
+fn validate_captured_amount(order: &RawOrder, amount: f64) -> Result<Option<Amount>, ServiceError> {
+    let mut errors = ValidationErrors::new();
+
+    if !order.seller_currency.is_fiat() {
+        let mut error = ValidationError::new("partial_capture_not_supported");
+        error.message = Some(format!("Partial capture is not supported for orders in \"{}\"", order.seller_currency).into());
+        errors.add("amount", error);
+    } else if !(amount > 0.0) {
+        let mut error = ValidationError::new("wrong_amount");
+        error.message = Some("Captured amount must be positive".into());
+        errors.add("amount", error);
+    } else {
+        let captured_amount = Amount::from_super_unit(order.seller_currency, BigDecimal::from(amount));
+        if captured_amount > order.total_amount {
+            let mut error = ValidationError::new("wrong_amount");
+            error.message = Some(
+                format!(
+                    "Captured amount {} exceeds order total {}",
+                    amount,
+                    order.total_amount.to_super_unit(order.seller_currency)
+                )
+                .into(),
+            );
+            errors.add("amount", error);
+        } else if captured_amount == order.total_amount {
+            return Ok(None);
+        } else {
+            return Ok(Some(captured_amount));
+        }
+    }
+
+    Err(ectx!(err ErrorContext::OrderState, ErrorKind::Validation(serde_json::to_value(errors).unwrap_or_default())))
+}
+
+/// Checks that the order can be refunded and converts the requested amount
+fn validate_refund(order: &RawOrder, refunds: &[Refund], amount: f64) -> Result<Amount, ServiceError> {
+    let mut errors = ValidationErrors::new();
+
+    match order.state {
+        PaymentState::Captured | PaymentState::PaymentToSellerNeeded | PaymentState::PaidToSeller => {}
+        state => {
+            let mut error = ValidationError::new("wrong_state");
+            error.message = Some(format!("Cannot refund order in state \"{}\"", state).into());
+            errors.add("order", error);
+            return Err(ectx!(err ErrorContext::Refund, ErrorKind::Validation(serde_json::to_value(errors).unwrap_or_default())));
+        }
+    }
+
+    if !order.seller_currency.is_fiat() {
+        let mut error = ValidationError::new("refund_not_supported");
+        error.message = Some(format!("Refunds are not supported for orders in \"{}\"", order.seller_currency).into());
+        errors.add("amount", error);
+    } else if !(amount > 0.0) {
+        let mut error = ValidationError::new("wrong_amount");
+        error.message = Some("Refund amount must be positive".into());
+        errors.add("amount", error);
+    } else {
+        let refund_amount = Amount::from_super_unit(order.seller_currency, BigDecimal::from(amount));
+        // refunds in progress are reserved, so that concurrent refunds cannot exceed the order
+        let pending_amount = refunds
+            .iter()
+            .filter(|refund| refund.status == RefundStatus::Pending)
+            .fold(Amount::zero(), |acc, refund| acc.checked_add(refund.amount).unwrap_or(Amount::MAX));
+        let available_amount = order.net_amount().checked_sub(pending_amount).unwrap_or(Amount::zero());
+
+        if refund_amount > available_amount {
+            let mut error = ValidationError::new("wrong_amount");
+            error.message = Some(
+                format!(
+                    "Refund amount {} exceeds the amount available for refund {}",
+                    amount,
+                    available_amount.to_super_unit(order.seller_currency)
+                )
+                .into(),
+            );
+            errors.add("amount", error);
+        } else {
+            return Ok(refund_amount);
+        }
+    }
+
+    Err(ectx!(err ErrorContext::Refund, ErrorKind::Validation(serde_json::to_value(errors).unwrap_or_default())))
+}
+
+/// Recalculates the fee for the amount left after a refund, returns the part of the fee returned to the seller.
+/// Unpaid fees are reduced, paid ones are left untouched
+pub fn adjust_fee_for_refund(
+    fees_repo: &FeeRepo,
+    order_percent: u64,
+    order: &RawOrder,
+    refunded_order: &RawOrder,
+) -> Result<Amount, ServiceError> {
+    let order_id = order.id;
+    let fee = match fees_repo
+        .get(SearchFee::OrderId(order_id))
+        .map_err(ectx!(try convert => order_id))?
+    {
+        Some(fee) => fee,
+        None => return Ok(Amount::zero()),
+    };
+
+    let fee_amount = create_fee(order_percent, order)?.amount;
+    let new_fee_amount = create_fee(order_percent, refunded_order)?.amount;
+    let fee_adjustment = fee_amount.checked_sub(new_fee_amount).unwrap_or(Amount::zero());
+
+    if fee.status == FeeStatus::NotPaid {
+        let fee_id = fee.id;
+        let update_fee = UpdateFee {
+            amount: Some(new_fee_amount),
+            ..Default::default()
+        };
+        fees_repo
+            .update(fee_id, update_fee.clone())
+            .map_err(ectx!(try convert => fee_id, update_fee))?;
+    }
+
+    Ok(fee_adjustment)
+}
+
+/// Returns the part of a refund to take back from the seller balance, which is the whole refund
+/// if the order has already been paid out or is part of a payout in progress
+pub fn refund_balance_adjustment(payouts_repo: &PayoutsRepo, order: &RawOrder, amount: Amount) -> Result<Amount, ServiceError> {
+    let order_id = order.id;
+    let payout = payouts_repo.get_by_order_id(order_id).map_err(ectx!(try convert => order_id))?;
+
+    if order.state == PaymentState::PaidToSeller || payout.is_some() {
+        Ok(amount)
+    } else {
+        Ok(Amount::zero())
+    }
+}
+
+/// Checks that the paid coins of the order can be sent back to the buyer
+fn validate_crypto_refund(order: &RawOrder, invoice: &RawInvoice) -> Result<(), ServiceError> {
+    let mut errors = ValidationErrors::new();
+
+    if order.state != PaymentState::RefundNeeded {
+        let mut error = ValidationError::new("wrong_state");
+        error.message = Some(format!("Cannot refund order in state \"{}\"", order.state).into());
+        errors.add("order", error);
+    } else if invoice.buyer_currency.is_fiat() {
+        let mut error = ValidationError::new("refund_not_supported");
+        error.message = Some(format!("Order {} was not paid in crypto", order.id).into());
+        errors.add("order", error);
+    } else {
+        return Ok(());
+    }
+
+    Err(ectx!(err ErrorContext::Refund, ErrorKind::Validation(serde_json::to_value(errors).unwrap_or_default())))
+}
+
+/// Returns the address of an active buyer wallet in the invoice currency
+fn find_buyer_wallet(user_wallets_repo: &UserWalletsRepo, invoice: &RawInvoice) -> Result<Option<WalletAddress>, ServiceError> {
+    let currency = match TureCurrency::try_from_currency(invoice.buyer_currency) {
+        Ok(currency) => currency,
+        Err(_) => return Ok(None),
+    };
+    let buyer_user_id = invoice.buyer_user_id;
+
+    let wallets = user_wallets_repo
+        .get_currency_wallets_by_user_id(currency, buyer_user_id)
+        .map_err(ectx!(try convert => currency, buyer_user_id))?;
+
+    Ok(wallets.into_iter().next().map(|wallet| wallet.address))
+}
+
+/// Starts the refund of an order paid in crypto if the buyer has a wallet to send the coins back to,
+/// otherwise the refund waits for the buyer to provide one
+fn start_crypto_refund_if_possible<T, F>(conn: &T, repo_factory: &F, order: &RawOrder, reason: Option<String>) -> Result<(), ServiceError>
+where
+    T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static,
+    F: ReposFactory<T>,
+{
+    let invoices_repo = repo_factory.create_invoices_v2_repo_with_sys_acl(conn);
+    let user_wallets_repo = repo_factory.create_user_wallets_repo_with_sys_acl(conn);
+
+    let invoice_id = order.invoice_id;
+    let invoice = invoices_repo.get(invoice_id).map_err(ectx!(try convert => invoice_id))?.ok_or({
+        let e = format_err!("Invoice {} not found", invoice_id);
+        ectx!(try err e, ErrorKind::Internal)
+    })?;
+
+    if invoice.buyer_currency.is_fiat() {
+        return Ok(());
+    }
+
+    match find_buyer_wallet(&*user_wallets_repo, &invoice)? {
+        Some(wallet_address) => start_crypto_refund(conn, repo_factory, order, &invoice, wallet_address, reason).map(|_| ()),
+        None => {
+            info!("Order {} has no refund address, waiting for it to be provided", order.id);
+            Ok(())
+        }
+    }
+}
+
+/// Creates a pending refund of the order net amount converted to the buyer currency
+/// and schedules sending it to the wallet
+fn start_crypto_refund<T, F>(
+    conn: &T,
+    repo_factory: &F,
+    order: &RawOrder,
+    invoice: &RawInvoice,
+    wallet_address: WalletAddress,
+    reason: Option<String>,
+) -> Result<Refund, ServiceError>
+where
+    T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static,
+    F: ReposFactory<T>,
+{
+    let refunds_repo = repo_factory.create_refunds_repo_with_sys_acl(conn);
+    let rates_repo = repo_factory.create_order_exchange_rates_repo_with_sys_acl(conn);
+    let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(conn);
+
+    let order_id = order.id;
+
+    let refunds = refunds_repo.list_by_order_id(order_id).map_err(ectx!(try convert => order_id))?;
+    if refunds.iter().any(|refund| refund.status != RefundStatus::Failed) {
+        let mut errors = ValidationErrors::new();
+        let mut error = ValidationError::new("refund_exists");
+        error.message = Some(format!("Order {} is already being refunded", order_id).into());
+        errors.add("order", error);
+        return Err(ectx!(err ErrorContext::Refund, ErrorKind::Validation(serde_json::to_value(errors).unwrap_or_default())));
+    }
+
+    let exchange_rate = if invoice.buyer_currency == order.seller_currency {
+        BigDecimal::from(1)
+    } else {
+        rates_repo
+            .get_active_rate_for_order(order_id)
+            .map_err(ectx!(try convert => order_id))?
+            .ok_or({
+                let e = format_err!("Order {} has no active exchange rate", order_id);
+                ectx!(try err e, ErrorKind::Internal)
+            })?
+            .exchange_rate
+    };
+
+    let amount = order.net_amount();
+    let crypto_amount = Amount::from_super_unit(invoice.buyer_currency, amount.to_super_unit(order.seller_currency) / exchange_rate);
+
+    let refund_id = RefundId::generate();
+    let new_refund = NewRefund {
+        id: refund_id,
+        order_id,
+        amount,
+        reason,
+        status: RefundStatus::Pending,
+        crypto_currency: Some(invoice.buyer_currency),
+        crypto_amount: Some(crypto_amount),
+        wallet_address: Some(wallet_address),
+        transaction_id: Some(TransactionId::new(*refund_id.inner())),
+        kind: RefundKind::Refund,
+    };
+    let refund = refunds_repo.create(new_refund.clone()).map_err(ectx!(try convert => new_refund))?;
+
+    let event = Event::new(EventPayload::CryptoRefundInitiated { refund_id });
+    event_store_repo.add_event(event.clone()).map_err(ectx!(try convert => event))?;
+
+    Ok(refund)
+}
+
+pub fn get_order_charge_id(
+    payment_intent_invoices_repo: &PaymentIntentInvoiceRepo,
+    payment_intent_repo: &PaymentIntentRepo,
+    order: &RawOrder,
+) -> Result<ChargeId, ServiceError> {
+    let order_invoice_id_cloned = order.invoice_id.clone();
+    let payment_intent_invoice = payment_intent_invoices_repo
+        .get(SearchPaymentIntentInvoice::InvoiceId(order.invoice_id))
+        .map_err(ectx!(try convert => order_invoice_id_cloned))?
+        .ok_or({
+            let e = format_err!("Record payment_intent_invoice by invoice id {} not found", order.invoice_id);
+            ectx!(try err e, ErrorKind::Internal)
+        })?;
+
+    let search = SearchPaymentIntent::Id(payment_intent_invoice.payment_intent_id);
+    let search_clone = search.clone();
+    let payment_intent = payment_intent_repo
+        .get(search.clone())
+        .map_err(ectx!(try convert => search))?
+        .ok_or({
+            let e = format_err!("payment intent {:?} not found", search_clone);
+            ectx!(try err e, ErrorKind::Internal)
+        })?;
+
+    let payment_intent_id = payment_intent.id;
+    payment_intent.charge_id.ok_or({
+        let e = format_err!("charge is absent in payment intent {:?}", payment_intent_id);
+        ectx!(err e, ErrorKind::Internal)
+    })
+}
+
+/// Changes made while handling an event are done by the system and are not restricted by the user acl
+fn create_orders_repo_for_change<'a, T, F>(repo_factory: &F, conn: &'a T, change: &OrderStateChange) -> Box<OrdersRepo + 'a>
+where
+    T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static,
+    F: ReposFactory<T>,
+{
+    match change.event_id {
+        Some(_) => repo_factory.create_orders_repo_with_sys_acl(conn),
+        None => repo_factory.create_orders_repo(conn, change.user_id),
+    }
+}
+
+fn add_order_webhook_notification(
+    event_store_repo: &EventStoreRepo,
+    event_type: WebhookEventType,
+    order: &RawOrder,
+) -> Result<(), ServiceError> {
+    let event = Event::new(EventPayload::WebhookNotificationCreated {
+        notification: WebhookNotification::for_order(event_type, order),
+    });
+    event_store_repo
+        .add_event(event.clone())
+        .map_err(ectx!(convert => event))
+        .map(|_| ())
+}
+
+/// Drops repeated order IDs of a bulk request, so that every order is processed and reported once
+fn dedup_order_ids(order_ids: Vec<OrderId>) -> Vec<OrderId> {
+    let mut seen = HashSet::new();
+    order_ids.into_iter().filter(|order_id| seen.insert(*order_id)).collect()
+}
+
+/// Gets the order and checks that the user may move it to the given state
+fn get_order_for_transition(
+    orders_repo: &OrdersRepo,
+    user_roles_repo: &UserRolesRepo,
+    user_id: Option<UserId>,
+    order_id: OrderId,
+    to: PaymentState,
+) -> Result<RawOrder, ServiceError> {
+    let order = orders_repo.get(order_id).map_err(ectx!(try convert => order_id))?.ok_or({
+        let e = format_err!("Order {} not found", order_id);
+        ectx!(try err e, ErrorKind::NotFound)
+    })?;
+
+    validate_state_transition(user_roles_repo, user_id, order.state, to)?;
+
+    Ok(order)
+}
+
+/// Checks the requested payment state change against the transition table and the roles of the user
+fn validate_state_transition(
+    user_roles_repo: &UserRolesRepo,
+    user_id: Option<UserId>,
+    from: PaymentState,
+    to: PaymentState,
+) -> Result<&'static PaymentStateTransition, ServiceError> {
+    let transition = match from.transition_to(to) {
+        Some(transition) => transition,
+        None => {
+            let mut errors = ValidationErrors::new();
+            let mut error = ValidationError::new("wrong_state");
+            error.message = Some(format!("Cannot change order state from \"{}\" to \"{}\"", from, to).into());
+            errors.add("order", error);
+            return Err(ectx!(err ErrorContext::OrderState, ErrorKind::Validation(serde_json::to_value(errors).unwrap_or_default())));
         }
+    };
+
+    let roles = list_user_roles(user_roles_repo, user_id)?;
+    if transition.is_allowed_for(&roles) {
+        Ok(transition)
+    } else {
+        let e = format_err!("User {:?} is not allowed to change order state from {} to {}", user_id, from, to);
+        Err(ectx!(err e, ErrorKind::Forbidden))
+    }
+}
+
+fn list_user_roles(user_roles_repo: &UserRolesRepo, user_id: Option<UserId>) -> Result<Vec<BillingRole>, ServiceError> {
+    match user_id {
+        Some(user_id) => user_roles_repo
+            .list_for_user(user_id)
+            .map_err(|e| ectx!(err e, ErrorKind::Internal => user_id)),
+        None => Ok(vec![]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use models::invoice_v2::InvoiceId;
+    use models::Currency;
+    use repos::repo_factory::tests::{create_raw_order, create_refund, validation_error_fields};
+
+    use super::*;
+
+    #[test]
+    fn test_validate_captured_amount() {
+        let order = create_raw_order(InvoiceId::generate(), Currency::Eur, Amount::new(1000));
+
+        assert_eq!(validate_captured_amount(&order, 5.5).unwrap(), Some(Amount::new(550)));
+        // capturing the whole order is not a partial capture
+        assert_eq!(validate_captured_amount(&order, 10.0).unwrap(), None);
+
+        assert_eq!(validation_error_fields(validate_captured_amount(&order, 10.5)), vec!["amount"]);
+        assert_eq!(validation_error_fields(validate_captured_amount(&order, 0.0)), vec!["amount"]);
+        assert_eq!(validation_error_fields(validate_captured_amount(&order, -1.0)), vec!["amount"]);
+
+        let crypto_order = create_raw_order(InvoiceId::generate(), Currency::Btc, Amount::new(100_000_000));
+        assert_eq!(
+            validation_error_fields(validate_captured_amount(&crypto_order, 0.5)),
+            vec!["amount"]
+        );
+    }
+
+    #[test]
+    fn test_validate_refund_of_partially_captured_order() {
+        let mut order = create_raw_order(InvoiceId::generate(), Currency::Eur, Amount::new(1000));
+        order.state = PaymentState::Captured;
+        order.captured_amount = Some(Amount::new(600));
+
+        assert_eq!(validate_refund(&order, &[], 6.0).unwrap(), Amount::new(600));
+        assert_eq!(validation_error_fields(validate_refund(&order, &[], 6.5)), vec!["amount"]);
+    }
+
+    #[test]
+    fn test_validate_multiple_refunds() {
+        let mut order = create_raw_order(InvoiceId::generate(), Currency::Eur, Amount::new(1000));
+        order.state = PaymentState::Captured;
+        // a succeeded refund is already deducted from the order
+        order.refunded_amount = Amount::new(300);
+        let refunds = vec![
+            create_refund(order.id, Amount::new(300), RefundStatus::Succeeded),
+            create_refund(order.id, Amount::new(200), RefundStatus::Pending),
+            create_refund(order.id, Amount::new(400), RefundStatus::Failed),
+        ];
+
+        // pending refunds are reserved, failed ones are not
+        assert_eq!(validate_refund(&order, &refunds, 5.0).unwrap(), Amount::new(500));
+        assert_eq!(validation_error_fields(validate_refund(&order, &refunds, 5.5)), vec!["amount"]);
+
+        order.refunded_amount = Amount::new(800);
+        assert_eq!(validation_error_fields(validate_refund(&order, &refunds, 0.5)), vec!["amount"]);
+    }
+
+    #[test]
+    fn test_validate_refund_state() {
+        let mut order = create_raw_order(InvoiceId::generate(), Currency::Eur, Amount::new(1000));
+        assert_eq!(validation_error_fields(validate_refund(&order, &[], 1.0)), vec!["order"]);
+
+        order.state = PaymentState::PaidToSeller;
+        assert!(validate_refund(&order, &[], 1.0).is_ok());
+    }
+
+    #[test]
+    fn test_dedup_order_ids() {
+        let first = OrderId::generate();
+        let second = OrderId::generate();
+
+        assert_eq!(dedup_order_ids(vec![first, second, first, second, first]), vec![first, second]);
+        assert_eq!(dedup_order_ids(vec![]), Vec::<OrderId>::new());
+    }
+
+    #[test]
+    fn test_bulk_order_results() {
+        let order = create_raw_order(InvoiceId::generate(), Currency::Btc, Amount::new(100_000_000));
+
+        let result = BulkOrderResultResponse::from_result(order.id, Ok(()));
+        assert!(result.success);
+        assert!(result.error.is_none());
+
+        // validation errors are reported as they are, other failures by their kind
+        let result = BulkOrderResultResponse::from_result(order.id, validate_captured_amount(&order, 0.5).map(|_| ()));
+        assert!(!result.success);
+        assert!(result.error.unwrap().get("amount").is_some());
+
+        let e = format_err!("Order {} not found", order.id);
+        let result = BulkOrderResultResponse::from_result(order.id, Err(ectx!(err e, ErrorKind::Internal)));
+        assert!(!result.success);
+        assert_eq!(result.error, Some(serde_json::Value::String(ErrorKind::Internal.to_string())));
     }
 }
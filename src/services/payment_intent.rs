@@ -267,6 +267,9 @@ fn new_payment_intent(
     fee_id: FeeId,
     stripe_payment_intent: stripe::PaymentIntent,
 ) -> Result<(NewPaymentIntent, NewPaymentIntentFee), ServiceError> {
+    let (last_payment_error_code, last_payment_error_message) =
+        last_payment_error_details(stripe_payment_intent.last_payment_error.as_ref());
+
     let payment_intent = NewPaymentIntent {
         id: PaymentIntentId(stripe_payment_intent.id.clone()),
         amount: stripe_payment_intent.amount.into(),
@@ -280,7 +283,7 @@ fn new_payment_intent(
             );
             move |_| ectx!(try err e, ErrorKind::Internal)
         })?,
-        last_payment_error_message: stripe_payment_intent.last_payment_error.map(|err| format!("{:?}", err)),
+        last_payment_error_message,
         receipt_email: stripe_payment_intent.receipt_email,
         charge_id: stripe_payment_intent
             .charges
@@ -289,6 +292,7 @@ fn new_payment_intent(
             .next()
             .map(|charge| ChargeId::new(charge.id)),
         status: stripe_payment_intent.status.into(),
+        last_payment_error_code,
     };
 
     let payment_intent_invoice = NewPaymentIntentFee {
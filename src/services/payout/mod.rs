@@ -19,7 +19,7 @@ use models::order_v2::{OrderId, OrderPaymentKind, RawOrder, StoreId};
 use models::*;
 use repos::ReposFactory;
 use services::types::spawn_on_pool;
-use services::ErrorKind;
+use services::{Error as ServiceError, ErrorKind};
 
 use super::types::{ServiceFutureV2, ServiceResultV2};
 
@@ -63,11 +63,21 @@ impl<
         let fut = spawn_on_pool(db_pool.clone(), cpu_pool.clone(), move |conn| {
             let orders_repo = repo_factory.create_orders_repo(&conn, user_id);
             let payouts_repo = repo_factory.create_payouts_repo(&conn, user_id);
+            let disputes_repo = repo_factory.create_disputes_repo(&conn, user_id);
+            let refunds_repo = repo_factory.create_refunds_repo(&conn, user_id);
 
             let orders_for_payout = orders_repo
                 .get_orders_for_payout(store_id.clone(), None)
                 .map_err(ectx!(try convert => store_id))?;
 
+            let unsettled_disputes = disputes_repo
+                .list_unsettled_adjustments_by_store_id(store_id.clone(), None)
+                .map_err(ectx!(try convert => store_id))?;
+
+            let unsettled_refunds = refunds_repo
+                .list_unsettled_adjustments_by_store_id(store_id.clone(), None)
+                .map_err(ectx!(try convert => store_id))?;
+
             let order_ids_without_payout = {
                 let order_ids = orders_for_payout.iter().map(|o| o.id).collect::<Vec<_>>();
 
@@ -80,26 +90,29 @@ impl<
             orders_for_payout
                 .into_iter()
                 .filter(|order| order_ids_without_payout.contains(&order.id))
-                .try_fold(
-                    HashMap::new(),
-                    |mut hash_map,
-                     RawOrder {
-                         total_amount,
-                         seller_currency,
-                         ..
-                     }| {
-                        {
-                            let gross_amount = hash_map.entry(seller_currency).or_insert(Amount::zero());
-                            *gross_amount = gross_amount.checked_add(total_amount)?;
-                        }
-                        Some(hash_map)
-                    },
-                )
+                .try_fold(HashMap::new(), |mut hash_map, order| {
+                    {
+                        let gross_amount = hash_map.entry(order.seller_currency).or_insert(Amount::zero());
+                        *gross_amount = gross_amount.checked_add(order.net_amount())?;
+                    }
+                    Some(hash_map)
+                })
                 .ok_or({
                     let e = err_msg("Overflow while calculating the gross amount of a payout");
                     ectx!(err e, ErrorKind::Internal)
                 })
-                .map(|hash| {
+                .map(|mut hash| {
+                    // disputes lost after the order has been paid out are taken back from the balance until a payout deducts them
+                    for dispute in unsettled_disputes {
+                        let balance = hash.entry(dispute.currency).or_insert(Amount::zero());
+                        *balance = balance.checked_sub(dispute.balance_adjustment).unwrap_or(Amount::zero());
+                    }
+                    // so are refunds of orders that had already been paid out
+                    for (refund, currency) in unsettled_refunds {
+                        let balance = hash.entry(currency).or_insert(Amount::zero());
+                        *balance = balance.checked_sub(refund.balance_adjustment).unwrap_or(Amount::zero());
+                    }
+
                     BalancesResponse::new(
                         hash.into_iter()
                             .map(|(currency, gross_amount)| (currency.into(), gross_amount.to_super_unit(currency)))
@@ -131,11 +144,21 @@ impl<
         let fut = spawn_on_pool(db_pool.clone(), cpu_pool.clone(), move |conn| {
             let orders_repo = repo_factory.create_orders_repo(&conn, user_id);
             let payouts_repo = repo_factory.create_payouts_repo(&conn, user_id);
+            let disputes_repo = repo_factory.create_disputes_repo(&conn, user_id);
+            let refunds_repo = repo_factory.create_refunds_repo(&conn, user_id);
 
             let orders_for_payout = orders_repo
                 .get_orders_for_payout(store_id.clone(), Some(currency.clone().into()))
                 .map_err(ectx!(try convert => store_id, currency))?;
 
+            let unsettled_disputes = disputes_repo
+                .list_unsettled_adjustments_by_store_id(store_id.clone(), Some(currency.clone().into()))
+                .map_err(ectx!(try convert => store_id, currency))?;
+
+            let unsettled_refunds = refunds_repo
+                .list_unsettled_adjustments_by_store_id(store_id.clone(), Some(currency.clone().into()))
+                .map_err(ectx!(try convert => store_id, currency))?;
+
             let order_ids_without_payout = {
                 let order_ids = orders_for_payout.iter().map(|o| o.id).collect::<Vec<_>>();
 
@@ -145,7 +168,7 @@ impl<
                     .map_err(ectx!(try convert => order_ids))
             }?;
 
-            orders_for_payout
+            let payout = orders_for_payout
                 .into_iter()
                 .filter(|order| order_ids_without_payout.contains(&order.id))
                 .try_fold(
@@ -154,16 +177,19 @@ impl<
                         currency,
                         gross_amount: Amount::zero(),
                     },
-                    |mut payout, RawOrder { id, total_amount, .. }| {
-                        payout.order_ids.push(id);
-                        payout.gross_amount = payout.gross_amount.checked_add(total_amount)?;
+                    |mut payout, order| {
+                        payout.order_ids.push(order.id);
+                        payout.gross_amount = payout.gross_amount.checked_add(order.net_amount())?;
                         Some(payout)
                     },
                 )
                 .ok_or({
                     let e = err_msg("Overflow while calculating the gross amount of a payout");
-                    ectx!(err e, ErrorKind::Internal)
-                })
+                    ectx!(try err e, ErrorKind::Internal)
+                })?;
+
+            let gross_amount = deduct_balance_adjustments(payout.gross_amount, &unsettled_disputes, &unsettled_refunds)?;
+            Ok(CalculatedPayoutExcludingFees { gross_amount, ..payout })
         })
         .and_then(move |calculated_payout_excluding_fees| {
             let CalculatedPayoutExcludingFees {
@@ -281,6 +307,8 @@ impl<
         spawn_on_pool(db_pool.clone(), cpu_pool.clone(), move |conn| {
             let orders_repo = repo_factory.create_orders_repo(&conn, Some(user_id));
             let payouts_repo = repo_factory.create_payouts_repo(&conn, Some(user_id));
+            let disputes_repo = repo_factory.create_disputes_repo(&conn, Some(user_id));
+            let refunds_repo = repo_factory.create_refunds_repo(&conn, Some(user_id));
             let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
 
             let order_ids_clone = order_ids.clone();
@@ -303,6 +331,10 @@ impl<
                 return Err(ErrorKind::from(errors).into());
             }
 
+            let mut store_ids = orders.iter().map(|order| order.store_id).collect::<Vec<_>>();
+            store_ids.sort();
+            store_ids.dedup();
+
             let OrdersForPayout { currency, orders } = validate_orders_for_payout(orders)?;
             if wallet_currency != currency {
                 let mut errors = ValidationErrors::new();
@@ -332,11 +364,27 @@ impl<
                 return Err(ErrorKind::from(errors).into());
             }
 
+            let mut unsettled_disputes = Vec::new();
+            let mut unsettled_refunds = Vec::new();
+            for store_id in store_ids {
+                unsettled_disputes.extend(
+                    disputes_repo
+                        .list_unsettled_adjustments_by_store_id(store_id, Some(currency.into()))
+                        .map_err(ectx!(try convert => store_id, currency))?,
+                );
+                unsettled_refunds.extend(
+                    refunds_repo
+                        .list_unsettled_adjustments_by_store_id(store_id, Some(currency.into()))
+                        .map_err(ectx!(try convert => store_id, currency))?,
+                );
+            }
+
             let gross_amount = orders
                 .iter()
                 .map(|o| o.total_amount)
                 .try_fold(Amount::new(0), |acc, next| acc.checked_add(next))
                 .ok_or(ErrorKind::Internal)?;
+            let gross_amount = deduct_balance_adjustments(gross_amount, &unsettled_disputes, &unsettled_refunds)?;
 
             let net_amount = gross_amount.checked_sub(blockchain_fee).ok_or({
                 let mut errors = ValidationErrors::new();
@@ -364,19 +412,69 @@ impl<
                 order_ids,
             };
 
-            let payout_initiated_event = Event::new(EventPayload::PayoutInitiated { payout_id: payout.id });
-            event_store_repo
-                .add_event(payout_initiated_event.clone())
-                .map_err(ectx!(try convert => payout_initiated_event))?;
-
-            payouts_repo
-                .create(payout.clone())
-                .map(PayoutOutput::from)
-                .map_err(ectx!(convert => payout))
+            conn.transaction::<_, ServiceError, _>(|| {
+                let payout_initiated_event = Event::new(EventPayload::PayoutInitiated { payout_id: payout.id });
+                event_store_repo
+                    .add_event(payout_initiated_event.clone())
+                    .map_err(ectx!(try convert => payout_initiated_event))?;
+
+                let payout = payouts_repo.create(payout.clone()).map_err(ectx!(try convert => payout))?;
+
+                let payout_id = payout.id;
+                let dispute_ids = unsettled_disputes.iter().map(|dispute| dispute.id).collect::<Vec<_>>();
+                if !dispute_ids.is_empty() {
+                    disputes_repo
+                        .settle_adjustments(dispute_ids.clone(), payout_id)
+                        .map_err(ectx!(try convert => dispute_ids, payout_id))?;
+                }
+
+                let refund_ids = unsettled_refunds.iter().map(|(refund, _)| refund.id).collect::<Vec<_>>();
+                if !refund_ids.is_empty() {
+                    refunds_repo
+                        .settle_adjustments(refund_ids.clone(), payout_id)
+                        .map_err(ectx!(try convert => refund_ids, payout_id))?;
+                }
+
+                Ok(PayoutOutput::from(payout))
+            })
         })
     }
 }
 
+/// Takes the adjustments of the lost disputes and of the refunds of paid out orders
+/// that are not deducted yet from the gross amount of a payout
+fn deduct_balance_adjustments(gross_amount: Amount, disputes: &[Dispute], refunds: &[(Refund, Currency)]) -> ServiceResultV2<Amount> {
+    let dispute_adjustments = disputes
+        .iter()
+        .try_fold(Amount::zero(), |acc, dispute| acc.checked_add(dispute.balance_adjustment))
+        .ok_or({
+            let e = err_msg("Overflow while adding up dispute adjustments of a payout");
+            ectx!(try err e, ErrorKind::Internal)
+        })?;
+
+    let refund_adjustments = refunds
+        .iter()
+        .try_fold(Amount::zero(), |acc, (refund, _)| acc.checked_add(refund.balance_adjustment))
+        .ok_or({
+            let e = err_msg("Overflow while adding up refund adjustments of a payout");
+            ectx!(try err e, ErrorKind::Internal)
+        })?;
+
+    gross_amount
+        .checked_sub(dispute_adjustments)
+        .and_then(|amount| amount.checked_sub(refund_adjustments))
+        .ok_or_else(|| {
+            let mut errors = ValidationErrors::new();
+            let mut error = ValidationError::new("payout_lt_balance_adjustments");
+            error.message = Some("Payout is less than the amount of lost disputes and refunds of paid out orders to deduct".into());
+            error.add_param("dispute_adjustments".into(), &dispute_adjustments);
+            error.add_param("refund_adjustments".into(), &refund_adjustments);
+            errors.add("order_ids", error);
+
+            ErrorKind::from(errors).into()
+        })
+}
+
 fn validate_orders_for_payout(orders: Vec<RawOrder>) -> ServiceResultV2<OrdersForPayout> {
     let mut errors = ValidationErrors::new();
 
@@ -425,10 +523,35 @@ fn validate_orders_for_payout(orders: Vec<RawOrder>) -> ServiceResultV2<OrdersFo
         currency,
         orders: orders
             .into_iter()
-            .map(|RawOrder { id, total_amount, .. }| OrderForPayout {
-                order_id: id,
-                total_amount,
+            .map(|order| OrderForPayout {
+                order_id: order.id,
+                total_amount: order.net_amount(),
             })
             .collect(),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use repos::repo_factory::tests::{create_refund, validation_error_fields};
+
+    use super::*;
+
+    #[test]
+    fn test_deduct_balance_adjustments() {
+        let mut refund = create_refund(OrderId::generate(), Amount::new(300), RefundStatus::Succeeded);
+        refund.balance_adjustment = Amount::new(300);
+        let refunds = vec![(refund, Currency::Btc)];
+
+        assert_eq!(deduct_balance_adjustments(Amount::new(1000), &[], &[]).unwrap(), Amount::new(1000));
+        // a refund of an order paid out earlier is taken from the next payout
+        assert_eq!(
+            deduct_balance_adjustments(Amount::new(1000), &[], &refunds).unwrap(),
+            Amount::new(700)
+        );
+        assert_eq!(
+            validation_error_fields(deduct_balance_adjustments(Amount::new(200), &[], &refunds)),
+            vec!["order_ids"]
+        );
+    }
+}
@@ -0,0 +1,85 @@
+//! Capture policies define what happens to paid orders a store does not capture or decline in time
+
+use diesel::connection::AnsiTransactionManager;
+use diesel::pg::Pg;
+use diesel::Connection;
+use futures_cpupool::CpuPool;
+use r2d2::{ManageConnection, Pool};
+
+use failure::Fail;
+
+use stq_http::client::HttpClient;
+use stq_types::StoreId;
+
+use super::types::ServiceFutureV2;
+use client::payments::PaymentsClient;
+use controller::context::DynamicContext;
+use controller::requests::SetStoreCapturePolicyRequest;
+use models::{NewStoreCapturePolicy, StoreCapturePolicy};
+use repos::repo_factory::ReposFactory;
+use services::accounts::AccountService;
+use services::types::spawn_on_pool;
+
+pub trait StoreCapturePolicyService {
+    /// Get the capture policy of the store, stores without one use the default action from the config
+    fn get(&self, store_id: StoreId) -> ServiceFutureV2<Option<StoreCapturePolicy>>;
+    /// Set the capture policy of the store
+    fn set(&self, store_id: StoreId, payload: SetStoreCapturePolicyRequest) -> ServiceFutureV2<StoreCapturePolicy>;
+}
+
+pub struct StoreCapturePolicyServiceImpl<
+    T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static,
+    M: ManageConnection<Connection = T>,
+    F: ReposFactory<T>,
+    C: HttpClient + Clone,
+    PC: PaymentsClient + Clone,
+    AS: AccountService + Clone,
+> {
+    pub db_pool: Pool<M>,
+    pub cpu_pool: CpuPool,
+    pub repo_factory: F,
+    pub dynamic_context: DynamicContext<C, PC, AS>,
+}
+
+impl<
+        T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static,
+        M: ManageConnection<Connection = T>,
+        F: ReposFactory<T>,
+        C: HttpClient + Clone,
+        PC: PaymentsClient + Clone,
+        AS: AccountService + Clone,
+    > StoreCapturePolicyService for StoreCapturePolicyServiceImpl<T, M, F, C, PC, AS>
+{
+    fn get(&self, store_id: StoreId) -> ServiceFutureV2<Option<StoreCapturePolicy>> {
+        let repo_factory = self.repo_factory.clone();
+        let user_id = self.dynamic_context.user_id;
+
+        let db_pool = self.db_pool.clone();
+        let cpu_pool = self.cpu_pool.clone();
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let store_capture_policies_repo = repo_factory.create_store_capture_policies_repo(&conn, user_id);
+            store_capture_policies_repo.get(store_id).map_err(ectx!(convert => store_id))
+        })
+    }
+
+    fn set(&self, store_id: StoreId, payload: SetStoreCapturePolicyRequest) -> ServiceFutureV2<StoreCapturePolicy> {
+        let repo_factory = self.repo_factory.clone();
+        let user_id = self.dynamic_context.user_id;
+
+        let db_pool = self.db_pool.clone();
+        let cpu_pool = self.cpu_pool.clone();
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let store_capture_policies_repo = repo_factory.create_store_capture_policies_repo(&conn, user_id);
+
+            let new_policy = NewStoreCapturePolicy {
+                store_id,
+                action: payload.action,
+            };
+            store_capture_policies_repo
+                .upsert(new_policy.clone())
+                .map_err(ectx!(convert => new_policy))
+        })
+    }
+}
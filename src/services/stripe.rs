@@ -1,5 +1,6 @@
 use std::sync::Arc;
 
+use chrono::Utc;
 use diesel::connection::AnsiTransactionManager;
 use diesel::pg::Pg;
 use diesel::Connection;
@@ -21,7 +22,7 @@ use stripe::Webhook;
 
 use repos::ReposFactory;
 use repos::{
-    FeeRepo, InvoicesV2Repo, OrdersRepo, PaymentIntentFeeRepo, PaymentIntentInvoiceRepo, PaymentIntentRepo, SearchPaymentIntent,
+    FeeRepo, InvoicesV2Repo, OrdersRepo, PaymentIntentFeeRepo, PaymentIntentInvoiceRepo, PaymentIntentRepo, SearchFee, SearchPaymentIntent,
     SearchPaymentIntentFee, SearchPaymentIntentInvoice,
 };
 
@@ -84,6 +85,7 @@ impl<
 
         let fut = spawn_on_pool(db_pool, cpu_pool, move |conn| {
             let event_store_repo = repo_factory.create_event_store_repo_with_sys_acl(&conn);
+            let stripe_webhook_events_repo = repo_factory.create_stripe_webhook_events_repo_with_sys_acl(&conn);
             conn.transaction(move || {
                 let event = Webhook::new()
                     .construct_event(event_payload, signature_header, signing_secret)
@@ -92,25 +94,67 @@ impl<
                         ectx!(try err e, ErrorKind::Internal)
                     })?;
                 info!("stripe handle_stripe_event event: {:?}", event);
+
+                let stripe_event_id = event.id.clone();
+                let new_webhook_event = NewStripeWebhookEvent {
+                    stripe_event_id: stripe_event_id.clone(),
+                    event_type: format!("{:?}", event.event_type),
+                };
+                let is_new_event = stripe_webhook_events_repo
+                    .register(new_webhook_event.clone())
+                    .map_err(ectx!(try convert => new_webhook_event))?;
+                if !is_new_event {
+                    info!("stripe handle_stripe_event duplicate delivery of event {} skipped", stripe_event_id);
+                    return Ok(());
+                }
+
+                let stripe_event_id = Some(stripe_event_id);
                 match (event.event_type, event.data.object) {
                     (PaymentIntentAmountCapturableUpdated, PaymentIntent(payment_intent)) => {
                         let payment_intent_id = payment_intent.id.clone();
                         event_store_repo
-                            .add_event(Event::new(EventPayload::PaymentIntentAmountCapturableUpdated { payment_intent }))
+                            .add_event(Event::new(EventPayload::PaymentIntentAmountCapturableUpdated {
+                                payment_intent,
+                                stripe_event_id,
+                            }))
                             .map_err(ectx!(try convert => payment_intent_id))?;
                     }
                     (PaymentIntentSucceeded, PaymentIntent(payment_intent)) => {
                         let payment_intent_id = payment_intent.id.clone();
                         event_store_repo
-                            .add_event(Event::new(EventPayload::PaymentIntentSucceeded { payment_intent }))
+                            .add_event(Event::new(EventPayload::PaymentIntentSucceeded {
+                                payment_intent,
+                                stripe_event_id,
+                            }))
                             .map_err(ectx!(try convert => payment_intent_id))?;
                     }
                     (PaymentIntentPaymentFailed, PaymentIntent(payment_intent)) => {
                         let payment_intent_id = payment_intent.id.clone();
                         event_store_repo
-                            .add_event(Event::new(EventPayload::PaymentIntentPaymentFailed { payment_intent }))
+                            .add_event(Event::new(EventPayload::PaymentIntentPaymentFailed {
+                                payment_intent,
+                                stripe_event_id,
+                            }))
                             .map_err(ectx!(try convert => payment_intent_id))?;
                     }
+                    (ChargeDisputeCreated, Dispute(dispute)) => {
+                        let dispute_id = dispute.id.clone();
+                        event_store_repo
+                            .add_event(Event::new(EventPayload::ChargeDisputeCreated { dispute, stripe_event_id }))
+                            .map_err(ectx!(try convert => dispute_id))?;
+                    }
+                    (ChargeDisputeUpdated, Dispute(dispute)) => {
+                        let dispute_id = dispute.id.clone();
+                        event_store_repo
+                            .add_event(Event::new(EventPayload::ChargeDisputeUpdated { dispute, stripe_event_id }))
+                            .map_err(ectx!(try convert => dispute_id))?;
+                    }
+                    (ChargeDisputeClosed, Dispute(dispute)) => {
+                        let dispute_id = dispute.id.clone();
+                        event_store_repo
+                            .add_event(Event::new(EventPayload::ChargeDisputeClosed { dispute, stripe_event_id }))
+                            .map_err(ectx!(try convert => dispute_id))?;
+                    }
                     (event_type, event_object) => {
                         warn!(
                             "stripe handle_stripe_event unprocessable event - type: {:?}, object: {:?}",
@@ -132,7 +176,9 @@ pub enum PaymentType {
         invoice: InvoiceV2,
         orders: Vec<RawOrder>,
     },
-    Fee,
+    Fee {
+        fee: Fee,
+    },
 }
 
 pub fn payment_intent_succeeded_or_amount_capturable_updated<C>(
@@ -144,6 +190,7 @@ pub fn payment_intent_succeeded_or_amount_capturable_updated<C>(
     payment_intent_fees_repo: &PaymentIntentFeeRepo,
     fees_repo: &FeeRepo,
     fee_config: config::FeeValues,
+    event_id: EventId,
     payment_intent: StripePaymentIntent,
 ) -> Result<PaymentType, ServiceError>
 where
@@ -189,6 +236,7 @@ where
                 invoices_repo,
                 fees_repo,
                 fee_config,
+                event_id,
                 payment_intent_invoice,
             )
             .map(|res| PaymentType::Invoice {
@@ -197,7 +245,7 @@ where
                 orders: res.1,
             }),
             (None, Some(payment_intent_fee)) => {
-                payment_intent_succeeded_or_amount_capturable_updated_fee(fees_repo, payment_intent_fee).map(|_| PaymentType::Fee)
+                payment_intent_succeeded_or_amount_capturable_updated_fee(fees_repo, payment_intent_fee).map(|fee| PaymentType::Fee { fee })
             }
             _ => {
                 let e = format_err!("Payment intent relationship by id {} not found.", payment_intent_id);
@@ -226,6 +274,7 @@ pub fn payment_intent_succeeded_or_amount_capturable_updated_invoice(
     invoice_repo: &InvoicesV2Repo,
     fees_repo: &FeeRepo,
     fee_config: config::FeeValues,
+    event_id: EventId,
     payment_intent_invoice: PaymentIntentInvoice,
 ) -> Result<(InvoiceV2, Vec<RawOrder>), ServiceError> {
     let invoice_id = payment_intent_invoice.invoice_id;
@@ -241,7 +290,33 @@ pub fn payment_intent_succeeded_or_amount_capturable_updated_invoice(
         .get_many_by_invoice_id(invoice.id)
         .map_err(ectx!(try convert => invoice_id))?;
 
+    // A previous payment attempt may have failed, the orders are payable again
+    let orders = orders
+        .into_iter()
+        .map(|order| {
+            if order.state.has_system_transition_to(PaymentState::Initial) {
+                orders_repo
+                    .update_state(
+                        order.id,
+                        PaymentState::Initial,
+                        OrderStateChange::by_event(event_id, "Payment retried"),
+                    )
+                    .map_err(ectx!(convert => order.id))
+            } else {
+                Ok(order)
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
     for order in orders.iter() {
+        // the payment intent event may be replayed, fees must not be created twice
+        let existing_fee = fees_repo
+            .get(SearchFee::OrderId(order.id))
+            .map_err(ectx!(try convert => order.id))?;
+        if existing_fee.is_some() {
+            continue;
+        }
+
         let new_fee = create_fee(fee_config.order_percent, order)?;
         let _ = fees_repo.create(new_fee).map_err(ectx!(try convert => order.id.clone()))?;
     }
@@ -249,11 +324,12 @@ pub fn payment_intent_succeeded_or_amount_capturable_updated_invoice(
     Ok((invoice, orders))
 }
 
-fn create_fee(order_percent: u64, order: &RawOrder) -> Result<NewFee, ServiceError> {
+/// Calculates the fee for the amount the seller finally receives, i.e. after partial capture and refunds
+pub fn create_fee(order_percent: u64, order: &RawOrder) -> Result<NewFee, ServiceError> {
     let hundred_percents = 100u64;
 
     let amount = order
-        .total_amount
+        .net_amount()
         .checked_div(Amount::from(hundred_percents))
         .and_then(|one_percent| one_percent.checked_mul(Amount::from(order_percent)))
         .ok_or(ectx!(try err ErrorContext::AmountConversion, ErrorKind::Internal))?;
@@ -270,10 +346,87 @@ fn create_fee(order_percent: u64, order: &RawOrder) -> Result<NewFee, ServiceErr
     })
 }
 
+pub fn payment_intent_payment_failed<C>(
+    conn: &C,
+    orders_repo: &OrdersRepo,
+    invoices_repo: &InvoicesV2Repo,
+    payment_intent_repo: &PaymentIntentRepo,
+    payment_intent_invoices_repo: &PaymentIntentInvoiceRepo,
+    event_id: EventId,
+    payment_intent: StripePaymentIntent,
+) -> Result<Option<(InvoiceV2, Vec<RawOrder>)>, ServiceError>
+where
+    C: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static,
+{
+    let payment_intent_id = PaymentIntentId(payment_intent.id.clone());
+    let payment_intent_id_cloned1 = payment_intent_id.clone();
+
+    let (last_payment_error_code, last_payment_error_message) = last_payment_error_details(payment_intent.last_payment_error.as_ref());
+    let payment_intent_update = UpdatePaymentIntent {
+        status: Some(payment_intent.status.into()),
+        last_payment_error_code,
+        last_payment_error_message,
+        ..Default::default()
+    };
+
+    let payment_intent_invoice = payment_intent_invoices_repo
+        .get(SearchPaymentIntentInvoice::PaymentIntentId(payment_intent_id.clone()))
+        .map_err(ectx!(try convert => payment_intent_id_cloned1))?;
+
+    conn.transaction::<_, ServiceError, _>(move || {
+        let payment_intent_id_cloned2 = payment_intent_id.clone();
+        payment_intent_repo
+            .update(payment_intent_id.clone(), payment_intent_update)
+            .map_err(ectx!(try convert => payment_intent_id_cloned2))?;
+
+        // Fee payment intents only need the error details
+        let payment_intent_invoice = match payment_intent_invoice {
+            None => return Ok(None),
+            Some(payment_intent_invoice) => payment_intent_invoice,
+        };
+
+        let invoice_id = payment_intent_invoice.invoice_id;
+        let invoice = invoices_repo.get(invoice_id).map_err(ectx!(try convert => invoice_id))?.ok_or({
+            let e = format_err!("Invoice {} not found", invoice_id);
+            ectx!(try err e, ErrorKind::Internal)
+        })?;
+
+        // The event could arrive after a successful attempt
+        if invoice.paid_at.is_some() {
+            return Ok(None);
+        }
+
+        let invoice = invoices_repo
+            .set_payment_failed(invoice_id, Utc::now().naive_utc())
+            .map_err(ectx!(try convert => invoice_id))?;
+
+        let orders = orders_repo
+            .get_many_by_invoice_id(invoice_id)
+            .map_err(ectx!(try convert => invoice_id))?
+            .into_iter()
+            .map(|order| {
+                if order.state.has_system_transition_to(PaymentState::PaymentFailed) {
+                    orders_repo
+                        .update_state(
+                            order.id,
+                            PaymentState::PaymentFailed,
+                            OrderStateChange::by_event(event_id, "Payment failed"),
+                        )
+                        .map_err(ectx!(convert => order.id))
+                } else {
+                    Ok(order)
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Some((invoice, orders)))
+    })
+}
+
 pub fn payment_intent_succeeded_or_amount_capturable_updated_fee(
     fees_repo: &FeeRepo,
     payment_intent_fee: PaymentIntentFee,
-) -> Result<(), ServiceError> {
+) -> Result<Fee, ServiceError> {
     let update_fee = UpdateFee {
         status: Some(FeeStatus::Paid),
         ..Default::default()
@@ -282,5 +435,4 @@ pub fn payment_intent_succeeded_or_amount_capturable_updated_fee(
     fees_repo
         .update(payment_intent_fee.fee_id.clone(), update_fee)
         .map_err(ectx!(convert => payment_intent_fee.fee_id.clone()))
-        .map(|_| ())
 }
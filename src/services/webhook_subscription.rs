@@ -0,0 +1,235 @@
+//! WebhookSubscription Service, presents CRUD operations with merchant webhook subscriptions
+use diesel::connection::AnsiTransactionManager;
+use diesel::pg::Pg;
+use diesel::Connection;
+use futures::{Future, IntoFuture};
+use futures_cpupool::CpuPool;
+use r2d2::{ManageConnection, Pool};
+use validator::{ValidationError, ValidationErrors};
+
+use failure::Fail;
+
+use stq_http::client::HttpClient;
+
+use client::payments::PaymentsClient;
+use controller::context::DynamicContext;
+use controller::requests::{CreateWebhookSubscriptionRequest, UpdateWebhookSubscriptionRequest};
+use models::order_v2::StoreId;
+use models::{NewWebhookSubscription, UpdateWebhookSubscription, WebhookDelivery, WebhookSubscription, WebhookSubscriptionId};
+use repos::{ReposFactory, WebhookSubscriptionsRepo};
+use services::accounts::AccountService;
+use services::types::{spawn_on_pool, ServiceResultV2};
+
+use super::types::ServiceFutureV2;
+use super::{Error, ErrorContext, ErrorKind};
+
+pub trait WebhookSubscriptionService {
+    fn create(&self, payload: CreateWebhookSubscriptionRequest) -> ServiceFutureV2<WebhookSubscription>;
+    fn get(&self, subscription_id: WebhookSubscriptionId) -> ServiceFutureV2<WebhookSubscription>;
+    fn list(&self, store_id: Option<StoreId>) -> ServiceFutureV2<Vec<WebhookSubscription>>;
+    fn update(
+        &self,
+        subscription_id: WebhookSubscriptionId,
+        payload: UpdateWebhookSubscriptionRequest,
+    ) -> ServiceFutureV2<WebhookSubscription>;
+    fn delete(&self, subscription_id: WebhookSubscriptionId) -> ServiceFutureV2<WebhookSubscription>;
+    fn get_deliveries(&self, subscription_id: WebhookSubscriptionId, skip: i64, count: i64) -> ServiceFutureV2<Vec<WebhookDelivery>>;
+}
+
+pub struct WebhookSubscriptionServiceImpl<
+    T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static,
+    M: ManageConnection<Connection = T>,
+    F: ReposFactory<T>,
+    C: HttpClient + Clone,
+    PC: PaymentsClient + Clone,
+    AS: AccountService + Clone,
+> {
+    pub db_pool: Pool<M>,
+    pub cpu_pool: CpuPool,
+    pub repo_factory: F,
+    pub dynamic_context: DynamicContext<C, PC, AS>,
+}
+
+impl<
+        T: Connection<Backend = Pg, TransactionManager = AnsiTransactionManager> + 'static,
+        M: ManageConnection<Connection = T>,
+        F: ReposFactory<T>,
+        C: HttpClient + Clone,
+        PC: PaymentsClient + Clone,
+        AS: AccountService + Clone,
+    > WebhookSubscriptionService for WebhookSubscriptionServiceImpl<T, M, F, C, PC, AS>
+{
+    fn create(&self, payload: CreateWebhookSubscriptionRequest) -> ServiceFutureV2<WebhookSubscription> {
+        let repo_factory = self.repo_factory.clone();
+        let user_id = self.dynamic_context.user_id;
+
+        let db_pool = self.db_pool.clone();
+        let cpu_pool = self.cpu_pool.clone();
+
+        let fut = validate_webhook_subscription(Some(&payload.url), Some(&payload.secret))
+            .into_future()
+            .and_then(move |_| {
+                spawn_on_pool(db_pool, cpu_pool, move |conn| {
+                    let webhook_subscriptions_repo = repo_factory.create_webhook_subscriptions_repo(&conn, user_id);
+
+                    let new_subscription = NewWebhookSubscription::from(payload);
+                    webhook_subscriptions_repo
+                        .create(new_subscription.clone())
+                        .map_err(ectx!(convert => new_subscription))
+                })
+            });
+
+        Box::new(fut)
+    }
+
+    fn get(&self, subscription_id: WebhookSubscriptionId) -> ServiceFutureV2<WebhookSubscription> {
+        let repo_factory = self.repo_factory.clone();
+        let user_id = self.dynamic_context.user_id;
+
+        let db_pool = self.db_pool.clone();
+        let cpu_pool = self.cpu_pool.clone();
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let webhook_subscriptions_repo = repo_factory.create_webhook_subscriptions_repo(&conn, user_id);
+
+            get_webhook_subscription(&*webhook_subscriptions_repo, subscription_id)
+        })
+    }
+
+    fn list(&self, store_id: Option<StoreId>) -> ServiceFutureV2<Vec<WebhookSubscription>> {
+        let repo_factory = self.repo_factory.clone();
+        let user_id = self.dynamic_context.user_id;
+
+        let db_pool = self.db_pool.clone();
+        let cpu_pool = self.cpu_pool.clone();
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let webhook_subscriptions_repo = repo_factory.create_webhook_subscriptions_repo(&conn, user_id);
+
+            webhook_subscriptions_repo.list(store_id).map_err(ectx!(convert => store_id))
+        })
+    }
+
+    fn update(
+        &self,
+        subscription_id: WebhookSubscriptionId,
+        payload: UpdateWebhookSubscriptionRequest,
+    ) -> ServiceFutureV2<WebhookSubscription> {
+        let repo_factory = self.repo_factory.clone();
+        let user_id = self.dynamic_context.user_id;
+
+        let db_pool = self.db_pool.clone();
+        let cpu_pool = self.cpu_pool.clone();
+
+        let fut = validate_webhook_subscription(
+            payload.url.as_ref().map(String::as_str),
+            payload.secret.as_ref().map(String::as_str),
+        )
+        .into_future()
+        .and_then(move |_| {
+            spawn_on_pool(db_pool, cpu_pool, move |conn| {
+                let webhook_subscriptions_repo = repo_factory.create_webhook_subscriptions_repo(&conn, user_id);
+
+                get_webhook_subscription(&*webhook_subscriptions_repo, subscription_id)?;
+
+                let update_subscription = UpdateWebhookSubscription::from(payload);
+                webhook_subscriptions_repo
+                    .update(subscription_id, update_subscription.clone())
+                    .map_err(ectx!(convert => subscription_id, update_subscription))
+            })
+        });
+
+        Box::new(fut)
+    }
+
+    fn delete(&self, subscription_id: WebhookSubscriptionId) -> ServiceFutureV2<WebhookSubscription> {
+        let repo_factory = self.repo_factory.clone();
+        let user_id = self.dynamic_context.user_id;
+
+        let db_pool = self.db_pool.clone();
+        let cpu_pool = self.cpu_pool.clone();
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let webhook_subscriptions_repo = repo_factory.create_webhook_subscriptions_repo(&conn, user_id);
+
+            webhook_subscriptions_repo
+                .delete(subscription_id)
+                .map_err(ectx!(try convert => subscription_id))?
+                .ok_or_else(|| not_found_error(subscription_id))
+        })
+    }
+
+    fn get_deliveries(&self, subscription_id: WebhookSubscriptionId, skip: i64, count: i64) -> ServiceFutureV2<Vec<WebhookDelivery>> {
+        let repo_factory = self.repo_factory.clone();
+        let user_id = self.dynamic_context.user_id;
+
+        let db_pool = self.db_pool.clone();
+        let cpu_pool = self.cpu_pool.clone();
+
+        spawn_on_pool(db_pool, cpu_pool, move |conn| {
+            let webhook_subscriptions_repo = repo_factory.create_webhook_subscriptions_repo(&conn, user_id);
+            let webhook_deliveries_repo = repo_factory.create_webhook_deliveries_repo_with_sys_acl(&conn);
+
+            // access to the delivery log is granted by access to the subscription
+            get_webhook_subscription(&*webhook_subscriptions_repo, subscription_id)?;
+
+            webhook_deliveries_repo
+                .list_by_subscription_id(subscription_id, skip, count)
+                .map_err(ectx!(convert => subscription_id, skip, count))
+        })
+    }
+}
+
+fn get_webhook_subscription(
+    webhook_subscriptions_repo: &WebhookSubscriptionsRepo,
+    subscription_id: WebhookSubscriptionId,
+) -> ServiceResultV2<WebhookSubscription> {
+    webhook_subscriptions_repo
+        .get(subscription_id)
+        .map_err(ectx!(try convert => subscription_id))?
+        .ok_or_else(|| not_found_error(subscription_id))
+}
+
+fn not_found_error(subscription_id: WebhookSubscriptionId) -> Error {
+    let e = format_err!("Webhook subscription with ID {} not found", subscription_id);
+    ectx!(err e, ErrorKind::NotFound)
+}
+
+fn validate_webhook_subscription(url: Option<&str>, secret: Option<&str>) -> ServiceResultV2<()> {
+    let mut errors = ValidationErrors::new();
+
+    if let Some(url) = url {
+        if !url.starts_with("https://") && !url.starts_with("http://") {
+            let mut error = ValidationError::new("wrong_url");
+            error.message = Some(format!("Webhook URL \"{}\" must be an absolute HTTP(S) URL", url).into());
+            errors.add("url", error);
+        }
+    }
+
+    if let Some(secret) = secret {
+        if secret.is_empty() {
+            let mut error = ValidationError::new("empty_secret");
+            error.message = Some("Webhook secret must not be empty".into());
+            errors.add("secret", error);
+        }
+    }
+
+    if errors.errors().is_empty() {
+        Ok(())
+    } else {
+        Err(ectx!(err ErrorContext::WebhookSubscription, ErrorKind::Validation(serde_json::to_value(errors).unwrap_or_default())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_webhook_subscription() {
+        assert!(validate_webhook_subscription(Some("https://example.com/webhooks"), Some("secret")).is_ok());
+        assert!(validate_webhook_subscription(None, None).is_ok());
+        assert!(validate_webhook_subscription(Some("ftp://example.com"), None).is_err());
+        assert!(validate_webhook_subscription(None, Some("")).is_err());
+    }
+}